@@ -1,3 +1,27 @@
+use pinetime_common::flash_layout::internal;
+use std::{env, fs, path::PathBuf};
+
+/// Bosch's BMA421 config blob isn't redistributable, it's picked up from here
+/// if present. Without it the accelerometer still works but the step counter doesn't.
+const BMA421_CONFIG_PATH: &str = "res/bma421/bma421_config.bin";
+
 fn main() {
-    built::write_built_file().expect("Failed to acquire build-time information")
+    built::write_built_file().expect("Failed to acquire build-time information");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = fs::read(BMA421_CONFIG_PATH).unwrap_or_default();
+    fs::write(out_dir.join("bma421_config.bin"), config)
+        .expect("Failed to write the BMA421 config");
+
+    // The application goes after the bootloader, see pinetime-bootloader
+    let memory_x = format!(
+        include_str!("memory.x.in"),
+        flash_origin = internal::APP_OFFSET,
+        flash_length = internal::APP_SIZE,
+    );
+    fs::write(out_dir.join("memory.x"), memory_x).expect("Failed to write memory.x");
+    println!("cargo:rustc-link-search={}", out_dir.display());
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=memory.x.in");
+    println!("cargo:rerun-if-changed={}", BMA421_CONFIG_PATH);
 }
@@ -0,0 +1,76 @@
+use pinetime_common::dfu::image::{ImageHeader, Version, MAX_FIRMWARE_SIZE};
+use std::{fs, path::PathBuf};
+use structopt::StructOpt;
+
+const ABOUT: &str = r#"Wraps a firmware binary in the header the watch's firmware update expects
+
+Examples:
+    # Build the binary and make an image of it
+    cargo objcopy --release --bin pinetime -- -O binary pinetime.bin
+    dfu-image -i pinetime.bin -o pinetime.img -v 0.1.0
+"#;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = ABOUT)]
+pub struct Opts {
+    /// Firmware binary, linked for the application slot
+    #[structopt(name = "firmware input file", long = "input", short = "i")]
+    pub input: PathBuf,
+
+    /// Image file to write
+    #[structopt(name = "image output file", long = "output", short = "o")]
+    pub output: PathBuf,
+
+    /// Firmware version, major.minor.patch
+    #[structopt(
+        name = "firmware version",
+        long = "firmware-version",
+        short = "v",
+        parse(try_from_str = parse_version)
+    )]
+    pub version: Version,
+}
+
+fn parse_version(s: &str) -> Result<Version, String> {
+    let parts: Vec<&str> = s.split('.').collect();
+    let err = || format!("Invalid version '{}', expected major.minor.patch", s);
+    match parts.as_slice() {
+        [major, minor, patch] => Ok(Version {
+            major: major.parse().map_err(|_| err())?,
+            minor: minor.parse().map_err(|_| err())?,
+            patch: patch.parse().map_err(|_| err())?,
+        }),
+        _ => Err(err()),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opts = Opts::from_args();
+
+    let firmware = fs::read(&opts.input)?;
+    if firmware.len() > MAX_FIRMWARE_SIZE as usize {
+        return Err(format!(
+            "Firmware is {} bytes, at most {} fit",
+            firmware.len(),
+            MAX_FIRMWARE_SIZE
+        )
+        .into());
+    }
+
+    let header = ImageHeader::new(opts.version, &firmware);
+    let mut image = header.to_bytes().to_vec();
+    image.extend_from_slice(&firmware);
+    fs::write(&opts.output, &image)?;
+
+    println!(
+        "Firmware {}, {} bytes, CRC {:08X}",
+        header.version, header.size, header.crc
+    );
+    println!(
+        "Wrote {} byte image to {}",
+        image.len(),
+        opts.output.display()
+    );
+
+    Ok(())
+}
@@ -1,24 +1,58 @@
-use chrono::{DateTime, Local, NaiveDateTime};
+use chrono::{Local, NaiveDateTime, Utc};
 use embedded_graphics_simulator::{
     sdl2::Keycode, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
 };
 use pinetime_common::{
+    alarm,
+    battery_history::{self, BatteryHistory},
+    battery_model::ChargeState,
     display::{self, PixelFormat, BACKGROUND_COLOR},
     embedded_graphics::prelude::*,
-    BatteryControllerExt, MilliVolts, SystemTimeExt,
+    heart_rate::SyntheticPpg,
+    low_battery::{self, LowBatteryMonitor},
+    notification::Category,
+    BatteryControllerExt, BootRecord, BpmEstimator, Countdown, CrashRecord, InputEvent, MilliVolts,
+    Notification, NotificationStore, ResetReason, SelfTest, Settings, Stopwatch, SystemTimeExt,
+    TimeZone,
 };
 use pinetime_graphics::{
     font_styles::FontStyles,
     icons::Icons,
-    screens::{WatchFace, WatchFaceResources},
+    screens::{Action, Resources, ScreenId, ScreenManager},
 };
-use std::{thread, time::Duration};
+use sim_input::SimInput;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+mod sim_input;
 
 const SIMULATOR_SCALE: u32 = 2;
 
 const FONT_STYLES: FontStyles = FontStyles::new();
 const ICONS: Icons = Icons::new();
 
+/// Cycled through with the N key
+const SIM_NOTIFICATIONS: [(Category, &str, &str); 4] = [
+    (
+        Category::Sms,
+        "Alice",
+        "Running 10 minutes late, go ahead and order without me",
+    ),
+    (
+        Category::Email,
+        "Build failed",
+        "pinetime-rs: clippy found 3 warnings on master",
+    ),
+    (Category::Call, "Bob", ""),
+    (
+        Category::Schedule,
+        "Standup",
+        "Starts in 5 minutes in the small meeting room. Bring the prototype boards.",
+    ),
+];
+
 fn main() -> Result<(), core::convert::Infallible> {
     let mut display =
         SimulatorDisplay::<PixelFormat>::with_default_color(display::SIZE, BACKGROUND_COLOR);
@@ -27,34 +61,89 @@ fn main() -> Result<(), core::convert::Infallible> {
 
     let mut sim_clock = SimClock::default();
     let mut sim_battery = SimBattery::default();
+    let mut sim_input = SimInput::default();
+    let mut sim_heart_rate = SimHeartRate::default();
+    let mut settings = Settings::default();
+    // Start out in the host's zone, the DST rule can be picked in the settings
+    let host_offset_minutes = Local::now().offset().local_minus_utc() / 60;
+    settings.time_zone = TimeZone::new(host_offset_minutes as i16, settings.time_zone.dst);
+    let mut notifications = NotificationStore::new();
+    let mut sim_monotonic = SimMonotonic::default();
+    let mut apps = SimApps {
+        battery_history: synthetic_battery_history(),
+        ..Default::default()
+    };
+    let boot_record = synthetic_boot_record();
+    // Everything a simulator has works
+    let self_test = SelfTest {
+        display: true,
+        touch: true,
+        battery: true,
+    };
+    let mut crash_record = None;
 
-    let mut screen = WatchFace::new(&FONT_STYLES, &ICONS);
+    let mut screen_manager = ScreenManager::new(&FONT_STYLES, &ICONS);
 
     clear_screen(&mut display)?;
 
     'running: loop {
-        sim_clock.update();
+        sim_clock.update(settings.time_zone);
+        sim_monotonic.update();
+        sim_battery.update();
+        sim_heart_rate.update(screen_manager.active() == ScreenId::HeartRate);
+        check_alarms(
+            &mut screen_manager,
+            &mut settings,
+            &mut apps,
+            &sim_clock.local(),
+        );
+        check_countdown(&mut screen_manager, &mut apps, sim_monotonic.now_ms);
+        check_low_battery(&mut screen_manager, &mut apps, &sim_battery);
 
-        let res = WatchFaceResources {
+        let res = Resources {
             sys_time: &sim_clock,
             bat_ctl: &sim_battery,
+            settings: &settings,
+            heart_rate_bpm: sim_heart_rate.estimator.bpm(),
+            notifications: &notifications,
+            now_ms: sim_monotonic.now_ms,
+            stopwatch: &apps.stopwatch,
+            countdown: &apps.countdown,
+            battery_history: &apps.battery_history,
+            boot_record: &boot_record,
+            self_test,
+            crash_record: crash_record.as_ref(),
         };
 
-        screen.update(&res).unwrap();
-        screen.draw(&mut display).unwrap();
-        screen.clear_redraw();
+        screen_manager.update(&res).unwrap();
+        screen_manager.draw(&mut display).unwrap();
 
         window.update(&display);
 
+        if let Some(input) = sim_input.poll_button() {
+            handle_input(
+                &mut screen_manager,
+                &mut settings,
+                &mut apps,
+                &sim_clock,
+                &sim_monotonic,
+                input,
+            );
+        }
+
         for event in window.events() {
-            match event {
+            let input = match event {
                 SimulatorEvent::Quit => break 'running,
                 SimulatorEvent::MouseButtonDown { point, .. } => {
                     println!("Down {:?}", point);
+                    sim_input.mouse_down(point);
+                    None
                 }
                 SimulatorEvent::MouseButtonUp { point, .. } => {
                     println!("Up {:?}", point);
+                    sim_input.mouse_up(point)
                 }
+                SimulatorEvent::KeyUp { keycode, .. } => sim_input.key_up(keycode),
                 SimulatorEvent::KeyDown {
                     keycode,
                     keymod: _,
@@ -70,13 +159,56 @@ fn main() -> Result<(), core::convert::Infallible> {
                                 println!("Battery {} %", sim_battery.percent_remaining);
                             }
                             Keycode::C => {
-                                sim_battery.charging = !sim_battery.charging;
+                                sim_battery.set_charging(!sim_battery.charging);
+                                screen_manager.on_charging(sim_battery.charging);
+                            }
+                            Keycode::N => {
+                                let (category, title, body) = SIM_NOTIFICATIONS
+                                    [notifications.revision() as usize % SIM_NOTIFICATIONS.len()];
+                                notifications.push(Notification::new(
+                                    category,
+                                    sim_clock.local(),
+                                    title,
+                                    body,
+                                ));
+                                screen_manager.on_notification();
+                            }
+                            Keycode::H => {
+                                sim_heart_rate.ppg.bpm += 20;
+                                if sim_heart_rate.ppg.bpm > 180 {
+                                    sim_heart_rate.ppg.bpm = 60;
+                                }
+                                println!("Heart rate {} BPM", sim_heart_rate.ppg.bpm);
+                            }
+                            Keycode::F => {
+                                sim_monotonic.skip(SimMonotonic::SKIP_MS);
+                                println!("Monotonic clock {} ms", sim_monotonic.now_ms);
+                            }
+                            Keycode::P => {
+                                // What the firmware shows on the boot after a panic
+                                let crash = synthetic_crash_record();
+                                println!("Crashed before the reset: {}", crash);
+                                crash_record = Some(crash);
+                                screen_manager.push(ScreenId::Crash);
                             }
                             _ => (),
                         }
+                        sim_input.key_down(keycode)
+                    } else {
+                        None
                     }
                 }
-                _ => {}
+                _ => None,
+            };
+            if let Some(input) = input {
+                handle_input(
+                    &mut screen_manager,
+                    &mut settings,
+                    &mut apps,
+                    &sim_clock,
+                    &sim_monotonic,
+                    input,
+                );
             }
         }
 
@@ -86,6 +218,88 @@ fn main() -> Result<(), core::convert::Infallible> {
     Ok(())
 }
 
+fn handle_input(
+    screen_manager: &mut ScreenManager,
+    settings: &mut Settings,
+    apps: &mut SimApps,
+    clock: &SimClock,
+    monotonic: &SimMonotonic,
+    event: InputEvent,
+) {
+    println!("{:?}", event);
+    match screen_manager.handle_event(event) {
+        Action::None => (),
+        Action::Push(_) | Action::Pop | Action::Switch(_) => {
+            println!("Screen {:?}", screen_manager.active())
+        }
+        Action::UpdateSettings(s) => {
+            println!("{:?}", s);
+            *settings = s;
+        }
+        Action::AlarmResponse(response) => {
+            println!("Alarm {:?}, vibration off", response);
+            apps.alarm_scheduler.respond(response, &clock.local());
+        }
+        Action::Stopwatch(control) => {
+            println!("Stopwatch {:?}", control);
+            apps.stopwatch.control(control, monotonic.now_ms);
+        }
+        Action::Countdown(control) => {
+            println!("Countdown {:?}", control);
+            apps.countdown.control(control, monotonic.now_ms);
+        }
+        Action::Power(action) => println!("Power {:?}, not simulated", action),
+    }
+}
+
+/// Ring or silence the alarms, there's no motor so the vibration is only printed
+fn check_alarms(
+    screen_manager: &mut ScreenManager,
+    settings: &mut Settings,
+    apps: &mut SimApps,
+    now: &NaiveDateTime,
+) {
+    match apps.alarm_scheduler.poll(&settings.alarms, now) {
+        Some(alarm::Event::Ring { alarm }) => {
+            println!("Alarm ringing {:?}, vibration on", alarm);
+            if let Some(index) = alarm.filter(|&i| settings.alarms[i].days.is_empty()) {
+                settings.alarms[index].enabled = false;
+            }
+            screen_manager.on_alarm_ringing();
+        }
+        Some(alarm::Event::Silenced) => {
+            println!("Alarm snoozed, nobody responded, vibration off");
+            screen_manager.on_alarm_silenced();
+        }
+        None => (),
+    }
+}
+
+/// Checked every frame instead of being scheduled like on the watch
+fn check_countdown(screen_manager: &mut ScreenManager, apps: &mut SimApps, now_ms: u32) {
+    if apps.countdown.poll(now_ms) {
+        println!("Countdown expired, vibration on");
+        screen_manager.on_countdown_expired();
+    }
+}
+
+/// Checked every frame instead of on each voltage reading like on the watch
+fn check_low_battery(screen_manager: &mut ScreenManager, apps: &mut SimApps, bat: &SimBattery) {
+    let event = apps
+        .low_battery
+        .update(bat.percent_remaining(), bat.voltage(), bat.charge_state());
+    match event {
+        Some(low_battery::Event::Warning) => {
+            println!("Battery low, vibration on");
+            screen_manager.on_low_battery();
+        }
+        Some(low_battery::Event::PowerSave(power_save)) => println!("Power save {}", power_save),
+        // The simulated voltage never drops that far
+        Some(low_battery::Event::Shutdown) => println!("Battery empty, shutting down"),
+        None => (),
+    }
+}
+
 fn clear_screen<D>(target: &mut D) -> Result<(), D::Error>
 where
     D: DrawTarget<Color = PixelFormat>,
@@ -95,29 +309,136 @@ where
 }
 
 pub struct SimClock {
-    pub dt: NaiveDateTime,
+    pub utc: NaiveDateTime,
+    pub time_zone: TimeZone,
+    boot: Instant,
 }
 
 impl Default for SimClock {
     fn default() -> Self {
         let mut sc = SimClock {
-            dt: NaiveDateTime::from_timestamp(0, 0),
+            utc: NaiveDateTime::from_timestamp(0, 0),
+            time_zone: TimeZone::UTC,
+            boot: Instant::now(),
         };
-        sc.update();
+        sc.update(TimeZone::UTC);
         sc
     }
 }
 
 impl SimClock {
-    pub fn update(&mut self) {
-        let now: DateTime<Local> = Local::now();
-        self.dt = NaiveDateTime::from_timestamp(now.timestamp(), now.timestamp_subsec_nanos());
+    /// The zone comes from the settings, like the firmware
+    pub fn update(&mut self, time_zone: TimeZone) {
+        self.utc = Utc::now().naive_utc();
+        self.time_zone = time_zone;
     }
 }
 
 impl SystemTimeExt for SimClock {
-    fn date_time(&self) -> &NaiveDateTime {
-        &self.dt
+    fn utc(&self) -> &NaiveDateTime {
+        &self.utc
+    }
+
+    fn uptime_ms(&self) -> u64 {
+        self.boot.elapsed().as_millis() as u64
+    }
+
+    fn time_zone(&self) -> &TimeZone {
+        &self.time_zone
+    }
+}
+
+/// State the firmware keeps outside of the screens
+#[derive(Default)]
+pub struct SimApps {
+    pub alarm_scheduler: alarm::Scheduler,
+    pub stopwatch: Stopwatch,
+    pub countdown: Countdown,
+    pub battery_history: BatteryHistory,
+    pub low_battery: LowBatteryMonitor,
+}
+
+/// A day of made up samples, charged in the morning then slowly discharging
+fn synthetic_battery_history() -> BatteryHistory {
+    let mut history = BatteryHistory::new();
+    for i in 0..battery_history::CAPACITY {
+        let (percent, state) = if i < 12 {
+            (40 + i as u8 * 5, ChargeState::Charging)
+        } else {
+            (100 - ((i - 12) / 3) as u8, ChargeState::Discharging)
+        };
+        history.push(battery_history::Sample {
+            voltage: MilliVolts(3500 + percent as u16 * 7),
+            percent,
+            state,
+        });
+    }
+    history
+}
+
+/// A few made up resets since the battery was plugged in, the last one by the watchdog
+fn synthetic_boot_record() -> BootRecord {
+    let mut record = BootRecord::new();
+    let resets = [
+        ResetReason::PowerOn,
+        ResetReason::Soft,
+        ResetReason::Pin,
+        ResetReason::Soft,
+        ResetReason::Watchdog,
+    ];
+    for reason in resets.iter() {
+        record.start_boot(*reason);
+        record.mark_good();
+    }
+    record
+}
+
+/// A panic like an `unwrap()` in a task would leave
+fn synthetic_crash_record() -> CrashRecord {
+    let mut record = CrashRecord::new();
+    record.set_message(format_args!(
+        "called `Result::unwrap()` on an `Err` value: Spi(Transmit)"
+    ));
+    record.set_location("src/main.rs", 1270, 36);
+    record.set_stack(
+        0x2000_3F80,
+        &[0x2000_0104, 0x0000_C1A5, 0x0000_0000, 0x0001_2E4D],
+    );
+    record
+}
+
+/// Fake free-running millisecond clock standing in for the RTC monotonic
+///
+/// Follows the host's clock but can be skipped ahead, and starts close to
+/// wrapping around so the wraparound gets exercised.
+pub struct SimMonotonic {
+    pub now_ms: u32,
+    last_update: Instant,
+}
+
+impl Default for SimMonotonic {
+    fn default() -> Self {
+        SimMonotonic {
+            now_ms: u32::MAX - 60_000,
+            last_update: Instant::now(),
+        }
+    }
+}
+
+impl SimMonotonic {
+    /// Skipped with the F key
+    pub const SKIP_MS: u32 = 10_000;
+
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update).as_millis() as u32;
+        // Keep the sub-millisecond remainder for the next update
+        self.last_update += Duration::from_millis(elapsed as u64);
+        self.skip(elapsed);
+    }
+
+    pub fn skip(&mut self, ms: u32) {
+        self.now_ms = self.now_ms.wrapping_add(ms);
     }
 }
 
@@ -125,6 +446,7 @@ pub struct SimBattery {
     pub charging: bool,
     pub voltage: MilliVolts,
     pub percent_remaining: u8,
+    last_charge_step: Instant,
 }
 
 impl Default for SimBattery {
@@ -133,6 +455,32 @@ impl Default for SimBattery {
             charging: false,
             voltage: MilliVolts(4180), // max, 4.21v
             percent_remaining: 100,
+            last_charge_step: Instant::now(),
+        }
+    }
+}
+
+impl SimBattery {
+    /// Charging goes a lot faster than the real thing
+    const CHARGE_STEP: Duration = Duration::from_millis(200);
+
+    pub fn set_charging(&mut self, charging: bool) {
+        self.charging = charging;
+        self.last_charge_step = Instant::now();
+        println!("Charging {}", charging);
+    }
+
+    /// Charge up one percent at a time while plugged in
+    pub fn update(&mut self) {
+        if !self.charging || self.percent_remaining >= 100 {
+            return;
+        }
+        if self.last_charge_step.elapsed() >= Self::CHARGE_STEP {
+            self.last_charge_step = Instant::now();
+            self.percent_remaining += 1;
+            if self.percent_remaining == 100 {
+                println!("Battery full");
+            }
         }
     }
 }
@@ -142,6 +490,14 @@ impl BatteryControllerExt for SimBattery {
         self.charging
     }
 
+    fn charge_state(&self) -> ChargeState {
+        match (self.charging, self.percent_remaining) {
+            (true, 100) => ChargeState::Full,
+            (true, _) => ChargeState::Charging,
+            (false, _) => ChargeState::Discharging,
+        }
+    }
+
     fn voltage(&self) -> MilliVolts {
         self.voltage
     }
@@ -150,3 +506,42 @@ impl BatteryControllerExt for SimBattery {
         self.percent_remaining
     }
 }
+
+/// Synthetic PPG trace fed through the same estimator as the firmware
+pub struct SimHeartRate {
+    pub ppg: SyntheticPpg,
+    pub estimator: BpmEstimator,
+    running: bool,
+    next_sample: Instant,
+}
+
+impl Default for SimHeartRate {
+    fn default() -> Self {
+        SimHeartRate {
+            ppg: SyntheticPpg::new(60),
+            estimator: BpmEstimator::new(),
+            running: false,
+            next_sample: Instant::now(),
+        }
+    }
+}
+
+impl SimHeartRate {
+    /// Only runs while the heart rate screen is shown, like the sensor
+    pub fn update(&mut self, shown: bool) {
+        if !shown {
+            self.running = false;
+            return;
+        }
+        if !self.running {
+            self.running = true;
+            self.estimator.reset();
+            self.next_sample = Instant::now();
+        }
+        let interval = Duration::from_millis(1000 / BpmEstimator::SAMPLE_RATE_HZ as u64);
+        while self.next_sample <= Instant::now() {
+            self.estimator.update(self.ppg.sample());
+            self.next_sample += interval;
+        }
+    }
+}
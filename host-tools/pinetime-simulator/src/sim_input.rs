@@ -0,0 +1,95 @@
+//! Synthesizes the firmware's input events from mouse and keyboard
+//!
+//! * Mouse click : tap
+//! * Mouse drag : slide gesture in the drag direction
+//! * Arrow keys : slide gestures from the center of the screen
+//! * Space : the side button, classified like the firmware does
+//! * D : double tap gesture, L : long press gesture
+
+use embedded_graphics_simulator::sdl2::Keycode;
+use pinetime_common::{
+    display, embedded_graphics::prelude::*, ButtonClassifier, Gesture, InputEvent,
+};
+use std::time::Instant;
+
+/// Minimum drag distance in pixels to count as a slide instead of a tap
+const SLIDE_THRESHOLD: i32 = 30;
+
+pub struct SimInput {
+    start: Instant,
+    mouse_down: Option<Point>,
+    button_down: bool,
+    button: ButtonClassifier,
+}
+
+impl Default for SimInput {
+    fn default() -> Self {
+        SimInput {
+            start: Instant::now(),
+            mouse_down: None,
+            button_down: false,
+            button: ButtonClassifier::new(),
+        }
+    }
+}
+
+impl SimInput {
+    pub fn mouse_down(&mut self, point: Point) {
+        self.mouse_down = Some(point);
+    }
+
+    pub fn mouse_up(&mut self, point: Point) -> Option<InputEvent> {
+        let start = self.mouse_down.take()?;
+        let delta = point - start;
+        if delta.x.abs().max(delta.y.abs()) < SLIDE_THRESHOLD {
+            return Some(InputEvent::Tap(point));
+        }
+        let gesture = if delta.x.abs() > delta.y.abs() {
+            if delta.x > 0 {
+                Gesture::SlideRight
+            } else {
+                Gesture::SlideLeft
+            }
+        } else if delta.y > 0 {
+            Gesture::SlideDown
+        } else {
+            Gesture::SlideUp
+        };
+        Some(InputEvent::Gesture(gesture, start))
+    }
+
+    pub fn key_down(&mut self, keycode: Keycode) -> Option<InputEvent> {
+        let center = Point::new(display::WIDTH as i32 / 2, display::HEIGHT as i32 / 2);
+        let gesture = match keycode {
+            Keycode::Up => Gesture::SlideUp,
+            Keycode::Down => Gesture::SlideDown,
+            Keycode::Left => Gesture::SlideLeft,
+            Keycode::Right => Gesture::SlideRight,
+            Keycode::D => Gesture::DoubleTap,
+            Keycode::L => Gesture::LongPress,
+            Keycode::Space => {
+                self.button_down = true;
+                return self.poll_button();
+            }
+            _ => return None,
+        };
+        Some(InputEvent::Gesture(gesture, center))
+    }
+
+    pub fn key_up(&mut self, keycode: Keycode) -> Option<InputEvent> {
+        if keycode == Keycode::Space {
+            self.button_down = false;
+            self.poll_button()
+        } else {
+            None
+        }
+    }
+
+    /// Call every frame so held/released button presses get classified
+    pub fn poll_button(&mut self) -> Option<InputEvent> {
+        let now_ms = self.start.elapsed().as_millis() as u32;
+        self.button
+            .update(self.button_down, now_ms)
+            .map(InputEvent::from)
+    }
+}
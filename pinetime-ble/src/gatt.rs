@@ -0,0 +1,120 @@
+//! GATT attribute definitions
+//!
+//! Attribute handles are the 1-based index into the server's attribute table.
+
+use pinetime_common::err_derive;
+
+#[derive(Debug, err_derive::Error)]
+pub enum Error {
+    #[error(display = "Invalid attribute handle")]
+    InvalidHandle,
+
+    #[error(display = "Attribute is not writable")]
+    WriteNotPermitted,
+
+    #[error(display = "Invalid attribute value length")]
+    InvalidLength,
+
+    #[error(display = "Invalid attribute value")]
+    InvalidValue,
+
+    #[error(display = "Busy, try again")]
+    Busy,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Uuid {
+    Uuid16(u16),
+    /// Little-endian bytes, as sent over the air
+    Uuid128([u8; 16]),
+}
+
+pub const PRIMARY_SERVICE: Uuid = Uuid::Uuid16(0x2800);
+pub const CHARACTERISTIC: Uuid = Uuid::Uuid16(0x2803);
+
+/// Characteristic properties, used in the characteristic declaration
+pub mod properties {
+    pub const READ: u8 = 0x02;
+    pub const WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+    pub const WRITE: u8 = 0x08;
+    pub const NOTIFY: u8 = 0x10;
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    pub fn is_readable(self) -> bool {
+        self != Access::Write
+    }
+
+    pub fn is_writable(self) -> bool {
+        self != Access::Read
+    }
+}
+
+/// Characteristic values owned by the server rather than the attribute table
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Characteristic {
+    ManufacturerName,
+    ModelNumber,
+    FirmwareRevision,
+    BatteryLevel,
+    CurrentTime,
+    LocalTimeInformation,
+    SupportedNewAlertCategory,
+    NewAlert,
+    DfuControlPoint,
+    DfuData,
+    DfuStatus,
+    CrashReport,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Value {
+    Static(&'static [u8]),
+    Dynamic(Characteristic),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Attribute {
+    pub uuid: Uuid,
+    pub access: Access,
+    pub value: Value,
+}
+
+impl Attribute {
+    /// Primary service declaration, `uuid` is the little-endian service UUID
+    pub const fn primary_service(uuid: &'static [u8]) -> Self {
+        Attribute {
+            uuid: PRIMARY_SERVICE,
+            access: Access::Read,
+            value: Value::Static(uuid),
+        }
+    }
+
+    /// Characteristic declaration: properties, value handle (LE) and UUID (LE)
+    pub const fn characteristic(decl: &'static [u8]) -> Self {
+        Attribute {
+            uuid: CHARACTERISTIC,
+            access: Access::Read,
+            value: Value::Static(decl),
+        }
+    }
+
+    pub const fn value(uuid: Uuid, access: Access, characteristic: Characteristic) -> Self {
+        Attribute {
+            uuid,
+            access,
+            value: Value::Dynamic(characteristic),
+        }
+    }
+
+    pub fn is_service(&self) -> bool {
+        self.uuid == PRIMARY_SERVICE
+    }
+}
@@ -0,0 +1,24 @@
+//! BLE peripheral services
+//!
+//! The GATT attribute table and the service value encodings live here, independent of
+//! the radio, so they can be exercised on the host. With the `rubble` feature the
+//! `provider` module adapts the table to rubble's `AttributeProvider`.
+
+#![no_std]
+
+#[cfg(feature = "rubble")]
+pub use rubble;
+
+pub mod gatt;
+#[cfg(feature = "rubble")]
+pub mod provider;
+pub mod server;
+pub mod services;
+
+pub use gatt::Error;
+#[cfg(feature = "rubble")]
+pub use provider::GattProvider;
+pub use server::{GattEvent, GattServer};
+
+/// Name used in the advertising data
+pub const DEVICE_NAME: &str = "PineTime";
@@ -0,0 +1,93 @@
+//! rubble `AttributeProvider` on top of the [`GattServer`]
+
+use crate::gatt::{Access, Uuid};
+use crate::server::{GattServer, ATTRIBUTES};
+use rubble::{
+    att::{AttUuid, Attribute, AttributeAccessPermissions, AttributeProvider, Handle, HandleRange},
+    uuid::{Uuid128, Uuid16},
+    Error,
+};
+
+const NUM_ATTRIBUTES: usize = ATTRIBUTES.len();
+
+pub struct GattProvider {
+    server: GattServer,
+    /// Value-less copy of the attribute handles, `group_end` has to hand out references
+    group_ends: [Attribute<&'static [u8]>; NUM_ATTRIBUTES],
+}
+
+impl GattProvider {
+    pub fn new(server: GattServer) -> Self {
+        let mut handle = 0;
+        let group_ends = [(); NUM_ATTRIBUTES].map(|_| {
+            handle += 1;
+            Attribute::new(Uuid16(0).into(), Handle::from_raw(handle), &[][..])
+        });
+        GattProvider { server, group_ends }
+    }
+
+    pub fn server(&self) -> &GattServer {
+        &self.server
+    }
+
+    pub fn server_mut(&mut self) -> &mut GattServer {
+        &mut self.server
+    }
+}
+
+fn att_uuid(uuid: Uuid) -> AttUuid {
+    match uuid {
+        Uuid::Uuid16(u) => Uuid16(u).into(),
+        Uuid::Uuid128(mut bytes) => {
+            // rubble wants big-endian
+            bytes.reverse();
+            Uuid128::from_bytes(bytes).into()
+        }
+    }
+}
+
+impl AttributeProvider for GattProvider {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, &Attribute<dyn AsRef<[u8]>>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let this: &Self = self;
+        let start = range.start().as_u16().max(1);
+        let end = range.end().as_u16().min(NUM_ATTRIBUTES as u16);
+        for handle in start..=end {
+            if let Some(attr) = this.server.attribute(handle) {
+                // Write-only values read back as empty
+                let value = this.server.read(handle).unwrap_or(&[]);
+                let attr = Attribute::new(att_uuid(attr.uuid), Handle::from_raw(handle), value);
+                f(this, &attr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == Uuid16(0x2800)
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<&Attribute<dyn AsRef<[u8]>>> {
+        let end = self.server.group_end(handle.as_u16())?;
+        self.group_ends
+            .get(usize::from(end) - 1)
+            .map(|a| a as &Attribute<dyn AsRef<[u8]>>)
+    }
+
+    fn attr_access_permissions(&self, handle: Handle) -> AttributeAccessPermissions {
+        match self.server.attribute(handle.as_u16()).map(|a| a.access) {
+            Some(Access::Write) => AttributeAccessPermissions::Writeable,
+            Some(Access::ReadWrite) => AttributeAccessPermissions::ReadableAndWriteable,
+            _ => AttributeAccessPermissions::Readable,
+        }
+    }
+
+    fn write_attr(&mut self, handle: Handle, data: &[u8]) -> Result<(), Error> {
+        self.server
+            .write(handle.as_u16(), data)
+            .map_err(|_| Error::InvalidValue)
+    }
+}
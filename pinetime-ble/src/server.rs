@@ -0,0 +1,344 @@
+//! The watch's GATT server
+//!
+//! Owns the attribute table and the dynamic characteristic values. Writes from the
+//! peer that the rest of the firmware needs to act on are queued up as [`GattEvent`]s.
+
+use crate::gatt::{properties, Access, Attribute, Characteristic, Error, Uuid, Value};
+use crate::services::{
+    alert_notification::{
+        NewAlert, NEW_ALERT_UUID, SUPPORTED_NEW_ALERT_CATEGORY, SUPPORTED_NEW_ALERT_CATEGORY_UUID,
+    },
+    battery::{self, BATTERY_LEVEL_UUID},
+    characteristic_declaration,
+    current_time::{
+        CurrentTime, LocalTimeInformation, CURRENT_TIME_UUID, LOCAL_TIME_INFORMATION_UUID,
+    },
+    device_information::{
+        DeviceInformation, FIRMWARE_REVISION_UUID, MANUFACTURER_NAME_UUID, MODEL_NUMBER_UUID,
+    },
+    dfu,
+    diagnostics::{self, CrashReport},
+};
+use heapless::Deque;
+use pinetime_common::{
+    chrono::NaiveDateTime,
+    dfu::{
+        receiver::{Command, Failure, Page},
+        Receiver,
+    },
+    CrashRecord, TimeZone,
+};
+
+/// Attribute handles of the characteristic values
+pub mod handles {
+    pub const MANUFACTURER_NAME: u16 = 0x0003;
+    pub const MODEL_NUMBER: u16 = 0x0005;
+    pub const FIRMWARE_REVISION: u16 = 0x0007;
+    pub const BATTERY_LEVEL: u16 = 0x000A;
+    pub const CURRENT_TIME: u16 = 0x000D;
+    pub const LOCAL_TIME_INFORMATION: u16 = 0x000F;
+    pub const SUPPORTED_NEW_ALERT_CATEGORY: u16 = 0x0012;
+    pub const NEW_ALERT: u16 = 0x0014;
+    pub const DFU_CONTROL_POINT: u16 = 0x0017;
+    pub const DFU_DATA: u16 = 0x0019;
+    pub const DFU_STATUS: u16 = 0x001B;
+    pub const CRASH_REPORT: u16 = 0x001E;
+}
+
+const READ: u8 = properties::READ;
+const WRITE: u8 = properties::WRITE;
+
+pub const MAX_PENDING_EVENTS: usize = 4;
+
+const DFU_CONTROL_POINT_DECL: [u8; 19] =
+    characteristic_declaration(WRITE, handles::DFU_CONTROL_POINT, dfu::CONTROL_POINT_UUID);
+const DFU_DATA_DECL: [u8; 19] =
+    characteristic_declaration(WRITE, handles::DFU_DATA, dfu::DATA_UUID);
+const DFU_STATUS_DECL: [u8; 19] =
+    characteristic_declaration(READ, handles::DFU_STATUS, dfu::STATUS_UUID);
+const CRASH_REPORT_DECL: [u8; 19] = characteristic_declaration(
+    READ | WRITE,
+    handles::CRASH_REPORT,
+    diagnostics::CRASH_REPORT_UUID,
+);
+
+pub const ATTRIBUTES: [Attribute; 30] = [
+    // 0x0001 Device Information service
+    Attribute::primary_service(&[0x0A, 0x18]),
+    Attribute::characteristic(&[READ, 0x03, 0x00, 0x29, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(MANUFACTURER_NAME_UUID),
+        Access::Read,
+        Characteristic::ManufacturerName,
+    ),
+    Attribute::characteristic(&[READ, 0x05, 0x00, 0x24, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(MODEL_NUMBER_UUID),
+        Access::Read,
+        Characteristic::ModelNumber,
+    ),
+    Attribute::characteristic(&[READ, 0x07, 0x00, 0x26, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(FIRMWARE_REVISION_UUID),
+        Access::Read,
+        Characteristic::FirmwareRevision,
+    ),
+    // 0x0008 Battery service
+    Attribute::primary_service(&[0x0F, 0x18]),
+    Attribute::characteristic(&[READ, 0x0A, 0x00, 0x19, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(BATTERY_LEVEL_UUID),
+        Access::Read,
+        Characteristic::BatteryLevel,
+    ),
+    // 0x000B Current Time service
+    Attribute::primary_service(&[0x05, 0x18]),
+    Attribute::characteristic(&[READ | WRITE, 0x0D, 0x00, 0x2B, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(CURRENT_TIME_UUID),
+        Access::ReadWrite,
+        Characteristic::CurrentTime,
+    ),
+    Attribute::characteristic(&[READ | WRITE, 0x0F, 0x00, 0x0F, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(LOCAL_TIME_INFORMATION_UUID),
+        Access::ReadWrite,
+        Characteristic::LocalTimeInformation,
+    ),
+    // 0x0010 Alert Notification service
+    Attribute::primary_service(&[0x11, 0x18]),
+    Attribute::characteristic(&[READ, 0x12, 0x00, 0x47, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(SUPPORTED_NEW_ALERT_CATEGORY_UUID),
+        Access::Read,
+        Characteristic::SupportedNewAlertCategory,
+    ),
+    Attribute::characteristic(&[WRITE, 0x14, 0x00, 0x46, 0x2A]),
+    Attribute::value(
+        Uuid::Uuid16(NEW_ALERT_UUID),
+        Access::Write,
+        Characteristic::NewAlert,
+    ),
+    // 0x0015 Firmware update service
+    Attribute::primary_service(&dfu::SERVICE_UUID),
+    Attribute::characteristic(&DFU_CONTROL_POINT_DECL),
+    Attribute::value(
+        Uuid::Uuid128(dfu::CONTROL_POINT_UUID),
+        Access::Write,
+        Characteristic::DfuControlPoint,
+    ),
+    Attribute::characteristic(&DFU_DATA_DECL),
+    Attribute::value(
+        Uuid::Uuid128(dfu::DATA_UUID),
+        Access::Write,
+        Characteristic::DfuData,
+    ),
+    Attribute::characteristic(&DFU_STATUS_DECL),
+    Attribute::value(
+        Uuid::Uuid128(dfu::STATUS_UUID),
+        Access::Read,
+        Characteristic::DfuStatus,
+    ),
+    // 0x001C Diagnostics service
+    Attribute::primary_service(&diagnostics::SERVICE_UUID),
+    Attribute::characteristic(&CRASH_REPORT_DECL),
+    Attribute::value(
+        Uuid::Uuid128(diagnostics::CRASH_REPORT_UUID),
+        Access::ReadWrite,
+        Characteristic::CrashReport,
+    ),
+];
+
+/// Things the firmware needs to handle after a peer wrote to a characteristic
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum GattEvent {
+    /// Peer wrote the Current Time characteristic, this is local time
+    SetDateTime(NaiveDateTime),
+    /// Peer wrote the Local Time Information characteristic
+    SetLocalTimeInformation(LocalTimeInformation),
+    /// Peer wrote the New Alert characteristic
+    NewAlert(NewAlert),
+    /// Write a page of the firmware update to the staging slot
+    DfuPage(Page),
+    /// All of the firmware update is in, check and install it
+    DfuFinish,
+}
+
+#[derive(Debug)]
+pub struct GattServer {
+    device_info: DeviceInformation,
+    battery_level: [u8; 1],
+    current_time: [u8; CurrentTime::SIZE],
+    local_time_information: [u8; LocalTimeInformation::SIZE],
+    dfu: Receiver,
+    dfu_status: [u8; Receiver::STATUS_SIZE],
+    crash_report: CrashReport,
+    events: Deque<GattEvent, MAX_PENDING_EVENTS>,
+}
+
+impl GattServer {
+    pub fn new(device_info: DeviceInformation) -> Self {
+        GattServer {
+            device_info,
+            battery_level: battery::encode_battery_level(0),
+            current_time: CurrentTime::new(NaiveDateTime::from_timestamp(0, 0)).to_le_bytes(),
+            local_time_information: LocalTimeInformation::new(
+                &TimeZone::UTC,
+                &NaiveDateTime::from_timestamp(0, 0),
+            )
+            .to_le_bytes(),
+            dfu: Receiver::new(),
+            dfu_status: Receiver::new().status_bytes(),
+            crash_report: CrashReport::new(),
+            events: Deque::new(),
+        }
+    }
+
+    pub fn attributes(&self) -> &'static [Attribute] {
+        &ATTRIBUTES
+    }
+
+    pub fn attribute(&self, handle: u16) -> Option<&'static Attribute> {
+        let index = usize::from(handle).checked_sub(1)?;
+        ATTRIBUTES.get(index)
+    }
+
+    /// Handle of the last attribute in the service declared at `handle`
+    pub fn group_end(&self, handle: u16) -> Option<u16> {
+        if !self.attribute(handle)?.is_service() {
+            return None;
+        }
+        let next_service = ATTRIBUTES
+            .iter()
+            .enumerate()
+            .skip(usize::from(handle))
+            .find(|(_, a)| a.is_service())
+            .map(|(index, _)| index as u16)
+            .unwrap_or(ATTRIBUTES.len() as u16);
+        Some(next_service)
+    }
+
+    pub fn read(&self, handle: u16) -> Result<&[u8], Error> {
+        let attr = self.attribute(handle).ok_or(Error::InvalidHandle)?;
+        Ok(match attr.value {
+            Value::Static(bytes) => bytes,
+            Value::Dynamic(c) => self.characteristic_value(c),
+        })
+    }
+
+    pub fn write(&mut self, handle: u16, data: &[u8]) -> Result<(), Error> {
+        let attr = self.attribute(handle).ok_or(Error::InvalidHandle)?;
+        if !attr.access.is_writable() {
+            return Err(Error::WriteNotPermitted);
+        }
+        match attr.value {
+            Value::Static(_) => Err(Error::WriteNotPermitted),
+            Value::Dynamic(c) => self.write_characteristic(c, data),
+        }
+    }
+
+    /// Next event produced by a peer write, if any
+    pub fn pop_event(&mut self) -> Option<GattEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn set_battery_level(&mut self, percent_remaining: u8) {
+        self.battery_level = battery::encode_battery_level(percent_remaining);
+    }
+
+    /// Values returned when a peer reads the Current Time and Local Time Information
+    /// characteristics
+    pub fn set_current_time(&mut self, utc: &NaiveDateTime, time_zone: &TimeZone) {
+        self.current_time = CurrentTime::new(time_zone.to_local(utc)).to_le_bytes();
+        self.local_time_information = LocalTimeInformation::new(time_zone, utc).to_le_bytes();
+    }
+
+    /// The firmware update was installed, or failed outside of the server
+    pub fn set_dfu_result(&mut self, result: Result<(), Failure>) {
+        match result {
+            Ok(()) => self.dfu.set_done(),
+            Err(failure) => {
+                self.dfu.fail(failure);
+            }
+        }
+        self.dfu_status = self.dfu.status_bytes();
+    }
+
+    /// Crash from before the last reset, the Crash Report reads back empty without one
+    pub fn set_crash_report(&mut self, record: &CrashRecord) {
+        self.crash_report.set(record);
+    }
+
+    fn characteristic_value(&self, c: Characteristic) -> &[u8] {
+        match c {
+            Characteristic::ManufacturerName => self.device_info.manufacturer_name.as_bytes(),
+            Characteristic::ModelNumber => self.device_info.model_number.as_bytes(),
+            Characteristic::FirmwareRevision => self.device_info.firmware_revision.as_bytes(),
+            Characteristic::BatteryLevel => &self.battery_level,
+            Characteristic::CurrentTime => &self.current_time,
+            Characteristic::LocalTimeInformation => &self.local_time_information,
+            Characteristic::SupportedNewAlertCategory => &SUPPORTED_NEW_ALERT_CATEGORY,
+            Characteristic::NewAlert => &[],
+            Characteristic::DfuControlPoint => &[],
+            Characteristic::DfuData => &[],
+            Characteristic::DfuStatus => &self.dfu_status,
+            Characteristic::CrashReport => self.crash_report.value(),
+        }
+    }
+
+    fn write_characteristic(&mut self, c: Characteristic, data: &[u8]) -> Result<(), Error> {
+        match c {
+            Characteristic::CurrentTime => {
+                let ct = CurrentTime::from_le_bytes(data)?;
+                self.current_time = ct.to_le_bytes();
+                self.push_event(GattEvent::SetDateTime(ct.date_time));
+                Ok(())
+            }
+            Characteristic::LocalTimeInformation => {
+                let info = LocalTimeInformation::from_le_bytes(data)?;
+                self.local_time_information = info.to_le_bytes();
+                self.push_event(GattEvent::SetLocalTimeInformation(info));
+                Ok(())
+            }
+            Characteristic::NewAlert => {
+                let alert = NewAlert::from_le_bytes(data)?;
+                self.push_event(GattEvent::NewAlert(alert));
+                Ok(())
+            }
+            Characteristic::DfuControlPoint => {
+                let command = Command::from_le_bytes(data).ok_or(Error::InvalidValue)?;
+                // Pages and the finish have to be handled in order, none can be dropped
+                if self.events.is_full() {
+                    return Err(Error::Busy);
+                }
+                let result = self.dfu.command(command);
+                self.dfu_status = self.dfu.status_bytes();
+                if result.map_err(|_| Error::InvalidValue)? {
+                    self.push_event(GattEvent::DfuFinish);
+                }
+                Ok(())
+            }
+            Characteristic::DfuData => {
+                if self.events.is_full() {
+                    return Err(Error::Busy);
+                }
+                let result = self.dfu.chunk(data);
+                self.dfu_status = self.dfu.status_bytes();
+                if let Some(page) = result.map_err(|_| Error::InvalidValue)? {
+                    self.push_event(GattEvent::DfuPage(page));
+                }
+                Ok(())
+            }
+            Characteristic::CrashReport => self.crash_report.set_offset_from_le_bytes(data),
+            _ => Err(Error::WriteNotPermitted),
+        }
+    }
+
+    /// Oldest events are dropped if the firmware isn't keeping up
+    fn push_event(&mut self, event: GattEvent) {
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        self.events.push_back(event).ok();
+    }
+}
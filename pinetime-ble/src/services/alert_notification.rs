@@ -0,0 +1,58 @@
+//! Alert Notification service (0x1811)
+//!
+//! The peer writes alerts to the New Alert characteristic:
+//! * category ID: u8
+//! * number of new alerts: u8
+//! * text: UTF-8, title and body separated by a `'\0'` (no separator means no body)
+//!
+//! The text is limited by the ATT MTU, anything past the end of a single write is lost.
+
+use crate::gatt::Error;
+use heapless::String;
+use pinetime_common::notification::{truncated, Category, Notification};
+
+pub const SERVICE_UUID: u16 = 0x1811;
+pub const SUPPORTED_NEW_ALERT_CATEGORY_UUID: u16 = 0x2A47;
+pub const NEW_ALERT_UUID: u16 = 0x2A46;
+
+/// Category ID bit mask, all the categories are supported
+pub const SUPPORTED_NEW_ALERT_CATEGORY: [u8; 2] = [0xFF, 0x03];
+
+const TITLE_SEPARATOR: char = '\0';
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct NewAlert {
+    pub category: Category,
+    /// Number of new alerts in the category, according to the peer
+    pub count: u8,
+    pub title: String<{ Notification::TITLE_LEN }>,
+    pub body: String<{ Notification::BODY_LEN }>,
+}
+
+impl NewAlert {
+    pub const MIN_SIZE: usize = 2;
+
+    /// Unknown categories are treated as a simple alert. Text that was cut off in
+    /// the middle of a UTF-8 sequence is dropped from there on.
+    pub fn from_le_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < Self::MIN_SIZE {
+            return Err(Error::InvalidLength);
+        }
+        let category = Category::from_u8(bytes[0]).unwrap_or_default();
+        let text = match core::str::from_utf8(&bytes[Self::MIN_SIZE..]) {
+            Ok(text) => text,
+            Err(e) => {
+                let valid = &bytes[Self::MIN_SIZE..Self::MIN_SIZE + e.valid_up_to()];
+                // Checked just above
+                core::str::from_utf8(valid).map_err(|_| Error::InvalidValue)?
+            }
+        };
+        let (title, body) = text.split_once(TITLE_SEPARATOR).unwrap_or((text, ""));
+        Ok(NewAlert {
+            category,
+            count: bytes[1],
+            title: truncated(title),
+            body: truncated(body),
+        })
+    }
+}
@@ -0,0 +1,9 @@
+//! Battery service (0x180F)
+
+pub const SERVICE_UUID: u16 = 0x180F;
+pub const BATTERY_LEVEL_UUID: u16 = 0x2A19;
+
+/// Battery level characteristic value, percent in the range 0..=100
+pub fn encode_battery_level(percent_remaining: u8) -> [u8; 1] {
+    [percent_remaining.min(100)]
+}
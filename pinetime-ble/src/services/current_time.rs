@@ -0,0 +1,351 @@
+//! Current Time service (0x1805)
+//!
+//! Current Time characteristic layout (10 bytes, little-endian):
+//! * year: u16
+//! * month: u8 (1..=12)
+//! * day: u8 (1..=31)
+//! * hours: u8
+//! * minutes: u8
+//! * seconds: u8
+//! * day of week: u8 (1 = Monday .. 7 = Sunday, 0 = unknown)
+//! * fractions256: u8 (1/256th of a second)
+//! * adjust reason: u8
+//!
+//! Current Time is local time, Local Time Information (2 bytes) has the offset:
+//! * time zone: i8 (standard offset from UTC in 15 minute steps, -128 = unknown)
+//! * DST offset: u8 (in 15 minute steps, 0/2/4/8, 255 = unknown)
+
+use crate::gatt::Error;
+use pinetime_common::chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use pinetime_common::{time_zone::DstRule, TimeZone};
+
+pub const SERVICE_UUID: u16 = 0x1805;
+pub const CURRENT_TIME_UUID: u16 = 0x2A2B;
+pub const LOCAL_TIME_INFORMATION_UUID: u16 = 0x2A0F;
+
+const QUARTER_HOUR_MINUTES: i16 = 15;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct CurrentTime {
+    pub date_time: NaiveDateTime,
+    pub adjust_reason: u8,
+}
+
+impl CurrentTime {
+    pub const SIZE: usize = 10;
+
+    pub const ADJUST_REASON_MANUAL: u8 = 1 << 0;
+    pub const ADJUST_REASON_EXTERNAL_REFERENCE: u8 = 1 << 1;
+    pub const ADJUST_REASON_TIME_ZONE: u8 = 1 << 2;
+    pub const ADJUST_REASON_DST: u8 = 1 << 3;
+
+    pub fn new(date_time: NaiveDateTime) -> Self {
+        CurrentTime {
+            date_time,
+            adjust_reason: 0,
+        }
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; Self::SIZE] {
+        let date = self.date_time.date();
+        let time = self.date_time.time();
+        let year = (date.year().clamp(0, u16::MAX as i32) as u16).to_le_bytes();
+        let fractions256 =
+            ((time.nanosecond().min(999_999_999) as u64 * 256) / 1_000_000_000) as u8;
+        [
+            year[0],
+            year[1],
+            date.month() as u8,
+            date.day() as u8,
+            time.hour() as u8,
+            time.minute() as u8,
+            time.second() as u8,
+            date.weekday().number_from_monday() as u8,
+            fractions256,
+            self.adjust_reason,
+        ]
+    }
+
+    /// The day of week field is ignored, it's implied by the date
+    pub fn from_le_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < Self::SIZE {
+            return Err(Error::InvalidLength);
+        }
+        let year = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let date = NaiveDate::from_ymd_opt(year as i32, bytes[2] as u32, bytes[3] as u32)
+            .ok_or(Error::InvalidValue)?;
+        let nanos = (bytes[8] as u64 * 1_000_000_000) / 256;
+        let date_time = date
+            .and_hms_nano_opt(
+                bytes[4] as u32,
+                bytes[5] as u32,
+                bytes[6] as u32,
+                nanos as u32,
+            )
+            .ok_or(Error::InvalidValue)?;
+        Ok(CurrentTime {
+            date_time,
+            adjust_reason: bytes[9],
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct LocalTimeInformation {
+    /// Standard offset from UTC in 15 minute steps, None if unknown
+    pub time_zone: Option<i8>,
+    /// Daylight saving offset in 15 minute steps, None if unknown
+    pub dst_offset: Option<u8>,
+}
+
+impl LocalTimeInformation {
+    pub const SIZE: usize = 2;
+
+    pub const TIME_ZONE_UNKNOWN: i8 = -128;
+    pub const DST_OFFSET_UNKNOWN: u8 = 255;
+
+    /// The watch's time zone at `utc`
+    pub fn new(time_zone: &TimeZone, utc: &NaiveDateTime) -> Self {
+        LocalTimeInformation {
+            time_zone: Some((time_zone.utc_offset_minutes / QUARTER_HOUR_MINUTES) as i8),
+            dst_offset: Some(
+                (time_zone.dst_offset(utc).num_minutes() / QUARTER_HOUR_MINUTES as i64) as u8,
+            ),
+        }
+    }
+
+    /// Time zone with the peer's offset. With a DST rule configured the watch keeps
+    /// switching by itself, otherwise the peer's current DST offset is folded into the
+    /// fixed offset until the next sync
+    pub fn apply(&self, time_zone: TimeZone) -> TimeZone {
+        let standard = match self.time_zone {
+            Some(tz) => tz as i16 * QUARTER_HOUR_MINUTES,
+            None => return time_zone,
+        };
+        let dst = match (time_zone.dst, self.dst_offset) {
+            (DstRule::None, Some(dst)) => dst as i16 * QUARTER_HOUR_MINUTES,
+            _ => 0,
+        };
+        TimeZone::new(standard + dst, time_zone.dst)
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; Self::SIZE] {
+        [
+            self.time_zone.unwrap_or(Self::TIME_ZONE_UNKNOWN) as u8,
+            self.dst_offset.unwrap_or(Self::DST_OFFSET_UNKNOWN),
+        ]
+    }
+
+    pub fn from_le_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < Self::SIZE {
+            return Err(Error::InvalidLength);
+        }
+        let time_zone = match bytes[0] as i8 {
+            Self::TIME_ZONE_UNKNOWN => None,
+            tz @ -48..=56 => Some(tz),
+            _ => return Err(Error::InvalidValue),
+        };
+        let dst_offset = match bytes[1] {
+            Self::DST_OFFSET_UNKNOWN => None,
+            dst @ (0 | 2 | 4 | 8) => Some(dst),
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok(LocalTimeInformation {
+            time_zone,
+            dst_offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(y, m, d).and_hms(h, min, s)
+    }
+
+    #[test]
+    fn current_time_round_trip() {
+        let mut current =
+            CurrentTime::new(NaiveDate::from_ymd(2021, 3, 1).and_hms_milli(12, 34, 56, 500));
+        current.adjust_reason = CurrentTime::ADJUST_REASON_MANUAL;
+        let bytes = current.to_le_bytes();
+        assert_eq!(bytes, [0xE5, 0x07, 3, 1, 12, 34, 56, 1, 128, 1]);
+        assert_eq!(CurrentTime::from_le_bytes(&bytes).unwrap(), current);
+
+        // Sunday
+        let bytes = CurrentTime::new(date_time(2021, 10, 31, 0, 0, 0)).to_le_bytes();
+        assert_eq!(bytes[7], 7);
+    }
+
+    #[test]
+    fn current_time_fractions() {
+        let date = NaiveDate::from_ymd(2021, 3, 1);
+        let fractions =
+            |nanos| CurrentTime::new(date.and_hms_nano(0, 0, 59, nanos)).to_le_bytes()[8];
+        assert_eq!(fractions(0), 0);
+        assert_eq!(fractions(3_906_249), 0);
+        assert_eq!(fractions(3_906_250), 1);
+        assert_eq!(fractions(999_999_999), 255);
+        // Leap second
+        assert_eq!(fractions(1_500_000_000), 255);
+
+        let mut bytes = CurrentTime::new(date.and_hms(0, 0, 0)).to_le_bytes();
+        bytes[8] = 255;
+        let decoded = CurrentTime::from_le_bytes(&bytes).unwrap();
+        assert_eq!(decoded.date_time.nanosecond(), 996_093_750);
+    }
+
+    #[test]
+    fn current_time_decoding() {
+        let bytes = [0xE5, 0x07, 3, 1, 12, 34, 56, 0, 0, 0];
+        // The day of week is ignored, even when wrong or unknown
+        let decoded = CurrentTime::from_le_bytes(&bytes).unwrap();
+        assert_eq!(decoded.date_time, date_time(2021, 3, 1, 12, 34, 56));
+        let mut wrong_day = bytes;
+        wrong_day[7] = 5;
+        assert_eq!(CurrentTime::from_le_bytes(&wrong_day).unwrap(), decoded);
+        // Anything past the characteristic is ignored too
+        let mut longer = [0; 12];
+        longer[..10].copy_from_slice(&bytes);
+        assert_eq!(CurrentTime::from_le_bytes(&longer).unwrap(), decoded);
+
+        assert!(matches!(
+            CurrentTime::from_le_bytes(&bytes[..9]),
+            Err(Error::InvalidLength)
+        ));
+        for (i, val) in [(2, 0), (2, 13), (3, 0), (3, 29), (4, 24), (5, 60), (6, 61)] {
+            let mut invalid = [0xE5, 0x07, 2, 28, 0, 0, 0, 0, 0, 0];
+            invalid[i] = val;
+            assert!(
+                matches!(
+                    CurrentTime::from_le_bytes(&invalid),
+                    Err(Error::InvalidValue)
+                ),
+                "byte {} = {}",
+                i,
+                val
+            );
+        }
+    }
+
+    #[test]
+    fn local_time_information_from_time_zone() {
+        let summer = date_time(2021, 7, 1, 12, 0, 0);
+        let winter = date_time(2021, 12, 1, 12, 0, 0);
+        let info = |offset_minutes, dst, utc: &NaiveDateTime| {
+            let info = LocalTimeInformation::new(&TimeZone::new(offset_minutes, dst), utc);
+            (info.time_zone.unwrap(), info.dst_offset.unwrap())
+        };
+        assert_eq!(info(0, DstRule::None, &summer), (0, 0));
+        assert_eq!(info(60, DstRule::Eu, &summer), (4, 4));
+        assert_eq!(info(60, DstRule::Eu, &winter), (4, 0));
+        assert_eq!(info(-8 * 60, DstRule::Us, &summer), (-32, 4));
+        assert_eq!(info(-8 * 60, DstRule::Us, &winter), (-32, 0));
+        assert_eq!(info(5 * 60 + 30, DstRule::None, &summer), (22, 0));
+
+        // Right at the EU switch, 01:00 UTC
+        assert_eq!(
+            info(60, DstRule::Eu, &date_time(2021, 3, 28, 0, 59, 59)),
+            (4, 0)
+        );
+        assert_eq!(
+            info(60, DstRule::Eu, &date_time(2021, 3, 28, 1, 0, 0)),
+            (4, 4)
+        );
+        assert_eq!(
+            info(60, DstRule::Eu, &date_time(2021, 10, 31, 0, 59, 59)),
+            (4, 4)
+        );
+        assert_eq!(
+            info(60, DstRule::Eu, &date_time(2021, 10, 31, 1, 0, 0)),
+            (4, 0)
+        );
+    }
+
+    #[test]
+    fn local_time_information_round_trip() {
+        for (time_zone, dst_offset) in [
+            (Some(0), Some(0)),
+            (Some(-48), Some(8)),
+            (Some(56), Some(2)),
+            (Some(22), None),
+            (None, Some(4)),
+            (None, None),
+        ] {
+            let info = LocalTimeInformation {
+                time_zone,
+                dst_offset,
+            };
+            let bytes = info.to_le_bytes();
+            assert_eq!(LocalTimeInformation::from_le_bytes(&bytes).unwrap(), info);
+        }
+        assert_eq!(
+            LocalTimeInformation {
+                time_zone: None,
+                dst_offset: None,
+            }
+            .to_le_bytes(),
+            [0x80, 0xFF]
+        );
+        assert_eq!(
+            LocalTimeInformation::from_le_bytes(&[0xE0, 4]).unwrap(),
+            LocalTimeInformation {
+                time_zone: Some(-32),
+                dst_offset: Some(4),
+            }
+        );
+    }
+
+    #[test]
+    fn local_time_information_decoding_errors() {
+        assert!(matches!(
+            LocalTimeInformation::from_le_bytes(&[0]),
+            Err(Error::InvalidLength)
+        ));
+        for bytes in [[57, 0], [-49i8 as u8, 0], [0, 1], [0, 3], [0, 16]] {
+            assert!(
+                matches!(
+                    LocalTimeInformation::from_le_bytes(&bytes),
+                    Err(Error::InvalidValue)
+                ),
+                "{:?}",
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn applying_local_time_information() {
+        let info = LocalTimeInformation {
+            time_zone: Some(4),
+            dst_offset: Some(4),
+        };
+        // The rule takes care of DST
+        assert_eq!(
+            info.apply(TimeZone::new(-5 * 60, DstRule::Eu)),
+            TimeZone::new(60, DstRule::Eu)
+        );
+        // Without one the current DST offset is folded in
+        assert_eq!(
+            info.apply(TimeZone::UTC),
+            TimeZone::new(2 * 60, DstRule::None)
+        );
+        let unknown_dst = LocalTimeInformation {
+            time_zone: Some(-14),
+            dst_offset: None,
+        };
+        assert_eq!(
+            unknown_dst.apply(TimeZone::UTC),
+            TimeZone::new(-3 * 60 - 30, DstRule::None)
+        );
+        // Unknown time zone leaves it alone
+        let unknown = LocalTimeInformation {
+            time_zone: None,
+            dst_offset: Some(4),
+        };
+        let time_zone = TimeZone::new(9 * 60, DstRule::Us);
+        assert_eq!(unknown.apply(time_zone), time_zone);
+    }
+}
@@ -0,0 +1,23 @@
+//! Device Information service (0x180A)
+
+pub const SERVICE_UUID: u16 = 0x180A;
+pub const MANUFACTURER_NAME_UUID: u16 = 0x2A29;
+pub const MODEL_NUMBER_UUID: u16 = 0x2A24;
+pub const FIRMWARE_REVISION_UUID: u16 = 0x2A26;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DeviceInformation {
+    pub manufacturer_name: &'static str,
+    pub model_number: &'static str,
+    pub firmware_revision: &'static str,
+}
+
+impl DeviceInformation {
+    pub const fn new(firmware_revision: &'static str) -> Self {
+        DeviceInformation {
+            manufacturer_name: "PINE64",
+            model_number: "PineTime",
+            firmware_revision,
+        }
+    }
+}
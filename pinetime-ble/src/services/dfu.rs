@@ -0,0 +1,17 @@
+//! Firmware update service (custom, 128-bit UUIDs)
+//!
+//! * Control Point: write commands, see [`pinetime_common::dfu::receiver`]
+//! * Data: write image chunks, with response so a busy watch can push back
+//! * Status: read the state, failure code and number of bytes received
+//!
+//! With rubble's 23 byte ATT MTU a chunk carries up to 16 bytes of the image.
+
+use crate::services::custom_uuid;
+
+pub const SERVICE_UUID: [u8; 16] = custom_uuid(0x01);
+pub const CONTROL_POINT_UUID: [u8; 16] = custom_uuid(0x02);
+pub const DATA_UUID: [u8; 16] = custom_uuid(0x03);
+pub const STATUS_UUID: [u8; 16] = custom_uuid(0x04);
+
+/// Largest chunk that fits a single write
+pub const MAX_CHUNK_SIZE: usize = 16;
@@ -0,0 +1,62 @@
+//! Diagnostics service (custom, 128-bit UUIDs)
+//!
+//! * Crash Report: the encoded [`CrashRecord`] from before the last reset, empty if there
+//!   wasn't a crash. rubble doesn't do long reads, so reads return up to [`MAX_READ_SIZE`]
+//!   bytes from an offset, write the offset (u16, little-endian) before reading the next part
+
+use crate::gatt::Error;
+use crate::services::custom_uuid;
+use pinetime_common::CrashRecord;
+
+pub const SERVICE_UUID: [u8; 16] = custom_uuid(0x05);
+pub const CRASH_REPORT_UUID: [u8; 16] = custom_uuid(0x06);
+
+/// What fits a read response with rubble's 23 byte ATT MTU
+pub const MAX_READ_SIZE: usize = 22;
+
+#[derive(Clone, Debug)]
+pub struct CrashReport {
+    bytes: [u8; CrashRecord::SIZE],
+    len: usize,
+    offset: usize,
+}
+
+impl Default for CrashReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrashReport {
+    pub const fn new() -> Self {
+        CrashReport {
+            bytes: [0; CrashRecord::SIZE],
+            len: 0,
+            offset: 0,
+        }
+    }
+
+    pub fn set(&mut self, record: &CrashRecord) {
+        self.bytes = record.encode();
+        self.len = CrashRecord::SIZE;
+        self.offset = 0;
+    }
+
+    /// Where the next read starts, the end of the report reads back empty
+    pub fn set_offset_from_le_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let offset = match bytes {
+            [a, b] => usize::from(u16::from_le_bytes([*a, *b])),
+            _ => return Err(Error::InvalidLength),
+        };
+        if offset > self.len {
+            return Err(Error::InvalidValue);
+        }
+        self.offset = offset;
+        Ok(())
+    }
+
+    pub fn value(&self) -> &[u8] {
+        let rest = &self.bytes[self.offset..self.len];
+        &rest[..rest.len().min(MAX_READ_SIZE)]
+    }
+}
@@ -0,0 +1,30 @@
+pub mod alert_notification;
+pub mod battery;
+pub mod current_time;
+pub mod device_information;
+pub mod dfu;
+pub mod diagnostics;
+
+/// Little-endian bytes of a UUID in the 3a4c0000-6e19-4f24-9a3b-7d9c5e2d6f80 range, used by
+/// the custom services
+pub const fn custom_uuid(id: u8) -> [u8; 16] {
+    [
+        0x80, 0x6F, 0x2D, 0x5E, 0x9C, 0x7D, 0x3B, 0x9A, 0x24, 0x4F, 0x19, 0x6E, id, 0x00, 0x4C,
+        0x3A,
+    ]
+}
+
+/// Characteristic declaration with a 128-bit UUID
+pub const fn characteristic_declaration(properties: u8, handle: u16, uuid: [u8; 16]) -> [u8; 19] {
+    let h = handle.to_le_bytes();
+    let mut decl = [0; 19];
+    decl[0] = properties;
+    decl[1] = h[0];
+    decl[2] = h[1];
+    let mut i = 0;
+    while i < uuid.len() {
+        decl[3 + i] = uuid[i];
+        i += 1;
+    }
+    decl
+}
@@ -0,0 +1,17 @@
+use pinetime_common::flash_layout::internal;
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    // Same template as the application, at the start of the flash
+    let memory_x = format!(
+        include_str!("../memory.x.in"),
+        flash_origin = internal::BOOTLOADER_OFFSET,
+        flash_length = internal::BOOTLOADER_SIZE,
+    );
+    fs::write(out_dir.join("memory.x"), memory_x).expect("Failed to write memory.x");
+    println!("cargo:rustc-link-search={}", out_dir.display());
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=../memory.x.in");
+}
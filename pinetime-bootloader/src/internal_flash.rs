@@ -0,0 +1,90 @@
+//! A region of the nRF52832's internal flash, written through the NVMC
+//!
+//! Offsets are relative to the start of the region.
+
+use crate::hal::pac::NVMC;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use pinetime_common::flash_layout::internal;
+
+#[derive(Debug)]
+pub enum Error {
+    OutOfBounds,
+    NotAligned,
+}
+
+pub struct InternalFlash {
+    nvmc: NVMC,
+    base: u32,
+    size: u32,
+}
+
+impl InternalFlash {
+    /// `base` has to be page aligned
+    pub fn new(nvmc: NVMC, base: u32, size: u32) -> Self {
+        InternalFlash { nvmc, base, size }
+    }
+
+    /// Absolute address of `offset`, if `len` bytes from there are in the region
+    fn address(&self, offset: u32, len: usize, align: u32) -> Result<u32, Error> {
+        if !offset.is_multiple_of(align) || !(len as u32).is_multiple_of(align) {
+            return Err(Error::NotAligned);
+        }
+        match offset.checked_add(len as u32) {
+            Some(end) if end <= self.size => Ok(self.base + offset),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmc.ready.read().ready().bit_is_clear() {}
+    }
+}
+
+impl ReadNorFlash for InternalFlash {
+    type Error = Error;
+
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let addr = self.address(offset, bytes.len(), 1)?;
+        // Memory mapped, within the region
+        let data = unsafe { core::slice::from_raw_parts(addr as *const u8, bytes.len()) };
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.size as usize
+    }
+}
+
+impl NorFlash for InternalFlash {
+    const WRITE_SIZE: usize = 4;
+
+    const ERASE_SIZE: usize = internal::PAGE_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let len = to.checked_sub(from).ok_or(Error::OutOfBounds)?;
+        let start = self.address(from, len as usize, internal::PAGE_SIZE)?;
+        self.nvmc.config.write(|w| w.wen().een());
+        for page in (start..start + len).step_by(internal::PAGE_SIZE as usize) {
+            self.nvmc.erasepage().write(|w| unsafe { w.bits(page) });
+            self.wait_ready();
+        }
+        self.nvmc.config.write(|w| w.wen().ren());
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let start = self.address(offset, bytes.len(), Self::WRITE_SIZE as u32)?;
+        self.nvmc.config.write(|w| w.wen().wen());
+        for (addr, word) in (start..).step_by(4).zip(bytes.chunks_exact(4)) {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            // Word aligned, within the region
+            unsafe { core::ptr::write_volatile(addr as *mut u32, word) };
+            self.wait_ready();
+        }
+        self.nvmc.config.write(|w| w.wen().ren());
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Installs firmware updates staged by the application, then starts it
+//!
+//! Lives at the start of the internal flash, the application follows it, see
+//! `pinetime_common::flash_layout::internal`. Installing and rolling back are done by
+//! `pinetime_common::dfu::swap`.
+
+#![no_main]
+#![no_std]
+
+use nrf52832_hal as hal;
+use panic_rtt_target as _;
+
+mod internal_flash;
+
+use cortex_m_rt::entry;
+use hal::{
+    delay::Delay,
+    gpio::{self, Level},
+    pac,
+    spim::{self, Spim},
+};
+use internal_flash::InternalFlash;
+use pinetime_common::{
+    dfu::{
+        swap::{self, Outcome},
+        BootStateStore,
+    },
+    flash_layout::{self, internal},
+};
+use pinetime_drivers::spi_flash::{FlashCsPin, SpiFlash};
+use rtt_target::{rprintln, rtt_init_print};
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Bootloader");
+
+    let core = pac::CorePeripherals::take().unwrap();
+    let p = pac::Peripherals::take().unwrap();
+    let gpio = gpio::p0::Parts::new(p.P0);
+
+    // The LCD shares the SPI bus, keep it deselected
+    let _lcd_cs = gpio.p0_25.into_push_pull_output(Level::High);
+    let spi_pins = spim::Pins {
+        sck: gpio.p0_02.into_push_pull_output(Level::Low).degrade(),
+        miso: Some(gpio.p0_04.into_floating_input().degrade()),
+        mosi: Some(gpio.p0_03.into_push_pull_output(Level::Low).degrade()),
+    };
+    let spim0 = Spim::new(p.SPIM0, spi_pins, spim::Frequency::M8, spim::MODE_3, 0);
+    let flash_cs: FlashCsPin = gpio.p0_05.into_push_pull_output(Level::High);
+    let mut spi_flash = SpiFlash::new(spim0, flash_cs);
+    let mut delay = Delay::new(core.SYST);
+
+    let mut app = InternalFlash::new(p.NVMC, internal::APP_OFFSET, internal::APP_SIZE);
+    let mut boot_state_store = BootStateStore::new(
+        flash_layout::BOOT_STATE_OFFSET,
+        flash_layout::BOOT_STATE_SECTORS,
+    );
+
+    let wdt = p.WDT;
+    feed_watchdog(&wdt);
+    let feed = || feed_watchdog(&wdt);
+    // The application left the flash powered down
+    match spi_flash.release_deep_power_down(&mut delay) {
+        Ok(()) => match swap::run(&mut app, &mut spi_flash, &mut boot_state_store, feed) {
+            Ok(Outcome::Unchanged) => (),
+            Ok(Outcome::Installed(image)) => rprintln!("Installed firmware {}", image.version),
+            Ok(Outcome::RolledBack) => rprintln!("Firmware update rolled back"),
+            Ok(Outcome::InvalidImage) => rprintln!("Invalid firmware update, not installed"),
+            Err(e) => rprintln!("Firmware update error {:?}", e),
+        },
+        Err(e) => rprintln!("SPI flash error {:?}", e),
+    }
+    spi_flash.deep_power_down().ok();
+
+    // Hand SPIM0 over disabled, the application sets it up again
+    let (spim0, _flash_cs) = spi_flash.free();
+    let spim0 = spim0.free();
+    spim0.enable.write(|w| w.enable().disabled());
+    feed_watchdog(&wdt);
+
+    // Only the vector table's stack pointer tells whether there's an application
+    let vector_table = internal::APP_OFFSET as *const u32;
+    let stack_pointer = unsafe { core::ptr::read_volatile(vector_table) };
+    if !(0x2000_0000..=0x2001_0000).contains(&stack_pointer) {
+        rprintln!("No application");
+        loop {
+            cortex_m::asm::wfi();
+        }
+    }
+    unsafe { start_app(vector_table) }
+}
+
+/// A reset doesn't stop the watchdog the application started, reload all the request
+/// registers it enabled
+fn feed_watchdog(wdt: &pac::WDT) {
+    if wdt.runstatus.read().runstatus().bit_is_clear() {
+        return;
+    }
+    let enabled = wdt.rren.read().bits();
+    for (i, rr) in wdt.rr.iter().enumerate() {
+        if enabled & (1 << i) != 0 {
+            rr.write(|w| w.rr().reload());
+        }
+    }
+}
+
+/// Jump to the application's reset handler, with its stack and vector table
+unsafe fn start_app(vector_table: *const u32) -> ! {
+    let scb = &*pac::SCB::ptr();
+    scb.vtor.write(vector_table as u32);
+    cortex_m::asm::bootload(vector_table)
+}
@@ -0,0 +1,399 @@
+//! Alarms and the scheduler that decides when they ring
+//!
+//! Times are the watch's local wall clock time, there's no notion of time zones or DST.
+//! An alarm without any repeat days rings once and is then disabled by its owner.
+
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use core::fmt;
+
+/// Number of alarms stored in the settings
+pub const MAX_ALARMS: usize = 4;
+
+/// Days of the week an alarm repeats on, bit 0 is Monday
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct Days(u8);
+
+impl Days {
+    pub const NONE: Days = Days(0);
+    pub const WEEKDAYS: Days = Days(0x1F);
+    pub const WEEKEND: Days = Days(0x60);
+    pub const EVERY_DAY: Days = Days(0x7F);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Days(bits & Self::EVERY_DAY.0)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, day: Weekday) -> bool {
+        self.0 & Self::bit(day) != 0
+    }
+
+    pub fn toggled(self, day: Weekday) -> Self {
+        Days(self.0 ^ Self::bit(day))
+    }
+
+    fn bit(day: Weekday) -> u8 {
+        1 << day.num_days_from_monday()
+    }
+}
+
+impl fmt::Display for Days {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Days::NONE => write!(f, "Once"),
+            Days::EVERY_DAY => write!(f, "Daily"),
+            Days::WEEKDAYS => write!(f, "Weekdays"),
+            Days::WEEKEND => write!(f, "Weekend"),
+            _ => {
+                // Initials, with a dot for the days it doesn't repeat on
+                for (index, initial) in "MTWTFSS".chars().enumerate() {
+                    let on = self.0 & (1 << index) != 0;
+                    write!(f, "{}", if on { initial } else { '.' })?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct Alarm {
+    pub hour: u8,
+    pub minute: u8,
+    /// Repeat days, none means it rings once
+    pub days: Days,
+    pub enabled: bool,
+}
+
+impl Default for Alarm {
+    fn default() -> Self {
+        Alarm {
+            hour: 7,
+            minute: 0,
+            days: Days::WEEKDAYS,
+            enabled: false,
+        }
+    }
+}
+
+impl Alarm {
+    pub const ENCODED_SIZE: usize = 3;
+
+    const ENABLED_BIT: u8 = 1 << 7;
+
+    pub fn time(&self) -> NaiveTime {
+        NaiveTime::from_hms(self.hour.min(23) as u32, self.minute.min(59) as u32, 0)
+    }
+
+    /// Earliest time strictly after `now` the alarm rings at, None if disabled
+    pub fn next_after(&self, now: &NaiveDateTime) -> Option<NaiveDateTime> {
+        if !self.enabled {
+            return None;
+        }
+        (0..=7)
+            .map(|days| now.date() + Duration::days(days))
+            .filter(|date| self.days.is_empty() || self.days.contains(date.weekday()))
+            .map(|date| date.and_time(self.time()))
+            .find(|t| t > now)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_SIZE] {
+        let enabled = if self.enabled { Self::ENABLED_BIT } else { 0 };
+        [self.hour, self.minute, self.days.bits() | enabled]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match *bytes {
+            [hour, minute, flags, ..] if hour < 24 && minute < 60 => Some(Alarm {
+                hour,
+                minute,
+                days: Days::from_bits(flags),
+                enabled: flags & Self::ENABLED_BIT != 0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The alarm (index) that rings next and when
+pub fn next_alarm(alarms: &[Alarm], now: &NaiveDateTime) -> Option<(usize, NaiveDateTime)> {
+    alarms
+        .iter()
+        .enumerate()
+        .filter_map(|(index, a)| a.next_after(now).map(|t| (index, t)))
+        .min_by_key(|(_, t)| *t)
+}
+
+/// What the user did about a ringing alarm
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Response {
+    Snooze,
+    Dismiss,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Event {
+    /// Start ringing, `alarm` is None when a snooze ran out
+    Ring { alarm: Option<usize> },
+    /// Nobody responded in time, the alarm was snoozed
+    Silenced,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Scheduler {
+    last_check: Option<NaiveDateTime>,
+    snoozed_until: Option<NaiveDateTime>,
+    ringing_since: Option<NaiveDateTime>,
+}
+
+impl Scheduler {
+    pub const SNOOZE_MINUTES: i64 = 9;
+
+    /// Ringing alarms are snoozed after this long
+    pub const RING_TIMEOUT_SECS: i64 = 60;
+
+    /// Clock jumps bigger than this (i.e. setting the time) don't ring the alarms in between
+    pub const MAX_CHECK_GAP_SECS: i64 = 60;
+
+    pub const fn new() -> Self {
+        Scheduler {
+            last_check: None,
+            snoozed_until: None,
+            ringing_since: None,
+        }
+    }
+
+    pub fn is_ringing(&self) -> bool {
+        self.ringing_since.is_some()
+    }
+
+    pub fn snoozed_until(&self) -> Option<NaiveDateTime> {
+        self.snoozed_until
+    }
+
+    /// Call regularly (about once a second), alarms that came due since the
+    /// previous call ring
+    pub fn poll(&mut self, alarms: &[Alarm], now: &NaiveDateTime) -> Option<Event> {
+        let last_check = self.last_check.replace(*now);
+
+        if let Some(since) = self.ringing_since {
+            if *now - since >= Duration::seconds(Self::RING_TIMEOUT_SECS) || *now < since {
+                self.snooze(now);
+                return Some(Event::Silenced);
+            }
+        }
+
+        let last_check = match last_check {
+            Some(t) if t <= *now && *now - t <= Duration::seconds(Self::MAX_CHECK_GAP_SECS) => t,
+            _ => {
+                // First call or the clock was changed, only a pending snooze can catch up
+                self.snoozed_until = self.snoozed_until.filter(|t| t > now);
+                return None;
+            }
+        };
+
+        let due_alarm = next_alarm(alarms, &last_check).filter(|(_, t)| t <= now);
+        if let Some((index, _)) = due_alarm {
+            self.ring(now);
+            return Some(Event::Ring { alarm: Some(index) });
+        }
+        if matches!(self.snoozed_until, Some(t) if t <= *now) {
+            self.ring(now);
+            return Some(Event::Ring { alarm: None });
+        }
+        None
+    }
+
+    pub fn respond(&mut self, response: Response, now: &NaiveDateTime) {
+        match response {
+            Response::Snooze => self.snooze(now),
+            Response::Dismiss => {
+                self.ringing_since = None;
+                self.snoozed_until = None;
+            }
+        }
+    }
+
+    fn ring(&mut self, now: &NaiveDateTime) {
+        self.ringing_since = Some(*now);
+        self.snoozed_until = None;
+    }
+
+    fn snooze(&mut self, now: &NaiveDateTime) {
+        self.ringing_since = None;
+        self.snoozed_until = Some(*now + Duration::minutes(Self::SNOOZE_MINUTES));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use chrono::NaiveDate;
+    use std::format;
+
+    /// 2021-03-01 is a Monday
+    fn at(day: u32, hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 3, day).and_hms(hour, minute, second)
+    }
+
+    fn alarm(hour: u8, minute: u8, days: Days) -> Alarm {
+        Alarm {
+            hour,
+            minute,
+            days,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn next_across_midnight() {
+        let daily = alarm(7, 0, Days::EVERY_DAY);
+        assert_eq!(daily.next_after(&at(1, 23, 30, 0)), Some(at(2, 7, 0, 0)));
+        // Strictly after, ringing right now means tomorrow
+        assert_eq!(daily.next_after(&at(1, 7, 0, 0)), Some(at(2, 7, 0, 0)));
+        assert_eq!(daily.next_after(&at(1, 6, 59, 59)), Some(at(1, 7, 0, 0)));
+
+        let midnight = alarm(0, 0, Days::NONE);
+        assert_eq!(
+            midnight.next_after(&at(1, 23, 59, 59)),
+            Some(at(2, 0, 0, 0))
+        );
+        // Across the end of the month too
+        assert_eq!(
+            midnight.next_after(&NaiveDate::from_ymd(2021, 2, 28).and_hms(12, 0, 0)),
+            Some(at(1, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn weekday_masks() {
+        let weekdays = alarm(7, 0, Days::WEEKDAYS);
+        // Friday after the alarm, skips the weekend
+        assert_eq!(weekdays.next_after(&at(5, 8, 0, 0)), Some(at(8, 7, 0, 0)));
+        assert_eq!(weekdays.next_after(&at(6, 6, 0, 0)), Some(at(8, 7, 0, 0)));
+
+        let weekend = alarm(9, 30, Days::WEEKEND);
+        assert_eq!(weekend.next_after(&at(1, 0, 0, 0)), Some(at(6, 9, 30, 0)));
+        assert_eq!(weekend.next_after(&at(6, 9, 30, 0)), Some(at(7, 9, 30, 0)));
+
+        // Only on Wednesdays, just missed it so a week later
+        let wednesday = alarm(12, 0, Days::NONE.toggled(Weekday::Wed));
+        assert_eq!(
+            wednesday.next_after(&at(3, 12, 0, 1)),
+            Some(at(10, 12, 0, 0))
+        );
+        assert_eq!(format!("{}", wednesday.days), "..W....");
+
+        let mut disabled = weekdays;
+        disabled.enabled = false;
+        assert_eq!(disabled.next_after(&at(1, 0, 0, 0)), None);
+    }
+
+    #[test]
+    fn earliest_alarm_is_next() {
+        let alarms = [
+            alarm(7, 0, Days::WEEKEND),
+            alarm(8, 0, Days::EVERY_DAY),
+            Alarm::default(),
+            alarm(6, 0, Days::NONE),
+        ];
+        assert_eq!(
+            next_alarm(&alarms, &at(1, 5, 0, 0)),
+            Some((3, at(1, 6, 0, 0)))
+        );
+        assert_eq!(
+            next_alarm(&alarms, &at(1, 7, 0, 0)),
+            Some((1, at(1, 8, 0, 0)))
+        );
+        assert_eq!(
+            next_alarm(&alarms, &at(5, 9, 0, 0)),
+            Some((3, at(6, 6, 0, 0)))
+        );
+        assert_eq!(next_alarm(&[Alarm::default()], &at(1, 0, 0, 0)), None);
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        for a in [alarm(23, 59, Days::from_bits(0x55)), Alarm::default()] {
+            assert_eq!(Alarm::from_bytes(&a.to_bytes()), Some(a));
+        }
+        assert_eq!(Alarm::from_bytes(&[24, 0, 0]), None);
+        assert_eq!(Alarm::from_bytes(&[7, 60, 0]), None);
+        assert_eq!(Alarm::from_bytes(&[7, 0]), None);
+    }
+
+    #[test]
+    fn rings_when_due_and_snoozes() {
+        let alarms = [alarm(7, 0, Days::EVERY_DAY)];
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.poll(&alarms, &at(1, 6, 59, 59)), None);
+        assert_eq!(
+            scheduler.poll(&alarms, &at(1, 7, 0, 0)),
+            Some(Event::Ring { alarm: Some(0) })
+        );
+        assert!(scheduler.is_ringing());
+        assert_eq!(scheduler.poll(&alarms, &at(1, 7, 0, 1)), None);
+
+        scheduler.respond(Response::Snooze, &at(1, 7, 0, 10));
+        assert!(!scheduler.is_ringing());
+        let until = at(1, 7, 9, 10);
+        assert_eq!(scheduler.snoozed_until(), Some(until));
+        assert_eq!(scheduler.poll(&alarms, &at(1, 7, 9, 9)), None);
+        assert_eq!(
+            scheduler.poll(&alarms, &until),
+            Some(Event::Ring { alarm: None })
+        );
+        assert_eq!(scheduler.snoozed_until(), None);
+
+        scheduler.respond(Response::Dismiss, &at(1, 7, 9, 20));
+        assert!(!scheduler.is_ringing());
+        assert_eq!(scheduler.poll(&alarms, &at(1, 7, 30, 0)), None);
+    }
+
+    #[test]
+    fn unanswered_alarm_is_snoozed() {
+        let alarms = [alarm(7, 0, Days::NONE)];
+        let mut scheduler = Scheduler::new();
+        scheduler.poll(&alarms, &at(1, 6, 59, 59));
+        scheduler.poll(&alarms, &at(1, 7, 0, 0));
+        assert_eq!(scheduler.poll(&alarms, &at(1, 7, 0, 59)), None);
+        assert_eq!(
+            scheduler.poll(&alarms, &at(1, 7, 1, 0)),
+            Some(Event::Silenced)
+        );
+        assert_eq!(scheduler.snoozed_until(), Some(at(1, 7, 10, 0)));
+    }
+
+    #[test]
+    fn setting_the_clock_skips_what_it_jumps_over() {
+        let alarms = [alarm(7, 0, Days::EVERY_DAY)];
+        let mut scheduler = Scheduler::new();
+        scheduler.poll(&alarms, &at(1, 6, 0, 0));
+        // Jumped past the alarm
+        assert_eq!(scheduler.poll(&alarms, &at(1, 8, 0, 0)), None);
+
+        scheduler.respond(Response::Snooze, &at(1, 8, 0, 0));
+        assert_eq!(scheduler.poll(&alarms, &at(1, 8, 0, 1)), None);
+        // A jump that doesn't reach the end of the snooze keeps it
+        assert_eq!(scheduler.poll(&alarms, &at(1, 8, 8, 30)), None);
+        assert_eq!(
+            scheduler.poll(&alarms, &at(1, 8, 9, 0)),
+            Some(Event::Ring { alarm: None })
+        );
+
+        // One past its end drops it
+        scheduler.respond(Response::Snooze, &at(1, 8, 9, 0));
+        assert_eq!(scheduler.poll(&alarms, &at(1, 9, 0, 0)), None);
+        assert_eq!(scheduler.snoozed_until(), None);
+        assert_eq!(scheduler.poll(&alarms, &at(1, 9, 0, 1)), None);
+    }
+}
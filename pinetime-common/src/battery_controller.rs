@@ -1,3 +1,4 @@
+use crate::battery_model::ChargeState;
 use core::fmt;
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
@@ -13,6 +14,8 @@ impl fmt::Display for MilliVolts {
 pub trait BatteryControllerExt {
     fn is_charging(&self) -> bool;
 
+    fn charge_state(&self) -> ChargeState;
+
     fn voltage(&self) -> MilliVolts;
 
     fn percent_remaining(&self) -> u8;
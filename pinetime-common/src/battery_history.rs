@@ -0,0 +1,226 @@
+//! Periodic battery samples, kept in RAM
+//!
+//! Makes power regressions visible, the battery screen plots the history.
+
+use crate::battery_model::ChargeState;
+use crate::MilliVolts;
+use core::{iter::Chain, slice};
+
+pub const SAMPLE_INTERVAL_SECS: u32 = 10 * 60;
+
+/// A day's worth of samples
+pub const CAPACITY: usize = 144;
+
+/// The discharge trend needs at least this much of a drop to estimate the time left
+const MIN_DROP_PERCENT: u8 = 2;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Sample {
+    pub voltage: MilliVolts,
+    pub percent: u8,
+    pub state: ChargeState,
+}
+
+impl Sample {
+    const EMPTY: Sample = Sample {
+        voltage: MilliVolts(0),
+        percent: 0,
+        state: ChargeState::Discharging,
+    };
+}
+
+pub type Iter<'a> = Chain<slice::Iter<'a, Sample>, slice::Iter<'a, Sample>>;
+
+#[derive(Clone, Debug)]
+pub struct BatteryHistory {
+    samples: [Sample; CAPACITY],
+    len: usize,
+    /// Where the next sample goes, the oldest one once full
+    next: usize,
+    revision: u32,
+}
+
+impl Default for BatteryHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatteryHistory {
+    pub const fn new() -> Self {
+        BatteryHistory {
+            samples: [Sample::EMPTY; CAPACITY],
+            len: 0,
+            next: 0,
+            revision: 0,
+        }
+    }
+
+    /// Changes whenever a sample is added
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Overwrites the oldest sample once full
+    pub fn push(&mut self, sample: Sample) {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % CAPACITY;
+        self.len = (self.len + 1).min(CAPACITY);
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    /// Oldest first
+    pub fn iter(&self) -> Iter<'_> {
+        if self.len < CAPACITY {
+            self.samples[..self.len].iter().chain([].iter())
+        } else {
+            let (newer, older) = self.samples.split_at(self.next);
+            older.iter().chain(newer.iter())
+        }
+    }
+
+    pub fn latest(&self) -> Option<&Sample> {
+        self.iter().next_back()
+    }
+
+    /// From the discharge rate since it was last charged, None while charging or
+    /// until there's enough of a trend
+    pub fn estimate_remaining_secs(&self) -> Option<u32> {
+        let latest = self.latest()?;
+        let count = self
+            .iter()
+            .rev()
+            .take_while(|s| s.state == ChargeState::Discharging)
+            .count();
+        if count == 0 {
+            return None;
+        }
+        let first = self.iter().nth(self.len - count)?;
+        let dropped = first.percent.saturating_sub(latest.percent);
+        if dropped < MIN_DROP_PERCENT {
+            return None;
+        }
+        let elapsed_secs = (count as u32 - 1) * SAMPLE_INTERVAL_SECS;
+        Some(latest.percent as u32 * elapsed_secs / dropped as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(percent: u8, state: ChargeState) -> Sample {
+        Sample {
+            voltage: MilliVolts(3_500 + percent as u16 * 7),
+            percent,
+            state,
+        }
+    }
+
+    fn discharging(percents: &[u8]) -> BatteryHistory {
+        let mut history = BatteryHistory::new();
+        for percent in percents {
+            history.push(sample(*percent, ChargeState::Discharging));
+        }
+        history
+    }
+
+    #[test]
+    fn empty() {
+        let history = BatteryHistory::new();
+        assert!(history.is_empty());
+        assert_eq!(history.iter().count(), 0);
+        assert_eq!(history.latest(), None);
+        assert_eq!(history.estimate_remaining_secs(), None);
+    }
+
+    #[test]
+    fn oldest_is_overwritten_once_full() {
+        let mut history = BatteryHistory::new();
+        for i in 0..CAPACITY + 10 {
+            let revision = history.revision();
+            history.push(sample(i as u8, ChargeState::Discharging));
+            assert_ne!(history.revision(), revision);
+            assert_eq!(history.len(), (i + 1).min(CAPACITY));
+            // Oldest first, across the wrap
+            assert!(history
+                .iter()
+                .map(|s| s.percent as usize)
+                .eq((i + 1).saturating_sub(CAPACITY)..=i));
+        }
+        assert_eq!(history.latest().map(|s| s.percent), Some(153));
+    }
+
+    #[test]
+    fn estimate_from_the_discharge_rate() {
+        // 10% in 10 intervals, 90% left at that rate
+        let history = discharging(&[100, 99, 98, 97, 96, 95, 94, 93, 92, 91, 90]);
+        assert_eq!(
+            history.estimate_remaining_secs(),
+            Some(90 * SAMPLE_INTERVAL_SECS)
+        );
+    }
+
+    #[test]
+    fn estimate_across_the_wrap() {
+        let mut history = BatteryHistory::new();
+        for i in 0..CAPACITY + 20 {
+            history.push(sample(100 - (i / 4) as u8, ChargeState::Discharging));
+        }
+        // A quarter percent per interval over the whole day
+        let latest = history.latest().unwrap().percent as u32;
+        let dropped = 100 - (20 / 4) as u32 - latest;
+        let elapsed = (CAPACITY as u32 - 1) * SAMPLE_INTERVAL_SECS;
+        assert_eq!(
+            history.estimate_remaining_secs(),
+            Some(latest * elapsed / dropped)
+        );
+    }
+
+    #[test]
+    fn only_since_last_charged() {
+        let mut history = discharging(&[80, 70, 60, 50]);
+        for percent in [55, 65, 75] {
+            history.push(sample(percent, ChargeState::Charging));
+        }
+        history.push(sample(100, ChargeState::Full));
+        assert_eq!(history.estimate_remaining_secs(), None);
+
+        // Unplugged, the fast drop before the charge doesn't count
+        for percent in [100, 99, 98] {
+            history.push(sample(percent, ChargeState::Discharging));
+        }
+        assert_eq!(
+            history.estimate_remaining_secs(),
+            Some(98 * SAMPLE_INTERVAL_SECS)
+        );
+
+        history.push(sample(98, ChargeState::Charging));
+        assert_eq!(history.estimate_remaining_secs(), None);
+    }
+
+    #[test]
+    fn no_estimate_without_a_trend() {
+        // Fewer than 2 samples
+        assert_eq!(discharging(&[80]).estimate_remaining_secs(), None);
+        // A flat trend would be a divide by zero
+        assert_eq!(discharging(&[80, 80, 80]).estimate_remaining_secs(), None);
+        // Recovering after a load
+        assert_eq!(discharging(&[78, 80, 81]).estimate_remaining_secs(), None);
+        // Not enough of a drop yet
+        assert_eq!(discharging(&[80, 80, 79]).estimate_remaining_secs(), None);
+        assert!(discharging(&[80, 79, 78])
+            .estimate_remaining_secs()
+            .is_some());
+        // Empty, nothing left
+        assert_eq!(discharging(&[5, 2, 0]).estimate_remaining_secs(), Some(0));
+    }
+}
@@ -0,0 +1,404 @@
+//! Battery state of charge from its voltage
+//!
+//! A LiPo cell's voltage isn't linear in the charge left, it's flat in the middle and
+//! drops off quickly when nearly empty. It also reads higher while charging and sags
+//! under load, so the voltage is averaged and the percentage only moves one way for as
+//! long as the charging state doesn't change.
+
+use crate::MilliVolts;
+
+/// Number of voltage samples averaged
+pub const FILTER_LEN: usize = 8;
+
+/// Highest percentage shown until the charger reports it's done, the voltage stops
+/// rising well before the battery is full
+pub const MAX_CHARGING_PERCENT: u8 = 99;
+
+/// While discharging an estimate this far above the shown percentage is believed
+/// anyway, e.g. the battery was charged while the watch was off
+const REBOUND_PERCENT: u8 = 20;
+
+/// Minimum voltage of the battery before shutdown (depends on the battery), it's empty
+/// on the discharge curve
+pub const BATTERY_MIN: MilliVolts = MilliVolts(3200);
+
+/// Lowest reading that can come from the battery, a failed ADC read gives 0
+pub const MIN_PLAUSIBLE_VOLTAGE: MilliVolts = MilliVolts(2_500);
+
+/// Highest reading that can come from the battery
+pub const MAX_PLAUSIBLE_VOLTAGE: MilliVolts = MilliVolts(4_500);
+
+/// Resting voltage vs. charge while discharging, highest first
+const DISCHARGE_CURVE: [(u16, u8); 12] = [
+    (4180, 100),
+    (4100, 92),
+    (4000, 82),
+    (3920, 72),
+    (3860, 62),
+    (3810, 52),
+    (3770, 42),
+    (3740, 32),
+    (3700, 22),
+    (3650, 12),
+    (3550, 5),
+    (BATTERY_MIN.0, 0),
+];
+
+/// Voltage vs. charge while charging, highest first
+const CHARGE_CURVE: [(u16, u8); 9] = [
+    (4200, 90),
+    (4150, 80),
+    (4100, 70),
+    (4050, 60),
+    (4000, 50),
+    (3950, 40),
+    (3900, 30),
+    (3800, 15),
+    (3600, 0),
+];
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ChargeState {
+    Discharging,
+    Charging,
+    /// Still connected to power, the charger is done
+    Full,
+}
+
+impl ChargeState {
+    /// From the charger's status pins
+    pub fn new(power_present: bool, charging: bool) -> Self {
+        match (power_present, charging) {
+            (_, true) => ChargeState::Charging,
+            (true, false) => ChargeState::Full,
+            (false, false) => ChargeState::Discharging,
+        }
+    }
+}
+
+/// Whether a reading could come from the battery at all
+pub fn is_plausible(voltage: MilliVolts) -> bool {
+    (MIN_PLAUSIBLE_VOLTAGE..=MAX_PLAUSIBLE_VOLTAGE).contains(&voltage)
+}
+
+/// Unfiltered estimate for a single voltage reading
+pub fn percent_from_voltage(voltage: MilliVolts, state: ChargeState) -> u8 {
+    match state {
+        ChargeState::Discharging => interpolate(&DISCHARGE_CURVE, voltage.0),
+        ChargeState::Charging => interpolate(&CHARGE_CURVE, voltage.0),
+        ChargeState::Full => 100,
+    }
+}
+
+fn interpolate(curve: &[(u16, u8)], mv: u16) -> u8 {
+    let (top_mv, top_percent) = curve[0];
+    if mv >= top_mv {
+        return top_percent;
+    }
+    for pair in curve.windows(2) {
+        let (hi_mv, hi_percent) = pair[0];
+        let (lo_mv, lo_percent) = pair[1];
+        if mv >= lo_mv {
+            let span = (hi_mv - lo_mv) as u32;
+            let above = (mv - lo_mv) as u32;
+            return lo_percent + ((hi_percent - lo_percent) as u32 * above / span) as u8;
+        }
+    }
+    curve[curve.len() - 1].1
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct StateOfCharge {
+    samples: [u16; FILTER_LEN],
+    len: usize,
+    next: usize,
+    state: ChargeState,
+    percent: Option<u8>,
+}
+
+impl Default for StateOfCharge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateOfCharge {
+    pub const fn new() -> Self {
+        StateOfCharge {
+            samples: [0; FILTER_LEN],
+            len: 0,
+            next: 0,
+            state: ChargeState::Discharging,
+            percent: None,
+        }
+    }
+
+    /// 0 until the first sample
+    pub fn percent(&self) -> u8 {
+        self.percent.unwrap_or(0)
+    }
+
+    pub fn state(&self) -> ChargeState {
+        self.state
+    }
+
+    /// Average of the recent samples
+    pub fn voltage(&self) -> MilliVolts {
+        let sum: u32 = self.samples[..self.len].iter().map(|v| *v as u32).sum();
+        MilliVolts((sum / self.len.max(1) as u32) as u16)
+    }
+
+    /// Returns true if the percentage changed. Implausible readings are dropped, a single
+    /// one would drag the average towards an empty battery.
+    pub fn update(&mut self, voltage: MilliVolts, state: ChargeState) -> bool {
+        if !is_plausible(voltage) {
+            return false;
+        }
+        if state != self.state {
+            // The voltage steps when the charger is (dis)connected, average from scratch
+            self.state = state;
+            self.len = 0;
+            self.next = 0;
+        }
+        self.samples[self.next] = voltage.0;
+        self.next = (self.next + 1) % FILTER_LEN;
+        self.len = (self.len + 1).min(FILTER_LEN);
+
+        let estimate = percent_from_voltage(self.voltage(), state);
+        let percent = match (self.percent, state) {
+            (None, ChargeState::Charging) => estimate.min(MAX_CHARGING_PERCENT),
+            (None, _) | (Some(_), ChargeState::Full) => estimate,
+            (Some(p), ChargeState::Discharging)
+                if estimate < p || estimate >= p.saturating_add(REBOUND_PERCENT) =>
+            {
+                estimate
+            }
+            (Some(p), ChargeState::Charging) if estimate > p => {
+                estimate.min(MAX_CHARGING_PERCENT).max(p)
+            }
+            (Some(p), _) => p,
+        };
+        let changed = self.percent != Some(percent);
+        self.percent = Some(percent);
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::low_battery::SHUTDOWN_VOLTAGE;
+
+    /// A day on battery from full to empty, one reading every 30 minutes in mV. Every
+    /// seventh one is taken while the display, motor or radio load the battery.
+    const DISCHARGE: [u16; 48] = [
+        4175, 4155, 4128, 4012, 4097, 4065, 4042, 4021, 3997, 3990, 3870, 3968, 3928, 3917, 3913,
+        3901, 3882, 3727, 3851, 3838, 3833, 3820, 3803, 3814, 3686, 3783, 3773, 3769, 3762, 3766,
+        3763, 3650, 3746, 3729, 3731, 3714, 3709, 3691, 3573, 3678, 3660, 3643, 3635, 3597, 3557,
+        3368, 3358, 3197,
+    ];
+
+    /// Plugged in, one reading every 5 minutes in mV. The constant current phase climbs
+    /// to 4.2 V, then it's held there while the current tapers off.
+    const CHARGE: [u16; 36] = [
+        3972, 3979, 3981, 3982, 3999, 4010, 4026, 4027, 4041, 4052, 4054, 4066, 4079, 4088, 4117,
+        4126, 4112, 4124, 4133, 4157, 4173, 4172, 4190, 4202, 4205, 4200, 4207, 4195, 4189, 4206,
+        4191, 4187, 4198, 4214, 4191, 4205,
+    ];
+
+    /// Percentages after each reading of `trace`
+    fn feed<const N: usize>(
+        soc: &mut StateOfCharge,
+        trace: &[u16; N],
+        state: ChargeState,
+    ) -> [u8; N] {
+        let mut percents = [0; N];
+        for (percent, mv) in percents.iter_mut().zip(trace) {
+            soc.update(MilliVolts(*mv), state);
+            *percent = soc.percent();
+        }
+        percents
+    }
+
+    #[test]
+    fn curves_are_monotonic() {
+        for state in [ChargeState::Discharging, ChargeState::Charging] {
+            let mut prev = 0;
+            for mv in 3000..=4300 {
+                let percent = percent_from_voltage(MilliVolts(mv), state);
+                assert!(
+                    percent >= prev,
+                    "{:?} {} mV: {} < {}",
+                    state,
+                    mv,
+                    percent,
+                    prev
+                );
+                assert!(percent <= 100);
+                prev = percent;
+            }
+        }
+        let discharging = |mv| percent_from_voltage(MilliVolts(mv), ChargeState::Discharging);
+        assert_eq!(discharging(0), 0);
+        assert_eq!(discharging(3200), 0);
+        assert_eq!(discharging(3810), 52);
+        assert_eq!(discharging(3835), 57);
+        assert_eq!(discharging(4180), 100);
+        assert_eq!(discharging(u16::MAX), 100);
+        let charging = |mv| percent_from_voltage(MilliVolts(mv), ChargeState::Charging);
+        assert_eq!(charging(3500), 0);
+        assert_eq!(charging(4300), 90);
+        assert_eq!(
+            percent_from_voltage(MilliVolts(3300), ChargeState::Full),
+            100
+        );
+    }
+
+    #[test]
+    fn voltage_is_averaged() {
+        let mut soc = StateOfCharge::new();
+        assert_eq!(soc.percent(), 0);
+        assert_eq!(soc.voltage(), MilliVolts(0));
+        soc.update(MilliVolts(3800), ChargeState::Discharging);
+        assert_eq!(soc.voltage(), MilliVolts(3800));
+        soc.update(MilliVolts(3900), ChargeState::Discharging);
+        assert_eq!(soc.voltage(), MilliVolts(3850));
+        // Only the last FILTER_LEN count
+        for _ in 0..FILTER_LEN {
+            soc.update(MilliVolts(3700), ChargeState::Discharging);
+        }
+        assert_eq!(soc.voltage(), MilliVolts(3700));
+    }
+
+    #[test]
+    fn discharging_never_goes_up() {
+        let mut soc = StateOfCharge::new();
+        let percents = feed(&mut soc, &DISCHARGE, ChargeState::Discharging);
+        assert_eq!(percents[0], 99);
+        for (i, pair) in percents.windows(2).enumerate() {
+            assert!(pair[1] <= pair[0], "reading {}: {:?}", i + 1, pair);
+        }
+        // The recovery after a reading under load isn't shown as a gain
+        assert_eq!(percents[3], percents[4]);
+        // The average lags the last few readings
+        assert_eq!(percents[47], 4);
+    }
+
+    #[test]
+    fn recovery_after_load_is_ignored() {
+        let mut soc = StateOfCharge::new();
+        for _ in 0..FILTER_LEN {
+            soc.update(MilliVolts(3810), ChargeState::Discharging);
+        }
+        assert_eq!(soc.percent(), 52);
+        // Sagging under a long load, then recovering
+        for _ in 0..FILTER_LEN {
+            soc.update(MilliVolts(3770), ChargeState::Discharging);
+        }
+        assert_eq!(soc.percent(), 42);
+        for _ in 0..FILTER_LEN {
+            assert!(!soc.update(MilliVolts(3810), ChargeState::Discharging));
+        }
+        assert_eq!(soc.percent(), 42);
+    }
+
+    #[test]
+    fn large_rebound_is_believed() {
+        // E.g. charged while the watch was off
+        let mut soc = StateOfCharge::new();
+        soc.update(MilliVolts(3650), ChargeState::Discharging);
+        assert_eq!(soc.percent(), 12);
+        // Short of the rebound, then well past it
+        for _ in 0..FILTER_LEN {
+            soc.update(MilliVolts(3720), ChargeState::Discharging);
+        }
+        assert_eq!(soc.percent(), 12);
+        // The average climbs over a few samples, the estimate is taken once it's far
+        // enough above
+        let mut changed = false;
+        for _ in 0..FILTER_LEN {
+            changed |= soc.update(MilliVolts(4100), ChargeState::Discharging);
+        }
+        assert!(changed);
+        assert!(soc.percent() >= 12 + REBOUND_PERCENT);
+        assert!(soc.percent() <= 92);
+    }
+
+    #[test]
+    fn charging_never_goes_down() {
+        let mut soc = StateOfCharge::new();
+        for _ in 0..FILTER_LEN {
+            soc.update(MilliVolts(3700), ChargeState::Discharging);
+        }
+        assert_eq!(soc.percent(), 22);
+
+        // Plugged in, the voltage jumps, the filter starts over
+        assert!(soc.update(MilliVolts(3950), ChargeState::Charging));
+        assert_eq!(soc.state(), ChargeState::Charging);
+        assert_eq!(soc.voltage(), MilliVolts(3950));
+        let percents = feed(&mut soc, &CHARGE, ChargeState::Charging);
+        for (i, pair) in percents.windows(2).enumerate() {
+            assert!(pair[1] >= pair[0], "reading {}: {:?}", i + 1, pair);
+        }
+        assert!(percents.iter().all(|p| *p <= MAX_CHARGING_PERCENT));
+        // The voltage stops rising before the battery is full
+        assert_eq!(soc.percent(), 89);
+
+        assert!(soc.update(MilliVolts(4200), ChargeState::Full));
+        assert_eq!(soc.percent(), 100);
+    }
+
+    #[test]
+    fn dropped_reading_is_ignored() {
+        let mut soc = StateOfCharge::new();
+        for _ in 0..FILTER_LEN {
+            soc.update(MilliVolts(3500), ChargeState::Discharging);
+        }
+        let percent = soc.percent();
+        // A failed ADC read, averaged in it would look like a battery below shutdown
+        assert!(!soc.update(MilliVolts(0), ChargeState::Discharging));
+        assert!(!soc.update(MilliVolts(5000), ChargeState::Discharging));
+        assert_eq!(soc.voltage(), MilliVolts(3500));
+        assert!(soc.voltage() >= SHUTDOWN_VOLTAGE);
+        assert_eq!(soc.percent(), percent);
+        assert!(!is_plausible(MilliVolts(0)));
+        assert!(is_plausible(MIN_PLAUSIBLE_VOLTAGE));
+        assert!(is_plausible(MAX_PLAUSIBLE_VOLTAGE));
+    }
+
+    #[test]
+    fn charging_is_capped_until_full() {
+        let mut soc = StateOfCharge::new();
+        soc.update(MilliVolts(4200), ChargeState::Full);
+        assert_eq!(soc.percent(), 100);
+        // Charger starts a top up
+        soc.update(MilliVolts(4200), ChargeState::Charging);
+        assert_eq!(soc.percent(), 100);
+
+        let mut soc = StateOfCharge::new();
+        soc.update(MilliVolts(4300), ChargeState::Charging);
+        assert_eq!(soc.percent(), 90);
+    }
+
+    #[test]
+    fn unplugged() {
+        let mut soc = StateOfCharge::new();
+        soc.update(MilliVolts(4200), ChargeState::Full);
+        assert_eq!(soc.percent(), 100);
+        // The voltage settles below the charging voltage
+        assert!(soc.update(MilliVolts(4120), ChargeState::Discharging));
+        assert_eq!(soc.state(), ChargeState::Discharging);
+        assert_eq!(soc.voltage(), MilliVolts(4120));
+        assert_eq!(soc.percent(), 94);
+    }
+
+    #[test]
+    fn charge_state_from_pins() {
+        assert_eq!(ChargeState::new(false, false), ChargeState::Discharging);
+        assert_eq!(ChargeState::new(true, true), ChargeState::Charging);
+        assert_eq!(ChargeState::new(true, false), ChargeState::Full);
+        // Charging without power doesn't happen, trust the charger
+        assert_eq!(ChargeState::new(false, true), ChargeState::Charging);
+    }
+}
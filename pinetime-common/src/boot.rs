@@ -0,0 +1,291 @@
+//! Boot diagnostics: why the chip reset and whether the firmware came up
+//!
+//! The [`BootRecord`] is kept in RAM that survives a reset (but not a power loss or
+//! System OFF), it's stored encoded with a CRC so garbage after a power up is detected.
+//!
+//! Record layout (little-endian):
+//! * magic: u32
+//! * boots: u32
+//! * consecutive bad boots: u16
+//! * booting: u8
+//! * last reset reason: u8
+//! * resets by reason: [u16; ResetReason::COUNT]
+//! * crc32 of everything above: u32
+
+use crate::crc::crc32;
+use core::fmt;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ResetReason {
+    PowerOn,
+    /// Reset pin
+    Pin,
+    Watchdog,
+    /// Requested by the firmware
+    Soft,
+    /// CPU lockup
+    Lockup,
+    /// Woken up from System OFF
+    WakeUp,
+    Other,
+}
+
+impl ResetReason {
+    pub const COUNT: usize = 7;
+    pub const ALL: [ResetReason; Self::COUNT] = [
+        ResetReason::PowerOn,
+        ResetReason::Pin,
+        ResetReason::Watchdog,
+        ResetReason::Soft,
+        ResetReason::Lockup,
+        ResetReason::WakeUp,
+        ResetReason::Other,
+    ];
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            ResetReason::PowerOn => 0,
+            ResetReason::Pin => 1,
+            ResetReason::Watchdog => 2,
+            ResetReason::Soft => 3,
+            ResetReason::Lockup => 4,
+            ResetReason::WakeUp => 5,
+            ResetReason::Other => 6,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(value)).copied()
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ResetReason::PowerOn => "Power on",
+            ResetReason::Pin => "Pin",
+            ResetReason::Watchdog => "Watchdog",
+            ResetReason::Soft => "Soft",
+            ResetReason::Lockup => "Lockup",
+            ResetReason::WakeUp => "Wake up",
+            ResetReason::Other => "Other",
+        }
+    }
+}
+
+impl fmt::Display for ResetReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Which parts of the hardware came up during init
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct SelfTest {
+    pub display: bool,
+    pub touch: bool,
+    /// The battery ADC gave a plausible reading
+    pub battery: bool,
+}
+
+impl SelfTest {
+    pub fn passed(&self) -> bool {
+        self.display && self.touch && self.battery
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BootRecord {
+    /// Since the last power loss
+    boots: u32,
+    /// Boots in a row that never got marked good
+    bad_boots: u16,
+    /// Set while booting, still set on the next boot if this one never got marked good
+    booting: bool,
+    last_reset: ResetReason,
+    resets: [u16; ResetReason::COUNT],
+}
+
+impl Default for BootRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const MAGIC: u32 = 0xB007_5EC0;
+
+impl BootRecord {
+    pub const SIZE: usize = 12 + 2 * ResetReason::COUNT + 4;
+
+    pub const fn new() -> Self {
+        BootRecord {
+            boots: 0,
+            bad_boots: 0,
+            booting: false,
+            last_reset: ResetReason::PowerOn,
+            resets: [0; ResetReason::COUNT],
+        }
+    }
+
+    /// Carry on from the previous boot's record, or start over if it didn't survive
+    pub fn from_retained(bytes: &[u8; Self::SIZE]) -> Self {
+        Self::decode(bytes).unwrap_or_default()
+    }
+
+    pub fn boots(&self) -> u32 {
+        self.boots
+    }
+
+    pub fn bad_boots(&self) -> u16 {
+        self.bad_boots
+    }
+
+    pub fn last_reset(&self) -> ResetReason {
+        self.last_reset
+    }
+
+    /// Resets for `reason` since the last power loss
+    pub fn resets(&self, reason: ResetReason) -> u16 {
+        self.resets[usize::from(reason.as_u8())]
+    }
+
+    /// Call early in init, counts the previous boot as bad if it never got marked good
+    pub fn start_boot(&mut self, reason: ResetReason) {
+        if self.booting {
+            self.bad_boots = self.bad_boots.saturating_add(1);
+        }
+        self.booting = true;
+        self.boots = self.boots.saturating_add(1);
+        self.last_reset = reason;
+        let count = &mut self.resets[usize::from(reason.as_u8())];
+        *count = count.saturating_add(1);
+    }
+
+    /// The firmware came up fine
+    pub fn mark_good(&mut self) {
+        self.booting = false;
+        self.bad_boots = 0;
+    }
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0_u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.boots.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.bad_boots.to_le_bytes());
+        bytes[10] = self.booting as u8;
+        bytes[11] = self.last_reset.as_u8();
+        for (i, count) in self.resets.iter().enumerate() {
+            bytes[12 + 2 * i..][..2].copy_from_slice(&count.to_le_bytes());
+        }
+        let crc = crc32(&bytes[..Self::SIZE - 4]);
+        bytes[Self::SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        if u32_at(0) != MAGIC || u32_at(Self::SIZE - 4) != crc32(&bytes[..Self::SIZE - 4]) {
+            return None;
+        }
+        let mut resets = [0_u16; ResetReason::COUNT];
+        for (i, count) in resets.iter_mut().enumerate() {
+            *count = u16_at(12 + 2 * i);
+        }
+        Some(BootRecord {
+            boots: u32_at(4),
+            bad_boots: u16_at(8),
+            booting: bytes[10] != 0,
+            last_reset: ResetReason::from_u8(bytes[11])?,
+            resets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Boot the way the firmware does, through the retained bytes
+    fn reboot(record: &BootRecord, reason: ResetReason, good: bool) -> BootRecord {
+        let mut record = BootRecord::from_retained(&record.encode());
+        record.start_boot(reason);
+        if good {
+            record.mark_good();
+        }
+        record
+    }
+
+    #[test]
+    fn reset_reasons_are_counted() {
+        let mut record = BootRecord::new();
+        for reason in [
+            ResetReason::PowerOn,
+            ResetReason::Soft,
+            ResetReason::Watchdog,
+            ResetReason::Soft,
+            ResetReason::WakeUp,
+        ] {
+            record = reboot(&record, reason, true);
+        }
+        assert_eq!(record.boots(), 5);
+        assert_eq!(record.last_reset(), ResetReason::WakeUp);
+        assert_eq!(record.resets(ResetReason::Soft), 2);
+        assert_eq!(record.resets(ResetReason::Watchdog), 1);
+        assert_eq!(record.resets(ResetReason::Lockup), 0);
+        assert_eq!(record.bad_boots(), 0);
+    }
+
+    #[test]
+    fn bad_boots_count_until_one_is_good() {
+        let mut record = reboot(&BootRecord::new(), ResetReason::PowerOn, false);
+        // Only counted once the next boot finds it never got marked good
+        assert_eq!(record.bad_boots(), 0);
+        record = reboot(&record, ResetReason::Watchdog, false);
+        assert_eq!(record.bad_boots(), 1);
+        record = reboot(&record, ResetReason::Watchdog, false);
+        assert_eq!(record.bad_boots(), 2);
+        record = reboot(&record, ResetReason::Watchdog, true);
+        assert_eq!(record.bad_boots(), 0);
+        record = reboot(&record, ResetReason::Soft, false);
+        assert_eq!(record.bad_boots(), 0);
+        assert_eq!(record.resets(ResetReason::Watchdog), 3);
+    }
+
+    #[test]
+    fn counters_saturate() {
+        let mut record = BootRecord::new();
+        for _ in 0..u16::MAX as u32 + 10 {
+            record.start_boot(ResetReason::Lockup);
+        }
+        assert_eq!(record.bad_boots(), u16::MAX);
+        assert_eq!(record.resets(ResetReason::Lockup), u16::MAX);
+        assert_eq!(record.boots(), u16::MAX as u32 + 10);
+    }
+
+    #[test]
+    fn garbage_starts_over() {
+        let mut record = BootRecord::new();
+        record.start_boot(ResetReason::Pin);
+        let bytes = record.encode();
+        assert_eq!(BootRecord::decode(&bytes), Some(record));
+
+        // RAM contents after a power up
+        assert_eq!(
+            BootRecord::from_retained(&[0xA5; BootRecord::SIZE]),
+            BootRecord::new()
+        );
+        for i in 0..BootRecord::SIZE {
+            let mut corrupted = bytes;
+            corrupted[i] ^= 0x10;
+            assert_eq!(BootRecord::decode(&corrupted), None, "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn reset_reason_codes() {
+        for reason in ResetReason::ALL {
+            assert_eq!(ResetReason::from_u8(reason.as_u8()), Some(reason));
+        }
+        assert_eq!(ResetReason::from_u8(ResetReason::COUNT as u8), None);
+    }
+}
@@ -0,0 +1,104 @@
+use core::fmt;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Brightness {
+    Off,
+    L1,
+    L2,
+    L3,
+    L4,
+    L5,
+    L6,
+    L7,
+}
+
+impl Default for Brightness {
+    fn default() -> Self {
+        Brightness::brightest()
+    }
+}
+
+impl Brightness {
+    pub fn as_u8(self) -> u8 {
+        use Brightness::*;
+        match self {
+            Off => 0,
+            L1 => 1,
+            L2 => 2,
+            L3 => 3,
+            L4 => 4,
+            L5 => 5,
+            L6 => 6,
+            L7 => 7,
+        }
+    }
+
+    pub fn from_u8(val: u8) -> Option<Self> {
+        use Brightness::*;
+        match val {
+            0 => Off,
+            1 => L1,
+            2 => L2,
+            3 => L3,
+            4 => L4,
+            5 => L5,
+            6 => L6,
+            7 => L7,
+            _ => return None,
+        }
+        .into()
+    }
+
+    pub fn brightest() -> Self {
+        Brightness::L7
+    }
+
+    pub fn dimmest() -> Self {
+        Brightness::L1
+    }
+
+    pub fn brighter(self) -> Self {
+        use Brightness::*;
+        match self {
+            Off => L1,
+            L1 => L2,
+            L2 => L3,
+            L3 => L4,
+            L4 => L5,
+            L5 => L6,
+            L6 => L7,
+            L7 => L7,
+        }
+    }
+
+    pub fn darker(self) -> Self {
+        use Brightness::*;
+        match self {
+            Off => Off,
+            L1 => Off,
+            L2 => L1,
+            L3 => L2,
+            L4 => L3,
+            L5 => L4,
+            L6 => L5,
+            L7 => L6,
+        }
+    }
+}
+
+impl fmt::Display for Brightness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Brightness::*;
+        let s = match self {
+            Off => "Off",
+            L1 => "1",
+            L2 => "2",
+            L3 => "3",
+            L4 => "4",
+            L5 => "5",
+            L6 => "6",
+            L7 => "Max",
+        };
+        write!(f, "{}", s)
+    }
+}
@@ -0,0 +1,351 @@
+//! RTC tick to time conversion with crystal drift correction
+//!
+//! The 32.768 kHz crystal is only accurate to some tens of ppm, a few seconds a day.
+//! [`DriftEstimator`] measures the error between external time syncs (e.g. over BLE)
+//! and [`DriftCorrection`] applies it when converting ticks to milliseconds.
+
+/// Larger estimates are taken as the clock or the reference having been changed
+pub const MAX_DRIFT_PPM: i32 = 500;
+
+/// Syncs closer together than this don't end a measurement, the error of the syncs
+/// themselves would dominate
+pub const MIN_MEASUREMENT_MS: i64 = 12 * 60 * 60 * 1000;
+
+/// Estimates are averaged over at most this many measurements
+const MAX_AVERAGED: i32 = 4;
+
+const PPM: i64 = 1_000_000;
+
+/// Ticks from `prev` to `now` on a counter that wraps from `max_ticks` to 0
+pub fn tick_delta(prev: u32, now: u32, max_ticks: u32) -> u32 {
+    if now >= prev {
+        now - prev
+    } else {
+        (max_ticks - prev) + now + 1
+    }
+}
+
+/// Extends a wrapping tick counter to 64 bits, so nothing derived from it jumps when
+/// the counter wraps. Has to see the counter at least once per wrap.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TickCounter {
+    max_ticks: u32,
+    last: u32,
+    total: u64,
+}
+
+impl TickCounter {
+    pub const fn new(max_ticks: u32) -> Self {
+        TickCounter {
+            max_ticks,
+            last: 0,
+            total: 0,
+        }
+    }
+
+    /// Total ticks counted, given the counter reads `now`
+    pub fn update(&mut self, now: u32) -> u64 {
+        self.total += tick_delta(self.last, now, self.max_ticks) as u64;
+        self.last = now;
+        self.total
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DriftCorrection {
+    tick_rate_hz: u32,
+    /// Positive if the crystal runs fast
+    ppm: i32,
+    /// Left over from the previous conversion, in 1 / (tick_rate_hz * (1e6 + ppm)) ms
+    remainder: u64,
+}
+
+impl DriftCorrection {
+    pub const fn new(tick_rate_hz: u32) -> Self {
+        DriftCorrection {
+            tick_rate_hz,
+            ppm: 0,
+            remainder: 0,
+        }
+    }
+
+    pub fn ppm(&self) -> i32 {
+        self.ppm
+    }
+
+    /// A change drops the fraction of a millisecond carried over
+    pub fn set_ppm(&mut self, ppm: i32) {
+        let ppm = ppm.clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM);
+        if ppm != self.ppm {
+            self.ppm = ppm;
+            self.remainder = 0;
+        }
+    }
+
+    /// Corrected milliseconds for `ticks` more ticks, the fraction of a millisecond
+    /// carries over to the next call so none are lost
+    pub fn ticks_to_ms(&mut self, ticks: u32) -> u64 {
+        let num = ticks as u64 * 1000 * PPM as u64 + self.remainder;
+        let den = self.tick_rate_hz as u64 * (PPM + self.ppm as i64) as u64;
+        self.remainder = num % den;
+        num / den
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DriftEstimator {
+    tick_rate_hz: u32,
+    /// Reference time the current measurement started at, ms since the epoch
+    start_ms: Option<i64>,
+    /// Uncorrected ticks counted since
+    ticks: u64,
+    measurements: i32,
+}
+
+impl DriftEstimator {
+    pub const fn new(tick_rate_hz: u32) -> Self {
+        DriftEstimator {
+            tick_rate_hz,
+            start_ms: None,
+            ticks: 0,
+            measurements: 0,
+        }
+    }
+
+    pub fn add_ticks(&mut self, ticks: u32) {
+        if self.start_ms.is_some() {
+            self.ticks += ticks as u64;
+        }
+    }
+
+    /// An external reference says it's `reference_ms` (since the epoch) now, all ticks
+    /// up to now must have been added.
+    ///
+    /// Returns a new estimate, averaged with `current_ppm`, when a measurement completes.
+    pub fn sync(&mut self, reference_ms: i64, current_ppm: i32) -> Option<i32> {
+        let elapsed_ms = match self.start_ms {
+            Some(start_ms) => reference_ms - start_ms,
+            None => {
+                self.restart(reference_ms);
+                return None;
+            }
+        };
+        if elapsed_ms <= 0 {
+            // The reference went backwards, start over
+            self.restart(reference_ms);
+            return None;
+        }
+        if elapsed_ms < MIN_MEASUREMENT_MS {
+            return None;
+        }
+
+        // Counted vs expected ticks, wide enough for any time between syncs
+        let expected = elapsed_ms as i128 * self.tick_rate_hz as i128;
+        let counted = self.ticks as i128 * 1000;
+        let measured = (counted - expected) * PPM as i128 / expected;
+        self.restart(reference_ms);
+        if measured.abs() > MAX_DRIFT_PPM as i128 {
+            return None;
+        }
+
+        self.measurements = (self.measurements + 1).min(MAX_AVERAGED);
+        Some(current_ppm + (measured as i32 - current_ppm) / self.measurements)
+    }
+
+    fn restart(&mut self, reference_ms: i64) {
+        self.start_ms = Some(reference_ms);
+        self.ticks = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_RATE_HZ: u32 = 1024;
+    /// The firmware counts ticks with a 32 bit timer
+    const MAX_TICKS: u32 = u32::MAX;
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+    /// A whole number of ticks per ppm, so the tests don't depend on rounding
+    const MEASUREMENT_MS: i64 = 46_875_000;
+
+    /// Ticks a crystal `ppm` off counts in `ms`, rounded
+    fn ticks_in(ms: i64, ppm: i32) -> u32 {
+        let num = ms as i128 * TICK_RATE_HZ as i128 * (PPM + ppm as i64) as i128;
+        let den = 1000 * PPM as i128;
+        ((num + den / 2) / den) as u32
+    }
+
+    #[test]
+    fn tick_delta_across_the_wrap() {
+        assert_eq!(tick_delta(10, 10, MAX_TICKS), 0);
+        assert_eq!(tick_delta(10, 25, MAX_TICKS), 15);
+        assert_eq!(tick_delta(MAX_TICKS, 0, MAX_TICKS), 1);
+        assert_eq!(tick_delta(MAX_TICKS - 2, 3, MAX_TICKS), 6);
+        // Just short of a whole wrap
+        assert_eq!(tick_delta(5, 4, MAX_TICKS), MAX_TICKS);
+        assert_eq!(tick_delta(0x00FF_FFFF, 0, 0x00FF_FFFF), 1);
+        assert_eq!(tick_delta(0x00FF_FFF0, 0x10, 0x00FF_FFFF), 0x20);
+    }
+
+    #[test]
+    fn tick_counter_carries_on_past_the_wrap() {
+        let mut counter = TickCounter::new(u32::MAX);
+        assert_eq!(counter.update(10), 10);
+        assert_eq!(counter.update(u32::MAX), u32::MAX as u64);
+        assert_eq!(counter.update(0), 1 << 32);
+        assert_eq!(counter.update(5), (1 << 32) + 5);
+        // The second wrap too
+        counter.update(u32::MAX - 1);
+        assert_eq!(counter.update(1), (2 << 32) + 1);
+    }
+
+    #[test]
+    fn tick_counter_with_a_narrow_counter() {
+        let max_ticks = 0x00FF_FFFF;
+        let mut counter = TickCounter::new(max_ticks);
+        assert_eq!(counter.update(max_ticks), max_ticks as u64);
+        assert_eq!(counter.update(3), max_ticks as u64 + 4);
+    }
+
+    #[test]
+    fn conversion_without_drift() {
+        let mut drift = DriftCorrection::new(TICK_RATE_HZ);
+        assert_eq!(drift.ticks_to_ms(TICK_RATE_HZ), 1000);
+        assert_eq!(drift.ticks_to_ms(0), 0);
+        // Fractions of a millisecond carry over, tick by tick adds up to the same
+        let ms: u64 = (0..TICK_RATE_HZ).map(|_| drift.ticks_to_ms(1)).sum();
+        assert_eq!(ms, 1000);
+        let ms: u64 = (0..10 * TICK_RATE_HZ).map(|_| drift.ticks_to_ms(3)).sum();
+        assert_eq!(ms, 30_000);
+    }
+
+    #[test]
+    fn conversion_with_drift() {
+        let mut drift = DriftCorrection::new(TICK_RATE_HZ);
+        drift.set_ppm(100);
+        assert_eq!(drift.ppm(), 100);
+        // A day's worth of ticks from a crystal at nominal rate, read as 100 ppm fast
+        let day_ticks = ticks_in(DAY_MS, 0);
+        assert_eq!(drift.ticks_to_ms(day_ticks), 86_391_360);
+
+        // Ticks counted by a crystal that really is off come out as a day
+        for ppm in [-MAX_DRIFT_PPM, -40, 0, 25, MAX_DRIFT_PPM] {
+            let mut drift = DriftCorrection::new(TICK_RATE_HZ);
+            drift.set_ppm(ppm);
+            let ms = drift.ticks_to_ms(ticks_in(DAY_MS, ppm));
+            assert!((ms as i64 - DAY_MS).abs() <= 1, "{} ppm: {} ms", ppm, ms);
+        }
+    }
+
+    #[test]
+    fn drift_is_clamped_and_changes_drop_the_remainder() {
+        let mut drift = DriftCorrection::new(TICK_RATE_HZ);
+        drift.set_ppm(MAX_DRIFT_PPM + 1);
+        assert_eq!(drift.ppm(), MAX_DRIFT_PPM);
+        drift.set_ppm(i32::MIN);
+        assert_eq!(drift.ppm(), -MAX_DRIFT_PPM);
+
+        let mut drift = DriftCorrection::new(TICK_RATE_HZ);
+        assert_eq!(drift.ticks_to_ms(1), 0);
+        // Setting the same drift again keeps the fraction carried over
+        drift.set_ppm(0);
+        assert_eq!(drift.ticks_to_ms(1023), 1000);
+        assert_eq!(drift.ticks_to_ms(1), 0);
+        drift.set_ppm(10);
+        assert_eq!(drift.ticks_to_ms(1023), 999);
+    }
+
+    #[test]
+    fn first_sync_only_starts_a_measurement() {
+        let mut estimator = DriftEstimator::new(TICK_RATE_HZ);
+        // Ticks before any sync aren't counted
+        estimator.add_ticks(ticks_in(MEASUREMENT_MS, 0));
+        assert_eq!(estimator.sync(MEASUREMENT_MS, 0), None);
+        estimator.add_ticks(ticks_in(MEASUREMENT_MS, 50));
+        assert_eq!(estimator.sync(2 * MEASUREMENT_MS, 0), Some(50));
+    }
+
+    #[test]
+    fn measurements_need_time_between_syncs() {
+        let mut estimator = DriftEstimator::new(TICK_RATE_HZ);
+        estimator.sync(0, 0);
+        estimator.add_ticks(ticks_in(MIN_MEASUREMENT_MS - 1, 200));
+        assert_eq!(estimator.sync(MIN_MEASUREMENT_MS - 1, 0), None);
+        // The measurement carries on through the early sync
+        estimator.add_ticks(ticks_in(MEASUREMENT_MS, 200) - ticks_in(MIN_MEASUREMENT_MS - 1, 200));
+        assert_eq!(estimator.sync(MEASUREMENT_MS, 0), Some(200));
+    }
+
+    #[test]
+    fn estimates_are_averaged() {
+        let mut estimator = DriftEstimator::new(TICK_RATE_HZ);
+        let mut ppm = 0;
+        let mut now_ms = 0;
+        estimator.sync(now_ms, ppm);
+        let mut measure = |estimator: &mut DriftEstimator, measured: i32, ppm: i32| {
+            now_ms += MEASUREMENT_MS;
+            estimator.add_ticks(ticks_in(MEASUREMENT_MS, measured));
+            estimator.sync(now_ms, ppm).unwrap()
+        };
+        ppm = measure(&mut estimator, 40, ppm);
+        assert_eq!(ppm, 40);
+        ppm = measure(&mut estimator, 20, ppm);
+        assert_eq!(ppm, 30);
+        ppm = measure(&mut estimator, 60, ppm);
+        assert_eq!(ppm, 40);
+        ppm = measure(&mut estimator, 80, ppm);
+        assert_eq!(ppm, 50);
+        // No more than the last few count
+        ppm = measure(&mut estimator, 10, ppm);
+        assert_eq!(ppm, 40);
+    }
+
+    #[test]
+    fn implausible_syncs_start_over() {
+        let mut estimator = DriftEstimator::new(TICK_RATE_HZ);
+        estimator.sync(MEASUREMENT_MS, 0);
+        // Clock or reference changed by a lot more than any crystal drifts
+        estimator.add_ticks(ticks_in(MEASUREMENT_MS, 2 * MAX_DRIFT_PPM));
+        assert_eq!(estimator.sync(2 * MEASUREMENT_MS, 0), None);
+        estimator.add_ticks(ticks_in(MEASUREMENT_MS, -2 * MAX_DRIFT_PPM));
+        assert_eq!(estimator.sync(3 * MEASUREMENT_MS, 0), None);
+
+        // Reference went backwards
+        estimator.add_ticks(ticks_in(MEASUREMENT_MS, 0));
+        assert_eq!(estimator.sync(MEASUREMENT_MS, 0), None);
+        estimator.add_ticks(ticks_in(MEASUREMENT_MS, -30));
+        assert_eq!(estimator.sync(2 * MEASUREMENT_MS, 0), Some(-30));
+    }
+
+    #[test]
+    fn time_carries_on_across_the_counter_wrap() {
+        // How the system time runs: the counter is read every second, starting
+        // shortly before it wraps
+        let mut counter = u32::MAX - 10 * TICK_RATE_HZ;
+        let mut ticks = TickCounter::new(MAX_TICKS);
+        ticks.update(counter);
+        let start = ticks.update(counter);
+        let mut drift = DriftCorrection::new(TICK_RATE_HZ);
+        drift.set_ppm(-20);
+        let mut estimator = DriftEstimator::new(TICK_RATE_HZ);
+        estimator.sync(0, drift.ppm());
+
+        let seconds = 60;
+        let mut ms = 0;
+        for _ in 0..seconds {
+            let prev = counter;
+            counter = counter.wrapping_add(TICK_RATE_HZ + 7);
+            let delta = tick_delta(prev, counter, MAX_TICKS);
+            assert_eq!(delta, TICK_RATE_HZ + 7);
+            estimator.add_ticks(delta);
+            ms += drift.ticks_to_ms(delta);
+        }
+        let counted = (seconds * (TICK_RATE_HZ + 7)) as u64;
+        assert_eq!(ticks.update(counter) - start, counted);
+        assert_eq!(
+            ms,
+            DriftCorrection::new(TICK_RATE_HZ).ticks_to_ms(counted as u32) + 1
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Countdown timer
+//!
+//! Like the [`Stopwatch`](crate::stopwatch::Stopwatch) it works off timestamps from a
+//! free-running millisecond clock. The owner calls [`Countdown::poll`] when the time
+//! runs out (see [`Countdown::remaining_ms`]) to find out if it expired.
+
+/// Longest duration that can be set, 99:59
+pub const MAX_DURATION_MS: u32 = (99 * 60 + 59) * 1000;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Control {
+    /// Change the duration, only while it isn't counting
+    Set { duration_ms: u32 },
+    /// Start (or restart once expired) if stopped, pause if running
+    StartPause,
+    /// Back to the full duration
+    Reset,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum State {
+    /// At the full duration
+    Idle,
+    Running {
+        started_at: u32,
+        /// Remaining at `started_at`
+        remaining_ms: u32,
+    },
+    Paused {
+        remaining_ms: u32,
+    },
+    Expired,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct Countdown {
+    duration_ms: u32,
+    state: State,
+}
+
+impl Default for Countdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Countdown {
+    pub const DEFAULT_DURATION_MS: u32 = 5 * 60 * 1000;
+
+    pub const fn new() -> Self {
+        Countdown {
+            duration_ms: Self::DEFAULT_DURATION_MS,
+            state: State::Idle,
+        }
+    }
+
+    pub fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Running { .. })
+    }
+
+    pub fn remaining_ms(&self, now_ms: u32) -> u32 {
+        match self.state {
+            State::Idle => self.duration_ms,
+            State::Running {
+                started_at,
+                remaining_ms,
+            } => remaining_ms.saturating_sub(now_ms.wrapping_sub(started_at)),
+            State::Paused { remaining_ms } => remaining_ms,
+            State::Expired => 0,
+        }
+    }
+
+    /// Returns true once, when the running countdown reached zero
+    pub fn poll(&mut self, now_ms: u32) -> bool {
+        if self.is_running() && self.remaining_ms(now_ms) == 0 {
+            self.state = State::Expired;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn control(&mut self, control: Control, now_ms: u32) {
+        match control {
+            Control::Set { duration_ms } => {
+                if !self.is_running() {
+                    self.duration_ms = duration_ms.min(MAX_DURATION_MS);
+                    self.state = State::Idle;
+                }
+            }
+            Control::StartPause => {
+                self.state = match self.state {
+                    State::Running { .. } => State::Paused {
+                        remaining_ms: self.remaining_ms(now_ms),
+                    },
+                    State::Idle | State::Expired if self.duration_ms == 0 => State::Idle,
+                    State::Idle | State::Expired => State::Running {
+                        started_at: now_ms,
+                        remaining_ms: self.duration_ms,
+                    },
+                    State::Paused { remaining_ms } => State::Running {
+                        started_at: now_ms,
+                        remaining_ms,
+                    },
+                }
+            }
+            Control::Reset => self.state = State::Idle,
+        }
+    }
+}
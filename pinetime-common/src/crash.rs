@@ -0,0 +1,288 @@
+//! What the firmware was doing when it panicked, kept for the next boot
+//!
+//! Written by the panic handler into RAM that survives the reset, see [`crate::boot`].
+//! Encoded with a CRC, like the boot record, so garbage isn't mistaken for a crash.
+//!
+//! Record layout (little-endian):
+//! * magic: u32
+//! * line: u32
+//! * column: u32
+//! * stack pointer: u32
+//! * message length: u8, message: [u8; MAX_MESSAGE_LEN]
+//! * file length: u8, file: [u8; MAX_FILE_LEN]
+//! * stack words: u8, stack: [u32; STACK_WORDS]
+//! * crc32 of everything above: u32
+//!
+//! Unused bytes are zero.
+
+use crate::crc::crc32;
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+pub const MAX_MESSAGE_LEN: usize = 96;
+/// Longer paths keep their end
+pub const MAX_FILE_LEN: usize = 48;
+pub const STACK_WORDS: usize = 16;
+
+const MAGIC: u32 = 0xC2A5_4ED0;
+
+const MESSAGE_OFFSET: usize = 16;
+const FILE_OFFSET: usize = MESSAGE_OFFSET + 1 + MAX_MESSAGE_LEN;
+const STACK_OFFSET: usize = FILE_OFFSET + 1 + MAX_FILE_LEN;
+const CRC_OFFSET: usize = STACK_OFFSET + 1 + 4 * STACK_WORDS;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct CrashRecord {
+    message: String<MAX_MESSAGE_LEN>,
+    file: String<MAX_FILE_LEN>,
+    line: u32,
+    column: u32,
+    stack_pointer: u32,
+    /// Words from the stack pointer up
+    stack: Vec<u32, STACK_WORDS>,
+}
+
+impl CrashRecord {
+    pub const SIZE: usize = CRC_OFFSET + 4;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Truncated to fit, formatting carries on past the end so the rest is just dropped
+    pub fn set_message(&mut self, args: fmt::Arguments<'_>) {
+        self.message.clear();
+        let mut truncate = Truncate {
+            text: &mut self.message,
+            full: false,
+        };
+        truncate.write_fmt(args).ok();
+    }
+
+    pub fn set_location(&mut self, file: &str, line: u32, column: u32) {
+        let mut start = file.len().saturating_sub(MAX_FILE_LEN);
+        while !file.is_char_boundary(start) {
+            start += 1;
+        }
+        self.file.clear();
+        // Fits after skipping the start
+        self.file.push_str(&file[start..]).ok();
+        self.line = line;
+        self.column = column;
+    }
+
+    /// `stack` starts at `stack_pointer`, only the first [`STACK_WORDS`] are kept
+    pub fn set_stack(&mut self, stack_pointer: u32, stack: &[u32]) {
+        self.stack_pointer = stack_pointer;
+        self.stack.clear();
+        self.stack
+            .extend_from_slice(&stack[..stack.len().min(STACK_WORDS)])
+            .ok();
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Empty if the location is unknown
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+
+    pub fn stack_pointer(&self) -> u32 {
+        self.stack_pointer
+    }
+
+    pub fn stack(&self) -> &[u32] {
+        &self.stack
+    }
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0_u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.line.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.column.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.stack_pointer.to_le_bytes());
+        bytes[MESSAGE_OFFSET] = self.message.len() as u8;
+        bytes[MESSAGE_OFFSET + 1..][..self.message.len()].copy_from_slice(self.message.as_bytes());
+        bytes[FILE_OFFSET] = self.file.len() as u8;
+        bytes[FILE_OFFSET + 1..][..self.file.len()].copy_from_slice(self.file.as_bytes());
+        bytes[STACK_OFFSET] = self.stack.len() as u8;
+        for (i, word) in self.stack.iter().enumerate() {
+            bytes[STACK_OFFSET + 1 + 4 * i..][..4].copy_from_slice(&word.to_le_bytes());
+        }
+        let crc = crc32(&bytes[..CRC_OFFSET]);
+        bytes[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        if u32_at(0) != MAGIC || u32_at(CRC_OFFSET) != crc32(&bytes[..CRC_OFFSET]) {
+            return None;
+        }
+        let text = |offset: usize, max_len: usize| {
+            let len = usize::from(bytes[offset]);
+            if len > max_len {
+                return None;
+            }
+            core::str::from_utf8(&bytes[offset + 1..][..len]).ok()
+        };
+        let mut record = CrashRecord {
+            line: u32_at(4),
+            column: u32_at(8),
+            stack_pointer: u32_at(12),
+            ..Default::default()
+        };
+        // Lengths were checked above
+        record
+            .message
+            .push_str(text(MESSAGE_OFFSET, MAX_MESSAGE_LEN)?)
+            .ok();
+        record.file.push_str(text(FILE_OFFSET, MAX_FILE_LEN)?).ok();
+        let words = usize::from(bytes[STACK_OFFSET]);
+        if words > STACK_WORDS {
+            return None;
+        }
+        for i in 0..words {
+            record.stack.push(u32_at(STACK_OFFSET + 1 + 4 * i)).ok();
+        }
+        Some(record)
+    }
+}
+
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.file.is_empty() {
+            write!(f, " ({}:{}:{})", self.file, self.line, self.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes whatever fits and drops the rest
+struct Truncate<'a, const N: usize> {
+    text: &'a mut String<N>,
+    full: bool,
+}
+
+impl<const N: usize> Write for Truncate<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.full || self.text.push(c).is_err() {
+                self.full = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{format, string::String};
+
+    fn record() -> CrashRecord {
+        let mut record = CrashRecord::new();
+        record.set_message(format_args!("index out of bounds: {} >= {}", 7, 4));
+        record.set_location("src/main.rs", 123, 45);
+        record.set_stack(0x2000_FF00, &[1, 2, 3, 0xDEAD_BEEF]);
+        record
+    }
+
+    /// Fix up the CRC after editing the fields, to get past it
+    fn reseal(bytes: &mut [u8; CrashRecord::SIZE]) {
+        let crc = crc32(&bytes[..CRC_OFFSET]);
+        bytes[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = record();
+        let decoded = CrashRecord::decode(&record.encode()).unwrap();
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.message(), "index out of bounds: 7 >= 4");
+        assert_eq!(decoded.file(), "src/main.rs");
+        assert_eq!((decoded.line(), decoded.column()), (123, 45));
+        assert_eq!(decoded.stack_pointer(), 0x2000_FF00);
+        assert_eq!(decoded.stack(), [1, 2, 3, 0xDEAD_BEEF]);
+        assert_eq!(
+            format!("{}", decoded),
+            "index out of bounds: 7 >= 4 (src/main.rs:123:45)"
+        );
+
+        let empty = CrashRecord::new();
+        assert_eq!(CrashRecord::decode(&empty.encode()), Some(empty));
+    }
+
+    #[test]
+    fn everything_is_truncated_to_fit() {
+        let mut record = CrashRecord::new();
+        let long: String = "é".repeat(MAX_MESSAGE_LEN);
+        record.set_message(format_args!("{}", long));
+        assert_eq!(record.message().len(), MAX_MESSAGE_LEN);
+        // Stops short of splitting a char
+        record.set_message(format_args!("{}{}", "x", long));
+        assert_eq!(record.message().len(), MAX_MESSAGE_LEN - 1);
+
+        // Paths keep their end, on a char boundary
+        let path = format!("{}/src/screens/some_screen.rs", "dïr".repeat(20));
+        record.set_location(&path, 1, 1);
+        assert!(record.file().len() <= MAX_FILE_LEN);
+        assert!(path.ends_with(record.file()));
+        assert!(record.file().ends_with("/src/screens/some_screen.rs"));
+
+        let stack: std::vec::Vec<u32> = (0..STACK_WORDS as u32 + 4).collect();
+        record.set_stack(0, &stack);
+        assert_eq!(record.stack(), &stack[..STACK_WORDS]);
+
+        assert_eq!(CrashRecord::decode(&record.encode()), Some(record));
+    }
+
+    #[test]
+    fn corrupted_records_are_rejected() {
+        let bytes = record().encode();
+        assert_eq!(CrashRecord::decode(&[0; CrashRecord::SIZE]), None);
+        for i in 0..CrashRecord::SIZE {
+            let mut corrupted = bytes;
+            corrupted[i] ^= 0x01;
+            assert_eq!(CrashRecord::decode(&corrupted), None, "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn bad_fields_are_rejected_even_with_a_good_crc() {
+        let bytes = record().encode();
+        let cases: [(usize, u8); 4] = [
+            (MESSAGE_OFFSET, MAX_MESSAGE_LEN as u8 + 1),
+            (FILE_OFFSET, MAX_FILE_LEN as u8 + 1),
+            (STACK_OFFSET, STACK_WORDS as u8 + 1),
+            // Not UTF-8
+            (MESSAGE_OFFSET + 1, 0xFF),
+        ];
+        for (offset, value) in cases {
+            let mut corrupted = bytes;
+            corrupted[offset] = value;
+            reseal(&mut corrupted);
+            assert_eq!(CrashRecord::decode(&corrupted), None, "offset {}", offset);
+        }
+
+        let mut wrong_magic = bytes;
+        wrong_magic[0] ^= 0xFF;
+        reseal(&mut wrong_magic);
+        assert_eq!(CrashRecord::decode(&wrong_magic), None);
+    }
+}
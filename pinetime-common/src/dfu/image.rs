@@ -0,0 +1,152 @@
+//! Firmware image format
+//!
+//! Header (little-endian), followed by the firmware binary as linked for the application
+//! slot:
+//! * magic: "PTFW"
+//! * header format: u8
+//! * reserved: [u8; 3]
+//! * firmware version: major u8, minor u8, patch u16
+//! * firmware size: u32
+//! * firmware crc32: u32
+//! * crc32 of everything above: u32
+//!
+//! The CRCs only catch images damaged in transit or in flash. Nothing is signed or
+//! hashed, so they don't tell where an image came from: whoever can connect over BLE
+//! can install any firmware that checks out.
+
+use crate::crc::{crc32, Crc32};
+use crate::flash_layout;
+use core::fmt;
+use embedded_storage::nor_flash::ReadNorFlash;
+use err_derive::Error;
+
+pub const MAGIC: [u8; 4] = *b"PTFW";
+pub const FORMAT: u8 = 1;
+
+/// Largest firmware that fits the application slot
+pub const MAX_FIRMWARE_SIZE: u32 = flash_layout::internal::APP_SIZE;
+
+/// Largest image, header included
+pub const MAX_IMAGE_SIZE: u32 = ImageHeader::SIZE as u32 + MAX_FIRMWARE_SIZE;
+
+#[derive(Debug, Error)]
+pub enum Error<E: fmt::Debug> {
+    #[error(display = "Flash error {:?}", _0)]
+    Flash(E),
+
+    #[error(display = "Invalid image header")]
+    InvalidHeader,
+
+    #[error(display = "Firmware too large, {} bytes", _0)]
+    TooLarge(u32),
+
+    #[error(display = "Firmware CRC mismatch")]
+    Crc,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u16,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ImageHeader {
+    pub version: Version,
+    /// Firmware size, without the header
+    pub size: u32,
+    /// CRC of the firmware, without the header
+    pub crc: u32,
+}
+
+impl ImageHeader {
+    pub const SIZE: usize = 24;
+
+    /// Header for `firmware`
+    pub fn new(version: Version, firmware: &[u8]) -> Self {
+        ImageHeader {
+            version,
+            size: firmware.len() as u32,
+            crc: crc32(firmware),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0_u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = FORMAT;
+        bytes[8] = self.version.major;
+        bytes[9] = self.version.minor;
+        bytes[10..12].copy_from_slice(&self.version.patch.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.size.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.crc.to_le_bytes());
+        let header_crc = crc32(&bytes[..20]);
+        bytes[20..24].copy_from_slice(&header_crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes<E: fmt::Debug>(bytes: &[u8]) -> Result<Self, Error<E>> {
+        if bytes.len() < Self::SIZE || bytes[0..4] != MAGIC || bytes[4] != FORMAT {
+            return Err(Error::InvalidHeader);
+        }
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        if crc32(&bytes[..20]) != u32_at(20) {
+            return Err(Error::InvalidHeader);
+        }
+        let header = ImageHeader {
+            version: Version {
+                major: bytes[8],
+                minor: bytes[9],
+                patch: u16::from_le_bytes([bytes[10], bytes[11]]),
+            },
+            size: u32_at(12),
+            crc: u32_at(16),
+        };
+        if header.size > MAX_FIRMWARE_SIZE {
+            return Err(Error::TooLarge(header.size));
+        }
+        Ok(header)
+    }
+}
+
+/// Check the image at `offset`, the firmware is read back to check its CRC
+pub fn verify<F>(flash: &mut F, offset: u32) -> Result<ImageHeader, Error<F::Error>>
+where
+    F: ReadNorFlash,
+    F::Error: fmt::Debug,
+{
+    let mut buf = [0_u8; ImageHeader::SIZE];
+    flash.read(offset, &mut buf).map_err(Error::Flash)?;
+    let header = ImageHeader::from_bytes(&buf)?;
+    let crc = firmware_crc(flash, offset + ImageHeader::SIZE as u32, header.size)
+        .map_err(Error::Flash)?;
+    if crc != header.crc {
+        return Err(Error::Crc);
+    }
+    Ok(header)
+}
+
+/// CRC of `size` bytes at `offset`
+pub fn firmware_crc<F>(flash: &mut F, offset: u32, size: u32) -> Result<u32, F::Error>
+where
+    F: ReadNorFlash,
+{
+    let mut crc = Crc32::new();
+    let mut buf = [0_u8; 256];
+    let mut pos = 0;
+    while pos < size {
+        let len = (size - pos).min(buf.len() as u32) as usize;
+        flash.read(offset + pos, &mut buf[..len])?;
+        crc.update(&buf[..len]);
+        pos += len as u32;
+    }
+    Ok(crc.finish())
+}
@@ -0,0 +1,15 @@
+//! Firmware updates
+//!
+//! An image, an [`ImageHeader`] followed by the firmware binary, is streamed over BLE into
+//! the staging slot on the external flash ([`receiver`]). Once it checks out the
+//! bootloader is asked to install it ([`swap`]) on the next reset. The bootloader keeps a
+//! backup of the running firmware and rolls back to it if the new one resets before
+//! confirming itself.
+
+pub mod image;
+pub mod receiver;
+pub mod swap;
+
+pub use image::{ImageHeader, Version};
+pub use receiver::Receiver;
+pub use swap::{BootStateStore, SwapState};
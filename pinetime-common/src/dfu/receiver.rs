@@ -0,0 +1,454 @@
+//! Reassembles an image sent in chunks into flash pages
+//!
+//! The peer writes commands to the control point and chunks to the data characteristic:
+//! * Start: 0x01, image size u32 (header included)
+//! * Finish: 0x02, after the last chunk, the image gets checked and installed
+//! * Abort: 0x03
+//! * Chunk: image offset u32, followed by the data
+//!
+//! Chunks have to arrive in order and can't cross a [`PAGE_SIZE`] boundary. A chunk that
+//! was already received is ignored, so retries are harmless. A chunk after a gap is
+//! rejected, the peer can read the status to find out where to resume from.
+//!
+//! Status (little-endian): state u8, failure u8, bytes received u32
+
+use crate::dfu::image::MAX_IMAGE_SIZE;
+use embedded_storage::nor_flash::NorFlash;
+
+/// Flash program page, chunks are buffered up to this
+pub const PAGE_SIZE: usize = 256;
+
+/// Largest chunk of data, the offset doesn't count
+pub const MAX_CHUNK_SIZE: usize = PAGE_SIZE;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Command {
+    Start { size: u32 },
+    Finish,
+    Abort,
+}
+
+impl Command {
+    pub fn from_le_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0x01, a, b, c, d] => Some(Command::Start {
+                size: u32::from_le_bytes([*a, *b, *c, *d]),
+            }),
+            [0x02] => Some(Command::Finish),
+            [0x03] => Some(Command::Abort),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum State {
+    Idle = 0,
+    Receiving = 1,
+    /// All chunks are in, waiting for Finish
+    Received = 2,
+    /// Finished, being checked and installed
+    Finishing = 3,
+    Done = 4,
+    Failed = 5,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Failure {
+    None = 0,
+    /// Command not valid in the current state
+    InvalidState = 1,
+    InvalidCommand = 2,
+    TooLarge = 3,
+    /// Chunk after a gap, not fatal
+    OutOfOrder = 4,
+    /// The firmware couldn't keep up
+    Busy = 5,
+    Flash = 6,
+    /// The received image didn't check out
+    InvalidImage = 7,
+}
+
+/// Received data to be written at `offset` into the image
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Page {
+    pub offset: u32,
+    len: usize,
+    data: [u8; PAGE_SIZE],
+}
+
+impl Page {
+    const fn new(offset: u32) -> Self {
+        Page {
+            offset,
+            len: 0,
+            data: [0; PAGE_SIZE],
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == PAGE_SIZE
+    }
+
+    /// Append `data`, which has to fit
+    fn fill(&mut self, data: &[u8]) -> usize {
+        self.data[self.len..][..data.len()].copy_from_slice(data);
+        self.len += data.len();
+        data.len()
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Receiver {
+    state: State,
+    failure: Failure,
+    size: u32,
+    received: u32,
+    page: Page,
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Receiver {
+    pub const STATUS_SIZE: usize = 6;
+
+    pub const fn new() -> Self {
+        Receiver {
+            state: State::Idle,
+            failure: Failure::None,
+            size: 0,
+            received: 0,
+            page: Page::new(0),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn failure(&self) -> Failure {
+        self.failure
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    pub fn status_bytes(&self) -> [u8; Self::STATUS_SIZE] {
+        let r = self.received.to_le_bytes();
+        [self.state as u8, self.failure as u8, r[0], r[1], r[2], r[3]]
+    }
+
+    /// Returns true when the image is complete and should be checked
+    pub fn command(&mut self, command: Command) -> Result<bool, Failure> {
+        match command {
+            Command::Start { size } => {
+                if self.state == State::Finishing {
+                    return Err(Failure::InvalidState);
+                }
+                *self = Receiver::new();
+                if size == 0 || size > MAX_IMAGE_SIZE {
+                    return Err(self.fail(Failure::TooLarge));
+                }
+                self.state = State::Receiving;
+                self.size = size;
+                Ok(false)
+            }
+            Command::Finish => {
+                if self.state != State::Received {
+                    return Err(Failure::InvalidState);
+                }
+                self.state = State::Finishing;
+                Ok(true)
+            }
+            Command::Abort => {
+                if self.state == State::Finishing {
+                    return Err(Failure::InvalidState);
+                }
+                *self = Receiver::new();
+                Ok(false)
+            }
+        }
+    }
+
+    /// Handle a chunk, returns a page to write once one fills up or the image is complete
+    pub fn chunk(&mut self, bytes: &[u8]) -> Result<Option<Page>, Failure> {
+        if self.state != State::Receiving {
+            return Err(Failure::InvalidState);
+        }
+        let (offset, data) = match bytes {
+            [a, b, c, d, data @ ..] if data.len() <= MAX_CHUNK_SIZE => {
+                (u32::from_le_bytes([*a, *b, *c, *d]), data)
+            }
+            _ => return Err(Failure::InvalidCommand),
+        };
+        let end = offset.saturating_add(data.len() as u32);
+        if end > self.size {
+            return Err(self.fail(Failure::TooLarge));
+        }
+        if !data.is_empty() && offset / PAGE_SIZE as u32 != (end - 1) / PAGE_SIZE as u32 {
+            return Err(Failure::InvalidCommand);
+        }
+        if offset > self.received {
+            return Err(Failure::OutOfOrder);
+        }
+        if end <= self.received {
+            // Retry of a chunk that's already in
+            return Ok(None);
+        }
+
+        self.received += self.page.fill(&data[(self.received - offset) as usize..]) as u32;
+        if self.received == self.size {
+            self.state = State::Received;
+        }
+        if self.page.is_full() || (self.state == State::Received && self.page.len > 0) {
+            let next = Page::new(self.received);
+            Ok(Some(core::mem::replace(&mut self.page, next)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The image was checked and installed
+    pub fn set_done(&mut self) {
+        self.state = State::Done;
+    }
+
+    /// Something went wrong outside of the receiver, the peer has to start over
+    pub fn fail(&mut self, failure: Failure) -> Failure {
+        self.state = State::Failed;
+        self.failure = failure;
+        failure
+    }
+}
+
+/// Write `page` into the image stored at `base`, erasing sectors as they're reached,
+/// `base` has to be erase sector aligned
+pub fn write_page<F>(flash: &mut F, base: u32, page: &Page) -> Result<(), F::Error>
+where
+    F: NorFlash,
+{
+    let addr = base + page.offset;
+    if addr.is_multiple_of(F::ERASE_SIZE as u32) {
+        flash.erase(addr, addr + F::ERASE_SIZE as u32)?;
+    }
+    flash.write(addr, page.data())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::dfu::image::{self, ImageHeader, Version};
+    use crate::flash_layout::DFU_STAGING_OFFSET;
+    use crate::test_flash::{self, RamFlash};
+    use std::vec::Vec;
+
+    const FLASH_SIZE: usize = 4 * 1024 * 1024;
+
+    fn image(firmware_len: usize) -> Vec<u8> {
+        let firmware: Vec<u8> = (0..firmware_len).map(|i| (i * 7 + i / 256) as u8).collect();
+        let header = ImageHeader::new(Version::default(), &firmware);
+        let mut image = header.to_bytes().to_vec();
+        image.extend_from_slice(&firmware);
+        image
+    }
+
+    fn chunk(offset: usize, data: &[u8]) -> Vec<u8> {
+        let mut bytes = (offset as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Send `image` in `chunk_size` chunks, writing the pages as the firmware does
+    fn send(
+        receiver: &mut Receiver,
+        flash: &mut RamFlash,
+        image: &[u8],
+        chunk_size: usize,
+    ) -> Result<(), test_flash::Error> {
+        let mut offset = 0;
+        while offset < image.len() {
+            // Chunks don't cross a page
+            let page_end = (offset / PAGE_SIZE + 1) * PAGE_SIZE;
+            let end = (offset + chunk_size).min(page_end).min(image.len());
+            if let Some(page) = receiver.chunk(&chunk(offset, &image[offset..end])).unwrap() {
+                write_page(flash, DFU_STAGING_OFFSET, &page)?;
+            }
+            offset = end;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            Command::from_le_bytes(&[0x01, 0x78, 0x56, 0x34, 0x12]),
+            Some(Command::Start { size: 0x1234_5678 })
+        );
+        assert_eq!(Command::from_le_bytes(&[0x02]), Some(Command::Finish));
+        assert_eq!(Command::from_le_bytes(&[0x03]), Some(Command::Abort));
+        assert_eq!(Command::from_le_bytes(&[0x01, 0, 0]), None);
+        assert_eq!(Command::from_le_bytes(&[0x02, 0]), None);
+        assert_eq!(Command::from_le_bytes(&[]), None);
+    }
+
+    #[test]
+    fn receives_a_valid_image() {
+        // Firmware size isn't a multiple of the page or the chunk size
+        let image = image(3 * 4096 + 100);
+        for chunk_size in [20, 128, MAX_CHUNK_SIZE] {
+            let mut flash = RamFlash::new(FLASH_SIZE);
+            let mut receiver = Receiver::new();
+            let start = Command::Start {
+                size: image.len() as u32,
+            };
+            assert_eq!(receiver.command(start), Ok(false));
+            send(&mut receiver, &mut flash, &image, chunk_size).unwrap();
+            assert_eq!(receiver.state(), State::Received);
+            assert_eq!(receiver.received(), image.len() as u32);
+            assert_eq!(receiver.command(Command::Finish), Ok(true));
+            assert_eq!(receiver.state(), State::Finishing);
+
+            let header = image::verify(&mut flash, DFU_STAGING_OFFSET).unwrap();
+            assert_eq!(header.size as usize, image.len() - ImageHeader::SIZE);
+            receiver.set_done();
+            assert_eq!(receiver.status_bytes()[..2], [State::Done as u8, 0]);
+        }
+    }
+
+    #[test]
+    fn retries_and_gaps() {
+        let image = image(1000);
+        let mut receiver = Receiver::new();
+        receiver
+            .command(Command::Start {
+                size: image.len() as u32,
+            })
+            .unwrap();
+        assert_eq!(receiver.chunk(&chunk(0, &image[..100])), Ok(None));
+        // Retry of the same chunk, and one overlapping what's in
+        assert_eq!(receiver.chunk(&chunk(0, &image[..100])), Ok(None));
+        assert_eq!(receiver.chunk(&chunk(50, &image[50..150])), Ok(None));
+        assert_eq!(receiver.received(), 150);
+        // Gap, not fatal
+        assert_eq!(
+            receiver.chunk(&chunk(200, &image[200..210])),
+            Err(Failure::OutOfOrder)
+        );
+        assert_eq!(receiver.state(), State::Receiving);
+        // Crossing a page
+        assert_eq!(
+            receiver.chunk(&chunk(150, &image[150..300])),
+            Err(Failure::InvalidCommand)
+        );
+        let page = receiver
+            .chunk(&chunk(150, &image[150..256]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(page.offset, 0);
+        assert_eq!(page.data(), &image[..256]);
+
+        let status = receiver.status_bytes();
+        assert_eq!(status, [State::Receiving as u8, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn rejected_commands_and_chunks() {
+        let mut receiver = Receiver::new();
+        assert_eq!(
+            receiver.command(Command::Finish),
+            Err(Failure::InvalidState)
+        );
+        assert_eq!(receiver.chunk(&chunk(0, &[1])), Err(Failure::InvalidState));
+        assert_eq!(
+            receiver.command(Command::Start {
+                size: MAX_IMAGE_SIZE + 1
+            }),
+            Err(Failure::TooLarge)
+        );
+        assert_eq!(receiver.state(), State::Failed);
+        assert_eq!(receiver.failure(), Failure::TooLarge);
+
+        receiver.command(Command::Start { size: 10 }).unwrap();
+        assert_eq!(receiver.chunk(&[0, 0]), Err(Failure::InvalidCommand));
+        assert_eq!(
+            receiver.chunk(&chunk(0, &[0; MAX_CHUNK_SIZE + 1])),
+            Err(Failure::InvalidCommand)
+        );
+        // Not complete yet
+        assert_eq!(
+            receiver.command(Command::Finish),
+            Err(Failure::InvalidState)
+        );
+        assert_eq!(receiver.chunk(&chunk(0, &[0; 11])), Err(Failure::TooLarge));
+        assert_eq!(receiver.state(), State::Failed);
+
+        // Starting over works from any state but Finishing
+        receiver.command(Command::Start { size: 1 }).unwrap();
+        assert!(receiver.chunk(&chunk(0, &[0])).unwrap().is_some());
+        receiver.command(Command::Finish).unwrap();
+        assert_eq!(receiver.command(Command::Abort), Err(Failure::InvalidState));
+        assert_eq!(
+            receiver.command(Command::Start { size: 1 }),
+            Err(Failure::InvalidState)
+        );
+        receiver.set_done();
+        assert_eq!(receiver.command(Command::Abort), Ok(false));
+        assert_eq!(receiver.state(), State::Idle);
+    }
+
+    #[test]
+    fn power_loss_mid_transfer_is_caught_by_the_crc() {
+        let image = image(2 * 4096);
+        let mut flash = RamFlash::new(FLASH_SIZE);
+        let mut receiver = Receiver::new();
+        let start = Command::Start {
+            size: image.len() as u32,
+        };
+        receiver.command(start).unwrap();
+        flash.cut_power_after(4096 + 1000);
+        assert_eq!(
+            send(&mut receiver, &mut flash, &image, MAX_CHUNK_SIZE),
+            Err(test_flash::Error::PowerLoss)
+        );
+        flash.restore_power();
+        assert!(image::verify(&mut flash, DFU_STAGING_OFFSET).is_err());
+
+        // Sent again from the start, over what's left of the last attempt
+        receiver.command(start).unwrap();
+        send(&mut receiver, &mut flash, &image, MAX_CHUNK_SIZE).unwrap();
+        assert!(image::verify(&mut flash, DFU_STAGING_OFFSET).is_ok());
+    }
+
+    #[test]
+    fn corrupted_image_fails_verification() {
+        let mut image = image(5000);
+        image[ImageHeader::SIZE + 1234] ^= 0x80;
+        let mut flash = RamFlash::new(FLASH_SIZE);
+        let mut receiver = Receiver::new();
+        receiver
+            .command(Command::Start {
+                size: image.len() as u32,
+            })
+            .unwrap();
+        send(&mut receiver, &mut flash, &image, MAX_CHUNK_SIZE).unwrap();
+        assert!(matches!(
+            image::verify(&mut flash, DFU_STAGING_OFFSET),
+            Err(image::Error::Crc)
+        ));
+    }
+}
@@ -0,0 +1,571 @@
+//! Installing a staged image, shared by the bootloader and the application
+//!
+//! The application stages an image and sets the state to `Pending`. On the next reset
+//! the bootloader:
+//! * Pending: checks the staged image, backs up the application slot, then Installing
+//! * Installing: copies the firmware into the application slot, then Testing and boots it
+//! * Testing: the new firmware didn't confirm itself, so RollingBack
+//! * RollingBack: copies the backup into the application slot, then RolledBack
+//!
+//! Every step can be repeated from the start, so a reset in the middle of one just
+//! carries on where it left off. Once running, the application confirms itself, which
+//! takes Testing (or RolledBack, after it's been reported) back to Idle.
+
+use crate::dfu::image::{self, ImageHeader};
+use crate::flash_layout::{self, internal};
+use crate::record_log::{self, RecordLog};
+use core::fmt;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use err_derive::Error;
+
+#[derive(Debug, Error)]
+pub enum Error<I: fmt::Debug, X: fmt::Debug> {
+    #[error(display = "Internal flash error {:?}", _0)]
+    Internal(I),
+
+    #[error(display = "External flash error {:?}", _0)]
+    External(X),
+
+    #[error(display = "Boot state error {:?}", _0)]
+    BootState(record_log::Error<X>),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum SwapState {
+    Idle,
+    Pending,
+    Installing,
+    Testing,
+    RollingBack,
+    RolledBack,
+}
+
+impl SwapState {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            SwapState::Idle => 0,
+            SwapState::Pending => 1,
+            SwapState::Installing => 2,
+            SwapState::Testing => 3,
+            SwapState::RollingBack => 4,
+            SwapState::RolledBack => 5,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => SwapState::Idle,
+            1 => SwapState::Pending,
+            2 => SwapState::Installing,
+            3 => SwapState::Testing,
+            4 => SwapState::RollingBack,
+            5 => SwapState::RolledBack,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BootState {
+    pub swap: SwapState,
+    /// Image being installed or tested
+    pub image: Option<ImageHeader>,
+}
+
+impl BootState {
+    pub const IDLE: Self = BootState {
+        swap: SwapState::Idle,
+        image: None,
+    };
+
+    const VERSION: u8 = 1;
+    const MAX_ENCODED_SIZE: usize = 1 + ImageHeader::SIZE;
+
+    fn encode(&self, buf: &mut [u8; Self::MAX_ENCODED_SIZE]) -> usize {
+        buf[0] = self.swap.as_u8();
+        match self.image {
+            Some(image) => {
+                buf[1..].copy_from_slice(&image.to_bytes());
+                Self::MAX_ENCODED_SIZE
+            }
+            None => 1,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(BootState {
+            swap: SwapState::from_u8(*bytes.first()?)?,
+            image: ImageHeader::from_bytes::<()>(&bytes[1..]).ok(),
+        })
+    }
+}
+
+/// What the bootloader did
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Outcome {
+    Unchanged,
+    Installed(ImageHeader),
+    RolledBack,
+    /// The staged image didn't check out, nothing was touched
+    InvalidImage,
+}
+
+pub struct BootStateStore {
+    log: RecordLog,
+}
+
+impl BootStateStore {
+    pub const fn new(base: u32, num_sectors: u32) -> Self {
+        BootStateStore {
+            log: RecordLog::new(base, num_sectors),
+        }
+    }
+
+    /// Most recently saved state, Idle if there is none
+    pub fn load<F>(&mut self, flash: &mut F) -> Result<BootState, record_log::Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: fmt::Debug,
+    {
+        let mut buf = [0_u8; BootState::MAX_ENCODED_SIZE];
+        Ok(match self.log.load(flash, &mut buf)? {
+            Some(info) if info.version == BootState::VERSION => {
+                BootState::decode(&buf[..info.len]).unwrap_or(BootState::IDLE)
+            }
+            _ => BootState::IDLE,
+        })
+    }
+
+    pub fn save<F>(
+        &mut self,
+        flash: &mut F,
+        state: &BootState,
+    ) -> Result<(), record_log::Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: fmt::Debug,
+    {
+        let mut buf = [0_u8; BootState::MAX_ENCODED_SIZE];
+        let len = state.encode(&mut buf);
+        self.log.append(flash, BootState::VERSION, &buf[..len])
+    }
+
+    /// Have the bootloader install the image in the staging slot on the next reset
+    pub fn request_install<F>(
+        &mut self,
+        flash: &mut F,
+        image: ImageHeader,
+    ) -> Result<(), record_log::Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: fmt::Debug,
+    {
+        let state = BootState {
+            swap: SwapState::Pending,
+            image: Some(image),
+        };
+        self.save(flash, &state)
+    }
+
+    /// Called by the application once it's up and running, returns the state it was in
+    pub fn confirm<F>(&mut self, flash: &mut F) -> Result<BootState, record_log::Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: fmt::Debug,
+    {
+        let state = self.load(flash)?;
+        if matches!(state.swap, SwapState::Testing | SwapState::RolledBack) {
+            self.save(flash, &BootState::IDLE)?;
+        }
+        Ok(state)
+    }
+}
+
+/// The bootloader's side, `app` is the application slot (offset 0 is its start) and
+/// `feed_watchdog` is called regularly during long copies
+pub fn run<I, X, W>(
+    app: &mut I,
+    external: &mut X,
+    store: &mut BootStateStore,
+    mut feed_watchdog: W,
+) -> Result<Outcome, Error<I::Error, X::Error>>
+where
+    I: NorFlash,
+    I::Error: fmt::Debug,
+    X: NorFlash,
+    X::Error: fmt::Debug,
+    W: FnMut(),
+{
+    loop {
+        let state = store.load(external).map_err(Error::BootState)?;
+        let next = match (state.swap, state.image) {
+            (SwapState::Pending, _) => {
+                let image = match image::verify(external, flash_layout::DFU_STAGING_OFFSET) {
+                    Ok(image) if state.image.map(|i| i == image).unwrap_or(true) => image,
+                    Err(image::Error::Flash(e)) => return Err(Error::External(e)),
+                    _ => {
+                        store
+                            .save(external, &BootState::IDLE)
+                            .map_err(Error::BootState)?;
+                        return Ok(Outcome::InvalidImage);
+                    }
+                };
+                copy(
+                    app,
+                    0,
+                    external,
+                    flash_layout::DFU_BACKUP_OFFSET,
+                    internal::APP_SIZE,
+                    &mut feed_watchdog,
+                )
+                .map_err(CopyError::into_backup_error)?;
+                BootState {
+                    swap: SwapState::Installing,
+                    image: Some(image),
+                }
+            }
+            (SwapState::Installing, Some(image)) => {
+                copy(
+                    external,
+                    flash_layout::DFU_STAGING_OFFSET + ImageHeader::SIZE as u32,
+                    app,
+                    0,
+                    image.size,
+                    &mut feed_watchdog,
+                )
+                .map_err(CopyError::into_install_error)?;
+                let crc = image::firmware_crc(app, 0, image.size).map_err(Error::Internal)?;
+                if crc == image.crc {
+                    let state = BootState {
+                        swap: SwapState::Testing,
+                        image: Some(image),
+                    };
+                    store.save(external, &state).map_err(Error::BootState)?;
+                    return Ok(Outcome::Installed(image));
+                }
+                BootState {
+                    swap: SwapState::RollingBack,
+                    image: Some(image),
+                }
+            }
+            // Also an Installing state that lost its image, which shouldn't happen
+            (SwapState::Testing, image) | (SwapState::Installing, image) => BootState {
+                swap: SwapState::RollingBack,
+                image,
+            },
+            (SwapState::RollingBack, _) => {
+                copy(
+                    external,
+                    flash_layout::DFU_BACKUP_OFFSET,
+                    app,
+                    0,
+                    internal::APP_SIZE,
+                    &mut feed_watchdog,
+                )
+                .map_err(CopyError::into_install_error)?;
+                let state = BootState {
+                    swap: SwapState::RolledBack,
+                    image: None,
+                };
+                store.save(external, &state).map_err(Error::BootState)?;
+                return Ok(Outcome::RolledBack);
+            }
+            (SwapState::Idle, _) | (SwapState::RolledBack, _) => return Ok(Outcome::Unchanged),
+        };
+        store.save(external, &next).map_err(Error::BootState)?;
+    }
+}
+
+enum CopyError<R, W> {
+    Read(R),
+    Write(W),
+}
+
+impl<I: fmt::Debug, X: fmt::Debug> CopyError<I, X> {
+    /// From the application slot to external flash
+    fn into_backup_error(self) -> Error<I, X> {
+        match self {
+            CopyError::Read(e) => Error::Internal(e),
+            CopyError::Write(e) => Error::External(e),
+        }
+    }
+}
+
+impl<X: fmt::Debug, I: fmt::Debug> CopyError<X, I> {
+    /// From external flash to the application slot
+    fn into_install_error(self) -> Error<I, X> {
+        match self {
+            CopyError::Read(e) => Error::External(e),
+            CopyError::Write(e) => Error::Internal(e),
+        }
+    }
+}
+
+/// Copy `len` bytes, erasing the destination sectors first, `to_offset` has to be erase
+/// sector aligned
+fn copy<R, W, F>(
+    from: &mut R,
+    from_offset: u32,
+    to: &mut W,
+    to_offset: u32,
+    len: u32,
+    feed_watchdog: &mut F,
+) -> Result<(), CopyError<R::Error, W::Error>>
+where
+    R: ReadNorFlash,
+    W: NorFlash,
+    F: FnMut(),
+{
+    let erase_size = W::ERASE_SIZE as u32;
+    let write_size = W::WRITE_SIZE as u32;
+    // Whatever follows the data pads out the last write
+    let len = len.div_ceil(write_size) * write_size;
+    let mut buf = [0_u8; 256];
+    let mut pos = 0;
+    while pos < len {
+        if pos % erase_size == 0 {
+            to.erase(to_offset + pos, to_offset + pos + erase_size)
+                .map_err(CopyError::Write)?;
+            feed_watchdog();
+        }
+        let chunk = (len - pos).min(buf.len() as u32) as usize;
+        from.read(from_offset + pos, &mut buf[..chunk])
+            .map_err(CopyError::Read)?;
+        to.write(to_offset + pos, &buf[..chunk])
+            .map_err(CopyError::Write)?;
+        pos += chunk as u32;
+        feed_watchdog();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::dfu::image::Version;
+    use crate::test_flash::RamFlash;
+    use std::vec::Vec;
+
+    const EXTERNAL_SIZE: usize = 4 * 1024 * 1024;
+
+    struct Watch {
+        app: RamFlash,
+        external: RamFlash,
+        store: BootStateStore,
+        original: Vec<u8>,
+        firmware: Vec<u8>,
+        image: ImageHeader,
+    }
+
+    fn pattern(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    impl Watch {
+        /// Running the original firmware, with `firmware` staged
+        fn new(firmware_len: usize) -> Self {
+            let original = pattern(internal::APP_SIZE as usize, 1);
+            let mut app = RamFlash::new(internal::APP_SIZE as usize);
+            app.data.copy_from_slice(&original);
+
+            let firmware = pattern(firmware_len, 2);
+            let image = ImageHeader::new(
+                Version {
+                    major: 1,
+                    minor: 2,
+                    patch: 3,
+                },
+                &firmware,
+            );
+            let mut external = RamFlash::new(EXTERNAL_SIZE);
+            let staging = flash_layout::DFU_STAGING_OFFSET as usize;
+            external.data[staging..][..ImageHeader::SIZE].copy_from_slice(&image.to_bytes());
+            external.data[staging + ImageHeader::SIZE..][..firmware_len].copy_from_slice(&firmware);
+
+            Watch {
+                app,
+                external,
+                store: BootStateStore::new(
+                    flash_layout::BOOT_STATE_OFFSET,
+                    flash_layout::BOOT_STATE_SECTORS,
+                ),
+                original,
+                firmware,
+                image,
+            }
+        }
+
+        fn request_install(&mut self) {
+            self.store
+                .request_install(&mut self.external, self.image)
+                .unwrap();
+        }
+
+        /// A reset into the bootloader, the store starts from scratch like it would
+        fn boot(
+            &mut self,
+        ) -> Result<Outcome, Error<crate::test_flash::Error, crate::test_flash::Error>> {
+            self.store = BootStateStore::new(
+                flash_layout::BOOT_STATE_OFFSET,
+                flash_layout::BOOT_STATE_SECTORS,
+            );
+            run(&mut self.app, &mut self.external, &mut self.store, || ())
+        }
+
+        fn state(&mut self) -> SwapState {
+            self.store.load(&mut self.external).unwrap().swap
+        }
+
+        fn runs_new_firmware(&self) -> bool {
+            self.app.data[..self.firmware.len()] == self.firmware[..]
+        }
+
+        fn runs_original_firmware(&self) -> bool {
+            self.app.data == self.original
+        }
+    }
+
+    #[test]
+    fn nothing_to_do() {
+        let mut watch = Watch::new(1000);
+        assert_eq!(watch.boot().unwrap(), Outcome::Unchanged);
+        assert!(watch.runs_original_firmware());
+        assert_eq!(watch.state(), SwapState::Idle);
+    }
+
+    #[test]
+    fn install_and_confirm() {
+        let mut watch = Watch::new(10_000);
+        watch.request_install();
+        assert_eq!(watch.boot().unwrap(), Outcome::Installed(watch.image));
+        assert!(watch.runs_new_firmware());
+        assert_eq!(watch.state(), SwapState::Testing);
+
+        let confirmed = watch.store.confirm(&mut watch.external).unwrap();
+        assert_eq!(confirmed.swap, SwapState::Testing);
+        assert_eq!(confirmed.image, Some(watch.image));
+        assert_eq!(watch.state(), SwapState::Idle);
+
+        assert_eq!(watch.boot().unwrap(), Outcome::Unchanged);
+        assert!(watch.runs_new_firmware());
+    }
+
+    #[test]
+    fn unconfirmed_image_reverts() {
+        let mut watch = Watch::new(10_000);
+        watch.request_install();
+        watch.boot().unwrap();
+        // The new firmware reset before confirming itself
+        assert_eq!(watch.boot().unwrap(), Outcome::RolledBack);
+        assert!(watch.runs_original_firmware());
+        assert_eq!(watch.state(), SwapState::RolledBack);
+
+        // Reported once by the original firmware confirming itself
+        assert_eq!(
+            watch.store.confirm(&mut watch.external).unwrap().swap,
+            SwapState::RolledBack
+        );
+        assert_eq!(watch.state(), SwapState::Idle);
+        assert_eq!(watch.boot().unwrap(), Outcome::Unchanged);
+        assert!(watch.runs_original_firmware());
+    }
+
+    #[test]
+    fn invalid_image_is_not_installed() {
+        let mut watch = Watch::new(10_000);
+        watch.request_install();
+        let staging = flash_layout::DFU_STAGING_OFFSET as usize + ImageHeader::SIZE;
+        watch.external.data[staging + 5000] ^= 0x01;
+        assert_eq!(watch.boot().unwrap(), Outcome::InvalidImage);
+        assert!(watch.runs_original_firmware());
+        assert_eq!(watch.state(), SwapState::Idle);
+    }
+
+    /// Cut the power after `bytes` written or erased on `flash`, then boot until done
+    fn power_loss_during_swap(cut_app: bool, bytes: usize) -> Watch {
+        let mut watch = Watch::new(40_000);
+        watch.request_install();
+        if cut_app {
+            watch.app.cut_power_after(bytes);
+        } else {
+            watch.external.cut_power_after(bytes);
+        }
+        assert!(watch.boot().is_err(), "{} bytes", bytes);
+        watch.app.restore_power();
+        watch.external.restore_power();
+        watch
+    }
+
+    #[test]
+    fn power_loss_mid_install_carries_on() {
+        // Backing up the app slot (erased and written), saving the states, then copying
+        // the firmware into the app slot
+        let backup = 2 * internal::APP_SIZE as usize;
+        let external_cuts = [0, 100, backup / 2, backup + 10, backup + 40, backup + 60];
+        let app_cuts = [0, 4096, 20_000, 40_000 + 4096 - 1];
+        let cuts = external_cuts
+            .iter()
+            .map(|&b| (false, b))
+            .chain(app_cuts.iter().map(|&b| (true, b)));
+        for (cut_app, bytes) in cuts {
+            let mut watch = power_loss_during_swap(cut_app, bytes);
+            assert_eq!(
+                watch.boot().unwrap(),
+                Outcome::Installed(watch.image),
+                "{} bytes",
+                bytes
+            );
+            assert!(watch.runs_new_firmware());
+
+            // The backup is still good for a rollback
+            assert_eq!(watch.boot().unwrap(), Outcome::RolledBack);
+            assert!(watch.runs_original_firmware(), "{} bytes", bytes);
+        }
+    }
+
+    #[test]
+    fn power_loss_mid_rollback_carries_on() {
+        for bytes in [0, 4096, 100_000, internal::APP_SIZE as usize - 1] {
+            let mut watch = Watch::new(40_000);
+            watch.request_install();
+            watch.boot().unwrap();
+            watch.app.cut_power_after(bytes);
+            assert!(watch.boot().is_err());
+            watch.app.restore_power();
+            assert_eq!(watch.state(), SwapState::RollingBack);
+
+            assert_eq!(watch.boot().unwrap(), Outcome::RolledBack);
+            assert!(watch.runs_original_firmware(), "{} bytes", bytes);
+        }
+    }
+
+    #[test]
+    fn boot_state_round_trip() {
+        let image = ImageHeader::new(Version::default(), &[1, 2, 3]);
+        for state in [
+            BootState::IDLE,
+            BootState {
+                swap: SwapState::Pending,
+                image: Some(image),
+            },
+            BootState {
+                swap: SwapState::RolledBack,
+                image: None,
+            },
+        ] {
+            let mut buf = [0; BootState::MAX_ENCODED_SIZE];
+            let len = state.encode(&mut buf);
+            assert_eq!(BootState::decode(&buf[..len]), Some(state));
+        }
+        assert_eq!(BootState::decode(&[6]), None);
+        assert_eq!(BootState::decode(&[]), None);
+    }
+}
@@ -0,0 +1,32 @@
+//! Layout of the 4MB external SPI NOR flash, and of the internal flash
+
+/// Erase sector size
+pub const SECTOR_SIZE: u32 = 4 * 1024;
+
+/// Firmware image received over BLE, header included
+pub const DFU_STAGING_OFFSET: u32 = 0x0020_0000;
+pub const DFU_STAGING_SIZE: u32 = 0x0008_0000;
+
+/// Copy of the application slot taken by the bootloader before installing an update
+pub const DFU_BACKUP_OFFSET: u32 = 0x0028_0000;
+pub const DFU_BACKUP_SIZE: u32 = 0x0008_0000;
+
+pub const SETTINGS_OFFSET: u32 = 0x0030_0000;
+pub const SETTINGS_SECTORS: u32 = 4;
+
+/// Update state shared by the bootloader and the application
+pub const BOOT_STATE_OFFSET: u32 = 0x0030_4000;
+pub const BOOT_STATE_SECTORS: u32 = 2;
+
+/// 512K internal flash of the nRF52832
+pub mod internal {
+    /// Erase page size
+    pub const PAGE_SIZE: u32 = 4 * 1024;
+
+    pub const BOOTLOADER_OFFSET: u32 = 0;
+    pub const BOOTLOADER_SIZE: u32 = 0x8000;
+
+    /// The application, linked to run from here
+    pub const APP_OFFSET: u32 = BOOTLOADER_OFFSET + BOOTLOADER_SIZE;
+    pub const APP_SIZE: u32 = 0x0008_0000 - APP_OFFSET;
+}
@@ -0,0 +1,506 @@
+//! Heart rate (BPM) estimation from raw PPG samples
+//!
+//! Samples are fed at a fixed [`BpmEstimator::SAMPLE_RATE_HZ`]. The signal goes through
+//! a DC removing high-pass filter and a short moving average low-pass, beats are then
+//! detected as peaks whose rise from the preceding trough is above an adaptive threshold,
+//! with a refractory period.
+//! The estimate is the mean of the most recent beat intervals, leaving out the shortest
+//! and longest quarter, so a single missed or spurious beat doesn't throw it off.
+//!
+//! Everything is integer math and independent of the sensor, so recorded traces
+//! can be replayed through it on the host.
+
+/// Number of beat intervals the estimate is taken from
+const INTERVALS: usize = 8;
+
+/// Valid intervals needed before reporting a value
+const MIN_INTERVALS: usize = 4;
+
+/// Moving average length, ~160 ms at 25 Hz
+const SMOOTHING: usize = 4;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BpmEstimator {
+    /// DC estimate, fixed point with [`Self::DC_SHIFT`] fractional bits
+    dc: i64,
+    dc_primed: bool,
+    smoothing: [i32; SMOOTHING],
+    smoothing_index: usize,
+    /// Previous two filtered samples, for peak detection
+    prev: [i32; 2],
+    /// Lowest filtered sample since the last peak
+    trough: i32,
+    /// Decaying peak amplitude the threshold is derived from
+    envelope: i32,
+    /// Samples since the last detected beat
+    since_beat: u32,
+    /// False until the first beat, its interval is meaningless
+    had_beat: bool,
+    intervals: [u16; INTERVALS],
+    interval_index: usize,
+    interval_len: usize,
+    samples: u32,
+}
+
+impl Default for BpmEstimator {
+    fn default() -> Self {
+        BpmEstimator::new()
+    }
+}
+
+impl BpmEstimator {
+    pub const SAMPLE_RATE_HZ: u32 = 25;
+
+    pub const MIN_BPM: u32 = 40;
+    pub const MAX_BPM: u32 = 200;
+
+    /// High-pass time constant, 2^5 samples (~1.3 s)
+    const DC_SHIFT: u32 = 5;
+
+    /// Samples ignored at the start while the filters settle
+    const SETTLE_SAMPLES: u32 = 2 * Self::SAMPLE_RATE_HZ;
+
+    /// Shortest plausible beat interval in samples
+    const MIN_INTERVAL: u32 = 60 * Self::SAMPLE_RATE_HZ / Self::MAX_BPM;
+
+    /// Longest plausible beat interval in samples
+    const MAX_INTERVAL: u32 = 60 * Self::SAMPLE_RATE_HZ / Self::MIN_BPM;
+
+    /// A missed beat makes for a gap up to twice the longest interval, anything
+    /// longer is taken as lost contact
+    const MAX_GAP: u32 = 2 * Self::MAX_INTERVAL;
+
+    pub const fn new() -> Self {
+        BpmEstimator {
+            dc: 0,
+            dc_primed: false,
+            smoothing: [0; SMOOTHING],
+            smoothing_index: 0,
+            prev: [0; 2],
+            trough: 0,
+            envelope: 0,
+            since_beat: 0,
+            had_beat: false,
+            intervals: [0; INTERVALS],
+            interval_index: 0,
+            interval_len: 0,
+            samples: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = BpmEstimator::new();
+    }
+
+    /// Feed a raw PPG sample, returns true when a beat was detected
+    pub fn update(&mut self, raw: u32) -> bool {
+        let filtered = self.filter(raw);
+        self.samples = self.samples.saturating_add(1);
+        self.since_beat = self.since_beat.saturating_add(1);
+
+        // Let the envelope decay by ~1/64 per sample so the threshold follows
+        // changes in amplitude (i.e. the watch moving on the wrist)
+        self.envelope -= self.envelope >> 6;
+
+        let [prev2, prev1] = self.prev;
+        self.prev = [prev1, filtered];
+        self.trough = self.trough.min(filtered);
+        if self.samples <= Self::SETTLE_SAMPLES {
+            self.trough = filtered;
+            return false;
+        }
+
+        // prev1 is a local maximum
+        let is_peak = prev1 > prev2 && prev1 >= filtered;
+        if !is_peak {
+            return false;
+        }
+        // Measured from the trough so a slowly wandering baseline doesn't matter
+        let amplitude = prev1 - self.trough;
+        self.trough = prev1.min(filtered);
+        if amplitude > self.envelope {
+            self.envelope = amplitude;
+        }
+        if amplitude < self.envelope / 2 || self.since_beat < Self::MIN_INTERVAL {
+            return false;
+        }
+
+        let interval = self.since_beat;
+        self.since_beat = 0;
+        if self.had_beat && interval <= Self::MAX_GAP {
+            self.push_interval(interval as u16);
+        } else if self.had_beat {
+            // Lost contact for a while, start over
+            self.interval_len = 0;
+        }
+        self.had_beat = true;
+        true
+    }
+
+    /// Current estimate, None until enough consistent beats were seen
+    pub fn bpm(&self) -> Option<u8> {
+        if self.interval_len < MIN_INTERVALS || self.since_beat > Self::MAX_GAP {
+            return None;
+        }
+        let mut sorted = [0_u16; INTERVALS];
+        let sorted = &mut sorted[..self.interval_len];
+        sorted.copy_from_slice(&self.intervals[..self.interval_len]);
+        sorted.sort_unstable();
+        let trim = sorted.len() / 4;
+        let kept = &sorted[trim..sorted.len() - trim];
+        let total: u32 = kept.iter().map(|i| *i as u32).sum();
+        // bpm = 60 * rate / (total / count), rounded
+        let bpm = (60 * Self::SAMPLE_RATE_HZ * kept.len() as u32 + total / 2) / total;
+        if (Self::MIN_BPM..=Self::MAX_BPM).contains(&bpm) {
+            Some(bpm as u8)
+        } else {
+            None
+        }
+    }
+
+    fn filter(&mut self, raw: u32) -> i32 {
+        let x = (raw as i64) << Self::DC_SHIFT;
+        if !self.dc_primed {
+            self.dc = x;
+            self.dc_primed = true;
+        }
+        self.dc += (x - self.dc) >> Self::DC_SHIFT;
+        let ac = ((x - self.dc) >> Self::DC_SHIFT) as i32;
+
+        self.smoothing[self.smoothing_index] = ac;
+        self.smoothing_index = (self.smoothing_index + 1) % SMOOTHING;
+        // Blood volume peaks show up as dips in reflected light, invert so beats are maxima
+        -(self.smoothing.iter().sum::<i32>() / SMOOTHING as i32)
+    }
+
+    fn push_interval(&mut self, interval: u16) {
+        self.intervals[self.interval_index] = interval;
+        self.interval_index = (self.interval_index + 1) % INTERVALS;
+        self.interval_len = (self.interval_len + 1).min(INTERVALS);
+    }
+}
+
+/// Idealized raw PPG signal: the reflected light dips for the first 30% of each beat on a
+/// constant baseline. The simulator shows it, tests sweep the rate with it.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SyntheticPpg {
+    pub bpm: u32,
+    /// Pulse depth in raw counts
+    pub depth: u32,
+    /// Position within the beat, a beat is `60 * SAMPLE_RATE_HZ` so it advances by `bpm`
+    phase: u32,
+}
+
+impl SyntheticPpg {
+    const BASELINE: u32 = 30_000;
+    const BEAT: u32 = 60 * BpmEstimator::SAMPLE_RATE_HZ;
+    const PULSE: u32 = Self::BEAT * 3 / 10;
+
+    pub const fn new(bpm: u32) -> Self {
+        SyntheticPpg {
+            bpm,
+            depth: 800,
+            phase: 0,
+        }
+    }
+
+    /// Next sample at [`BpmEstimator::SAMPLE_RATE_HZ`]
+    pub fn sample(&mut self) -> u32 {
+        let phase = self.phase;
+        self.phase = (self.phase + self.bpm) % Self::BEAT;
+        if phase >= Self::PULSE {
+            return Self::BASELINE;
+        }
+        // Parabola through 0 at both ends of the pulse, `depth` in the middle
+        let dip = 4 * self.depth * phase / Self::PULSE * (Self::PULSE - phase) / Self::PULSE;
+        Self::BASELINE - dip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = BpmEstimator::SAMPLE_RATE_HZ;
+
+    /// Sitting still, ~62 bpm with some beat to beat variation and breathing in the
+    /// baseline. Each pulse has a smaller dicrotic dip after the main one.
+    const RESTING: [u32; 300] = [
+        24260, 24322, 24274, 24253, 24182, 23875, 23631, 23706, 24047, 24266, 24304, 24256, 24132,
+        24132, 24200, 24279, 24276, 24291, 24253, 24249, 24249, 24193, 24196, 24180, 24227, 24160,
+        24162, 24142, 24076, 23893, 23634, 23351, 23515, 23768, 23944, 24001, 23945, 23765, 23730,
+        23833, 23895, 23877, 23864, 23816, 23840, 23838, 23785, 23747, 23751, 23777, 23704, 23735,
+        23699, 23666, 23472, 23148, 23050, 23269, 23586, 23669, 23670, 23631, 23467, 23565, 23668,
+        23682, 23739, 23722, 23684, 23751, 23743, 23767, 23790, 23839, 23825, 23836, 23852, 23740,
+        23597, 23243, 23281, 23565, 23839, 23953, 24017, 23926, 23846, 23931, 24013, 24087, 24094,
+        24144, 24109, 24151, 24154, 24172, 24222, 24221, 24245, 24271, 24274, 24166, 24019, 23645,
+        23600, 23926, 24160, 24265, 24282, 24211, 24129, 24145, 24279, 24307, 24280, 24287, 24289,
+        24289, 24264, 24261, 24226, 24193, 24194, 24218, 24196, 24114, 23895, 23575, 23461, 23684,
+        23911, 24030, 23985, 23911, 23839, 23841, 23938, 23970, 23947, 23942, 23877, 23845, 23869,
+        23908, 23834, 23781, 23802, 23818, 23742, 23759, 23609, 23369, 23070, 23131, 23490, 23634,
+        23675, 23707, 23548, 23583, 23606, 23656, 23703, 23712, 23719, 23716, 23755, 23678, 23732,
+        23750, 23813, 23730, 23785, 23776, 23773, 23679, 23379, 23208, 23348, 23708, 23911, 23951,
+        23893, 23856, 23815, 23993, 24027, 24051, 24080, 24112, 24152, 24123, 24134, 24174, 24153,
+        24148, 24224, 24194, 24162, 23870, 23528, 23631, 23949, 24232, 24283, 24266, 24194, 24113,
+        24192, 24238, 24310, 24278, 24284, 24308, 24309, 24254, 24322, 24249, 24275, 24267, 24231,
+        24165, 23993, 23632, 23503, 23697, 23993, 24134, 24096, 23983, 23890, 23914, 24012, 24024,
+        24026, 23962, 23989, 23933, 23919, 23952, 23893, 23870, 23852, 23831, 23863, 23834, 23736,
+        23481, 23172, 23093, 23393, 23642, 23713, 23737, 23656, 23569, 23537, 23714, 23714, 23689,
+        23700, 23731, 23734, 23730, 23718, 23721, 23749, 23734, 23723, 23732, 23694, 23502, 23242,
+        23100, 23447, 23719, 23841, 23881, 23822, 23729, 23782, 23870, 23954, 24011, 23992, 24035,
+        24054, 24065, 24100, 24088, 24088, 24097, 24166, 24149, 24150, 24097, 23801, 23590, 23635,
+        23939,
+    ];
+
+    /// Walking, ~112 bpm. The arm swing adds a 1.8 Hz wobble and the baseline drifts as
+    /// the watch shifts on the wrist.
+    const WALKING: [u32; 300] = [
+        27298, 27246, 27212, 27094, 27168, 26947, 27015, 27067, 27191, 27370, 27270, 27336, 27311,
+        27212, 26951, 26674, 26420, 26599, 26871, 26995, 27035, 27028, 26806, 26939, 27042, 27198,
+        27091, 27092, 26650, 26509, 26307, 26485, 26617, 26680, 26764, 26682, 26715, 26863, 26942,
+        27011, 26941, 26827, 26412, 26207, 26514, 26646, 26742, 26646, 26666, 26682, 26650, 26818,
+        26953, 27084, 26819, 26515, 26468, 26640, 26881, 26867, 26767, 26701, 26722, 26875, 26959,
+        27133, 27211, 27171, 26863, 26557, 26728, 26876, 27017, 27082, 27015, 26982, 27043, 27129,
+        27237, 27301, 27356, 27293, 27048, 26828, 27027, 27207, 27180, 27351, 27132, 27042, 27230,
+        27268, 27411, 27461, 27426, 27300, 26948, 26858, 27049, 27209, 27190, 27125, 26954, 26972,
+        27119, 27148, 27322, 27347, 27246, 26893, 26639, 26898, 27161, 27148, 26870, 26801, 26803,
+        26820, 26947, 27107, 27098, 27098, 26929, 26551, 26661, 26813, 26895, 26841, 26867, 26617,
+        26638, 26544, 26784, 26834, 26822, 26716, 26515, 26359, 26597, 26825, 26654, 26627, 26487,
+        26267, 26433, 26559, 26584, 26700, 26670, 26425, 26315, 26360, 26803, 26790, 26747, 26630,
+        26619, 26499, 26576, 26697, 26701, 26702, 26563, 26534, 26616, 26865, 26938, 27070, 26814,
+        26821, 26762, 26783, 26775, 26939, 26940, 26755, 26553, 26614, 27014, 27260, 27137, 27212,
+        27060, 26928, 26807, 26905, 27046, 27042, 26907, 26672, 26726, 26997, 27307, 27461, 27278,
+        27064, 27036, 26994, 26920, 27055, 27083, 26893, 26738, 26520, 26681, 26977, 27099, 27225,
+        27189, 26967, 26924, 26995, 26912, 26825, 26838, 26818, 26729, 26384, 26572, 26966, 27069,
+        27024, 27079, 26813, 26703, 26652, 26668, 26702, 26658, 26533, 26475, 26255, 26420, 26641,
+        26778, 26970, 26788, 26544, 26402, 26420, 26479, 26441, 26502, 26352, 26147, 26139, 26474,
+        26865, 26869, 26871, 26807, 26706, 26565, 26578, 26598, 26632, 26511, 26259, 26203, 26449,
+        26821, 27028, 27069, 27051, 26773, 26748, 26791, 26819, 26691, 26734, 26529, 26354, 26437,
+        26879, 27236, 27222, 27114, 27088, 27012, 27112, 26949, 26857, 26807, 26443, 26351, 26657,
+        27072, 27227, 27307, 27318, 27316, 27156, 27377, 27181, 27144, 26909, 26654, 26567, 26708,
+        26961,
+    ];
+
+    /// Loose strap, ~74 bpm. The pulse is barely above the noise.
+    const WEAK: [u32; 300] = [
+        21008, 20993, 21013, 21002, 20987, 20979, 20971, 20913, 20877, 20920, 20962, 20956, 20970,
+        20948, 20941, 20930, 20963, 20945, 20953, 20950, 20950, 20965, 20933, 20946, 20938, 20946,
+        20915, 20887, 20842, 20863, 20921, 20928, 20955, 20930, 20939, 20925, 20947, 20962, 20970,
+        20952, 20947, 20956, 20968, 20982, 20964, 20968, 20974, 20932, 20899, 20914, 20962, 20980,
+        21001, 21002, 20991, 20972, 21014, 21018, 21033, 21028, 21020, 21046, 21044, 21036, 21047,
+        21042, 21046, 21017, 20977, 20966, 20994, 21046, 21055, 21057, 21051, 21032, 21060, 21051,
+        21053, 21066, 21048, 21057, 21049, 21050, 21041, 21040, 21039, 21028, 20987, 20949, 20964,
+        21031, 21020, 21030, 21031, 21008, 21000, 21009, 21006, 21005, 21019, 21009, 20989, 21000,
+        20986, 20999, 20978, 20946, 20913, 20892, 20926, 20951, 20952, 20963, 20945, 20942, 20934,
+        20954, 20944, 20944, 20936, 20954, 20953, 20944, 20940, 20930, 20924, 20897, 20851, 20873,
+        20899, 20940, 20947, 20942, 20919, 20939, 20924, 20955, 20957, 20962, 20948, 20948, 20959,
+        20969, 20977, 20980, 20978, 20970, 20922, 20893, 20924, 20985, 20985, 20990, 20996, 20994,
+        20990, 21023, 21019, 21032, 21021, 21036, 21019, 21047, 21041, 21043, 21055, 21014, 20989,
+        20962, 21011, 21029, 21055, 21069, 21055, 21028, 21034, 21052, 21056, 21064, 21069, 21063,
+        21054, 21070, 21071, 21057, 21037, 20998, 20985, 20946, 20988, 21022, 21033, 21025, 21014,
+        21016, 20998, 21013, 21025, 21008, 21005, 21010, 21007, 20990, 20995, 20982, 20988, 20985,
+        20949, 20898, 20888, 20895, 20952, 20945, 20968, 20947, 20934, 20932, 20943, 20953, 20938,
+        20943, 20930, 20944, 20944, 20941, 20943, 20938, 20936, 20885, 20853, 20873, 20911, 20939,
+        20941, 20946, 20922, 20922, 20941, 20958, 20956, 20965, 20952, 20963, 20968, 20976, 20980,
+        20987, 20967, 20949, 20909, 20916, 20935, 20977, 20996, 21015, 21001, 20988, 21000, 21010,
+        21027, 21041, 21033, 21035, 21033, 21047, 21059, 21035, 21034, 21042, 20993, 20960, 20985,
+        21032, 21058, 21047, 21063, 21031, 21033, 21039, 21054, 21057, 21057, 21056, 21063, 21064,
+        21055, 21050, 21059, 21043, 21002, 20959, 20954, 20984, 21023, 21014, 21030, 20989, 20981,
+        20990,
+    ];
+
+    /// Lying on a table with the LED on, only ambient light and noise.
+    const OFF_WRIST: [u32; 300] = [
+        2775, 2754, 2744, 2756, 2729, 2700, 2720, 2708, 2692, 2692, 2694, 2700, 2695, 2702, 2726,
+        2698, 2703, 2716, 2739, 2726, 2761, 2738, 2752, 2776, 2780, 2796, 2824, 2843, 2851, 2863,
+        2900, 2906, 2916, 2952, 2945, 2977, 3002, 3012, 3024, 3054, 3062, 3079, 3092, 3138, 3132,
+        3169, 3176, 3183, 3209, 3218, 3229, 3222, 3251, 3270, 3275, 3288, 3301, 3294, 3317, 3279,
+        3296, 3270, 3310, 3299, 3316, 3288, 3263, 3297, 3258, 3251, 3255, 3254, 3229, 3225, 3187,
+        3217, 3181, 3168, 3157, 3136, 3119, 3088, 3079, 3068, 3056, 3024, 3007, 2992, 2976, 2953,
+        2929, 2907, 2909, 2882, 2861, 2886, 2839, 2824, 2801, 2774, 2787, 2760, 2751, 2753, 2744,
+        2726, 2717, 2736, 2713, 2691, 2710, 2702, 2701, 2710, 2707, 2732, 2715, 2722, 2740, 2727,
+        2756, 2753, 2779, 2767, 2795, 2785, 2813, 2834, 2856, 2890, 2906, 2885, 2910, 2953, 2961,
+        2958, 2999, 2999, 3021, 3035, 3065, 3114, 3109, 3121, 3169, 3150, 3169, 3183, 3206, 3211,
+        3244, 3235, 3257, 3262, 3258, 3265, 3283, 3261, 3294, 3298, 3301, 3299, 3304, 3294, 3304,
+        3273, 3298, 3271, 3280, 3260, 3252, 3237, 3251, 3210, 3196, 3199, 3185, 3167, 3139, 3126,
+        3127, 3086, 3088, 3071, 3046, 3011, 3029, 2989, 2971, 2946, 2920, 2910, 2898, 2868, 2863,
+        2858, 2834, 2813, 2812, 2797, 2784, 2752, 2728, 2750, 2732, 2719, 2723, 2706, 2699, 2725,
+        2682, 2710, 2712, 2723, 2707, 2714, 2702, 2719, 2701, 2727, 2743, 2752, 2759, 2791, 2771,
+        2806, 2814, 2834, 2851, 2890, 2871, 2917, 2936, 2936, 2949, 2960, 2983, 3037, 3005, 3055,
+        3060, 3109, 3108, 3106, 3133, 3158, 3173, 3175, 3197, 3209, 3233, 3242, 3253, 3253, 3278,
+        3270, 3273, 3284, 3290, 3293, 3276, 3316, 3307, 3314, 3291, 3301, 3290, 3282, 3296, 3259,
+        3264, 3226, 3242, 3221, 3198, 3189, 3156, 3153, 3152, 3102, 3129, 3110, 3072, 3045, 3027,
+        3010, 2997, 3014, 2955, 2959, 2947, 2902, 2892, 2886, 2853, 2860, 2855, 2813, 2789, 2793,
+    ];
+
+    /// Feed `seconds` of the synthetic signal, returns the beats detected
+    fn feed(estimator: &mut BpmEstimator, ppg: &mut SyntheticPpg, seconds: u32) -> u32 {
+        (0..seconds * RATE)
+            .filter(|_| estimator.update(ppg.sample()))
+            .count() as u32
+    }
+
+    /// Feed a whole window, returns the beats detected
+    fn replay<const N: usize>(estimator: &mut BpmEstimator, window: &[u32; N]) -> u32 {
+        window.iter().filter(|raw| estimator.update(**raw)).count() as u32
+    }
+
+    fn assert_near(bpm: Option<u8>, expected: u32, tolerance: u32) {
+        let bpm = bpm.expect("no estimate") as u32;
+        assert!(
+            bpm + tolerance >= expected && bpm <= expected + tolerance,
+            "{} bpm, expected {}",
+            bpm,
+            expected
+        );
+    }
+
+    #[test]
+    fn synthetic_signal() {
+        let mut ppg = SyntheticPpg::new(60);
+        let mut beat = [0; RATE as usize];
+        for raw in beat.iter_mut() {
+            *raw = ppg.sample();
+        }
+        assert_eq!(beat[0], SyntheticPpg::BASELINE);
+        let deepest = SyntheticPpg::BASELINE - beat.iter().min().unwrap();
+        assert!((790..=800).contains(&deepest), "{}", deepest);
+        assert!(beat[8..].iter().all(|raw| *raw == SyntheticPpg::BASELINE));
+        // Exactly one beat later it starts over
+        assert_eq!(ppg.sample(), beat[0]);
+        assert_eq!(ppg.sample(), beat[1]);
+    }
+
+    #[test]
+    fn steady_rates() {
+        for bpm in [45, 60, 72, 75, 100, 120, 150, 180] {
+            let mut estimator = BpmEstimator::new();
+            let beats = feed(&mut estimator, &mut SyntheticPpg::new(bpm), 20);
+            // All but the settling time and the first beat or two
+            assert!(beats + 3 >= 18 * bpm / 60, "{} bpm: {} beats", bpm, beats);
+            assert_near(estimator.bpm(), bpm, 2);
+        }
+    }
+
+    #[test]
+    fn nothing_until_settled() {
+        let mut estimator = BpmEstimator::new();
+        let mut ppg = SyntheticPpg::new(75);
+        for _ in 0..BpmEstimator::SETTLE_SAMPLES {
+            assert!(!estimator.update(ppg.sample()));
+        }
+        // The first beat only starts an interval, four are needed
+        let mut beats = 0;
+        while beats < MIN_INTERVALS {
+            assert_eq!(estimator.bpm(), None);
+            if estimator.update(ppg.sample()) {
+                beats += 1;
+            }
+        }
+        assert_eq!(estimator.bpm(), None);
+        feed(&mut estimator, &mut ppg, 1);
+        assert_near(estimator.bpm(), 75, 2);
+    }
+
+    #[test]
+    fn resting() {
+        let mut estimator = BpmEstimator::new();
+        let beats = replay(&mut estimator, &RESTING);
+        assert!(beats >= 9, "{} beats", beats);
+        assert_near(estimator.bpm(), 62, 3);
+    }
+
+    #[test]
+    fn walking() {
+        let mut estimator = BpmEstimator::new();
+        replay(&mut estimator, &WALKING);
+        assert_near(estimator.bpm(), 112, 4);
+    }
+
+    #[test]
+    fn weak_signal() {
+        let mut estimator = BpmEstimator::new();
+        replay(&mut estimator, &WEAK);
+        assert_near(estimator.bpm(), 74, 3);
+    }
+
+    #[test]
+    fn threshold_follows_the_amplitude_down() {
+        let mut estimator = BpmEstimator::new();
+        replay(&mut estimator, &RESTING);
+        // The strap loosened, the envelope from the strong pulses and the step in the
+        // baseline takes a while to decay
+        replay(&mut estimator, &WEAK);
+        replay(&mut estimator, &WEAK);
+        assert_near(estimator.bpm(), 74, 3);
+    }
+
+    #[test]
+    fn follows_rate_changes() {
+        let mut estimator = BpmEstimator::new();
+        let mut ppg = SyntheticPpg::new(60);
+        feed(&mut estimator, &mut ppg, 15);
+        assert_near(estimator.bpm(), 60, 2);
+        ppg.bpm = 110;
+        feed(&mut estimator, &mut ppg, 10);
+        assert_near(estimator.bpm(), 110, 2);
+        ppg.bpm = 80;
+        feed(&mut estimator, &mut ppg, 10);
+        assert_near(estimator.bpm(), 80, 2);
+    }
+
+    #[test]
+    fn odd_beats_are_left_out() {
+        let mut estimator = BpmEstimator::new();
+        let mut ppg = SyntheticPpg::new(75);
+        feed(&mut estimator, &mut ppg, 15);
+        // One beat too weak to be seen, a long interval
+        let beat_samples = 60 * RATE / ppg.bpm;
+        ppg.depth = 50;
+        for _ in 0..beat_samples {
+            estimator.update(ppg.sample());
+        }
+        ppg.depth = 800;
+        for _ in 0..beat_samples * 2 {
+            estimator.update(ppg.sample());
+            assert_near(estimator.bpm(), 75, 2);
+        }
+    }
+
+    #[test]
+    fn contact_lost_and_regained() {
+        let mut estimator = BpmEstimator::new();
+        let mut ppg = SyntheticPpg::new(90);
+        feed(&mut estimator, &mut ppg, 15);
+        assert_near(estimator.bpm(), 90, 2);
+
+        // Off the wrist, nothing but a constant reading. The step can pass for a
+        // beat, the estimate goes within a few seconds anyway.
+        for _ in 0..10 * RATE {
+            estimator.update(30_000);
+        }
+        assert_eq!(estimator.bpm(), None);
+
+        // Starts over instead of mixing in the old rate
+        ppg.bpm = 60;
+        feed(&mut estimator, &mut ppg, 15);
+        assert_near(estimator.bpm(), 60, 2);
+    }
+
+    #[test]
+    fn no_estimate_without_a_pulse() {
+        let mut estimator = BpmEstimator::new();
+        replay(&mut estimator, &OFF_WRIST);
+        assert_eq!(estimator.bpm(), None);
+    }
+
+    #[test]
+    fn reset() {
+        let mut estimator = BpmEstimator::new();
+        feed(&mut estimator, &mut SyntheticPpg::new(75), 15);
+        assert!(estimator.bpm().is_some());
+        estimator.reset();
+        assert_eq!(estimator, BpmEstimator::new());
+        assert_eq!(estimator.bpm(), None);
+    }
+}
@@ -0,0 +1,368 @@
+use core::fmt;
+use embedded_graphics::{geometry::Point, primitives::Rectangle};
+
+/// Touch panel gestures, values match the CST816S gesture register
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[repr(u8)]
+pub enum Gesture {
+    SlideDown = 0x01,
+    SlideUp = 0x02,
+    SlideLeft = 0x03,
+    SlideRight = 0x04,
+    SingleTap = 0x05,
+    DoubleTap = 0x0B,
+    LongPress = 0x0C,
+}
+
+impl Gesture {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        use Gesture::*;
+        match val {
+            0x01 => SlideDown,
+            0x02 => SlideUp,
+            0x03 => SlideLeft,
+            0x04 => SlideRight,
+            0x05 => SingleTap,
+            0x0B => DoubleTap,
+            0x0C => LongPress,
+            _ => return None,
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ButtonEvent {
+    ShortPress,
+    LongPress,
+    DoublePress,
+    /// Button let go after a long press
+    Release,
+}
+
+/// Input events routed to the active screen
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum InputEvent {
+    Tap(Point),
+    /// Any gesture other than a single tap, with the point it happened at
+    Gesture(Gesture, Point),
+    Button(ButtonEvent),
+}
+
+impl InputEvent {
+    /// Map a raw touch report to an event
+    pub fn from_touch(gesture: Gesture, point: Point) -> Self {
+        match gesture {
+            Gesture::SingleTap => InputEvent::Tap(point),
+            g => InputEvent::Gesture(g, point),
+        }
+    }
+
+    pub fn tap_point(&self) -> Option<Point> {
+        match self {
+            InputEvent::Tap(p) => Some(*p),
+            _ => None,
+        }
+    }
+
+    pub fn gesture(&self) -> Option<Gesture> {
+        match self {
+            InputEvent::Gesture(g, _) => Some(*g),
+            _ => None,
+        }
+    }
+
+    /// True if this is a tap inside of `area`
+    pub fn is_tap_within(&self, area: &Rectangle) -> bool {
+        self.tap_point().map(|p| area.contains(p)).unwrap_or(false)
+    }
+
+    /// Index of the first area hit by a tap, if any
+    pub fn hit_test<'a, I>(&self, areas: I) -> Option<usize>
+    where
+        I: IntoIterator<Item = &'a Rectangle>,
+    {
+        let p = self.tap_point()?;
+        areas.into_iter().position(|a| a.contains(p))
+    }
+}
+
+impl From<ButtonEvent> for InputEvent {
+    fn from(e: ButtonEvent) -> Self {
+        InputEvent::Button(e)
+    }
+}
+
+/// Turns button up/down samples into press events
+///
+/// A short press is only reported once the double press window has passed
+/// without a second press. Timestamps are free-running milliseconds and are
+/// allowed to wrap.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ButtonClassifier {
+    state: ButtonState,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum ButtonState {
+    Idle,
+    Pressed { since: u32 },
+    Released { at: u32 },
+    SecondPress,
+    LongPressed { since: u32 },
+}
+
+impl Default for ButtonClassifier {
+    fn default() -> Self {
+        ButtonClassifier::new()
+    }
+}
+
+impl ButtonClassifier {
+    pub const LONG_PRESS_MS: u32 = 1000;
+    pub const DOUBLE_PRESS_WINDOW_MS: u32 = 300;
+    /// Held this long the firmware resets, whatever else is going on
+    pub const VERY_LONG_PRESS_MS: u32 = 5000;
+
+    pub const fn new() -> Self {
+        ButtonClassifier {
+            state: ButtonState::Idle,
+        }
+    }
+
+    /// True while the classifier needs to keep being updated to produce an event
+    pub fn is_busy(&self) -> bool {
+        self.state != ButtonState::Idle
+    }
+
+    /// How long the button has been held down for, None unless it's held for a single or
+    /// long press
+    pub fn press_duration_ms(&self, now_ms: u32) -> Option<u32> {
+        match self.state {
+            ButtonState::Pressed { since } | ButtonState::LongPressed { since } => {
+                Some(now_ms.wrapping_sub(since))
+            }
+            _ => None,
+        }
+    }
+
+    /// True once the press has been held long enough to reset
+    pub fn is_very_long_press(&self, now_ms: u32) -> bool {
+        self.press_duration_ms(now_ms)
+            .is_some_and(|ms| ms >= Self::VERY_LONG_PRESS_MS)
+    }
+
+    pub fn update(&mut self, is_pressed: bool, now_ms: u32) -> Option<ButtonEvent> {
+        use ButtonState::*;
+        let (state, event) = match (self.state, is_pressed) {
+            (Idle, true) => (Pressed { since: now_ms }, None),
+            (Idle, false) => (Idle, None),
+            (Pressed { since }, true) => {
+                if now_ms.wrapping_sub(since) >= Self::LONG_PRESS_MS {
+                    (LongPressed { since }, Some(ButtonEvent::LongPress))
+                } else {
+                    (self.state, None)
+                }
+            }
+            (Pressed { .. }, false) => (Released { at: now_ms }, None),
+            (Released { at }, true) => {
+                if now_ms.wrapping_sub(at) >= Self::DOUBLE_PRESS_WINDOW_MS {
+                    // Not polled since the window passed, this is a new press
+                    (Pressed { since: now_ms }, Some(ButtonEvent::ShortPress))
+                } else {
+                    (SecondPress, Some(ButtonEvent::DoublePress))
+                }
+            }
+            (Released { at }, false) => {
+                if now_ms.wrapping_sub(at) >= Self::DOUBLE_PRESS_WINDOW_MS {
+                    (Idle, Some(ButtonEvent::ShortPress))
+                } else {
+                    (self.state, None)
+                }
+            }
+            (SecondPress, true) => (SecondPress, None),
+            (SecondPress, false) => (Idle, None),
+            (LongPressed { .. }, true) => (self.state, None),
+            (LongPressed { .. }, false) => (Idle, Some(ButtonEvent::Release)),
+        };
+        self.state = state;
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Classifier = ButtonClassifier;
+
+    /// Button driven by a fake clock
+    struct Button {
+        classifier: ButtonClassifier,
+        now_ms: u32,
+    }
+
+    impl Button {
+        fn new(now_ms: u32) -> Self {
+            Button {
+                classifier: ButtonClassifier::new(),
+                now_ms,
+            }
+        }
+
+        /// Sample the button at `ms` after the last sample
+        fn at(&mut self, ms: u32, is_pressed: bool) -> Option<ButtonEvent> {
+            self.now_ms = self.now_ms.wrapping_add(ms);
+            self.classifier.update(is_pressed, self.now_ms)
+        }
+
+        /// Sample it every 10 ms for `ms`, the events seen
+        fn hold(&mut self, ms: u32, is_pressed: bool) -> heapless::Vec<ButtonEvent, 8> {
+            let mut events = heapless::Vec::new();
+            for _ in 0..ms / 10 {
+                if let Some(event) = self.at(10, is_pressed) {
+                    events.push(event).unwrap();
+                }
+            }
+            events
+        }
+
+        fn press_duration(&self) -> Option<u32> {
+            self.classifier.press_duration_ms(self.now_ms)
+        }
+    }
+
+    #[test]
+    fn short_press_once_the_window_has_passed() {
+        let mut button = Button::new(0);
+        assert_eq!(button.at(0, true), None);
+        assert_eq!(button.at(80, false), None);
+        assert!(button.classifier.is_busy());
+        assert_eq!(
+            button.at(Classifier::DOUBLE_PRESS_WINDOW_MS - 1, false),
+            None
+        );
+        assert_eq!(button.at(1, false), Some(ButtonEvent::ShortPress));
+        assert!(!button.classifier.is_busy());
+        assert_eq!(button.hold(1000, false), []);
+    }
+
+    #[test]
+    fn double_press_within_the_window() {
+        let mut button = Button::new(0);
+        button.at(0, true);
+        button.at(80, false);
+        assert_eq!(
+            button.at(Classifier::DOUBLE_PRESS_WINDOW_MS - 1, true),
+            Some(ButtonEvent::DoublePress)
+        );
+        // Holding the second press doesn't make it a long press
+        assert_eq!(button.hold(2 * Classifier::LONG_PRESS_MS, true), []);
+        assert_eq!(button.press_duration(), None);
+        assert_eq!(button.at(10, false), None);
+        assert!(!button.classifier.is_busy());
+        assert_eq!(button.hold(1000, false), []);
+    }
+
+    #[test]
+    fn second_press_right_at_the_end_of_the_window() {
+        // Sampled on time, the window ends before the second press
+        let mut button = Button::new(0);
+        button.at(0, true);
+        button.at(80, false);
+        assert_eq!(
+            button.hold(Classifier::DOUBLE_PRESS_WINDOW_MS, false),
+            [ButtonEvent::ShortPress]
+        );
+        assert_eq!(button.at(0, true), None);
+
+        // Not sampled in between, still two presses
+        let mut button = Button::new(0);
+        button.at(0, true);
+        button.at(80, false);
+        assert_eq!(
+            button.at(Classifier::DOUBLE_PRESS_WINDOW_MS, true),
+            Some(ButtonEvent::ShortPress)
+        );
+        assert_eq!(button.at(80, false), None);
+        assert_eq!(
+            button.at(Classifier::DOUBLE_PRESS_WINDOW_MS, false),
+            Some(ButtonEvent::ShortPress)
+        );
+    }
+
+    #[test]
+    fn long_press_at_the_threshold() {
+        let mut button = Button::new(0);
+        button.at(0, true);
+        assert_eq!(button.at(Classifier::LONG_PRESS_MS - 1, true), None);
+        assert_eq!(button.at(1, true), Some(ButtonEvent::LongPress));
+        // Reported once, then the release
+        assert_eq!(button.hold(500, true), []);
+        assert_eq!(button.at(10, false), Some(ButtonEvent::Release));
+        assert!(!button.classifier.is_busy());
+        assert_eq!(button.hold(1000, false), []);
+    }
+
+    #[test]
+    fn released_just_before_a_long_press() {
+        let mut button = Button::new(0);
+        button.at(0, true);
+        assert_eq!(button.at(Classifier::LONG_PRESS_MS - 1, true), None);
+        assert_eq!(button.at(1, false), None);
+        assert_eq!(
+            button.hold(Classifier::DOUBLE_PRESS_WINDOW_MS, false),
+            [ButtonEvent::ShortPress]
+        );
+    }
+
+    #[test]
+    fn very_long_press_at_the_threshold() {
+        let mut button = Button::new(0);
+        assert!(!button.classifier.is_very_long_press(0));
+        button.at(0, true);
+        assert_eq!(
+            button.hold(Classifier::VERY_LONG_PRESS_MS - 10, true),
+            [ButtonEvent::LongPress]
+        );
+        assert_eq!(button.at(9, true), None);
+        assert_eq!(
+            button.press_duration(),
+            Some(Classifier::VERY_LONG_PRESS_MS - 1)
+        );
+        assert!(!button.classifier.is_very_long_press(button.now_ms));
+        assert_eq!(button.at(1, true), None);
+        assert!(button.classifier.is_very_long_press(button.now_ms));
+        assert_eq!(button.at(10, false), Some(ButtonEvent::Release));
+        assert!(!button.classifier.is_very_long_press(button.now_ms));
+    }
+
+    #[test]
+    fn clock_wraps() {
+        let mut button = Button::new(u32::MAX - 500);
+        button.at(0, true);
+        assert_eq!(button.at(Classifier::LONG_PRESS_MS - 1, true), None);
+        assert_eq!(button.at(1, true), Some(ButtonEvent::LongPress));
+        button.hold(
+            Classifier::VERY_LONG_PRESS_MS - Classifier::LONG_PRESS_MS,
+            true,
+        );
+        assert!(button.classifier.is_very_long_press(button.now_ms));
+        assert_eq!(button.at(10, false), Some(ButtonEvent::Release));
+
+        let mut button = Button::new(u32::MAX - 100);
+        button.at(0, true);
+        button.at(80, false);
+        assert_eq!(
+            button.at(Classifier::DOUBLE_PRESS_WINDOW_MS - 1, false),
+            None
+        );
+        assert_eq!(button.at(1, false), Some(ButtonEvent::ShortPress));
+    }
+}
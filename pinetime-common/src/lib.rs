@@ -1,14 +1,54 @@
 #![no_std]
 
+pub use crate::alarm::Alarm;
 pub use crate::animated_display::{AnimatedDisplay, RefreshDirection};
 pub use crate::battery_controller::{BatteryControllerExt, MilliVolts};
+pub use crate::battery_history::BatteryHistory;
+pub use crate::boot::{BootRecord, ResetReason, SelfTest};
+pub use crate::brightness::Brightness;
+pub use crate::countdown::Countdown;
+pub use crate::crash::CrashRecord;
 pub use crate::display::AtomicDisplayAwakeState;
+pub use crate::heart_rate::BpmEstimator;
+pub use crate::input::{ButtonClassifier, ButtonEvent, Gesture, InputEvent};
+pub use crate::low_battery::LowBatteryMonitor;
+pub use crate::notification::{Notification, NotificationStore};
+pub use crate::settings::{Settings, SettingsStore, TimeFormat};
+pub use crate::stopwatch::Stopwatch;
 pub use crate::system_time::SystemTimeExt;
+pub use crate::task_health::{TaskHealth, TimeSource};
+pub use crate::time_zone::TimeZone;
 pub use chrono;
 pub use embedded_graphics;
+pub use embedded_storage;
 pub use err_derive;
 
+pub mod alarm;
 mod animated_display;
 mod battery_controller;
+pub mod battery_history;
+pub mod battery_model;
+pub mod boot;
+mod brightness;
+pub mod clock_drift;
+pub mod countdown;
+pub mod crash;
+pub mod crc;
+pub mod dfu;
 pub mod display;
+pub mod flash_layout;
+pub mod heart_rate;
+mod input;
+pub mod low_battery;
+pub mod notification;
+pub mod record_log;
+pub mod settings;
+pub mod stopwatch;
 mod system_time;
+pub mod task_health;
+#[cfg(test)]
+mod test_flash;
+pub mod text;
+pub mod time_zone;
+pub mod vibration;
+pub mod wrist_tilt;
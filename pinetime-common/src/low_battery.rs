@@ -0,0 +1,244 @@
+//! What to do as the battery runs down
+//!
+//! Warns once when the charge gets low, then saves power by capping the backlight and
+//! checking the battery less often, and finally asks for a shutdown before the voltage
+//! drops far enough to brown out. Plugging in the charger resets everything.
+
+use crate::battery_model::{ChargeState, BATTERY_MIN};
+use crate::{Brightness, MilliVolts};
+
+/// Warn once at or below this
+pub const WARNING_PERCENT: u8 = 15;
+
+/// Power save at or below this
+pub const POWER_SAVE_PERCENT: u8 = 5;
+
+/// How far above `BATTERY_MIN` the filtered voltage is cut off. The average trails the
+/// readings by a few polls and a reading under load sags by up to ~150 mV, waiting any
+/// longer risks a brown out before the shutdown.
+const SHUTDOWN_MARGIN: MilliVolts = MilliVolts(250);
+
+/// Shut down below this (filtered) voltage
+pub const SHUTDOWN_VOLTAGE: MilliVolts = MilliVolts(BATTERY_MIN.0 + SHUTDOWN_MARGIN.0);
+
+/// Consecutive readings below the shutdown voltage, a sag under load isn't enough
+pub const SHUTDOWN_READINGS: u8 = 3;
+
+/// Backlight cap while saving power
+pub const POWER_SAVE_MAX_BRIGHTNESS: Brightness = Brightness::L2;
+
+/// Shortest voltage poll interval while saving power
+pub const POWER_SAVE_VOLTAGE_POLL_INTERVAL_MS: u16 = 60_000;
+
+/// The charge has to come back this far above a threshold to leave it again
+const HYSTERESIS_PERCENT: u8 = 3;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Event {
+    /// Went below `WARNING_PERCENT`, alert the user
+    Warning,
+    PowerSave(bool),
+    /// Turn off until the button is pressed
+    Shutdown,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct LowBatteryMonitor {
+    warned: bool,
+    power_save: bool,
+    low_voltage_readings: u8,
+}
+
+impl LowBatteryMonitor {
+    pub const fn new() -> Self {
+        LowBatteryMonitor {
+            warned: false,
+            power_save: false,
+            low_voltage_readings: 0,
+        }
+    }
+
+    pub fn is_power_save(&self) -> bool {
+        self.power_save
+    }
+
+    /// `brightness` limited while saving power
+    pub fn max_brightness(&self, brightness: Brightness) -> Brightness {
+        if self.power_save {
+            brightness.min(POWER_SAVE_MAX_BRIGHTNESS)
+        } else {
+            brightness
+        }
+    }
+
+    /// `interval_ms` lengthened while saving power
+    pub fn voltage_poll_interval_ms(&self, interval_ms: u16) -> u16 {
+        if self.power_save {
+            interval_ms.max(POWER_SAVE_VOLTAGE_POLL_INTERVAL_MS)
+        } else {
+            interval_ms
+        }
+    }
+
+    /// Call after every battery reading, at most one event is returned at a time
+    pub fn update(
+        &mut self,
+        percent: u8,
+        voltage: MilliVolts,
+        state: ChargeState,
+    ) -> Option<Event> {
+        if state != ChargeState::Discharging {
+            self.warned = false;
+            self.low_voltage_readings = 0;
+            return self.set_power_save(false);
+        }
+
+        if voltage < SHUTDOWN_VOLTAGE {
+            self.low_voltage_readings = self.low_voltage_readings.saturating_add(1);
+            if self.low_voltage_readings >= SHUTDOWN_READINGS {
+                return Some(Event::Shutdown);
+            }
+        } else {
+            self.low_voltage_readings = 0;
+        }
+
+        if percent <= WARNING_PERCENT && !self.warned {
+            self.warned = true;
+            return Some(Event::Warning);
+        } else if percent >= WARNING_PERCENT + HYSTERESIS_PERCENT {
+            self.warned = false;
+        }
+
+        if percent <= POWER_SAVE_PERCENT {
+            self.set_power_save(true)
+        } else if percent >= POWER_SAVE_PERCENT + HYSTERESIS_PERCENT {
+            self.set_power_save(false)
+        } else {
+            None
+        }
+    }
+
+    fn set_power_save(&mut self, power_save: bool) -> Option<Event> {
+        if power_save == self.power_save {
+            return None;
+        }
+        self.power_save = power_save;
+        Some(Event::PowerSave(power_save))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ChargeState::*;
+
+    const OK_VOLTAGE: MilliVolts = MilliVolts(3700);
+    const LOW_VOLTAGE: MilliVolts = MilliVolts(SHUTDOWN_VOLTAGE.0 - 1);
+
+    #[test]
+    fn warns_once_until_recharged_past_the_hysteresis() {
+        let mut monitor = LowBatteryMonitor::new();
+        assert_eq!(monitor.update(50, OK_VOLTAGE, Discharging), None);
+        assert_eq!(
+            monitor.update(WARNING_PERCENT, OK_VOLTAGE, Discharging),
+            Some(Event::Warning)
+        );
+        assert_eq!(
+            monitor.update(WARNING_PERCENT - 1, OK_VOLTAGE, Discharging),
+            None
+        );
+        // Wobbling around the threshold doesn't warn again
+        assert_eq!(
+            monitor.update(WARNING_PERCENT + 1, OK_VOLTAGE, Discharging),
+            None
+        );
+        assert_eq!(
+            monitor.update(WARNING_PERCENT, OK_VOLTAGE, Discharging),
+            None
+        );
+
+        let recovered = WARNING_PERCENT + HYSTERESIS_PERCENT;
+        assert_eq!(monitor.update(recovered, OK_VOLTAGE, Discharging), None);
+        assert_eq!(
+            monitor.update(WARNING_PERCENT, OK_VOLTAGE, Discharging),
+            Some(Event::Warning)
+        );
+    }
+
+    #[test]
+    fn power_save_with_hysteresis() {
+        let mut monitor = LowBatteryMonitor::new();
+        monitor.update(WARNING_PERCENT, OK_VOLTAGE, Discharging);
+        assert_eq!(
+            monitor.update(POWER_SAVE_PERCENT, OK_VOLTAGE, Discharging),
+            Some(Event::PowerSave(true))
+        );
+        assert!(monitor.is_power_save());
+        assert_eq!(
+            monitor.max_brightness(Brightness::L5),
+            POWER_SAVE_MAX_BRIGHTNESS
+        );
+        assert_eq!(monitor.max_brightness(Brightness::L1), Brightness::L1);
+        assert_eq!(
+            monitor.voltage_poll_interval_ms(1_000),
+            POWER_SAVE_VOLTAGE_POLL_INTERVAL_MS
+        );
+
+        let below_exit = POWER_SAVE_PERCENT + HYSTERESIS_PERCENT - 1;
+        assert_eq!(monitor.update(below_exit, OK_VOLTAGE, Discharging), None);
+        assert!(monitor.is_power_save());
+        assert_eq!(
+            monitor.update(below_exit + 1, OK_VOLTAGE, Discharging),
+            Some(Event::PowerSave(false))
+        );
+        assert_eq!(monitor.max_brightness(Brightness::L5), Brightness::L5);
+        assert_eq!(monitor.voltage_poll_interval_ms(1_000), 1_000);
+    }
+
+    #[test]
+    fn shutdown_takes_consecutive_low_readings() {
+        let mut monitor = LowBatteryMonitor::new();
+        monitor.update(WARNING_PERCENT, OK_VOLTAGE, Discharging);
+        monitor.update(POWER_SAVE_PERCENT, OK_VOLTAGE, Discharging);
+        for _ in 1..SHUTDOWN_READINGS {
+            assert_eq!(monitor.update(0, LOW_VOLTAGE, Discharging), None);
+        }
+        // A reading back above it starts the count over
+        assert_eq!(monitor.update(0, SHUTDOWN_VOLTAGE, Discharging), None);
+        for _ in 1..SHUTDOWN_READINGS {
+            assert_eq!(monitor.update(0, LOW_VOLTAGE, Discharging), None);
+        }
+        assert_eq!(
+            monitor.update(0, LOW_VOLTAGE, Discharging),
+            Some(Event::Shutdown)
+        );
+    }
+
+    #[test]
+    fn charger_resets_everything() {
+        let mut monitor = LowBatteryMonitor::new();
+        monitor.update(WARNING_PERCENT, OK_VOLTAGE, Discharging);
+        monitor.update(POWER_SAVE_PERCENT, OK_VOLTAGE, Discharging);
+        for _ in 1..SHUTDOWN_READINGS {
+            monitor.update(0, LOW_VOLTAGE, Discharging);
+        }
+
+        assert_eq!(
+            monitor.update(0, LOW_VOLTAGE, Charging),
+            Some(Event::PowerSave(false))
+        );
+        assert_eq!(monitor.update(0, LOW_VOLTAGE, Full), None);
+
+        // Unplugged again, starts from scratch
+        assert_eq!(
+            monitor.update(WARNING_PERCENT, OK_VOLTAGE, Discharging),
+            Some(Event::Warning)
+        );
+        for _ in 1..SHUTDOWN_READINGS {
+            assert_eq!(
+                monitor.update(WARNING_PERCENT, LOW_VOLTAGE, Discharging),
+                None
+            );
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Received notifications
+//!
+//! A bounded store of the most recent notifications, the oldest one is dropped
+//! when a new one arrives and the store is full.
+
+use chrono::NaiveDateTime;
+use core::fmt;
+use heapless::{String, Vec};
+
+/// Alert categories, values match the Bluetooth Alert Notification category IDs
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub enum Category {
+    #[default]
+    SimpleAlert,
+    Email,
+    News,
+    Call,
+    MissedCall,
+    Sms,
+    VoiceMail,
+    Schedule,
+    HighPriority,
+    InstantMessage,
+}
+
+impl Category {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Category::SimpleAlert,
+            1 => Category::Email,
+            2 => Category::News,
+            3 => Category::Call,
+            4 => Category::MissedCall,
+            5 => Category::Sms,
+            6 => Category::VoiceMail,
+            7 => Category::Schedule,
+            8 => Category::HighPriority,
+            9 => Category::InstantMessage,
+            _ => return None,
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Category::SimpleAlert => "Alert",
+            Category::Email => "Email",
+            Category::News => "News",
+            Category::Call => "Call",
+            Category::MissedCall => "Missed call",
+            Category::Sms => "SMS",
+            Category::VoiceMail => "Voicemail",
+            Category::Schedule => "Schedule",
+            Category::HighPriority => "Important",
+            Category::InstantMessage => "Message",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Notification {
+    pub category: Category,
+    /// When it was received
+    pub timestamp: NaiveDateTime,
+    pub title: String<{ Notification::TITLE_LEN }>,
+    pub body: String<{ Notification::BODY_LEN }>,
+}
+
+impl Notification {
+    /// Max title length in bytes
+    pub const TITLE_LEN: usize = 32;
+
+    /// Max body length in bytes
+    pub const BODY_LEN: usize = 160;
+
+    /// Longer title and body get truncated
+    pub fn new(category: Category, timestamp: NaiveDateTime, title: &str, body: &str) -> Self {
+        Notification {
+            category,
+            timestamp,
+            title: truncated(title),
+            body: truncated(body),
+        }
+    }
+}
+
+/// Copy of `s` cut at the last char boundary that fits
+pub fn truncated<const N: usize>(s: &str) -> String<N> {
+    let mut end = s.len().min(N);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut out = String::new();
+    // Always fits
+    out.push_str(&s[..end]).ok();
+    out
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct NotificationStore {
+    items: Vec<Notification, { NotificationStore::CAPACITY }>,
+    /// Slot of the oldest item once full
+    head: usize,
+    revision: u32,
+}
+
+impl Default for NotificationStore {
+    fn default() -> Self {
+        NotificationStore::new()
+    }
+}
+
+impl NotificationStore {
+    pub const CAPACITY: usize = 8;
+
+    pub const fn new() -> Self {
+        NotificationStore {
+            items: Vec::new(),
+            head: 0,
+            revision: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Changes whenever the contents change
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    /// Add a notification, dropping the oldest one if full
+    pub fn push(&mut self, notification: Notification) {
+        if let Err(notification) = self.items.push(notification) {
+            self.items[self.head] = notification;
+            self.head = (self.head + 1) % Self::CAPACITY;
+        }
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.head = 0;
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    /// Index 0 is the newest
+    pub fn get(&self, index: usize) -> Option<&Notification> {
+        let len = self.items.len();
+        if index >= len {
+            return None;
+        }
+        // Newest is the slot just before the oldest
+        let slot = (self.head + len - 1 - index) % len;
+        self.items.get(slot)
+    }
+
+    pub fn newest(&self) -> Option<&Notification> {
+        self.get(0)
+    }
+
+    /// Newest first
+    pub fn iter(&self) -> impl Iterator<Item = &Notification> + '_ {
+        (0..self.len()).filter_map(move |index| self.get(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use chrono::NaiveDate;
+    use std::{format, vec::Vec};
+
+    fn notification(n: usize) -> Notification {
+        let timestamp = NaiveDate::from_ymd(2021, 3, 1).and_hms(12, 0, n as u32);
+        Notification::new(Category::Sms, timestamp, &format!("#{}", n), "")
+    }
+
+    fn titles(store: &NotificationStore) -> Vec<&str> {
+        store.iter().map(|n| n.title.as_str()).collect()
+    }
+
+    #[test]
+    fn newest_first() {
+        let mut store = NotificationStore::new();
+        assert!(store.is_empty());
+        assert_eq!(store.newest(), None);
+        for n in 0..3 {
+            store.push(notification(n));
+        }
+        assert_eq!(store.len(), 3);
+        assert_eq!(titles(&store), ["#2", "#1", "#0"]);
+        assert_eq!(store.newest(), Some(&notification(2)));
+        assert_eq!(store.get(3), None);
+    }
+
+    #[test]
+    fn oldest_dropped_when_full() {
+        let mut store = NotificationStore::new();
+        for n in 0..NotificationStore::CAPACITY {
+            store.push(notification(n));
+        }
+        assert_eq!(
+            titles(&store),
+            ["#7", "#6", "#5", "#4", "#3", "#2", "#1", "#0"]
+        );
+        store.push(notification(8));
+        assert_eq!(
+            titles(&store),
+            ["#8", "#7", "#6", "#5", "#4", "#3", "#2", "#1"]
+        );
+
+        // Around the ring more than once
+        for n in 9..30 {
+            store.push(notification(n));
+        }
+        assert_eq!(store.len(), NotificationStore::CAPACITY);
+        let expected: Vec<_> = (22..30).rev().map(|n| format!("#{}", n)).collect();
+        assert_eq!(titles(&store), expected);
+    }
+
+    #[test]
+    fn clearing() {
+        let mut store = NotificationStore::new();
+        for n in 0..NotificationStore::CAPACITY + 3 {
+            store.push(notification(n));
+        }
+        let revision = store.revision();
+        store.clear();
+        assert!(store.is_empty());
+        assert_ne!(store.revision(), revision);
+        assert_eq!(store.iter().count(), 0);
+
+        // Fills up from scratch again
+        for n in 0..NotificationStore::CAPACITY + 1 {
+            store.push(notification(n));
+        }
+        assert_eq!(store.get(0).unwrap().title, "#8");
+        assert_eq!(store.get(7).unwrap().title, "#1");
+    }
+
+    #[test]
+    fn revision_changes_on_every_push() {
+        let mut store = NotificationStore::new();
+        let mut revisions = Vec::new();
+        for n in 0..2 * NotificationStore::CAPACITY {
+            store.push(notification(n));
+            revisions.push(store.revision());
+        }
+        revisions.dedup();
+        assert_eq!(revisions.len(), 2 * NotificationStore::CAPACITY);
+    }
+
+    #[test]
+    fn long_text_is_truncated() {
+        let title = "Ä".repeat(Notification::TITLE_LEN);
+        let body = "x".repeat(Notification::BODY_LEN + 10);
+        let n = Notification::new(
+            Category::Email,
+            NaiveDateTime::from_timestamp(0, 0),
+            &title,
+            &body,
+        );
+        // Cut at a char boundary, 'Ä' takes two bytes
+        assert_eq!(n.title.as_str(), &title[..Notification::TITLE_LEN]);
+        assert_eq!(n.body.len(), Notification::BODY_LEN);
+        assert_eq!(truncated::<3>("aÄb").as_str(), "aÄ");
+        assert_eq!(truncated::<2>("aÄb").as_str(), "a");
+    }
+
+    #[test]
+    fn categories() {
+        for id in 0..=9 {
+            assert_eq!(Category::from_u8(id).unwrap().as_u8(), id);
+        }
+        assert_eq!(Category::from_u8(10), None);
+        assert_eq!(format!("{}", Category::MissedCall), "Missed call");
+    }
+}
@@ -0,0 +1,383 @@
+//! Wear-leveled, CRC protected record log on NOR flash
+//!
+//! A region of whole erase sectors is split into fixed size slots. Every save appends a
+//! record to the next free slot, once a sector is full the next one (wrapping around the
+//! region) is erased and used. Loading scans the whole region and picks the valid record
+//! with the highest sequence number, so a write interrupted by power loss just leaves
+//! behind a slot with a bad CRC and the previous record is used instead.
+//!
+//! Slot layout (little-endian):
+//! * magic: u16
+//! * version: u8
+//! * payload length: u8
+//! * sequence number: u32
+//! * payload: [u8; MAX_PAYLOAD_SIZE]
+//! * crc32 of everything above: u32
+
+use crate::crc::Crc32;
+use embedded_storage::nor_flash::NorFlash;
+use err_derive::Error;
+
+#[derive(Debug, Error)]
+pub enum Error<E: core::fmt::Debug> {
+    #[error(display = "Flash error {:?}", _0)]
+    Flash(E),
+
+    #[error(display = "Payload too large")]
+    PayloadTooLarge,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct RecordInfo {
+    pub version: u8,
+    pub len: usize,
+    pub seq: u32,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RecordLog {
+    base: u32,
+    num_sectors: u32,
+    /// Slot index of the next append, None until the region has been scanned
+    next_slot: Option<u32>,
+    next_seq: u32,
+}
+
+const MAGIC: u16 = 0x5E7C;
+const ERASED: u8 = 0xFF;
+
+impl RecordLog {
+    pub const SLOT_SIZE: usize = 64;
+    pub const HEADER_SIZE: usize = 8;
+    pub const CRC_SIZE: usize = 4;
+    pub const MAX_PAYLOAD_SIZE: usize = Self::SLOT_SIZE - Self::HEADER_SIZE - Self::CRC_SIZE;
+
+    /// `base` must be erase sector aligned, the region must be at least 2 sectors so the
+    /// latest record survives erasing the next sector
+    pub const fn new(base: u32, num_sectors: u32) -> Self {
+        RecordLog {
+            base,
+            num_sectors,
+            next_slot: None,
+            next_seq: 0,
+        }
+    }
+
+    /// Read the most recent valid record into `payload`
+    pub fn load<F>(
+        &mut self,
+        flash: &mut F,
+        payload: &mut [u8],
+    ) -> Result<Option<RecordInfo>, Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: core::fmt::Debug,
+    {
+        let mut latest: Option<(u32, RecordInfo)> = None;
+        let mut slot_buf = [0_u8; Self::SLOT_SIZE];
+
+        for slot in 0..self.num_slots::<F>() {
+            flash
+                .read(self.slot_addr(slot), &mut slot_buf)
+                .map_err(Error::Flash)?;
+            if let Some(info) = Self::decode(&slot_buf) {
+                if latest.map(|(_, l)| info.seq > l.seq).unwrap_or(true) {
+                    let len = info.len.min(payload.len());
+                    payload[..len].copy_from_slice(&slot_buf[Self::HEADER_SIZE..][..len]);
+                    latest = Some((slot, info));
+                }
+            }
+        }
+
+        match latest {
+            Some((slot, info)) => {
+                // Anything written after the latest record in its sector is garbage from an
+                // interrupted write, appends continue after it
+                let last_used = self.last_used_slot_in_sector(flash, slot)?;
+                self.next_slot = Some((last_used + 1) % self.num_slots::<F>());
+                self.next_seq = info.seq.wrapping_add(1);
+                Ok(Some(info))
+            }
+            None => {
+                self.next_slot = Some(0);
+                self.next_seq = 0;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn append<F>(
+        &mut self,
+        flash: &mut F,
+        version: u8,
+        payload: &[u8],
+    ) -> Result<(), Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: core::fmt::Debug,
+    {
+        if payload.len() > Self::MAX_PAYLOAD_SIZE {
+            return Err(Error::PayloadTooLarge);
+        }
+        if self.next_slot.is_none() {
+            let mut scratch = [0_u8; Self::MAX_PAYLOAD_SIZE];
+            self.load(flash, &mut scratch)?;
+        }
+        let per_sector = self.slots_per_sector::<F>();
+        let mut slot = self.next_slot.unwrap_or(0);
+
+        let mut slot_buf = [0_u8; Self::SLOT_SIZE];
+        flash
+            .read(self.slot_addr(slot), &mut slot_buf)
+            .map_err(Error::Flash)?;
+        if !slot.is_multiple_of(per_sector) && !Self::is_erased(&slot_buf) {
+            // Never erase the sector holding the latest record, move on to the next one
+            slot = ((slot / per_sector + 1) * per_sector) % self.num_slots::<F>();
+        }
+        if slot.is_multiple_of(per_sector) {
+            let sector_addr = self.slot_addr(slot);
+            flash
+                .erase(sector_addr, sector_addr + F::ERASE_SIZE as u32)
+                .map_err(Error::Flash)?;
+        }
+
+        let seq = self.next_seq;
+        slot_buf.iter_mut().for_each(|b| *b = ERASED);
+        slot_buf[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        slot_buf[2] = version;
+        slot_buf[3] = payload.len() as u8;
+        slot_buf[4..8].copy_from_slice(&seq.to_le_bytes());
+        slot_buf[Self::HEADER_SIZE..][..payload.len()].copy_from_slice(payload);
+        let crc = Self::slot_crc(&slot_buf);
+        slot_buf[Self::SLOT_SIZE - Self::CRC_SIZE..].copy_from_slice(&crc.to_le_bytes());
+
+        flash
+            .write(self.slot_addr(slot), &slot_buf)
+            .map_err(Error::Flash)?;
+
+        self.next_slot = Some((slot + 1) % self.num_slots::<F>());
+        self.next_seq = seq.wrapping_add(1);
+
+        Ok(())
+    }
+
+    fn decode(slot_buf: &[u8; Self::SLOT_SIZE]) -> Option<RecordInfo> {
+        let magic = u16::from_le_bytes([slot_buf[0], slot_buf[1]]);
+        let len = slot_buf[3] as usize;
+        if magic != MAGIC || len > Self::MAX_PAYLOAD_SIZE {
+            return None;
+        }
+        let crc_bytes = &slot_buf[Self::SLOT_SIZE - Self::CRC_SIZE..];
+        let crc = u32::from_le_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+        if crc != Self::slot_crc(slot_buf) {
+            return None;
+        }
+        Some(RecordInfo {
+            version: slot_buf[2],
+            len,
+            seq: u32::from_le_bytes([slot_buf[4], slot_buf[5], slot_buf[6], slot_buf[7]]),
+        })
+    }
+
+    fn slot_crc(slot_buf: &[u8; Self::SLOT_SIZE]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&slot_buf[..Self::SLOT_SIZE - Self::CRC_SIZE]);
+        crc.finish()
+    }
+
+    fn last_used_slot_in_sector<F>(&self, flash: &mut F, slot: u32) -> Result<u32, Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: core::fmt::Debug,
+    {
+        let per_sector = self.slots_per_sector::<F>();
+        let sector_end = (slot / per_sector + 1) * per_sector;
+        let mut header = [0_u8; Self::HEADER_SIZE];
+        let mut last = slot;
+        for s in slot + 1..sector_end {
+            flash
+                .read(self.slot_addr(s), &mut header)
+                .map_err(Error::Flash)?;
+            if !Self::is_erased(&header) {
+                last = s;
+            }
+        }
+        Ok(last)
+    }
+
+    fn is_erased(bytes: &[u8]) -> bool {
+        bytes.iter().all(|b| *b == ERASED)
+    }
+
+    fn slots_per_sector<F: NorFlash>(&self) -> u32 {
+        (F::ERASE_SIZE / Self::SLOT_SIZE) as u32
+    }
+
+    fn num_slots<F: NorFlash>(&self) -> u32 {
+        self.slots_per_sector::<F>() * self.num_sectors
+    }
+
+    fn slot_addr(&self, slot: u32) -> u32 {
+        self.base + slot * Self::SLOT_SIZE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_flash::{self, RamFlash};
+
+    const SECTORS: u32 = 2;
+    const SLOTS: u32 = SECTORS * (RamFlash::ERASE_SIZE / RecordLog::SLOT_SIZE) as u32;
+
+    /// Region starts a sector in, to catch base address mix-ups
+    fn setup() -> (RamFlash, RecordLog) {
+        let flash = RamFlash::new(4 * RamFlash::ERASE_SIZE);
+        (flash, RecordLog::new(RamFlash::ERASE_SIZE as u32, SECTORS))
+    }
+
+    fn payload(n: u32) -> [u8; 4] {
+        n.to_le_bytes()
+    }
+
+    /// Load with a fresh log, like after a reset
+    fn load_fresh(flash: &mut RamFlash) -> Option<(RecordInfo, u32)> {
+        let mut log = RecordLog::new(RamFlash::ERASE_SIZE as u32, SECTORS);
+        let mut buf = [0; RecordLog::MAX_PAYLOAD_SIZE];
+        log.load(flash, &mut buf)
+            .unwrap()
+            .map(|info| (info, u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])))
+    }
+
+    #[test]
+    fn empty_region_has_no_record() {
+        let (mut flash, _) = setup();
+        assert_eq!(load_fresh(&mut flash), None);
+    }
+
+    #[test]
+    fn latest_record_is_loaded() {
+        let (mut flash, mut log) = setup();
+        for n in 0..5 {
+            log.append(&mut flash, 3, &payload(n)).unwrap();
+        }
+        let (info, value) = load_fresh(&mut flash).unwrap();
+        assert_eq!(
+            info,
+            RecordInfo {
+                version: 3,
+                len: 4,
+                seq: 4
+            }
+        );
+        assert_eq!(value, 4);
+        // Nothing outside the region is touched
+        assert!(flash.data[..RamFlash::ERASE_SIZE]
+            .iter()
+            .all(|b| *b == 0xFF));
+        assert!(flash.data[3 * RamFlash::ERASE_SIZE..]
+            .iter()
+            .all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn wraps_around_the_region() {
+        let (mut flash, mut log) = setup();
+        let records = 3 * SLOTS + 5;
+        for n in 0..records {
+            log.append(&mut flash, 1, &payload(n)).unwrap();
+            if n % 37 == 0 {
+                // Picks up where it left off after a reset too
+                log = RecordLog::new(RamFlash::ERASE_SIZE as u32, SECTORS);
+            }
+        }
+        let (info, value) = load_fresh(&mut flash).unwrap();
+        assert_eq!(value, records - 1);
+        assert_eq!(info.seq, records - 1);
+        // A sector is erased each time the log moves into it, and only then
+        let per_sector = SLOTS / SECTORS;
+        assert_eq!(flash.erases as u32, records.div_ceil(per_sector));
+    }
+
+    #[test]
+    fn torn_write_falls_back_to_the_previous_record() {
+        for cut in [1, RecordLog::HEADER_SIZE, RecordLog::SLOT_SIZE - 1] {
+            let (mut flash, mut log) = setup();
+            for n in 0..10 {
+                log.append(&mut flash, 1, &payload(n)).unwrap();
+            }
+            flash.cut_power_after(cut);
+            assert!(matches!(
+                log.append(&mut flash, 1, &payload(10)),
+                Err(Error::Flash(test_flash::Error::PowerLoss))
+            ));
+            flash.restore_power();
+            assert_eq!(load_fresh(&mut flash).unwrap().1, 9);
+
+            // The torn slot is skipped, not written over
+            let mut log = RecordLog::new(RamFlash::ERASE_SIZE as u32, SECTORS);
+            let mut buf = [0; RecordLog::MAX_PAYLOAD_SIZE];
+            log.load(&mut flash, &mut buf).unwrap();
+            log.append(&mut flash, 1, &payload(11)).unwrap();
+            let (info, value) = load_fresh(&mut flash).unwrap();
+            assert_eq!(value, 11);
+            assert_eq!(info.seq, 10);
+        }
+    }
+
+    #[test]
+    fn torn_erase_keeps_the_latest_record() {
+        let (mut flash, mut log) = setup();
+        // Fill the region, the next append erases the sector with the oldest records
+        for n in 0..SLOTS {
+            log.append(&mut flash, 1, &payload(n)).unwrap();
+        }
+        flash.cut_power_after(RamFlash::ERASE_SIZE / 2);
+        assert!(log.append(&mut flash, 1, &payload(SLOTS)).is_err());
+        flash.restore_power();
+        assert_eq!(load_fresh(&mut flash).unwrap().1, SLOTS - 1);
+
+        let mut log = RecordLog::new(RamFlash::ERASE_SIZE as u32, SECTORS);
+        log.append(&mut flash, 1, &payload(SLOTS)).unwrap();
+        assert_eq!(load_fresh(&mut flash).unwrap().1, SLOTS);
+    }
+
+    #[test]
+    fn bad_crc_in_the_newest_slot_is_ignored() {
+        let (mut flash, mut log) = setup();
+        for n in 0..3 {
+            log.append(&mut flash, 1, &payload(n)).unwrap();
+        }
+        // Flip a payload bit of the newest record
+        let newest = RamFlash::ERASE_SIZE + 2 * RecordLog::SLOT_SIZE;
+        flash.data[newest + RecordLog::HEADER_SIZE] ^= 0x01;
+        let (info, value) = load_fresh(&mut flash).unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(info.seq, 1);
+
+        // Corrupt CRC bytes are just as bad
+        flash.data[newest - 1] ^= 0x80;
+        assert_eq!(load_fresh(&mut flash).unwrap().1, 0);
+    }
+
+    #[test]
+    fn sequence_numbers_decide_not_slot_order() {
+        let (mut flash, mut log) = setup();
+        // Wrap so the newest record sits before older ones in the region
+        for n in 0..SLOTS + 2 {
+            log.append(&mut flash, 1, &payload(n)).unwrap();
+        }
+        assert_eq!(load_fresh(&mut flash).unwrap().1, SLOTS + 1);
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected() {
+        let (mut flash, mut log) = setup();
+        let big = [0; RecordLog::MAX_PAYLOAD_SIZE + 1];
+        assert!(matches!(
+            log.append(&mut flash, 1, &big),
+            Err(Error::PayloadTooLarge)
+        ));
+        assert_eq!(load_fresh(&mut flash), None);
+    }
+}
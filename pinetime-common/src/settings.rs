@@ -0,0 +1,264 @@
+//! User settings, persisted in a [`RecordLog`]
+//!
+//! Fields are encoded in declaration order. New fields are only ever appended, a record
+//! written by older firmware is shorter and the missing fields get their defaults.
+//! `Settings::VERSION` is only bumped for incompatible layout changes, older versions
+//! are then discarded in favor of the defaults.
+
+use crate::alarm::{Alarm, MAX_ALARMS};
+use crate::clock_drift::MAX_DRIFT_PPM;
+use crate::record_log::{self, RecordLog};
+use crate::time_zone::TimeZone;
+use crate::wrist_tilt::Sensitivity;
+use crate::Brightness;
+use core::fmt;
+use embedded_storage::nor_flash::NorFlash;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub enum TimeFormat {
+    /// 12-hour clock with AM/PM
+    #[default]
+    H12,
+    H24,
+}
+
+impl TimeFormat {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            TimeFormat::H12 => 0,
+            TimeFormat::H24 => 1,
+        }
+    }
+
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => TimeFormat::H12,
+            1 => TimeFormat::H24,
+            _ => return None,
+        }
+        .into()
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            TimeFormat::H12 => TimeFormat::H24,
+            TimeFormat::H24 => TimeFormat::H12,
+        }
+    }
+}
+
+impl fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TimeFormat::H12 => "12h",
+            TimeFormat::H24 => "24h",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Settings {
+    /// Backlight level the display ramps up to when woken
+    pub brightness: Brightness,
+    pub display_timeout_secs: u8,
+    pub screen_refresh_interval_ms: u16,
+    /// Time between backlight level steps when ramping on/off
+    pub backlight_ramp_ms: u16,
+    pub voltage_poll_interval_ms: u16,
+    pub time_format: TimeFormat,
+    /// Wake the display when the wrist is raised
+    pub wrist_raise: bool,
+    pub wrist_raise_sensitivity: Sensitivity,
+    pub alarms: [Alarm; MAX_ALARMS],
+    pub time_zone: TimeZone,
+    /// RTC crystal error learned from time syncs, positive if it runs fast
+    pub rtc_drift_ppm: i16,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            brightness: Brightness::brightest(),
+            display_timeout_secs: 5,
+            screen_refresh_interval_ms: 20,
+            backlight_ramp_ms: 50,
+            voltage_poll_interval_ms: 5000,
+            time_format: TimeFormat::default(),
+            wrist_raise: true,
+            wrist_raise_sensitivity: Sensitivity::default(),
+            alarms: [Alarm::default(); MAX_ALARMS],
+            time_zone: TimeZone::UTC,
+            rtc_drift_ppm: 0,
+        }
+    }
+}
+
+impl Settings {
+    pub const VERSION: u8 = 1;
+
+    pub const MAX_ENCODED_SIZE: usize = RecordLog::MAX_PAYLOAD_SIZE;
+
+    /// Returns the number of bytes used
+    pub fn encode(&self, buf: &mut [u8; Self::MAX_ENCODED_SIZE]) -> usize {
+        let mut w = Writer { buf, pos: 0 };
+        w.u8(self.brightness.as_u8());
+        w.u8(self.display_timeout_secs);
+        w.u16(self.screen_refresh_interval_ms);
+        w.u16(self.backlight_ramp_ms);
+        w.u16(self.voltage_poll_interval_ms);
+        w.u8(self.time_format.as_u8());
+        w.u8(self.wrist_raise as u8);
+        w.u8(self.wrist_raise_sensitivity.as_u8());
+        for alarm in self.alarms.iter() {
+            w.bytes(&alarm.to_bytes());
+        }
+        w.bytes(&self.time_zone.to_bytes());
+        w.u16(self.rtc_drift_ppm as u16);
+        w.pos
+    }
+
+    pub fn decode(bytes: &[u8]) -> Self {
+        let d = Settings::default();
+        let mut r = Reader { bytes, pos: 0 };
+        let mut settings = Settings {
+            brightness: r
+                .u8()
+                .and_then(Brightness::from_u8)
+                .filter(|b| *b != Brightness::Off)
+                .unwrap_or(d.brightness),
+            display_timeout_secs: r.u8().filter(|t| *t != 0).unwrap_or(d.display_timeout_secs),
+            screen_refresh_interval_ms: r
+                .u16()
+                .filter(|t| *t != 0)
+                .unwrap_or(d.screen_refresh_interval_ms),
+            backlight_ramp_ms: r.u16().unwrap_or(d.backlight_ramp_ms),
+            voltage_poll_interval_ms: r
+                .u16()
+                .filter(|t| *t != 0)
+                .unwrap_or(d.voltage_poll_interval_ms),
+            time_format: r
+                .u8()
+                .and_then(TimeFormat::from_u8)
+                .unwrap_or(d.time_format),
+            wrist_raise: r.u8().map(|v| v != 0).unwrap_or(d.wrist_raise),
+            wrist_raise_sensitivity: r
+                .u8()
+                .and_then(Sensitivity::from_u8)
+                .unwrap_or(d.wrist_raise_sensitivity),
+            alarms: d.alarms,
+            time_zone: d.time_zone,
+            rtc_drift_ppm: d.rtc_drift_ppm,
+        };
+        for alarm in settings.alarms.iter_mut() {
+            if let Some(a) = r.bytes(Alarm::ENCODED_SIZE).and_then(Alarm::from_bytes) {
+                *alarm = a;
+            }
+        }
+        if let Some(tz) = r
+            .bytes(TimeZone::ENCODED_SIZE)
+            .and_then(TimeZone::from_bytes)
+        {
+            settings.time_zone = tz;
+        }
+        if let Some(ppm) = r
+            .u16()
+            .map(|p| p as i16)
+            .filter(|p| (p.abs() as i32) <= MAX_DRIFT_PPM)
+        {
+            settings.rtc_drift_ppm = ppm;
+        }
+        settings
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, v: u8) {
+        self.buf[self.pos] = v;
+        self.pos += 1;
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.buf[self.pos..self.pos + v.len()].copy_from_slice(v);
+        self.pos += v.len();
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let v = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(v)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b = self.bytes(2)?;
+        Some(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let b = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(b)
+    }
+}
+
+/// Loads and saves [`Settings`] records in a region of flash
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SettingsStore {
+    log: RecordLog,
+}
+
+impl SettingsStore {
+    pub const fn new(base: u32, num_sectors: u32) -> Self {
+        SettingsStore {
+            log: RecordLog::new(base, num_sectors),
+        }
+    }
+
+    /// Most recently saved settings, None if there are none (or they're incompatible)
+    pub fn load<F>(
+        &mut self,
+        flash: &mut F,
+    ) -> Result<Option<Settings>, record_log::Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: core::fmt::Debug,
+    {
+        let mut buf = [0_u8; Settings::MAX_ENCODED_SIZE];
+        Ok(match self.log.load(flash, &mut buf)? {
+            Some(info) if info.version == Settings::VERSION => {
+                Some(Settings::decode(&buf[..info.len]))
+            }
+            _ => None,
+        })
+    }
+
+    pub fn save<F>(
+        &mut self,
+        flash: &mut F,
+        settings: &Settings,
+    ) -> Result<(), record_log::Error<F::Error>>
+    where
+        F: NorFlash,
+        F::Error: core::fmt::Debug,
+    {
+        let mut buf = [0_u8; Settings::MAX_ENCODED_SIZE];
+        let len = settings.encode(&mut buf);
+        self.log.append(flash, Settings::VERSION, &buf[..len])
+    }
+}
@@ -0,0 +1,203 @@
+//! Stopwatch with laps
+//!
+//! Works off timestamps from a free-running millisecond clock instead of counting
+//! ticks, so it keeps time while nothing is looking at it.
+//! Timestamps are allowed to wrap, only differences between them are used.
+
+use heapless::Deque;
+
+/// Most recent laps kept, older ones are dropped
+pub const MAX_LAPS: usize = 20;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Control {
+    /// Start if stopped, stop if running
+    StartStop,
+    /// Lap if running, reset if stopped
+    LapReset,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct Lap {
+    /// 1 is the first lap since the reset
+    pub number: u16,
+    pub duration_ms: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Stopwatch {
+    /// Timestamp of the latest start while running
+    started_at: Option<u32>,
+    /// Time counted up to the latest start
+    accumulated_ms: u32,
+    /// Elapsed time at the latest lap
+    last_split_ms: u32,
+    lap_count: u16,
+    /// Lap durations, oldest first
+    laps: Deque<u32, MAX_LAPS>,
+}
+
+// The deque doesn't implement it in this heapless version
+impl PartialEq for Stopwatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.started_at == other.started_at
+            && self.accumulated_ms == other.accumulated_ms
+            && self.last_split_ms == other.last_split_ms
+            && self.lap_count == other.lap_count
+            && self.laps.iter().eq(other.laps.iter())
+    }
+}
+
+impl Eq for Stopwatch {}
+
+impl Stopwatch {
+    pub const fn new() -> Self {
+        Stopwatch {
+            started_at: None,
+            accumulated_ms: 0,
+            last_split_ms: 0,
+            lap_count: 0,
+            laps: Deque::new(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// True if stopped at zero
+    pub fn is_reset(&self) -> bool {
+        !self.is_running() && self.accumulated_ms == 0
+    }
+
+    pub fn elapsed_ms(&self, now_ms: u32) -> u32 {
+        match self.started_at {
+            Some(t) => self.accumulated_ms.saturating_add(now_ms.wrapping_sub(t)),
+            None => self.accumulated_ms,
+        }
+    }
+
+    pub fn lap_count(&self) -> u16 {
+        self.lap_count
+    }
+
+    /// Laps kept, newest first
+    pub fn laps(&self) -> impl Iterator<Item = Lap> + '_ {
+        let first_number = self.lap_count - self.laps.len() as u16 + 1;
+        self.laps
+            .iter()
+            .enumerate()
+            .rev()
+            .map(move |(index, &duration_ms)| Lap {
+                number: first_number + index as u16,
+                duration_ms,
+            })
+    }
+
+    pub fn control(&mut self, control: Control, now_ms: u32) {
+        match (control, self.started_at) {
+            (Control::StartStop, Some(_)) => {
+                self.accumulated_ms = self.elapsed_ms(now_ms);
+                self.started_at = None;
+            }
+            (Control::StartStop, None) => self.started_at = Some(now_ms),
+            (Control::LapReset, Some(_)) => self.lap(now_ms),
+            (Control::LapReset, None) => *self = Self::new(),
+        }
+    }
+
+    fn lap(&mut self, now_ms: u32) {
+        let elapsed_ms = self.elapsed_ms(now_ms);
+        if self.laps.is_full() {
+            self.laps.pop_front();
+        }
+        // Can't fail, made room above
+        self.laps.push_back(elapsed_ms - self.last_split_ms).ok();
+        self.last_split_ms = elapsed_ms;
+        self.lap_count = self.lap_count.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn laps(stopwatch: &Stopwatch) -> Vec<(u16, u32)> {
+        stopwatch
+            .laps()
+            .map(|lap| (lap.number, lap.duration_ms))
+            .collect()
+    }
+
+    #[test]
+    fn start_stop_resumes() {
+        let mut sw = Stopwatch::new();
+        assert!(sw.is_reset());
+        assert_eq!(sw.elapsed_ms(1_000), 0);
+
+        sw.control(Control::StartStop, 1_000);
+        assert!(sw.is_running());
+        assert!(!sw.is_reset());
+        assert_eq!(sw.elapsed_ms(1_250), 250);
+
+        sw.control(Control::StartStop, 1_500);
+        assert!(!sw.is_running());
+        assert_eq!(sw.elapsed_ms(9_000), 500);
+
+        // Time stopped doesn't count
+        sw.control(Control::StartStop, 10_000);
+        assert_eq!(sw.elapsed_ms(10_100), 600);
+    }
+
+    #[test]
+    fn laps_and_reset() {
+        let mut sw = Stopwatch::new();
+        sw.control(Control::StartStop, 0);
+        sw.control(Control::LapReset, 1_000);
+        sw.control(Control::LapReset, 3_500);
+        // Stopped time isn't part of the lap
+        sw.control(Control::StartStop, 4_000);
+        sw.control(Control::StartStop, 10_000);
+        sw.control(Control::LapReset, 10_200);
+        assert_eq!(sw.lap_count(), 3);
+        assert_eq!(laps(&sw), [(3, 700), (2, 2_500), (1, 1_000)]);
+
+        // Lap/reset only resets while stopped
+        sw.control(Control::StartStop, 11_000);
+        assert_eq!(sw.elapsed_ms(11_000), 5_000);
+        sw.control(Control::LapReset, 12_000);
+        assert!(sw.is_reset());
+        assert_eq!(sw.lap_count(), 0);
+        assert_eq!(laps(&sw), []);
+        assert_eq!(sw.elapsed_ms(20_000), 0);
+    }
+
+    #[test]
+    fn lap_overflow_drops_the_oldest() {
+        let mut sw = Stopwatch::new();
+        sw.control(Control::StartStop, 0);
+        let total = MAX_LAPS as u32 + 5;
+        for n in 1..=total {
+            // Lap n lasts n ms
+            sw.control(Control::LapReset, n * (n + 1) / 2);
+        }
+        assert_eq!(sw.lap_count(), total as u16);
+        let laps = laps(&sw);
+        assert_eq!(laps.len(), MAX_LAPS);
+        assert_eq!(laps[0], (total as u16, total));
+        assert_eq!(laps[MAX_LAPS - 1], (6, 6));
+        assert!(laps.iter().all(|(number, ms)| *number as u32 == *ms));
+    }
+
+    #[test]
+    fn clock_wrap() {
+        let mut sw = Stopwatch::new();
+        sw.control(Control::StartStop, u32::MAX - 499);
+        sw.control(Control::LapReset, 500);
+        assert_eq!(sw.elapsed_ms(1_000), 1_500);
+        assert_eq!(laps(&sw), [(1, 1_000)]);
+    }
+}
@@ -1,5 +1,17 @@
+use crate::time_zone::TimeZone;
 use chrono::NaiveDateTime;
 
 pub trait SystemTimeExt {
-    fn date_time(&self) -> &NaiveDateTime;
+    /// The system clock, always in UTC, with millisecond resolution
+    fn utc(&self) -> &NaiveDateTime;
+
+    /// Milliseconds since boot, unaffected by setting the clock
+    fn uptime_ms(&self) -> u64;
+
+    fn time_zone(&self) -> &TimeZone;
+
+    /// Wall clock time, what's shown and what the alarms go by
+    fn local(&self) -> NaiveDateTime {
+        self.time_zone().to_local(self.utc())
+    }
 }
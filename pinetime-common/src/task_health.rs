@@ -0,0 +1,155 @@
+//! Keeps track of whether the supervised tasks are still running
+//!
+//! Each supervised task checks in every time it runs, the watchdog is only pet while none
+//! of them has gone longer than its deadline without checking in. A hung task then ends
+//! in a watchdog reset instead of a frozen watch.
+//!
+//! Check-ins are atomic, so tasks at any priority can share the registry without locking.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Free-running milliseconds, wraps
+pub trait TimeSource {
+    fn now_ms(&self) -> u32;
+}
+
+pub struct TaskHealth<K, T, const N: usize> {
+    time: T,
+    /// Each task and how long it can go without checking in, in milliseconds
+    tasks: [(K, u32); N],
+    check_ins_ms: [AtomicU32; N],
+}
+
+impl<K, T, const N: usize> TaskHealth<K, T, N>
+where
+    K: Copy + Eq,
+    T: TimeSource,
+{
+    /// All tasks start out checked in at 0 ms, so this doesn't need the time source to be
+    /// running yet
+    pub fn new(time: T, tasks: [(K, u32); N]) -> Self {
+        TaskHealth {
+            time,
+            tasks,
+            check_ins_ms: [(); N].map(|_| AtomicU32::new(0)),
+        }
+    }
+
+    /// Tasks that aren't supervised are ignored
+    pub fn check_in(&self, task: K) {
+        if let Some(index) = self.tasks.iter().position(|(k, _)| *k == task) {
+            self.check_ins_ms[index].store(self.time.now_ms(), Ordering::Relaxed);
+        }
+    }
+
+    /// First task that's gone longer than its deadline without checking in
+    ///
+    /// Compared as signed differences so the time source wrapping doesn't matter, and a
+    /// check-in from a task preempting this one after reading the time isn't taken as
+    /// ages ago. Deadlines have to be under `i32::MAX` ms.
+    pub fn overdue(&self) -> Option<K> {
+        let now_ms = self.time.now_ms();
+        self.tasks
+            .iter()
+            .zip(self.check_ins_ms.iter())
+            .find(|((_, deadline_ms), check_in_ms)| {
+                let since_ms = now_ms.wrapping_sub(check_in_ms.load(Ordering::Relaxed)) as i32;
+                since_ms > *deadline_ms as i32
+            })
+            .map(|((task, _), _)| *task)
+    }
+
+    pub fn all_checked_in(&self) -> bool {
+        self.overdue().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Task {
+        Draw,
+        Clock,
+        Unsupervised,
+    }
+
+    struct FakeTime<'a>(&'a Cell<u32>);
+
+    impl<'a> TimeSource for FakeTime<'a> {
+        fn now_ms(&self) -> u32 {
+            self.0.get()
+        }
+    }
+
+    fn health(now: &Cell<u32>) -> TaskHealth<Task, FakeTime<'_>, 2> {
+        TaskHealth::new(FakeTime(now), [(Task::Draw, 2_000), (Task::Clock, 2_500)])
+    }
+
+    #[test]
+    fn missed_deadline() {
+        let now = Cell::new(0);
+        let health = health(&now);
+        assert!(health.all_checked_in());
+
+        now.set(2_000);
+        assert_eq!(health.overdue(), None);
+        now.set(2_001);
+        assert_eq!(health.overdue(), Some(Task::Draw));
+
+        health.check_in(Task::Draw);
+        now.set(2_501);
+        assert_eq!(health.overdue(), Some(Task::Clock));
+        // Unsupervised tasks don't count for anything
+        health.check_in(Task::Unsupervised);
+        assert_eq!(health.overdue(), Some(Task::Clock));
+    }
+
+    #[test]
+    fn late_check_in_recovers() {
+        let now = Cell::new(0);
+        let health = health(&now);
+        now.set(10_000);
+        assert_eq!(health.overdue(), Some(Task::Draw));
+        health.check_in(Task::Draw);
+        assert_eq!(health.overdue(), Some(Task::Clock));
+        health.check_in(Task::Clock);
+        assert!(health.all_checked_in());
+        now.set(11_999);
+        assert!(health.all_checked_in());
+    }
+
+    #[test]
+    fn check_in_after_reading_the_time_is_not_overdue() {
+        let now = Cell::new(5_000);
+        let health = health(&now);
+        health.check_in(Task::Draw);
+        health.check_in(Task::Clock);
+        // A preempting task checked in at 5000 ms, overdue() had read 4999 ms
+        now.set(4_999);
+        assert!(health.all_checked_in());
+    }
+
+    #[test]
+    fn timer_wrap() {
+        let now = Cell::new(u32::MAX - 1_000);
+        let health = health(&now);
+        health.check_in(Task::Draw);
+        health.check_in(Task::Clock);
+
+        now.set(500);
+        assert!(health.all_checked_in());
+        now.set(999);
+        assert!(health.all_checked_in());
+        now.set(1_000);
+        assert_eq!(health.overdue(), Some(Task::Draw));
+        health.check_in(Task::Draw);
+        now.set(1_500);
+        assert_eq!(health.overdue(), Some(Task::Clock));
+        health.check_in(Task::Clock);
+        now.set(3_000);
+        assert!(health.all_checked_in());
+    }
+}
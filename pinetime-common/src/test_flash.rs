@@ -0,0 +1,114 @@
+//! NOR flash in RAM for the host tests, with simulated power loss
+//!
+//! Writes can only clear bits, like the real thing. After [`RamFlash::cut_power_after`]
+//! bytes have been written or erased, the operation in progress stops half way and every
+//! later one fails, until [`RamFlash::restore_power`].
+
+extern crate std;
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use std::{vec, vec::Vec};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error {
+    OutOfBounds,
+    NotAligned,
+    PowerLoss,
+}
+
+pub struct RamFlash {
+    pub data: Vec<u8>,
+    /// Bytes left to write or erase before the power goes out
+    budget: Option<usize>,
+    pub erases: usize,
+}
+
+impl RamFlash {
+    pub const ERASED: u8 = 0xFF;
+
+    pub fn new(size: usize) -> Self {
+        RamFlash {
+            data: vec![Self::ERASED; size],
+            budget: None,
+            erases: 0,
+        }
+    }
+
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.budget = Some(bytes);
+    }
+
+    pub fn restore_power(&mut self) {
+        self.budget = None;
+    }
+
+    /// Spend the budget on up to `len` bytes, how many can be done
+    fn spend(&mut self, len: usize) -> usize {
+        match &mut self.budget {
+            Some(budget) => {
+                let n = len.min(*budget);
+                *budget -= n;
+                n
+            }
+            None => len,
+        }
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<(usize, usize), Error> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() => Ok((start, end)),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+}
+
+impl ReadNorFlash for RamFlash {
+    type Error = Error;
+
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let (start, end) = self.check(offset, bytes.len())?;
+        bytes.copy_from_slice(&self.data[start..end]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !(from as usize).is_multiple_of(Self::ERASE_SIZE)
+            || !(to as usize).is_multiple_of(Self::ERASE_SIZE)
+        {
+            return Err(Error::NotAligned);
+        }
+        let (start, end) = self.check(from, to.saturating_sub(from) as usize)?;
+        let done = self.spend(end - start);
+        self.data[start..start + done].fill(Self::ERASED);
+        if done < end - start {
+            return Err(Error::PowerLoss);
+        }
+        self.erases += (end - start) / Self::ERASE_SIZE;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let (start, _) = self.check(offset, bytes.len())?;
+        let done = self.spend(bytes.len());
+        for (d, b) in self.data[start..].iter_mut().zip(&bytes[..done]) {
+            *d &= *b;
+        }
+        if done < bytes.len() {
+            return Err(Error::PowerLoss);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+//! Text layout helpers for the monospace fonts
+
+/// Split `text` into lines of at most `max_cols` chars
+///
+/// Lines are broken at spaces where possible, words longer than a line are split.
+/// Explicit newlines are kept. Trailing spaces are dropped from each line.
+pub fn wrap_lines(text: &str, max_cols: usize) -> WrapLines<'_> {
+    WrapLines {
+        text,
+        max_cols: max_cols.max(1),
+        done: false,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WrapLines<'a> {
+    text: &'a str,
+    max_cols: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for WrapLines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let text = self.text;
+        let mut last_space = None;
+        // End of this line and start of the next one, and whether it was wrapped
+        let mut split = None;
+        for (cols, (i, c)) in text.char_indices().enumerate() {
+            if c == '\n' {
+                split = Some((i, i + 1, false));
+                break;
+            }
+            if cols == self.max_cols {
+                split = Some(match (c, last_space) {
+                    (' ', _) => (i, i + 1, true),
+                    (_, Some(s)) => (s, s + 1, true),
+                    (_, None) => (i, i, true),
+                });
+                break;
+            }
+            if c == ' ' {
+                last_space = Some(i);
+            }
+        }
+
+        let line = match split {
+            Some((end, next, wrapped)) => {
+                let rest = &text[next..];
+                self.text = if wrapped {
+                    rest.trim_start_matches(' ')
+                } else {
+                    rest
+                };
+                if wrapped && self.text.is_empty() {
+                    self.done = true;
+                }
+                &text[..end]
+            }
+            None => {
+                self.done = true;
+                text
+            }
+        };
+        Some(line.trim_end_matches(' '))
+    }
+}
@@ -0,0 +1,194 @@
+//! UTC offset and daylight saving time rules
+//!
+//! The system clock is kept in UTC, the time zone turns it into the local time that's
+//! shown and that the alarms go by.
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use core::fmt;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub enum DstRule {
+    /// The offset is fixed
+    #[default]
+    None,
+    /// European Union, last Sunday of March to the last Sunday of October at 01:00 UTC
+    Eu,
+    /// United States and Canada, second Sunday of March to the first Sunday of
+    /// November at 02:00 local time
+    Us,
+}
+
+impl DstRule {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            DstRule::None => 0,
+            DstRule::Eu => 1,
+            DstRule::Us => 2,
+        }
+    }
+
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => DstRule::None,
+            1 => DstRule::Eu,
+            2 => DstRule::Us,
+            _ => return None,
+        }
+        .into()
+    }
+
+    /// How far clocks go forward while daylight saving time is in effect
+    pub fn offset(self) -> Duration {
+        match self {
+            DstRule::None => Duration::zero(),
+            DstRule::Eu | DstRule::Us => Duration::hours(1),
+        }
+    }
+
+    /// Next rule, wrapping around
+    pub fn cycled(self) -> Self {
+        match self {
+            DstRule::None => DstRule::Eu,
+            DstRule::Eu => DstRule::Us,
+            DstRule::Us => DstRule::None,
+        }
+    }
+}
+
+impl fmt::Display for DstRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DstRule::None => "Off",
+            DstRule::Eu => "EU",
+            DstRule::Us => "US",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct TimeZone {
+    /// Standard time offset from UTC
+    pub utc_offset_minutes: i16,
+    pub dst: DstRule,
+}
+
+impl TimeZone {
+    pub const UTC: TimeZone = TimeZone {
+        utc_offset_minutes: 0,
+        dst: DstRule::None,
+    };
+
+    pub const MIN_OFFSET_MINUTES: i16 = -12 * 60;
+    pub const MAX_OFFSET_MINUTES: i16 = 14 * 60;
+
+    pub const ENCODED_SIZE: usize = 3;
+
+    pub fn new(utc_offset_minutes: i16, dst: DstRule) -> Self {
+        TimeZone {
+            utc_offset_minutes: utc_offset_minutes
+                .clamp(Self::MIN_OFFSET_MINUTES, Self::MAX_OFFSET_MINUTES),
+            dst,
+        }
+    }
+
+    /// True if daylight saving time is in effect at `utc`
+    pub fn is_dst(&self, utc: &NaiveDateTime) -> bool {
+        let standard = self.standard_offset();
+        // The transitions aren't anywhere near new year, the UTC year will do
+        let year = utc.year();
+        let (start, end) = match self.dst {
+            DstRule::None => return false,
+            DstRule::Eu => (
+                last_sunday(year, 3).and_hms(1, 0, 0),
+                last_sunday(year, 10).and_hms(1, 0, 0),
+            ),
+            DstRule::Us => (
+                nth_sunday(year, 3, 2).and_hms(2, 0, 0) - standard,
+                // 02:00 daylight time
+                nth_sunday(year, 11, 1).and_hms(2, 0, 0) - standard - self.dst.offset(),
+            ),
+        };
+        start <= *utc && *utc < end
+    }
+
+    /// Offset from UTC at `utc`, including DST
+    pub fn offset(&self, utc: &NaiveDateTime) -> Duration {
+        self.standard_offset() + self.dst_offset(utc)
+    }
+
+    /// Daylight saving part of the offset at `utc`, zero outside DST
+    pub fn dst_offset(&self, utc: &NaiveDateTime) -> Duration {
+        if self.is_dst(utc) {
+            self.dst.offset()
+        } else {
+            Duration::zero()
+        }
+    }
+
+    pub fn to_local(&self, utc: &NaiveDateTime) -> NaiveDateTime {
+        *utc + self.offset(utc)
+    }
+
+    /// Local times skipped when DST starts are taken as standard time, local times
+    /// repeated when DST ends as the first (DST) occurrence
+    pub fn to_utc(&self, local: &NaiveDateTime) -> NaiveDateTime {
+        let standard = *local - self.standard_offset();
+        let dst = standard - self.dst.offset();
+        if self.is_dst(&dst) {
+            dst
+        } else {
+            standard
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_SIZE] {
+        let offset = self.utc_offset_minutes.to_le_bytes();
+        [offset[0], offset[1], self.dst.as_u8()]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match *bytes {
+            [o0, o1, dst, ..] => {
+                let offset = i16::from_le_bytes([o0, o1]);
+                if !(Self::MIN_OFFSET_MINUTES..=Self::MAX_OFFSET_MINUTES).contains(&offset) {
+                    return None;
+                }
+                Some(TimeZone::new(offset, DstRule::from_u8(dst)?))
+            }
+            _ => None,
+        }
+    }
+
+    fn standard_offset(&self) -> Duration {
+        Duration::minutes(self.utc_offset_minutes as i64)
+    }
+}
+
+/// Formats the standard offset like "+5:30", "-8:00"
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.utc_offset_minutes < 0 {
+            '-'
+        } else {
+            '+'
+        };
+        let minutes = self.utc_offset_minutes.unsigned_abs();
+        write!(f, "{}{}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+fn last_sunday(year: i32, month: u32) -> NaiveDate {
+    let next_month = if month == 12 {
+        NaiveDate::from_ymd(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(year, month + 1, 1)
+    };
+    let last_day = next_month.pred();
+    last_day - Duration::days(last_day.weekday().num_days_from_sunday() as i64)
+}
+
+/// `n` starts at 1 for the first Sunday of the month
+fn nth_sunday(year: i32, month: u32, n: u32) -> NaiveDate {
+    NaiveDate::from_weekday_of_month(year, month, Weekday::Sun, n as u8)
+}
@@ -0,0 +1,318 @@
+//! Vibration patterns and the player that steps through them
+//!
+//! The player doesn't know about timers, it hands out [`Step`]s and the caller is
+//! expected to switch the motor accordingly and call [`Player::advance`] once the
+//! step's duration has elapsed.
+//!
+//! Every started pattern gets a new generation, steps scheduled for a pattern that
+//! has since been replaced or stopped are ignored.
+
+/// Higher priority patterns interrupt lower ones, lower ones are dropped while a
+/// higher one is playing
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Repeat {
+    Times(u8),
+    /// Until stopped or interrupted
+    Forever,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Pattern {
+    /// Alternating on and off durations in milliseconds, starting with on
+    pub durations_ms: &'static [u16],
+    pub repeat: Repeat,
+    pub priority: Priority,
+}
+
+impl Pattern {
+    /// Power plugged in or removed
+    pub const CHARGING: Pattern = Pattern {
+        durations_ms: &[30],
+        repeat: Repeat::Times(1),
+        priority: Priority::Low,
+    };
+
+    /// Battery getting low, see [`crate::low_battery`]
+    pub const LOW_BATTERY: Pattern = Pattern {
+        durations_ms: &[300, 150, 300],
+        repeat: Repeat::Times(1),
+        priority: Priority::Normal,
+    };
+
+    pub const NOTIFICATION: Pattern = Pattern {
+        durations_ms: &[80, 100, 80],
+        repeat: Repeat::Times(1),
+        priority: Priority::Normal,
+    };
+
+    pub const TIMER: Pattern = Pattern {
+        durations_ms: &[150, 100, 150, 100, 150, 800],
+        repeat: Repeat::Times(3),
+        priority: Priority::High,
+    };
+
+    pub const ALARM: Pattern = Pattern {
+        durations_ms: &[400, 200, 400, 1000],
+        repeat: Repeat::Forever,
+        priority: Priority::High,
+    };
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Step {
+    pub motor_on: bool,
+    pub duration_ms: u16,
+    /// Pass back to [`Player::advance`]
+    pub generation: u8,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Command {
+    /// Set the motor and advance after the step's duration
+    Step(Step),
+    /// Done, turn the motor off
+    Off,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Player {
+    pattern: Option<Pattern>,
+    index: usize,
+    /// Completed plays of the pattern
+    plays: u8,
+    generation: u8,
+}
+
+impl Player {
+    pub const fn new() -> Self {
+        Player {
+            pattern: None,
+            index: 0,
+            plays: 0,
+            generation: 0,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.pattern.is_some()
+    }
+
+    /// Priority of the pattern that's playing
+    pub fn priority(&self) -> Option<Priority> {
+        self.pattern.map(|p| p.priority)
+    }
+
+    /// Start `pattern` from the beginning, replacing the current one.
+    /// Returns None (and keeps playing) if a higher priority pattern is playing.
+    pub fn play(&mut self, pattern: Pattern) -> Option<Command> {
+        if matches!(self.priority(), Some(p) if p > pattern.priority) {
+            return None;
+        }
+        self.generation = self.generation.wrapping_add(1);
+        self.pattern = Some(pattern);
+        self.index = 0;
+        self.plays = 0;
+        Some(self.command())
+    }
+
+    /// Stop whatever is playing, the motor should be turned off
+    pub fn stop(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.pattern = None;
+    }
+
+    /// Move on to the next step once the previous one has elapsed.
+    /// Returns None if `generation` is from a pattern that was replaced or stopped.
+    pub fn advance(&mut self, generation: u8) -> Option<Command> {
+        if generation != self.generation || self.pattern.is_none() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.command())
+    }
+
+    fn command(&mut self) -> Command {
+        let pattern = match self.pattern {
+            Some(p) if !p.durations_ms.is_empty() => p,
+            _ => {
+                self.stop();
+                return Command::Off;
+            }
+        };
+        let len = pattern.durations_ms.len();
+        if self.index >= len {
+            self.index = 0;
+            self.plays = self.plays.saturating_add(1);
+        }
+        let last_play = match pattern.repeat {
+            Repeat::Times(n) if self.plays >= n => {
+                self.stop();
+                return Command::Off;
+            }
+            Repeat::Times(n) => self.plays + 1 == n,
+            Repeat::Forever => false,
+        };
+        let motor_on = self.index.is_multiple_of(2);
+        // A trailing off step of the last play doesn't need waiting for
+        if last_play && !motor_on && self.index == len - 1 {
+            self.stop();
+            return Command::Off;
+        }
+        Command::Step(Step {
+            motor_on,
+            duration_ms: pattern.durations_ms[self.index],
+            generation: self.generation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    /// Motor on/off and duration of each step until the player is done, at most `limit`
+    fn run(player: &mut Player, first: Option<Command>, limit: usize) -> Vec<(bool, u16)> {
+        let mut steps = Vec::new();
+        let mut cmd = first;
+        while let Some(Command::Step(step)) = cmd {
+            steps.push((step.motor_on, step.duration_ms));
+            if steps.len() == limit {
+                break;
+            }
+            cmd = player.advance(step.generation);
+        }
+        steps
+    }
+
+    fn step(cmd: Option<Command>) -> Step {
+        match cmd {
+            Some(Command::Step(step)) => step,
+            other => panic!("expected a step, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn steps_in_order() {
+        let mut player = Player::new();
+        let first = player.play(Pattern::NOTIFICATION);
+        assert_eq!(
+            run(&mut player, first, 100),
+            [(true, 80), (false, 100), (true, 80)]
+        );
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn repeats_without_the_last_trailing_pause() {
+        let mut player = Player::new();
+        let first = player.play(Pattern::TIMER);
+        let steps = run(&mut player, first, 100);
+        let pattern = Pattern::TIMER.durations_ms;
+        assert_eq!(steps.len(), 3 * pattern.len() - 1);
+        for (i, (motor_on, duration)) in steps.iter().enumerate() {
+            assert_eq!(*motor_on, i % 2 == 0);
+            assert_eq!(*duration, pattern[i % pattern.len()]);
+        }
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn forever_until_stopped() {
+        let mut player = Player::new();
+        let first = player.play(Pattern::ALARM);
+        let steps = run(&mut player, first, 1000);
+        assert_eq!(steps.len(), 1000);
+        assert!(player.is_playing());
+
+        let generation = step(player.advance(player.generation)).generation;
+        player.stop();
+        assert!(!player.is_playing());
+        // The step scheduled before the stop comes in late
+        assert_eq!(player.advance(generation), None);
+    }
+
+    #[test]
+    fn higher_priority_interrupts() {
+        let mut player = Player::new();
+        let notification = step(player.play(Pattern::NOTIFICATION));
+        let alarm = step(player.play(Pattern::ALARM));
+        assert_eq!(player.priority(), Some(Priority::High));
+        // The notification's pending step is ignored, the alarm carries on
+        assert_eq!(player.advance(notification.generation), None);
+        assert_eq!(
+            step(player.advance(alarm.generation)).duration_ms,
+            Pattern::ALARM.durations_ms[1]
+        );
+    }
+
+    #[test]
+    fn lower_priority_is_dropped() {
+        let mut player = Player::new();
+        let timer = step(player.play(Pattern::TIMER));
+        assert_eq!(player.play(Pattern::NOTIFICATION), None);
+        assert_eq!(player.play(Pattern::CHARGING), None);
+        assert_eq!(player.priority(), Some(Priority::High));
+        let next = step(player.advance(timer.generation));
+        assert_eq!(next.duration_ms, Pattern::TIMER.durations_ms[1]);
+
+        // Once done, anything plays again
+        player.stop();
+        assert!(player.play(Pattern::CHARGING).is_some());
+    }
+
+    #[test]
+    fn same_priority_restarts() {
+        let mut player = Player::new();
+        let first = step(player.play(Pattern::NOTIFICATION));
+        player.advance(first.generation);
+        let second = step(player.play(Pattern::NOTIFICATION));
+        assert_ne!(first.generation, second.generation);
+        assert_eq!(player.advance(first.generation), None);
+        let first = Some(Command::Step(second));
+        assert_eq!(
+            run(&mut player, first, 100),
+            [(true, 80), (false, 100), (true, 80)]
+        );
+    }
+
+    #[test]
+    fn generation_wraps() {
+        let mut player = Player::new();
+        for _ in 0..300 {
+            let stale = step(player.play(Pattern::NOTIFICATION));
+            player.stop();
+            assert_eq!(player.advance(stale.generation), None);
+        }
+        let first = player.play(Pattern::CHARGING);
+        assert_eq!(run(&mut player, first, 100), [(true, 30)]);
+    }
+
+    #[test]
+    fn empty_and_zero_times_patterns() {
+        let mut player = Player::new();
+        let empty = Pattern {
+            durations_ms: &[],
+            repeat: Repeat::Forever,
+            priority: Priority::High,
+        };
+        assert_eq!(player.play(empty), Some(Command::Off));
+        assert!(!player.is_playing());
+
+        let never = Pattern {
+            repeat: Repeat::Times(0),
+            ..Pattern::NOTIFICATION
+        };
+        assert_eq!(player.play(never), Some(Command::Off));
+        assert!(!player.is_playing());
+    }
+}
@@ -0,0 +1,373 @@
+//! Wrist raise detection from accelerometer samples
+//!
+//! Samples are in milli-g in the watch's frame: x across the display (towards 3 o'clock),
+//! y along the strap (towards 12 o'clock) and z out of the display, so a watch lying face up
+//! reads z = +1000.
+//!
+//! A raise is detected when the display turns to face up (z high, little sideways tilt),
+//! coming from a position where z was lower by at least the sensitivity's threshold within
+//! the last [`WristTiltDetector::WINDOW`] samples, and stays that way for
+//! [`WristTiltDetector::HOLD`] samples. The display has to be turned away again before the
+//! next raise is detected.
+
+use core::fmt;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct Sample {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl Sample {
+    pub const fn new(x: i16, y: i16, z: i16) -> Self {
+        Sample { x, y, z }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub enum Sensitivity {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl Sensitivity {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Sensitivity::Low => 0,
+            Sensitivity::Medium => 1,
+            Sensitivity::High => 2,
+        }
+    }
+
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Sensitivity::Low,
+            1 => Sensitivity::Medium,
+            2 => Sensitivity::High,
+            _ => return None,
+        }
+        .into()
+    }
+
+    pub fn higher(self) -> Self {
+        match self {
+            Sensitivity::Low => Sensitivity::Medium,
+            _ => Sensitivity::High,
+        }
+    }
+
+    pub fn lower(self) -> Self {
+        match self {
+            Sensitivity::High => Sensitivity::Medium,
+            _ => Sensitivity::Low,
+        }
+    }
+
+    /// Minimum rise of z (milli-g) within the window
+    fn z_rise_threshold(self) -> i16 {
+        match self {
+            Sensitivity::Low => 900,
+            Sensitivity::Medium => 650,
+            Sensitivity::High => 400,
+        }
+    }
+}
+
+impl fmt::Display for Sensitivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Sensitivity::Low => "Low",
+            Sensitivity::Medium => "Med",
+            Sensitivity::High => "High",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct WristTiltDetector {
+    sensitivity: Sensitivity,
+    z_history: [i16; Self::WINDOW],
+    /// Next history slot to write
+    index: usize,
+    /// Number of valid history entries
+    len: usize,
+    in_view_count: u8,
+    /// False after a detection until the display is turned away
+    armed: bool,
+}
+
+impl Default for WristTiltDetector {
+    fn default() -> Self {
+        WristTiltDetector::new(Sensitivity::default())
+    }
+}
+
+impl WristTiltDetector {
+    /// Number of samples the raise has to happen within, ~1 second at the 10 Hz poll rate
+    pub const WINDOW: usize = 10;
+
+    /// Consecutive samples the display has to be in view
+    pub const HOLD: u8 = 2;
+
+    /// Display facing up enough to be looked at
+    const VIEW_Z_MIN: i16 = 600;
+
+    /// Display not tilted too far towards 3 or 9 o'clock
+    const VIEW_X_MAX: i16 = 500;
+
+    pub const fn new(sensitivity: Sensitivity) -> Self {
+        WristTiltDetector {
+            sensitivity,
+            z_history: [0; Self::WINDOW],
+            index: 0,
+            len: 0,
+            in_view_count: 0,
+            armed: true,
+        }
+    }
+
+    pub fn sensitivity(&self) -> Sensitivity {
+        self.sensitivity
+    }
+
+    pub fn set_sensitivity(&mut self, sensitivity: Sensitivity) {
+        self.sensitivity = sensitivity;
+    }
+
+    /// Forget the sample history, i.e. after not being fed for a while
+    pub fn reset(&mut self) {
+        *self = WristTiltDetector::new(self.sensitivity);
+    }
+
+    /// Feed a sample, returns true when a wrist raise is detected
+    pub fn update(&mut self, sample: Sample) -> bool {
+        let lowest_z = self.z_history[..self.len].iter().copied().min();
+
+        self.z_history[self.index] = sample.z;
+        self.index = (self.index + 1) % Self::WINDOW;
+        self.len = (self.len + 1).min(Self::WINDOW);
+
+        if !Self::is_in_view(&sample) {
+            self.in_view_count = 0;
+            self.armed = true;
+            return false;
+        }
+        self.in_view_count = self.in_view_count.saturating_add(1);
+
+        let rose = match lowest_z {
+            Some(lowest_z) => {
+                sample.z.saturating_sub(lowest_z) >= self.sensitivity.z_rise_threshold()
+            }
+            None => false,
+        };
+
+        if self.armed && rose && self.in_view_count >= Self::HOLD {
+            self.armed = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_in_view(sample: &Sample) -> bool {
+        sample.z >= Self::VIEW_Z_MIN && sample.x.saturating_abs() <= Self::VIEW_X_MAX
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arm hanging by the side while walking, then raised to look at the watch and
+    /// held there, in milli-g at 10 Hz
+    const RAISE_WHILE_WALKING: [(i16, i16, i16); 24] = [
+        (-80, -980, 60),
+        (-120, -950, -90),
+        (-60, -1010, 110),
+        (-100, -960, -40),
+        (-90, -990, 80),
+        (-140, -930, -120),
+        (-70, -1000, 30),
+        // Raise
+        (-150, -850, 180),
+        (-200, -620, 450),
+        (-180, -380, 720),
+        (-120, -210, 880),
+        (-90, -160, 940),
+        (-60, -140, 970),
+        (-70, -150, 960),
+        (-80, -130, 980),
+        (-60, -160, 950),
+        (-50, -140, 970),
+        (-70, -150, 960),
+        // Turned away again
+        (-150, -480, 620),
+        (-200, -800, 300),
+        (-120, -970, 50),
+        (-90, -990, -20),
+        (-100, -980, 40),
+        (-80, -1000, 10),
+    ];
+
+    /// Watch lying face up on a table, picked up and put down again
+    const ON_THE_TABLE: [(i16, i16, i16); 12] = [
+        (5, -10, 1002),
+        (3, -12, 998),
+        (6, -8, 1001),
+        (40, 60, 1100),
+        (-30, 120, 940),
+        (20, -40, 1060),
+        (10, 30, 980),
+        (-20, 10, 1010),
+        (4, -9, 1000),
+        (5, -11, 999),
+        (6, -10, 1003),
+        (4, -10, 1000),
+    ];
+
+    /// Forearm resting on the desk, display tilted towards 9 o'clock, then rotated
+    /// to face up over about half a second
+    const TYPING_THEN_ROTATE: [(i16, i16, i16); 12] = [
+        (-930, -60, 280),
+        (-950, -40, 250),
+        (-920, -70, 300),
+        (-940, -50, 270),
+        (-930, -60, 290),
+        (-800, -60, 560),
+        (-560, -50, 800),
+        (-300, -40, 940),
+        (-150, -50, 980),
+        (-120, -60, 990),
+        (-130, -50, 985),
+        (-110, -60, 990),
+    ];
+
+    fn feed<const N: usize>(
+        detector: &mut WristTiltDetector,
+        trace: &[(i16, i16, i16); N],
+    ) -> [bool; N] {
+        let mut detected = [false; N];
+        for (d, (x, y, z)) in detected.iter_mut().zip(trace) {
+            *d = detector.update(Sample::new(*x, *y, *z));
+        }
+        detected
+    }
+
+    fn detections(detected: &[bool]) -> usize {
+        detected.iter().filter(|d| **d).count()
+    }
+
+    #[test]
+    fn raise_while_walking() {
+        for sensitivity in [Sensitivity::Low, Sensitivity::Medium, Sensitivity::High] {
+            let mut detector = WristTiltDetector::new(sensitivity);
+            let detected = feed(&mut detector, &RAISE_WHILE_WALKING);
+            // Once in view for HOLD samples, then only once
+            let first = detected.iter().position(|d| *d);
+            assert_eq!(first, Some(10), "{:?}", sensitivity);
+            assert_eq!(detections(&detected), 1, "{:?}", sensitivity);
+        }
+    }
+
+    #[test]
+    fn turned_away_rearms() {
+        let mut detector = WristTiltDetector::default();
+        assert_eq!(detections(&feed(&mut detector, &RAISE_WHILE_WALKING)), 1);
+        assert_eq!(detections(&feed(&mut detector, &RAISE_WHILE_WALKING)), 1);
+    }
+
+    #[test]
+    fn lying_on_the_table() {
+        let mut detector = WristTiltDetector::new(Sensitivity::High);
+        for _ in 0..5 {
+            assert_eq!(detections(&feed(&mut detector, &ON_THE_TABLE)), 0);
+        }
+    }
+
+    #[test]
+    fn rotating_from_the_side() {
+        // z only rises ~700 mg, from a position that was never in view
+        let mut detector = WristTiltDetector::new(Sensitivity::Low);
+        assert_eq!(detections(&feed(&mut detector, &TYPING_THEN_ROTATE)), 0);
+        let mut detector = WristTiltDetector::new(Sensitivity::Medium);
+        let detected = feed(&mut detector, &TYPING_THEN_ROTATE);
+        assert_eq!(detected.iter().position(|d| *d), Some(8));
+        assert_eq!(detections(&detected), 1);
+    }
+
+    #[test]
+    fn raise_too_slow() {
+        // The same raise stretched over 3 seconds, z never rises enough within the window
+        let mut detector = WristTiltDetector::new(Sensitivity::High);
+        let mut detected = 0;
+        for i in 0..=30 {
+            let z = -100 + i * 1050 / 30;
+            if detector.update(Sample::new(-100, -900 + i * 25, z)) {
+                detected += 1;
+            }
+        }
+        for _ in 0..10 {
+            detector.update(Sample::new(-100, -150, 950));
+        }
+        assert_eq!(detected, 0);
+    }
+
+    #[test]
+    fn sideways_isnt_in_view() {
+        let mut detector = WristTiltDetector::new(Sensitivity::High);
+        for sample in [
+            Sample::new(0, -1000, 0),
+            Sample::new(0, -1000, 0),
+            Sample::new(-600, 0, 800),
+            Sample::new(-600, 0, 800),
+            Sample::new(620, 0, 780),
+            Sample::new(620, 0, 780),
+        ] {
+            assert!(!detector.update(sample));
+        }
+        // Straightened out while still within the window
+        assert!(!detector.update(Sample::new(-100, 0, 980)));
+        assert!(detector.update(Sample::new(-100, 0, 980)));
+    }
+
+    #[test]
+    fn brief_glance_isnt_held() {
+        let mut detector = WristTiltDetector::new(Sensitivity::High);
+        for _ in 0..3 {
+            detector.update(Sample::new(0, -1000, 0));
+        }
+        // In view for a single sample at a time
+        for _ in 0..4 {
+            assert!(!detector.update(Sample::new(0, -100, 950)));
+            assert!(!detector.update(Sample::new(0, -900, 200)));
+        }
+    }
+
+    #[test]
+    fn reset_forgets_the_history() {
+        let mut detector = WristTiltDetector::new(Sensitivity::High);
+        detector.update(Sample::new(0, -1000, 0));
+        detector.reset();
+        assert_eq!(detector.sensitivity(), Sensitivity::High);
+        // No earlier low z to compare with
+        assert!(!detector.update(Sample::new(0, -100, 980)));
+        assert!(!detector.update(Sample::new(0, -100, 980)));
+    }
+
+    #[test]
+    fn sensitivity_settings() {
+        for sensitivity in [Sensitivity::Low, Sensitivity::Medium, Sensitivity::High] {
+            assert_eq!(Sensitivity::from_u8(sensitivity.as_u8()), Some(sensitivity));
+        }
+        assert_eq!(Sensitivity::from_u8(3), None);
+        assert_eq!(
+            Sensitivity::Low.higher().higher().higher(),
+            Sensitivity::High
+        );
+        assert_eq!(Sensitivity::High.lower().lower().lower(), Sensitivity::Low);
+    }
+}
@@ -2,12 +2,12 @@
 // to clip while scrolling
 
 use crate::hal::prelude::{OutputPin, _embedded_hal_blocking_delay_DelayUs as DelayUs};
-use display_interface::WriteOnlyDataCommand;
+use display_interface::{DataFormat, WriteOnlyDataCommand};
 use pinetime_common::embedded_graphics::{
     draw_target::DrawTarget, pixelcolor::Rgb565, prelude::*, primitives::Rectangle,
 };
 use pinetime_common::{display, AnimatedDisplay, RefreshDirection};
-use st7789::{Error, Orientation, ST7789};
+use st7789::{instruction::Instruction, Error, Orientation, ST7789};
 
 pub const SCROLL_DELTA: u16 = 16;
 
@@ -18,7 +18,8 @@ where
 {
     in_progress_animation: Option<RefreshDirection>,
     scroll_offset: u16,
-    display: ST7789<DI, RST>,
+    /// Only taken while entering sleep
+    display: Option<ST7789<DI, RST>>,
 }
 
 impl<DI, RST, PinE> AnimatedSt7789<DI, RST>
@@ -30,15 +31,32 @@ where
         AnimatedSt7789 {
             in_progress_animation: None,
             scroll_offset: 0,
-            display: ST7789::new(di, rst, size_x, size_y),
+            display: Some(ST7789::new(di, rst, size_x, size_y)),
         }
     }
 
     pub fn init(&mut self, delay_source: &mut impl DelayUs<u32>) -> Result<(), Error<PinE>> {
-        self.display.init(delay_source)?;
-        self.display.set_orientation(Orientation::Portrait)?;
+        self.display().init(delay_source)?;
+        self.display().set_orientation(Orientation::Portrait)?;
         Ok(())
     }
+
+    /// Sleep in (SLPIN), until the next `init`. The driver doesn't expose the command, so
+    /// it's sent straight to the interface.
+    pub fn sleep(&mut self) -> Result<(), Error<PinE>> {
+        let display = self.display.take().unwrap();
+        let size = display.size();
+        let (mut di, rst) = display.release();
+        let result = di
+            .send_commands(DataFormat::U8(&[Instruction::SLPIN as u8]))
+            .map_err(|_| Error::DisplayError);
+        self.display = Some(ST7789::new(di, rst, size.width as u16, size.height as u16));
+        result
+    }
+
+    fn display(&mut self) -> &mut ST7789<DI, RST> {
+        self.display.as_mut().unwrap()
+    }
 }
 
 impl<DI, OUT, PinE> DrawTarget for AnimatedSt7789<DI, OUT>
@@ -53,25 +71,25 @@ where
     where
         T: IntoIterator<Item = Pixel<Rgb565>>,
     {
-        self.display.draw_iter(item)
+        self.display().draw_iter(item)
     }
 
     fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
     where
         I: IntoIterator<Item = Self::Color>,
     {
-        self.display.fill_contiguous(area, colors)
+        self.display().fill_contiguous(area, colors)
     }
 
     fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
-        self.display.fill_solid(area, color)
+        self.display().fill_solid(area, color)
     }
 
     fn clear(&mut self, color: Rgb565) -> Result<(), Self::Error>
     where
         Self: Sized,
     {
-        self.display.clear(color)
+        self.display().clear(color)
     }
 }
 
@@ -81,7 +99,7 @@ where
     OUT: OutputPin<Error = PinE>,
 {
     fn size(&self) -> Size {
-        self.display.size()
+        self.display.as_ref().unwrap().size()
     }
 }
 
@@ -106,15 +124,16 @@ where
     // scrolling progresses
     // rect size is (width, SCROLL_DELTA), offset moves with scroll_offset
     fn update_animations(&mut self) -> Result<(), Error<PinE>> {
+        let scroll_offset = self.scroll_offset;
         let is_done = match self.in_progress_animation {
             Some(RefreshDirection::Up) => {
-                self.display.set_scroll_offset(self.scroll_offset)?;
+                self.display().set_scroll_offset(scroll_offset)?;
                 self.scroll_offset += SCROLL_DELTA;
                 self.scroll_offset = self.scroll_offset.clamp(0, display::VERT_LINES);
                 self.scroll_offset == display::VERT_LINES
             }
             Some(RefreshDirection::Down) => {
-                self.display.set_scroll_offset(self.scroll_offset)?;
+                self.display().set_scroll_offset(scroll_offset)?;
                 self.scroll_offset = self.scroll_offset.wrapping_sub(SCROLL_DELTA);
                 self.scroll_offset = self.scroll_offset.clamp(0, display::VERT_LINES);
                 self.scroll_offset == display::VERT_LINES
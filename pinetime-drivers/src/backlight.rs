@@ -2,95 +2,8 @@ use crate::hal::{
     gpio::{p0, Output, PushPull},
     prelude::OutputPin,
 };
-use core::fmt;
-use rtic::time::duration::Milliseconds;
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-pub enum Brightness {
-    Off,
-    L1,
-    L2,
-    L3,
-    L4,
-    L5,
-    L6,
-    L7,
-}
-
-impl Default for Brightness {
-    fn default() -> Self {
-        Brightness::brightest()
-    }
-}
-
-impl Brightness {
-    fn as_u8(self) -> u8 {
-        use Brightness::*;
-        match self {
-            Off => 0,
-            L1 => 1,
-            L2 => 2,
-            L3 => 3,
-            L4 => 4,
-            L5 => 5,
-            L6 => 6,
-            L7 => 7,
-        }
-    }
-
-    pub fn brightest() -> Self {
-        Brightness::L7
-    }
-
-    pub fn dimmest() -> Self {
-        Brightness::L1
-    }
-
-    pub fn brighter(self) -> Self {
-        use Brightness::*;
-        match self {
-            Off => L1,
-            L1 => L2,
-            L2 => L3,
-            L3 => L4,
-            L4 => L5,
-            L5 => L6,
-            L6 => L7,
-            L7 => L7,
-        }
-    }
-
-    pub fn darker(self) -> Self {
-        use Brightness::*;
-        match self {
-            Off => Off,
-            L1 => Off,
-            L2 => L1,
-            L3 => L2,
-            L4 => L3,
-            L5 => L4,
-            L6 => L5,
-            L7 => L6,
-        }
-    }
-}
-
-impl fmt::Display for Brightness {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use Brightness::*;
-        let s = match self {
-            Off => "Off",
-            L1 => "1",
-            L2 => "2",
-            L3 => "3",
-            L4 => "4",
-            L5 => "5",
-            L6 => "6",
-            L7 => "Max",
-        };
-        write!(f, "{}", s)
-    }
-}
+pub use pinetime_common::Brightness;
 
 pub type LowPin = p0::P0_14<Output<PushPull>>;
 pub type MidPin = p0::P0_22<Output<PushPull>>;
@@ -104,8 +17,6 @@ pub struct Backlight {
 }
 
 impl Backlight {
-    pub const RAMP_INC_MS: Milliseconds<u32> = Milliseconds(50);
-
     pub fn new(low: LowPin, mid: MidPin, high: HighPin) -> Self {
         let mut backlight = Backlight {
             low,
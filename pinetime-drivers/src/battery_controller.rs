@@ -2,10 +2,13 @@ use crate::hal::{
     gpio::{p0, Floating, Input, Pin},
     gpiote::GpioteChannel,
     pac,
-    prelude::{InputPin, _embedded_hal_adc_OneShot as OneShot},
+    prelude::{_embedded_hal_adc_OneShot as OneShot, InputPin},
     saadc::{self, Saadc, SaadcConfig},
 };
-use pinetime_common::{BatteryControllerExt, MilliVolts};
+use pinetime_common::{
+    battery_model::{self, ChargeState, StateOfCharge},
+    BatteryControllerExt, MilliVolts,
+};
 use rtic::time::duration::Milliseconds;
 
 /// High = battery, Low = charging.
@@ -23,22 +26,12 @@ pub struct BatteryController {
     charging: bool,
     power_present: bool,
     voltage: MilliVolts,
-    percent_remaining: u8,
+    state_of_charge: StateOfCharge,
 }
 
 impl BatteryController {
-    /// Maximum voltage of battery (max charging voltage is 4.21)
-    pub const BATTERY_MAX: MilliVolts = MilliVolts(4180);
-
-    /// Minimum voltage of battery before shutdown (depends on the battery)
-    pub const BATTERY_MIN: MilliVolts = MilliVolts(3200);
-
-    pub const VOLTAGE_POLL_INTERVAL_MS: Milliseconds<u32> = Milliseconds(5000);
-
     pub const POWER_PRESENCE_DEBOUNCE_MS: Milliseconds<u32> = Milliseconds(200);
 
-    pub const CHARGE_EVENT_RING_DURATION: Milliseconds<u32> = Milliseconds(30);
-
     pub fn new(
         adc: pac::SAADC,
         charge_indication_pin: ChargeIndicationPin,
@@ -67,7 +60,7 @@ impl BatteryController {
             charging: false,
             power_present: false,
             voltage: MilliVolts(0),
-            percent_remaining: 0,
+            state_of_charge: StateOfCharge::new(),
         }
     }
 
@@ -79,19 +72,24 @@ impl BatteryController {
         self.voltage
     }
 
+    /// Average of the recent readings, a sag under load barely moves it
+    pub fn filtered_voltage(&self) -> MilliVolts {
+        self.state_of_charge.voltage()
+    }
+
     pub fn percent_remaining(&self) -> u8 {
-        self.percent_remaining
+        self.state_of_charge.percent()
+    }
+
+    pub fn charge_state(&self) -> ChargeState {
+        ChargeState::new(self.power_present, self.charging)
     }
 
     pub fn update_charging_io(&mut self) -> bool {
-        let mut changed = false;
+        // Only notify if power present changes
+        self.update_charge_indication();
 
-        let charging = self.charge_indication_pin.is_low().unwrap();
-        if charging != self.charging {
-            self.charging = charging;
-            // Only notify if power present changes
-            //changed = true;
-        }
+        let mut changed = false;
 
         let power_present = self.power_presence_pin.is_low().unwrap();
         if power_present != self.power_present {
@@ -102,37 +100,39 @@ impl BatteryController {
         changed
     }
 
-    pub fn update_voltage(&mut self) -> bool {
-        let mut changed = false;
+    /// The charge indication pin has no interrupt, poll it to find out when charging is done.
+    /// Returns true if it changed.
+    pub fn update_charge_indication(&mut self) -> bool {
+        let charging = self.charge_indication_pin.is_low().unwrap();
+        let changed = charging != self.charging;
+        self.charging = charging;
+        changed
+    }
 
-        let voltage_raw = self
-            .adc
-            .read(&mut self.voltage_pin)
-            .unwrap_or(0)
-            .clamp(0, i16::MAX) as u32;
-        let voltage = Self::raw_voltage_to_volts(voltage_raw);
-        if voltage != self.voltage {
-            self.voltage = voltage;
-            self.percent_remaining = if voltage.0 > Self::BATTERY_MAX.0 {
-                100
-            } else if voltage.0 < Self::BATTERY_MIN.0 {
-                0
-            } else {
-                let v = (voltage.0 - Self::BATTERY_MIN.0) as u32;
-                let vd = (Self::BATTERY_MAX.0 - Self::BATTERY_MIN.0) as u32;
-                ((v * 100) / vd) as u8
-            };
-            changed = true;
-        }
+    /// Whether the last reading could come from the battery, a failed ADC read gives 0
+    pub fn has_plausible_voltage(&self) -> bool {
+        battery_model::is_plausible(self.voltage)
+    }
 
-        changed
+    /// Returns true if the percentage remaining changed
+    pub fn update_voltage(&mut self) -> bool {
+        self.voltage = match self.adc.read(&mut self.voltage_pin) {
+            Ok(raw) => Self::raw_voltage_to_volts(raw.clamp(0, i16::MAX) as u32),
+            Err(_) => MilliVolts(0),
+        };
+        // Keep the average from before, the reading can't be the battery's
+        if !self.has_plausible_voltage() {
+            return false;
+        }
+        let state = self.charge_state();
+        self.state_of_charge.update(self.voltage, state)
     }
 
-    /// Returns (ChargingStatusChanged, VoltageChanged)
+    /// Returns (ChargingStatusChanged, PercentRemainingChanged)
     pub fn update(&mut self) -> (bool, bool) {
         let charging_changed = self.update_charging_io();
-        let voltage_changed = self.update_voltage();
-        (charging_changed, voltage_changed)
+        let percent_changed = self.update_voltage();
+        (charging_changed, percent_changed)
     }
 
     /// A hardware voltage divider divides the battery voltage by 2
@@ -151,6 +151,10 @@ impl BatteryControllerExt for BatteryController {
         BatteryController::is_charging(self)
     }
 
+    fn charge_state(&self) -> ChargeState {
+        BatteryController::charge_state(self)
+    }
+
     fn voltage(&self) -> MilliVolts {
         BatteryController::voltage(self)
     }
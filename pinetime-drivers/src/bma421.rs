@@ -0,0 +1,585 @@
+//! Bosch BMA421 accelerometer driver
+//!
+//! Pins:
+//! * P0.08 : Interrupt 1
+//! * P0.06 : I²C SDA (shared with the touch panel)
+//! * P0.07 : I²C SCL (shared with the touch panel)
+//!
+//! I²C
+//! Device address : 0x18
+//!
+//! The step counter (and the other motion features) run on the sensor's
+//! internal microcontroller, which needs Bosch's config blob uploaded on
+//! every power up. Raw acceleration data works without it.
+//!
+//! The driver only relies on the embedded-hal blocking I²C traits, so it works
+//! on a shared bus proxy, or on the fake sensor in the tests.
+
+use crate::hal::{
+    gpio::{p0, Floating, Input},
+    gpiote::GpioteChannel,
+    prelude::{
+        _embedded_hal_blocking_delay_DelayMs as DelayMs, _embedded_hal_blocking_i2c_Write as Write,
+        _embedded_hal_blocking_i2c_WriteRead as WriteRead,
+    },
+};
+use core::fmt;
+
+pub type InterruptPin = p0::P0_08<Input<Floating>>;
+
+/// BMA421 I2C address
+pub const ADDRESS: u8 = 0x18;
+
+pub const CHIP_ID: u8 = 0x11;
+
+/// Config blob is written in chunks of this many bytes
+const CONFIG_CHUNK_SIZE: usize = 32;
+
+/// Size of the feature config area, accessed through the FEATURES_IN register
+const FEATURES_SIZE: usize = 64;
+
+/// Step counter settings offset within the feature config
+const STEP_COUNTER_OFFSET: usize = 0x3A;
+const STEP_COUNTER_WATERMARK_MSB_MASK: u8 = 0x03;
+const STEP_COUNTER_RESET: u8 = 1 << 2;
+const STEP_COUNTER_ENABLE: u8 = 1 << 4;
+
+/// Feature interrupt bit of the step counter, in INT_STATUS_0 and INT1_MAP
+const STEP_COUNTER_INT: u8 = 1 << 1;
+
+/// INTERNAL_STATUS message once the config blob is running
+const INTERNAL_STATUS_INIT_OK: u8 = 0x01;
+const INTERNAL_STATUS_MESSAGE_MASK: u8 = 0x0F;
+
+/// 100 Hz, averaging 4 samples, continuous filter mode
+const ACC_CONF_100HZ: u8 = 0x08 | (0x02 << 4) | (1 << 7);
+const ACC_RANGE_2G: u8 = 0x00;
+
+/// Output enabled, push-pull, active high
+const INT1_IO_CTRL_PUSH_PULL_ACTIVE_HIGH: u8 = (1 << 3) | (1 << 1);
+
+const PWR_CTRL_ACC_EN: u8 = 1 << 2;
+const PWR_CONF_ADV_POWER_SAVE: u8 = 1 << 0;
+const PWR_CONF_FIFO_SELF_WAKEUP: u8 = 1 << 1;
+
+const CMD_SOFT_RESET: u8 = 0xB6;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// Unexpected chip ID
+    ChipId(u8),
+    /// The config blob didn't start, contains the INTERNAL_STATUS message
+    ConfigLoad(u8),
+    /// The features need the config blob to be loaded
+    NoConfig,
+}
+
+/// Raw 12-bit acceleration sample, +/-2g range
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct Acceleration {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl Acceleration {
+    /// Counts per g in the +/-2g range
+    pub const LSB_PER_G: i32 = 1024;
+
+    fn from_le_bytes(bytes: &[u8; 6]) -> Self {
+        // 12 bit values are left aligned in the 16 bit registers
+        let axis = |lsb: u8, msb: u8| i16::from_le_bytes([lsb, msb]) >> 4;
+        Acceleration {
+            x: axis(bytes[0], bytes[1]),
+            y: axis(bytes[2], bytes[3]),
+            z: axis(bytes[4], bytes[5]),
+        }
+    }
+
+    pub fn x_milli_g(&self) -> i32 {
+        self.x as i32 * 1000 / Self::LSB_PER_G
+    }
+
+    pub fn y_milli_g(&self) -> i32 {
+        self.y as i32 * 1000 / Self::LSB_PER_G
+    }
+
+    pub fn z_milli_g(&self) -> i32 {
+        self.z as i32 * 1000 / Self::LSB_PER_G
+    }
+}
+
+impl fmt::Display for Acceleration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}, {}", self.x, self.y, self.z)
+    }
+}
+
+/// Configure the interrupt pin to generate events on a GPIOTE channel,
+/// the sensor drives it high on a step counter interrupt
+pub fn enable_interrupt(int_pin: InterruptPin, channel: &GpioteChannel<'_>) {
+    let int_pin = int_pin.degrade();
+    channel.input_pin(&int_pin).lo_to_hi().enable_interrupt();
+}
+
+/// BMA421 driver
+pub struct Bma421<I2C> {
+    i2c: I2C,
+    config_loaded: bool,
+}
+
+impl<I2C, E> Bma421<I2C>
+where
+    I2C: WriteRead<Error = E> + Write<Error = E>,
+{
+    pub fn new(i2c: I2C) -> Self {
+        Bma421 {
+            i2c,
+            config_loaded: false,
+        }
+    }
+
+    pub fn free(self) -> I2C {
+        self.i2c
+    }
+
+    pub fn is_config_loaded(&self) -> bool {
+        self.config_loaded
+    }
+
+    /// Reset the sensor, upload `config` (skipped if empty) and start sampling
+    pub fn init<D: DelayMs<u8>>(&mut self, delay: &mut D, config: &[u8]) -> Result<(), Error<E>> {
+        let chip_id = self.read_register(Register::ChipId)?;
+        if chip_id != CHIP_ID {
+            return Err(Error::ChipId(chip_id));
+        }
+
+        self.write_register(Register::Cmd, CMD_SOFT_RESET)?;
+        delay.delay_ms(2);
+        self.config_loaded = false;
+
+        if !config.is_empty() {
+            self.load_config(delay, config)?;
+        }
+
+        self.write_register(Register::AccConf, ACC_CONF_100HZ)?;
+        self.write_register(Register::AccRange, ACC_RANGE_2G)?;
+        self.write_register(Register::PwrCtrl, PWR_CTRL_ACC_EN)?;
+        Ok(())
+    }
+
+    fn load_config<D: DelayMs<u8>>(
+        &mut self,
+        delay: &mut D,
+        config: &[u8],
+    ) -> Result<(), Error<E>> {
+        // Advanced power save must be off while uploading
+        self.write_register(Register::PwrConf, 0x00)?;
+        delay.delay_ms(1);
+        self.write_register(Register::InitCtrl, 0x00)?;
+
+        let mut tx = [0_u8; CONFIG_CHUNK_SIZE + 1];
+        tx[0] = Register::FeaturesIn.addr();
+        for (index, chunk) in config.chunks(CONFIG_CHUNK_SIZE).enumerate() {
+            // Destination is addressed in words
+            let word_addr = (index * CONFIG_CHUNK_SIZE / 2) as u16;
+            self.write_register(Register::ReservedReg5B, (word_addr & 0x0F) as u8)?;
+            self.write_register(Register::ReservedReg5C, (word_addr >> 4) as u8)?;
+            tx[1..=chunk.len()].copy_from_slice(chunk);
+            self.i2c
+                .write(ADDRESS, &tx[..=chunk.len()])
+                .map_err(Error::I2c)?;
+        }
+
+        self.write_register(Register::InitCtrl, 0x01)?;
+        // Takes up to 140 ms to start
+        delay.delay_ms(150);
+        let status = self.read_register(Register::InternalStatus)? & INTERNAL_STATUS_MESSAGE_MASK;
+        if status != INTERNAL_STATUS_INIT_OK {
+            return Err(Error::ConfigLoad(status));
+        }
+        self.config_loaded = true;
+        Ok(())
+    }
+
+    /// Advanced power save, the sensor only wakes up its interface when accessed.
+    /// Register writes need 450 us between them while enabled.
+    pub fn set_power_save(&mut self, enable: bool) -> Result<(), Error<E>> {
+        let conf = if enable {
+            PWR_CONF_ADV_POWER_SAVE | PWR_CONF_FIFO_SELF_WAKEUP
+        } else {
+            0x00
+        };
+        self.write_register(Register::PwrConf, conf)
+    }
+
+    pub fn acceleration(&mut self) -> Result<Acceleration, Error<E>> {
+        let mut rx = [0_u8; 6];
+        self.read_registers(Register::AccData, &mut rx)?;
+        Ok(Acceleration::from_le_bytes(&rx))
+    }
+
+    /// Enable the step counter and its interrupt on INT1
+    ///
+    /// The interrupt fires every `watermark * 20` steps, 0 disables it.
+    pub fn enable_step_counter(&mut self, watermark: u16) -> Result<(), Error<E>> {
+        self.modify_features(|f| {
+            let s = &mut f[STEP_COUNTER_OFFSET..STEP_COUNTER_OFFSET + 2];
+            s[0] = watermark as u8;
+            s[1] &= !STEP_COUNTER_WATERMARK_MSB_MASK;
+            s[1] |= (watermark >> 8) as u8 & STEP_COUNTER_WATERMARK_MSB_MASK;
+            s[1] |= STEP_COUNTER_ENABLE;
+        })?;
+        self.write_register(Register::Int1IoCtrl, INT1_IO_CTRL_PUSH_PULL_ACTIVE_HIGH)?;
+        self.write_register(Register::IntLatch, 0x00)?;
+        self.write_register(Register::Int1Map, STEP_COUNTER_INT)
+    }
+
+    pub fn reset_step_counter(&mut self) -> Result<(), Error<E>> {
+        self.modify_features(|f| f[STEP_COUNTER_OFFSET + 1] |= STEP_COUNTER_RESET)
+    }
+
+    pub fn step_count(&mut self) -> Result<u32, Error<E>> {
+        let mut rx = [0_u8; 4];
+        self.read_registers(Register::StepCounter, &mut rx)?;
+        Ok(u32::from_le_bytes(rx))
+    }
+
+    /// Reads and clears the feature interrupt status,
+    /// returns true if the step counter interrupt was pending
+    pub fn take_step_interrupt(&mut self) -> Result<bool, Error<E>> {
+        Ok(self.read_register(Register::IntStatus0)? & STEP_COUNTER_INT != 0)
+    }
+
+    /// Read-modify-write of the feature config
+    fn modify_features<F>(&mut self, f: F) -> Result<(), Error<E>>
+    where
+        F: FnOnce(&mut [u8; FEATURES_SIZE]),
+    {
+        if !self.config_loaded {
+            return Err(Error::NoConfig);
+        }
+        let mut tx = [0_u8; FEATURES_SIZE + 1];
+        tx[0] = Register::FeaturesIn.addr();
+        let mut features = [0_u8; FEATURES_SIZE];
+        self.read_registers(Register::FeaturesIn, &mut features)?;
+        f(&mut features);
+        tx[1..].copy_from_slice(&features);
+        self.i2c.write(ADDRESS, &tx).map_err(Error::I2c)
+    }
+
+    fn read_register(&mut self, register: Register) -> Result<u8, Error<E>> {
+        let mut rx = [0_u8; 1];
+        self.read_registers(register, &mut rx)?;
+        Ok(rx[0])
+    }
+
+    fn read_registers(&mut self, register: Register, rx: &mut [u8]) -> Result<(), Error<E>> {
+        let tx = [register.addr()];
+        self.i2c.write_read(ADDRESS, &tx, rx).map_err(Error::I2c)
+    }
+
+    fn write_register(&mut self, register: Register, value: u8) -> Result<(), Error<E>> {
+        let tx = [register.addr(), value];
+        self.i2c.write(ADDRESS, &tx).map_err(Error::I2c)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[repr(u8)]
+enum Register {
+    ChipId = 0x00,
+    AccData = 0x12,
+    IntStatus0 = 0x1C,
+    StepCounter = 0x1E,
+    InternalStatus = 0x2A,
+    AccConf = 0x40,
+    AccRange = 0x41,
+    Int1IoCtrl = 0x53,
+    IntLatch = 0x55,
+    Int1Map = 0x56,
+    InitCtrl = 0x59,
+    ReservedReg5B = 0x5B,
+    ReservedReg5C = 0x5C,
+    FeaturesIn = 0x5E,
+    PwrConf = 0x7C,
+    PwrCtrl = 0x7D,
+    Cmd = 0x7E,
+}
+
+impl Register {
+    fn addr(self) -> u8 {
+        self as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{vec, vec::Vec};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    struct BusError;
+
+    /// Register map of the sensor, FEATURES_IN goes to the config blob while
+    /// INIT_CTRL is 0 and to the feature config after
+    struct FakeSensor {
+        registers: [u8; 0x80],
+        config: Vec<u8>,
+        features: [u8; FEATURES_SIZE],
+        /// Register writes, not counting FEATURES_IN
+        writes: Vec<(u8, u8)>,
+        fail: bool,
+    }
+
+    impl FakeSensor {
+        fn new() -> Self {
+            let mut registers = [0; 0x80];
+            registers[Register::ChipId as usize] = CHIP_ID;
+            registers[Register::InternalStatus as usize] = INTERNAL_STATUS_INIT_OK;
+            FakeSensor {
+                registers,
+                config: vec![0; 256],
+                features: [0; FEATURES_SIZE],
+                writes: Vec::new(),
+                fail: false,
+            }
+        }
+
+        fn set(&mut self, register: Register, bytes: &[u8]) {
+            let start = register as usize;
+            self.registers[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    impl Write for FakeSensor {
+        type Error = BusError;
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+            assert_eq!(address, ADDRESS);
+            if self.fail {
+                return Err(BusError);
+            }
+            let (register, data) = (bytes[0], &bytes[1..]);
+            if register == Register::FeaturesIn as u8 {
+                if self.registers[Register::InitCtrl as usize] == 0 {
+                    let word_addr = (self.registers[Register::ReservedReg5B as usize] as usize)
+                        | (self.registers[Register::ReservedReg5C as usize] as usize) << 4;
+                    let start = word_addr * 2;
+                    self.config[start..start + data.len()].copy_from_slice(data);
+                } else {
+                    self.features[..data.len()].copy_from_slice(data);
+                }
+                return Ok(());
+            }
+            assert_eq!(data.len(), 1);
+            self.registers[register as usize] = data[0];
+            self.writes.push((register, data[0]));
+            Ok(())
+        }
+    }
+
+    impl WriteRead for FakeSensor {
+        type Error = BusError;
+
+        fn write_read(&mut self, address: u8, tx: &[u8], rx: &mut [u8]) -> Result<(), Self::Error> {
+            assert_eq!(address, ADDRESS);
+            if self.fail {
+                return Err(BusError);
+            }
+            let register = tx[0] as usize;
+            if tx[0] == Register::FeaturesIn as u8 {
+                rx.copy_from_slice(&self.features[..rx.len()]);
+            } else {
+                rx.copy_from_slice(&self.registers[register..register + rx.len()]);
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeDelay {
+        ms: u32,
+    }
+
+    impl DelayMs<u8> for FakeDelay {
+        fn delay_ms(&mut self, ms: u8) {
+            self.ms += ms as u32;
+        }
+    }
+
+    fn config_blob(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 3 + 1) as u8).collect()
+    }
+
+    fn loaded() -> Bma421<FakeSensor> {
+        let mut bma = Bma421::new(FakeSensor::new());
+        bma.init(&mut FakeDelay::default(), &config_blob(64))
+            .unwrap();
+        bma
+    }
+
+    #[test]
+    fn init_without_config() {
+        let mut bma = Bma421::new(FakeSensor::new());
+        let mut delay = FakeDelay::default();
+        bma.init(&mut delay, &[]).unwrap();
+        assert!(!bma.is_config_loaded());
+        assert_eq!(delay.ms, 2);
+        assert_eq!(
+            bma.free().writes,
+            [
+                (Register::Cmd as u8, CMD_SOFT_RESET),
+                (Register::AccConf as u8, ACC_CONF_100HZ),
+                (Register::AccRange as u8, ACC_RANGE_2G),
+                (Register::PwrCtrl as u8, PWR_CTRL_ACC_EN),
+            ]
+        );
+    }
+
+    #[test]
+    fn init_checks_the_chip_id() {
+        let mut sensor = FakeSensor::new();
+        sensor.set(Register::ChipId, &[0x13]);
+        let mut bma = Bma421::new(sensor);
+        assert!(matches!(
+            bma.init(&mut FakeDelay::default(), &[]),
+            Err(Error::ChipId(0x13))
+        ));
+        assert!(bma.free().writes.is_empty());
+    }
+
+    #[test]
+    fn init_uploads_the_config() {
+        // Doesn't end on a whole chunk
+        let config = config_blob(3 * CONFIG_CHUNK_SIZE + 6);
+        let mut bma = Bma421::new(FakeSensor::new());
+        let mut delay = FakeDelay::default();
+        bma.init(&mut delay, &config).unwrap();
+        assert!(bma.is_config_loaded());
+        assert!(delay.ms >= 140);
+
+        let sensor = bma.free();
+        assert_eq!(sensor.config[..config.len()], config[..]);
+        assert!(sensor.config[config.len()..].iter().all(|b| *b == 0));
+        // Power save off for the upload, the blob started after it
+        let pwr_conf = sensor
+            .writes
+            .iter()
+            .position(|w| w.0 == Register::PwrConf as u8);
+        let started = sensor
+            .writes
+            .iter()
+            .position(|w| *w == (Register::InitCtrl as u8, 0x01));
+        assert!(pwr_conf.unwrap() < started.unwrap());
+        assert_eq!(sensor.registers[Register::PwrConf as usize], 0x00);
+        assert_eq!(
+            sensor.registers[Register::PwrCtrl as usize],
+            PWR_CTRL_ACC_EN
+        );
+    }
+
+    #[test]
+    fn config_that_doesnt_start() {
+        let mut sensor = FakeSensor::new();
+        sensor.set(Register::InternalStatus, &[0x12]);
+        let mut bma = Bma421::new(sensor);
+        assert!(matches!(
+            bma.init(&mut FakeDelay::default(), &config_blob(32)),
+            Err(Error::ConfigLoad(0x02))
+        ));
+        assert!(!bma.is_config_loaded());
+    }
+
+    #[test]
+    fn acceleration() {
+        let mut sensor = FakeSensor::new();
+        // x = 1 g, y = -0.5 g, z = -1 count, left aligned
+        sensor.set(Register::AccData, &[0x00, 0x40, 0x00, 0xE0, 0xF0, 0xFF]);
+        let mut bma = Bma421::new(sensor);
+        let acc = bma.acceleration().unwrap();
+        assert_eq!(
+            acc,
+            Acceleration {
+                x: 1024,
+                y: -512,
+                z: -1
+            }
+        );
+        assert_eq!(
+            (acc.x_milli_g(), acc.y_milli_g(), acc.z_milli_g()),
+            (1000, -500, 0)
+        );
+    }
+
+    #[test]
+    fn step_counter() {
+        let mut bma = Bma421::new(FakeSensor::new());
+        assert!(matches!(bma.enable_step_counter(1), Err(Error::NoConfig)));
+        assert!(matches!(bma.reset_step_counter(), Err(Error::NoConfig)));
+
+        let mut bma = loaded();
+        bma.i2c.features[STEP_COUNTER_OFFSET + 1] = 0xE3;
+        bma.i2c.features[0] = 0x55;
+        bma.enable_step_counter(0x2AB).unwrap();
+        let features = bma.i2c.features;
+        assert_eq!(features[STEP_COUNTER_OFFSET], 0xAB);
+        // The other bits are left alone
+        assert_eq!(
+            features[STEP_COUNTER_OFFSET + 1],
+            0xE2 | STEP_COUNTER_ENABLE
+        );
+        assert_eq!(features[0], 0x55);
+        assert_eq!(
+            bma.i2c.registers[Register::Int1Map as usize],
+            STEP_COUNTER_INT
+        );
+        assert_eq!(
+            bma.i2c.registers[Register::Int1IoCtrl as usize],
+            INT1_IO_CTRL_PUSH_PULL_ACTIVE_HIGH
+        );
+
+        bma.reset_step_counter().unwrap();
+        assert_eq!(
+            bma.i2c.features[STEP_COUNTER_OFFSET + 1],
+            0xE2 | STEP_COUNTER_ENABLE | STEP_COUNTER_RESET
+        );
+
+        bma.i2c
+            .set(Register::StepCounter, &[0x39, 0x30, 0x01, 0x00]);
+        assert_eq!(bma.step_count().unwrap(), 0x0001_3039);
+        assert!(!bma.take_step_interrupt().unwrap());
+        bma.i2c.set(Register::IntStatus0, &[STEP_COUNTER_INT | 1]);
+        assert!(bma.take_step_interrupt().unwrap());
+    }
+
+    #[test]
+    fn power_save() {
+        let mut bma = Bma421::new(FakeSensor::new());
+        bma.set_power_save(true).unwrap();
+        assert_eq!(
+            bma.i2c.registers[Register::PwrConf as usize],
+            PWR_CONF_ADV_POWER_SAVE | PWR_CONF_FIFO_SELF_WAKEUP
+        );
+        bma.set_power_save(false).unwrap();
+        assert_eq!(bma.i2c.registers[Register::PwrConf as usize], 0);
+    }
+
+    #[test]
+    fn bus_errors() {
+        let mut bma = loaded();
+        bma.i2c.fail = true;
+        assert!(matches!(bma.acceleration(), Err(Error::I2c(BusError))));
+        assert!(matches!(
+            bma.enable_step_counter(1),
+            Err(Error::I2c(BusError))
+        ));
+        assert!(matches!(
+            bma.init(&mut FakeDelay::default(), &[]),
+            Err(Error::I2c(BusError))
+        ));
+    }
+}
@@ -1,8 +1,10 @@
 use crate::hal::{
     gpio::{p0, Floating, Input, Output, Pin, PushPull},
     gpiote::GpioteChannel,
+    pac,
     prelude::{InputPin, OutputPin},
 };
+use pinetime_common::{ButtonClassifier, ButtonEvent};
 use rtic::time::duration::Milliseconds;
 
 pub type ButtonEnablePin = p0::P0_15<Output<PushPull>>;
@@ -11,11 +13,15 @@ pub type ButtonPin = p0::P0_13<Input<Floating>>;
 pub struct Button {
     _enable_pin: ButtonEnablePin,
     input_pin: Pin<Input<Floating>>,
+    classifier: ButtonClassifier,
 }
 
 impl Button {
     pub const DEBOUNCE_MS: Milliseconds<u32> = Milliseconds(75);
 
+    /// How often to poll while a press is being classified
+    pub const POLL_INTERVAL_MS: Milliseconds<u32> = Milliseconds(50);
+
     pub fn new(
         mut enable_pin: ButtonEnablePin,
         input_pin: ButtonPin,
@@ -23,14 +29,43 @@ impl Button {
     ) -> Self {
         enable_pin.set_high().unwrap();
         let input_pin = input_pin.degrade();
-        channel.input_pin(&input_pin).lo_to_hi().enable_interrupt();
+        channel.input_pin(&input_pin).toggle().enable_interrupt();
         Button {
             _enable_pin: enable_pin,
             input_pin,
+            classifier: ButtonClassifier::new(),
         }
     }
 
     pub fn is_pressed(&self) -> bool {
         self.input_pin.is_high().unwrap()
     }
+
+    /// Sample the button, keep calling every `POLL_INTERVAL_MS` while `is_busy`
+    pub fn poll(&mut self, now_ms: u32) -> Option<ButtonEvent> {
+        let is_pressed = self.is_pressed();
+        self.classifier.update(is_pressed, now_ms)
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.classifier.is_busy()
+    }
+
+    /// How long the button has been held down for, as of the last `poll`
+    pub fn press_duration_ms(&self, now_ms: u32) -> Option<u32> {
+        self.classifier.press_duration_ms(now_ms)
+    }
+
+    pub fn is_very_long_press(&self, now_ms: u32) -> bool {
+        self.classifier.is_very_long_press(now_ms)
+    }
+
+    /// Let a press wake the chip up from System OFF.
+    /// Doesn't need the button, whoever shuts down may not be able to get at it.
+    pub fn enable_wakeup() {
+        // The HAL doesn't expose the pin's sense setting, the pin is always P0.13
+        unsafe {
+            (*pac::P0::ptr()).pin_cnf[13].modify(|_, w| w.sense().high());
+        }
+    }
 }
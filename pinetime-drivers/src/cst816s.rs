@@ -9,14 +9,23 @@
 //! I²C
 //! Device address : 0x15
 //! Frequency : from 10Khz to 400Khz
+//!
+//! The driver only relies on the embedded-hal blocking I²C traits, the bus is
+//! shared with the BMA421.
 
 use crate::hal::{
     gpio::{p0, Floating, Input, Output, Pin, PushPull},
     gpiote::GpioteChannel,
-    prelude::{OutputPin, _embedded_hal_blocking_delay_DelayMs as DelayMs},
-    twim::{self, Error, Twim},
+    prelude::{
+        OutputPin, _embedded_hal_blocking_delay_DelayMs as DelayMs,
+        _embedded_hal_blocking_i2c_Write as Write,
+        _embedded_hal_blocking_i2c_WriteRead as WriteRead,
+    },
 };
 use core::fmt;
+use pinetime_common::{embedded_graphics::geometry::Point, InputEvent};
+
+pub use pinetime_common::Gesture;
 
 /// CST816S I2C address
 pub const ADDRESS: u8 = 0x15;
@@ -26,47 +35,6 @@ pub const ADDRESS: u8 = 0x15;
 /// at ~390Khz with correct timings.
 pub const MAX_FREQUENCY: u32 = 0x06200000;
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-#[repr(u8)]
-pub enum Gesture {
-    SlideDown = 0x01,
-    SlideUp = 0x02,
-    SlideLeft = 0x03,
-    SlideRight = 0x04,
-    SingleTap = 0x05,
-    DoubleTap = 0x0B,
-    LongPress = 0x0C,
-}
-
-impl Gesture {
-    /*
-    fn as_u8(self) -> u8 {
-        self as u8
-    }
-    */
-
-    fn from_u8(val: u8) -> Option<Self> {
-        use Gesture::*;
-        match val {
-            0x01 => SlideDown,
-            0x02 => SlideUp,
-            0x03 => SlideLeft,
-            0x04 => SlideRight,
-            0x05 => SingleTap,
-            0x0B => DoubleTap,
-            0x0C => LongPress,
-            _ => return None,
-        }
-        .into()
-    }
-}
-
-impl fmt::Display for Gesture {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct TouchData {
     pub x: u16,
@@ -94,6 +62,14 @@ impl TouchData {
             is_touching: num_touch_points > 0,
         }
     }
+
+    pub fn point(&self) -> Point {
+        Point::new(self.x as i32, self.y as i32)
+    }
+
+    pub fn input_event(&self) -> Option<InputEvent> {
+        self.gesture.map(|g| InputEvent::from_touch(g, self.point()))
+    }
 }
 
 impl fmt::Display for TouchData {
@@ -110,19 +86,19 @@ pub type ResetPin = p0::P0_10<Output<PushPull>>;
 pub type InterruptPin = p0::P0_28<Input<Floating>>;
 
 /// CST816S driver
-pub struct Cst816s<TWIM> {
-    twim: Twim<TWIM>,
+pub struct Cst816s<I2C> {
+    i2c: I2C,
     reset_pin: ResetPin,
     _int_pin: Pin<Input<Floating>>,
     buffer: [u8; 7],
 }
 
-impl<TWIM> Cst816s<TWIM>
+impl<I2C, E> Cst816s<I2C>
 where
-    TWIM: twim::Instance,
+    I2C: WriteRead<Error = E> + Write<Error = E>,
 {
     pub fn new(
-        twim: Twim<TWIM>,
+        i2c: I2C,
         reset_pin: ResetPin,
         int_pin: InterruptPin,
         channel: &GpioteChannel<'_>,
@@ -130,14 +106,14 @@ where
         let int_pin = int_pin.degrade();
         channel.input_pin(&int_pin).lo_to_hi().enable_interrupt();
         Cst816s {
-            twim,
+            i2c,
             reset_pin,
             _int_pin: int_pin,
             buffer: [0; 7],
         }
     }
 
-    pub fn init<T: DelayMs<u8>>(&mut self, delay: &mut T) -> Result<(), Error> {
+    pub fn init<T: DelayMs<u8>>(&mut self, delay: &mut T) -> Result<(), E> {
         self.reset_pin.set_high().unwrap();
         delay.delay_ms(50);
         self.reset_pin.set_low().unwrap();
@@ -165,8 +141,8 @@ where
         Ok(())
     }
 
-    /*
-    pub fn sleep<T: DelayMs<u8>>(&mut self, delay: &mut T) -> Result<(), Error> {
+    /// Deep sleep, only a reset (i.e. `init`) wakes it back up
+    pub fn sleep<T: DelayMs<u8>>(&mut self, delay: &mut T) -> Result<(), E> {
         self.reset_pin.set_low().unwrap();
         delay.delay_ms(5);
         self.reset_pin.set_high().unwrap();
@@ -174,29 +150,29 @@ where
         self.write_register(Register::PowerMode, 0x03)?;
         Ok(())
     }
-    */
+
+    pub fn read_input_event(&mut self) -> Option<InputEvent> {
+        self.read_touch_data().and_then(|t| t.input_event())
+    }
 
     pub fn read_touch_data(&mut self) -> Option<TouchData> {
         let addr = [0];
-        match self
-            .twim
-            .copy_write_then_read(ADDRESS, &addr, &mut self.buffer)
-        {
+        match self.i2c.write_read(ADDRESS, &addr, &mut self.buffer) {
             Err(_e) => None,
             Ok(()) => Some(TouchData::from_le_bytes(&self.buffer)),
         }
     }
 
-    fn read_register(&mut self, register: Register) -> Result<u8, Error> {
+    fn read_register(&mut self, register: Register) -> Result<u8, E> {
         let tx = [register.addr()];
         let mut rx = [0_u8; 1];
-        self.twim.copy_write_then_read(ADDRESS, &tx, &mut rx)?;
+        self.i2c.write_read(ADDRESS, &tx, &mut rx)?;
         Ok(rx[0])
     }
 
-    fn write_register(&mut self, register: Register, value: u8) -> Result<(), Error> {
+    fn write_register(&mut self, register: Register, value: u8) -> Result<(), E> {
         let tx = [register.addr(), value];
-        self.twim.write(ADDRESS, &tx)?;
+        self.i2c.write(ADDRESS, &tx)?;
         Ok(())
     }
 }
@@ -208,7 +184,7 @@ enum Register {
     Wakeup1 = 0xA7,
     Motion = 0xEC,
     IrqCtl = 0xFA,
-    //PowerMode = 0xA5,
+    PowerMode = 0xA5,
 }
 
 impl Register {
@@ -0,0 +1,411 @@
+//! Tianyi Hexin HRS3300 heart rate sensor driver
+//!
+//! Pins:
+//! * P0.06 : I²C SDA (shared with the touch panel)
+//! * P0.07 : I²C SCL (shared with the touch panel)
+//!
+//! I²C
+//! Device address : 0x44
+//!
+//! The driver only relies on the embedded-hal blocking I²C traits.
+
+use crate::hal::prelude::{
+    _embedded_hal_blocking_i2c_Write as Write, _embedded_hal_blocking_i2c_WriteRead as WriteRead,
+};
+
+/// HRS3300 I2C address
+pub const ADDRESS: u8 = 0x44;
+
+pub const DEVICE_ID: u8 = 0x21;
+
+const ENABLE_HEN: u8 = 1 << 7;
+const ENABLE_WAIT_TIME_MASK: u8 = 0x70;
+const ENABLE_PDRIVE1: u8 = 1 << 3;
+const PDRIVER_PDRIVE0: u8 = 1 << 6;
+/// Power on, low nibble is reserved and must be 0xF
+const PDRIVER_PON: u8 = (1 << 5) | 0x0F;
+/// 16 bit HRS and ALS resolution
+const RES_16_BIT: u8 = 0x66;
+const HGAIN_SHIFT: u8 = 2;
+const HGAIN_MASK: u8 = 0x1C;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// Unexpected device ID
+    DeviceId(u8),
+}
+
+/// Time between conversions
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum WaitTime {
+    Ms800,
+    Ms400,
+    Ms200,
+    Ms100,
+    Ms75,
+    Ms50,
+    Ms12_5,
+    Ms0,
+}
+
+impl WaitTime {
+    fn bits(self) -> u8 {
+        (self as u8) << 4
+    }
+}
+
+/// LED drive current
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Drive {
+    Ma12_5,
+    Ma20,
+    Ma30,
+    Ma40,
+}
+
+/// Photodiode gain
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Gain {
+    X1,
+    X2,
+    X4,
+    X8,
+    X64,
+}
+
+impl Gain {
+    fn bits(self) -> u8 {
+        let hgain = match self {
+            Gain::X1 => 0,
+            Gain::X2 => 1,
+            Gain::X4 => 2,
+            Gain::X8 => 3,
+            Gain::X64 => 4,
+        };
+        (hgain << HGAIN_SHIFT) & HGAIN_MASK
+    }
+}
+
+/// HRS3300 driver
+pub struct Hrs3300<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C, E> Hrs3300<I2C>
+where
+    I2C: WriteRead<Error = E> + Write<Error = E>,
+{
+    pub fn new(i2c: I2C) -> Self {
+        Hrs3300 { i2c }
+    }
+
+    pub fn free(self) -> I2C {
+        self.i2c
+    }
+
+    /// Check the device ID and configure it, leaves the sensor disabled
+    pub fn init(&mut self) -> Result<(), Error<E>> {
+        let id = self.read_register(Register::Id)?;
+        if id != DEVICE_ID {
+            return Err(Error::DeviceId(id));
+        }
+        self.write_register(Register::Enable, WaitTime::Ms12_5.bits())?;
+        self.write_register(Register::PDriver, PDRIVER_PON)?;
+        self.write_register(Register::Res, RES_16_BIT)?;
+        self.set_drive(Drive::Ma20)?;
+        self.set_gain(Gain::X64)?;
+        Ok(())
+    }
+
+    /// Turn on the LED and start converting
+    pub fn enable(&mut self) -> Result<(), Error<E>> {
+        self.modify_register(Register::Enable, |v| v | ENABLE_HEN)?;
+        self.modify_register(Register::PDriver, |v| v | PDRIVER_PON)
+    }
+
+    pub fn disable(&mut self) -> Result<(), Error<E>> {
+        self.modify_register(Register::Enable, |v| v & !ENABLE_HEN)?;
+        self.modify_register(Register::PDriver, |v| v & !PDRIVER_PON)
+    }
+
+    pub fn set_wait_time(&mut self, wait_time: WaitTime) -> Result<(), Error<E>> {
+        self.modify_register(Register::Enable, |v| {
+            (v & !ENABLE_WAIT_TIME_MASK) | wait_time.bits()
+        })
+    }
+
+    /// The drive current is split across a bit in ENABLE and one in PDRIVER
+    pub fn set_drive(&mut self, drive: Drive) -> Result<(), Error<E>> {
+        let bits = drive as u8;
+        self.modify_register(Register::Enable, |v| {
+            let v = v & !ENABLE_PDRIVE1;
+            if bits & 0b10 != 0 {
+                v | ENABLE_PDRIVE1
+            } else {
+                v
+            }
+        })?;
+        self.modify_register(Register::PDriver, |v| {
+            let v = v & !PDRIVER_PDRIVE0;
+            if bits & 0b01 != 0 {
+                v | PDRIVER_PDRIVE0
+            } else {
+                v
+            }
+        })
+    }
+
+    pub fn set_gain(&mut self, gain: Gain) -> Result<(), Error<E>> {
+        self.write_register(Register::HGain, gain.bits())
+    }
+
+    /// Raw PPG (green LED reflection) reading
+    pub fn read_hrs(&mut self) -> Result<u32, Error<E>> {
+        let m = self.read_register(Register::C0DataM)? as u32;
+        let h = self.read_register(Register::C0DataH)? as u32;
+        let l = self.read_register(Register::C0DataL)? as u32;
+        Ok((m << 8) | ((h & 0x0F) << 4) | (l & 0x0F) | ((l & 0x30) << 12))
+    }
+
+    /// Raw ambient light reading
+    pub fn read_als(&mut self) -> Result<u32, Error<E>> {
+        let m = self.read_register(Register::C1DataM)? as u32;
+        let h = self.read_register(Register::C1DataH)? as u32;
+        let l = self.read_register(Register::C1DataL)? as u32;
+        Ok((m << 3) | ((h & 0x3F) << 11) | (l & 0x07))
+    }
+
+    fn modify_register<F>(&mut self, register: Register, f: F) -> Result<(), Error<E>>
+    where
+        F: FnOnce(u8) -> u8,
+    {
+        let value = self.read_register(register)?;
+        self.write_register(register, f(value))
+    }
+
+    fn read_register(&mut self, register: Register) -> Result<u8, Error<E>> {
+        let tx = [register.addr()];
+        let mut rx = [0_u8; 1];
+        self.i2c
+            .write_read(ADDRESS, &tx, &mut rx)
+            .map_err(Error::I2c)?;
+        Ok(rx[0])
+    }
+
+    fn write_register(&mut self, register: Register, value: u8) -> Result<(), Error<E>> {
+        let tx = [register.addr(), value];
+        self.i2c.write(ADDRESS, &tx).map_err(Error::I2c)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[repr(u8)]
+enum Register {
+    Id = 0x00,
+    Enable = 0x01,
+    C1DataM = 0x08,
+    C0DataM = 0x09,
+    C0DataH = 0x0A,
+    PDriver = 0x0C,
+    C1DataH = 0x0D,
+    C1DataL = 0x0E,
+    C0DataL = 0x0F,
+    Res = 0x16,
+    HGain = 0x17,
+}
+
+impl Register {
+    fn addr(self) -> u8 {
+        self as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    struct BusError;
+
+    struct FakeSensor {
+        registers: [u8; 0x20],
+        /// Register writes
+        writes: Vec<(u8, u8)>,
+        fail: bool,
+    }
+
+    impl FakeSensor {
+        fn new() -> Self {
+            let mut registers = [0; 0x20];
+            registers[Register::Id as usize] = DEVICE_ID;
+            FakeSensor {
+                registers,
+                writes: Vec::new(),
+                fail: false,
+            }
+        }
+
+        fn get(&self, register: Register) -> u8 {
+            self.registers[register as usize]
+        }
+
+        fn set(&mut self, register: Register, value: u8) {
+            self.registers[register as usize] = value;
+        }
+    }
+
+    impl Write for FakeSensor {
+        type Error = BusError;
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+            assert_eq!(address, ADDRESS);
+            if self.fail {
+                return Err(BusError);
+            }
+            assert_eq!(bytes.len(), 2);
+            self.registers[bytes[0] as usize] = bytes[1];
+            self.writes.push((bytes[0], bytes[1]));
+            Ok(())
+        }
+    }
+
+    impl WriteRead for FakeSensor {
+        type Error = BusError;
+
+        fn write_read(&mut self, address: u8, tx: &[u8], rx: &mut [u8]) -> Result<(), Self::Error> {
+            assert_eq!(address, ADDRESS);
+            if self.fail {
+                return Err(BusError);
+            }
+            assert_eq!(rx.len(), 1);
+            rx[0] = self.registers[tx[0] as usize];
+            Ok(())
+        }
+    }
+
+    fn initialized() -> Hrs3300<FakeSensor> {
+        let mut hrs = Hrs3300::new(FakeSensor::new());
+        hrs.init().unwrap();
+        hrs
+    }
+
+    #[test]
+    fn init() {
+        let sensor = initialized().free();
+        // 12.5 ms wait, 20 mA drive, sensor off
+        assert_eq!(sensor.get(Register::Enable), 0x60);
+        assert_eq!(sensor.get(Register::PDriver), PDRIVER_PON | PDRIVER_PDRIVE0);
+        assert_eq!(sensor.get(Register::Res), RES_16_BIT);
+        assert_eq!(sensor.get(Register::HGain), 0x10);
+    }
+
+    #[test]
+    fn init_checks_the_device_id() {
+        let mut sensor = FakeSensor::new();
+        sensor.set(Register::Id, 0x42);
+        let mut hrs = Hrs3300::new(sensor);
+        assert!(matches!(hrs.init(), Err(Error::DeviceId(0x42))));
+        assert!(hrs.free().writes.is_empty());
+    }
+
+    #[test]
+    fn enable_and_disable() {
+        let mut hrs = initialized();
+        hrs.enable().unwrap();
+        let sensor = hrs.free();
+        assert_eq!(sensor.get(Register::Enable), ENABLE_HEN | 0x60);
+        assert_eq!(sensor.get(Register::PDriver), PDRIVER_PON | PDRIVER_PDRIVE0);
+
+        let mut hrs = Hrs3300::new(sensor);
+        hrs.disable().unwrap();
+        let sensor = hrs.free();
+        // The wait time and drive current are kept for the next enable
+        assert_eq!(sensor.get(Register::Enable), 0x60);
+        assert_eq!(sensor.get(Register::PDriver), PDRIVER_PDRIVE0);
+    }
+
+    #[test]
+    fn wait_time_bits() {
+        let mut hrs = initialized();
+        hrs.enable().unwrap();
+        for (wait_time, bits) in [
+            (WaitTime::Ms800, 0x00),
+            (WaitTime::Ms100, 0x30),
+            (WaitTime::Ms0, 0x70),
+        ] {
+            hrs.set_wait_time(wait_time).unwrap();
+            let sensor = hrs.free();
+            assert_eq!(sensor.get(Register::Enable), ENABLE_HEN | bits);
+            hrs = Hrs3300::new(sensor);
+        }
+    }
+
+    #[test]
+    fn drive_is_split_across_two_registers() {
+        let mut hrs = initialized();
+        hrs.enable().unwrap();
+        for (drive, pdrive1, pdrive0) in [
+            (Drive::Ma12_5, 0, 0),
+            (Drive::Ma20, 0, PDRIVER_PDRIVE0),
+            (Drive::Ma30, ENABLE_PDRIVE1, 0),
+            (Drive::Ma40, ENABLE_PDRIVE1, PDRIVER_PDRIVE0),
+        ] {
+            hrs.set_drive(drive).unwrap();
+            let sensor = hrs.free();
+            assert_eq!(sensor.get(Register::Enable), ENABLE_HEN | 0x60 | pdrive1);
+            assert_eq!(sensor.get(Register::PDriver), PDRIVER_PON | pdrive0);
+            hrs = Hrs3300::new(sensor);
+        }
+    }
+
+    #[test]
+    fn gain_bits() {
+        let mut hrs = initialized();
+        for (gain, bits) in [
+            (Gain::X1, 0x00),
+            (Gain::X2, 0x04),
+            (Gain::X4, 0x08),
+            (Gain::X8, 0x0C),
+            (Gain::X64, 0x10),
+        ] {
+            hrs.set_gain(gain).unwrap();
+            let sensor = hrs.free();
+            assert_eq!(sensor.get(Register::HGain), bits);
+            hrs = Hrs3300::new(sensor);
+        }
+    }
+
+    #[test]
+    fn readout_is_assembled_from_three_registers() {
+        let mut sensor = FakeSensor::new();
+        // Bits outside the data fields are set, they have to be masked off
+        sensor.set(Register::C0DataM, 0xAB);
+        sensor.set(Register::C0DataH, 0xFC);
+        sensor.set(Register::C0DataL, 0xF5);
+        sensor.set(Register::C1DataM, 0x12);
+        sensor.set(Register::C1DataH, 0xFF);
+        sensor.set(Register::C1DataL, 0xFD);
+        let mut hrs = Hrs3300::new(sensor);
+        // L[5:4] M[7:0] H[3:0] L[3:0]
+        assert_eq!(
+            hrs.read_hrs().unwrap(),
+            0x3 << 16 | 0xAB << 8 | 0xC << 4 | 0x5
+        );
+        // H[5:0] M[7:0] L[2:0]
+        assert_eq!(hrs.read_als().unwrap(), 0x3F << 11 | 0x12 << 3 | 0x5);
+    }
+
+    #[test]
+    fn bus_errors_are_passed_on() {
+        let mut sensor = FakeSensor::new();
+        sensor.fail = true;
+        let mut hrs = Hrs3300::new(sensor);
+        assert!(matches!(hrs.init(), Err(Error::I2c(BusError))));
+        assert!(matches!(hrs.enable(), Err(Error::I2c(BusError))));
+        assert!(matches!(hrs.read_hrs(), Err(Error::I2c(BusError))));
+    }
+}
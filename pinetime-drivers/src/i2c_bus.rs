@@ -0,0 +1,12 @@
+//! TWIM1 is shared between the CST816S touch panel, the BMA421 accelerometer and the
+//! HRS3300 heart rate sensor
+//!
+//! Uses shared-bus' atomic-check mutex, so all users of the bus must run at the
+//! same priority (it panics on contention instead of blocking).
+
+use crate::hal::{pac, twim::Twim};
+
+pub type Twim1 = Twim<pac::TWIM1>;
+pub type I2cBusMutex = shared_bus::AtomicCheckMutex<Twim1>;
+pub type I2cBusManager = shared_bus::BusManager<I2cBusMutex>;
+pub type I2cProxy = shared_bus::I2cProxy<'static, I2cBusMutex>;
@@ -4,13 +4,21 @@ use nrf52832_hal as hal;
 
 pub use display_interface;
 pub use display_interface_spi;
+pub use embedded_storage;
+pub use shared_bus;
 pub use st7789;
 
 pub mod animated_st7789;
 pub mod backlight;
 pub mod battery_controller;
+pub mod bma421;
 pub mod button;
 pub mod cst816s;
+pub mod hrs3300;
+pub mod i2c_bus;
 pub mod lcd;
 pub mod motor_controller;
+pub mod power;
+pub mod spi_bus;
+pub mod spi_flash;
 pub mod watchdog;
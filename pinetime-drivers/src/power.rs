@@ -0,0 +1,50 @@
+//! Chip power modes
+
+use crate::hal::pac::POWER;
+use pinetime_common::ResetReason;
+
+pub struct Power {
+    power: POWER,
+}
+
+impl Power {
+    pub fn new(power: POWER) -> Self {
+        Power { power }
+    }
+
+    /// Why the chip last reset, cleared so the next reset is reported on its own.
+    /// Nothing is set after a power on
+    pub fn take_reset_reason(&mut self) -> ResetReason {
+        let reas = self.power.resetreas.read();
+        let reason = if reas.dog().bit_is_set() {
+            ResetReason::Watchdog
+        } else if reas.lockup().bit_is_set() {
+            ResetReason::Lockup
+        } else if reas.sreq().bit_is_set() {
+            ResetReason::Soft
+        } else if reas.resetpin().bit_is_set() {
+            ResetReason::Pin
+        } else if reas.off().bit_is_set() {
+            ResetReason::WakeUp
+        } else if reas.bits() != 0 {
+            ResetReason::Other
+        } else {
+            ResetReason::PowerOn
+        };
+        // Cleared by writing 1s
+        self.power
+            .resetreas
+            .write(|w| unsafe { w.bits(reas.bits()) });
+        reason
+    }
+
+    /// Turn off everything but the wakeup sources, waking up is a reset.
+    /// GPIO outputs keep their level, switch off whatever draws current first.
+    pub fn system_off(&mut self) -> ! {
+        self.power.systemoff.write(|w| w.systemoff().enter());
+        // System OFF is only emulated while a debugger is attached, this is as far as it gets
+        loop {
+            cortex_m::asm::wfe();
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! SPIM0 is shared between the ST7789 display and the SPI NOR flash
+//!
+//! The bus belongs to whichever device has its chip select asserted. [`SpiBus::split`]
+//! hands out a [`SpiProxy`] and a [`ChipSelect`] for each device. Asserting a chip
+//! select claims the bus, and fails with [`Error::Busy`] while the other device holds
+//! it. Deasserting it hands the bus back. A proxy only clocks data while its own
+//! device is selected, so a transfer can never end up on the other device.
+
+use crate::hal::{pac, spim::Spim};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+use embedded_hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+pub type Spim0 = Spim<pac::SPIM0>;
+pub type Spim0Proxy = SpiProxy<'static, Spim0>;
+pub type Spim0ChipSelect<P> = ChipSelect<'static, P>;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error<E> {
+    Spi(E),
+    Pin(E),
+    /// The other device has its chip select asserted
+    Busy,
+    /// Transfer without asserting the device's chip select first
+    NotSelected,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[repr(u8)]
+pub enum Device {
+    Display = 1,
+    Flash = 2,
+}
+
+const NO_DEVICE: u8 = 0;
+
+pub struct SpiBus<SPI> {
+    spi: UnsafeCell<SPI>,
+    owner: AtomicU8,
+}
+
+// The bus is only reached through the selected device's proxy
+unsafe impl<SPI: Send> Sync for SpiBus<SPI> {}
+
+pub struct Parts<'a, SPI, DisplayCs, FlashCs> {
+    pub display_spi: SpiProxy<'a, SPI>,
+    pub display_cs: ChipSelect<'a, DisplayCs>,
+    pub flash_spi: SpiProxy<'a, SPI>,
+    pub flash_cs: ChipSelect<'a, FlashCs>,
+}
+
+impl<SPI> SpiBus<SPI> {
+    pub const fn new(spi: SPI) -> Self {
+        SpiBus {
+            spi: UnsafeCell::new(spi),
+            owner: AtomicU8::new(NO_DEVICE),
+        }
+    }
+
+    /// One proxy and chip select per device, taking the bus for good keeps there from
+    /// being more than one of each
+    pub fn split<DisplayCs, FlashCs>(
+        &mut self,
+        display_cs: DisplayCs,
+        flash_cs: FlashCs,
+    ) -> Parts<'_, SPI, DisplayCs, FlashCs> {
+        let bus = &*self;
+        Parts {
+            display_spi: SpiProxy::new(bus, Device::Display),
+            display_cs: ChipSelect::new(&bus.owner, Device::Display, display_cs),
+            flash_spi: SpiProxy::new(bus, Device::Flash),
+            flash_cs: ChipSelect::new(&bus.owner, Device::Flash, flash_cs),
+        }
+    }
+}
+
+pub struct SpiProxy<'a, SPI> {
+    bus: &'a SpiBus<SPI>,
+    device: Device,
+}
+
+impl<'a, SPI> SpiProxy<'a, SPI> {
+    fn new(bus: &'a SpiBus<SPI>, device: Device) -> Self {
+        SpiProxy { bus, device }
+    }
+
+    fn spi<E>(&mut self) -> Result<&mut SPI, Error<E>> {
+        if self.bus.owner.load(Ordering::Acquire) != self.device as u8 {
+            return Err(Error::NotSelected);
+        }
+        // Nothing else touches the bus until this device's chip select is deasserted
+        Ok(unsafe { &mut *self.bus.spi.get() })
+    }
+}
+
+impl<'a, SPI> Write<u8> for SpiProxy<'a, SPI>
+where
+    SPI: Write<u8>,
+{
+    type Error = Error<SPI::Error>;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.spi()?.write(words).map_err(Error::Spi)
+    }
+}
+
+impl<'a, SPI> Transfer<u8> for SpiProxy<'a, SPI>
+where
+    SPI: Transfer<u8>,
+{
+    type Error = Error<SPI::Error>;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.spi()?.transfer(words).map_err(Error::Spi)
+    }
+}
+
+pub struct ChipSelect<'a, P> {
+    owner: &'a AtomicU8,
+    device: Device,
+    pin: P,
+}
+
+impl<'a, P> ChipSelect<'a, P> {
+    fn new(owner: &'a AtomicU8, device: Device, pin: P) -> Self {
+        ChipSelect { owner, device, pin }
+    }
+}
+
+impl<'a, P> OutputPin for ChipSelect<'a, P>
+where
+    P: OutputPin,
+{
+    type Error = Error<P::Error>;
+
+    /// Select the device, claiming the bus
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let device = self.device as u8;
+        match self
+            .owner
+            .compare_exchange(NO_DEVICE, device, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => (),
+            Err(owner) if owner == device => (),
+            Err(_) => return Err(Error::Busy),
+        }
+        self.pin.set_low().map_err(|e| {
+            self.owner.store(NO_DEVICE, Ordering::Release);
+            Error::Pin(e)
+        })
+    }
+
+    /// Deselect the device, handing the bus back if it had it
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let result = self.pin.set_high().map_err(Error::Pin);
+        self.owner
+            .compare_exchange(
+                self.device as u8,
+                NO_DEVICE,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .ok();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::convert::Infallible;
+    use std::{cell::Cell, rc::Rc, vec::Vec};
+
+    #[derive(Default)]
+    struct FakeSpi {
+        written: Vec<u8>,
+    }
+
+    impl Write<u8> for FakeSpi {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+            self.written.extend_from_slice(words);
+            Ok(())
+        }
+    }
+
+    impl Transfer<u8> for FakeSpi {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+            self.written.extend_from_slice(words);
+            words.iter_mut().for_each(|w| *w = !*w);
+            Ok(words)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct FakePin(Rc<Cell<bool>>);
+
+    impl FakePin {
+        fn high() -> Self {
+            FakePin(Rc::new(Cell::new(true)))
+        }
+
+        fn is_high(&self) -> bool {
+            self.0.get()
+        }
+    }
+
+    impl OutputPin for FakePin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transfers_need_the_chip_select() {
+        let mut bus = SpiBus::new(FakeSpi::default());
+        let mut parts = bus.split(FakePin::high(), FakePin::high());
+        assert_eq!(parts.display_spi.write(&[1]), Err(Error::NotSelected));
+        assert_eq!(parts.flash_spi.transfer(&mut [1]), Err(Error::NotSelected));
+
+        parts.flash_cs.set_low().unwrap();
+        assert_eq!(parts.flash_spi.transfer(&mut [0x0F]), Ok(&[0xF0][..]));
+        parts.flash_cs.set_high().unwrap();
+        assert_eq!(parts.flash_spi.write(&[2]), Err(Error::NotSelected));
+
+        drop(parts);
+        assert_eq!(bus.spi.into_inner().written, [0x0F]);
+    }
+
+    #[test]
+    fn selected_device_keeps_the_bus_until_deselected() {
+        let display_pin = FakePin::high();
+        let flash_pin = FakePin::high();
+        let mut bus = SpiBus::new(FakeSpi::default());
+        let mut parts = bus.split(display_pin.clone(), flash_pin.clone());
+
+        parts.display_cs.set_low().unwrap();
+        assert!(!display_pin.is_high());
+        // Asserting it again while it's held is fine
+        parts.display_cs.set_low().unwrap();
+
+        assert_eq!(parts.flash_cs.set_low(), Err(Error::Busy));
+        assert!(flash_pin.is_high());
+        assert_eq!(parts.flash_spi.write(&[1]), Err(Error::NotSelected));
+        // Deselecting a device that doesn't have the bus doesn't release it
+        parts.flash_cs.set_high().unwrap();
+        assert_eq!(parts.display_spi.write(&[2]), Ok(()));
+
+        parts.display_cs.set_high().unwrap();
+        assert!(display_pin.is_high());
+        assert_eq!(parts.display_spi.write(&[3]), Err(Error::NotSelected));
+
+        parts.flash_cs.set_low().unwrap();
+        assert!(!flash_pin.is_high());
+        assert_eq!(parts.flash_spi.write(&[4]), Ok(()));
+        assert_eq!(parts.display_cs.set_low(), Err(Error::Busy));
+        parts.flash_cs.set_high().unwrap();
+
+        drop(parts);
+        assert_eq!(bus.spi.into_inner().written, [2, 4]);
+    }
+}
@@ -0,0 +1,627 @@
+//! XTX XT25F32B 4MB SPI NOR flash driver
+//!
+//! Pins:
+//! * P0.05 : Chip select
+//! * P0.02 : SPI SCK (shared with the LCD)
+//! * P0.03 : SPI MOSI (shared with the LCD)
+//! * P0.04 : SPI MISO
+//!
+//! The driver only relies on the embedded-hal blocking SPI traits, any bus (or a fake
+//! flash in RAM) will do.
+//!
+//! Everything but [`SpiFlash::release_deep_power_down`] is refused with
+//! [`Error::PoweredDown`] while the flash is in deep power-down, it wouldn't answer.
+
+use crate::hal::gpio::{p0, Output, PushPull};
+use core::fmt;
+use embedded_hal::{
+    blocking::{
+        delay::DelayUs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+pub type FlashCsPin = p0::P0_05<Output<PushPull>>;
+
+pub const CAPACITY: u32 = 4 * 1024 * 1024;
+pub const PAGE_SIZE: u32 = 256;
+pub const SECTOR_SIZE: u32 = 4 * 1024;
+pub const BLOCK_32K_SIZE: u32 = 32 * 1024;
+pub const BLOCK_64K_SIZE: u32 = 64 * 1024;
+
+/// Expected JEDEC ID of the XT25F32B
+pub const JEDEC_ID: JedecId = JedecId {
+    manufacturer: 0x0B,
+    memory_type: 0x40,
+    capacity: 0x16,
+};
+
+/// Time to come back out of deep power-down
+const RELEASE_DPD_DELAY_US: u8 = 30;
+
+/// Status reads before giving up on a program or erase. A read takes a few microseconds
+/// at 8 MHz, so this is well past the longest (64KB block) erase time of ~2 seconds.
+pub const MAX_BUSY_POLLS: u32 = 2_000_000;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    ChipSelect,
+    OutOfBounds,
+    NotAligned,
+    /// Only waking it up is accepted in deep power-down
+    PoweredDown,
+    /// Still busy after [`MAX_BUSY_POLLS`] status reads
+    Timeout,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+impl fmt::Display for JedecId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02X} {:02X} {:02X}",
+            self.manufacturer, self.memory_type, self.capacity
+        )
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[repr(u8)]
+enum Command {
+    WriteEnable = 0x06,
+    ReadStatus = 0x05,
+    FastRead = 0x0B,
+    PageProgram = 0x02,
+    SectorErase = 0x20,
+    BlockErase32K = 0x52,
+    BlockErase64K = 0xD8,
+    ReadJedecId = 0x9F,
+    DeepPowerDown = 0xB9,
+    ReleaseDeepPowerDown = 0xAB,
+}
+
+impl Command {
+    fn as_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Status register write-in-progress bit
+const STATUS_WIP: u8 = 1 << 0;
+
+pub struct SpiFlash<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    powered_down: bool,
+}
+
+impl<SPI, CS, E> SpiFlash<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    pub fn new(spi: SPI, mut cs: CS) -> Self {
+        cs.set_high().ok();
+        SpiFlash {
+            spi,
+            cs,
+            powered_down: false,
+        }
+    }
+
+    pub fn is_powered_down(&self) -> bool {
+        self.powered_down
+    }
+
+    pub fn jedec_id(&mut self) -> Result<JedecId, Error<E>> {
+        self.check_awake()?;
+        let mut buf = [Command::ReadJedecId.as_u8(), 0, 0, 0];
+        self.transfer(&mut buf)?;
+        Ok(JedecId {
+            manufacturer: buf[1],
+            memory_type: buf[2],
+            capacity: buf[3],
+        })
+    }
+
+    pub fn read_status(&mut self) -> Result<u8, Error<E>> {
+        self.check_awake()?;
+        let mut buf = [Command::ReadStatus.as_u8(), 0];
+        self.transfer(&mut buf)?;
+        Ok(buf[1])
+    }
+
+    pub fn is_busy(&mut self) -> Result<bool, Error<E>> {
+        Ok(self.read_status()? & STATUS_WIP != 0)
+    }
+
+    pub fn wait_while_busy(&mut self) -> Result<(), Error<E>> {
+        for _ in 0..MAX_BUSY_POLLS {
+            if !self.is_busy()? {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    pub fn fast_read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), Error<E>> {
+        self.check_awake()?;
+        Self::check_bounds(addr, buf.len())?;
+        let cmd = Self::addr_command(Command::FastRead, addr);
+        // Command, 24 bit address and a dummy byte
+        let header = [cmd[0], cmd[1], cmd[2], cmd[3], 0];
+        self.select()?;
+        let res = self
+            .spi
+            .write(&header)
+            .and_then(|_| self.spi.transfer(buf).map(|_| ()));
+        self.deselect();
+        res.map_err(Error::Spi)
+    }
+
+    /// Program up to a page of data, must not cross a page boundary
+    pub fn page_program(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
+        self.check_awake()?;
+        Self::check_bounds(addr, data.len())?;
+        if (addr % PAGE_SIZE) as usize + data.len() > PAGE_SIZE as usize {
+            return Err(Error::NotAligned);
+        }
+        self.write_enable()?;
+        let cmd = Self::addr_command(Command::PageProgram, addr);
+        self.select()?;
+        let res = self.spi.write(&cmd).and_then(|_| self.spi.write(data));
+        self.deselect();
+        res.map_err(Error::Spi)?;
+        self.wait_while_busy()
+    }
+
+    /// Program any amount of data, split up into page programs
+    pub fn program(&mut self, mut addr: u32, mut data: &[u8]) -> Result<(), Error<E>> {
+        Self::check_bounds(addr, data.len())?;
+        while !data.is_empty() {
+            let page_remaining = (PAGE_SIZE - (addr % PAGE_SIZE)) as usize;
+            let (chunk, rest) = data.split_at(page_remaining.min(data.len()));
+            self.page_program(addr, chunk)?;
+            addr += chunk.len() as u32;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    pub fn erase_sector(&mut self, addr: u32) -> Result<(), Error<E>> {
+        self.erase(Command::SectorErase, SECTOR_SIZE, addr)
+    }
+
+    pub fn erase_block_32k(&mut self, addr: u32) -> Result<(), Error<E>> {
+        self.erase(Command::BlockErase32K, BLOCK_32K_SIZE, addr)
+    }
+
+    pub fn erase_block_64k(&mut self, addr: u32) -> Result<(), Error<E>> {
+        self.erase(Command::BlockErase64K, BLOCK_64K_SIZE, addr)
+    }
+
+    /// Erase `from..to` using the largest erase commands possible
+    pub fn erase_range(&mut self, from: u32, to: u32) -> Result<(), Error<E>> {
+        self.check_awake()?;
+        if !from.is_multiple_of(SECTOR_SIZE) || !to.is_multiple_of(SECTOR_SIZE) || to < from {
+            return Err(Error::NotAligned);
+        }
+        if to > CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+        let mut addr = from;
+        while addr < to {
+            let remaining = to - addr;
+            if addr.is_multiple_of(BLOCK_64K_SIZE) && remaining >= BLOCK_64K_SIZE {
+                self.erase_block_64k(addr)?;
+                addr += BLOCK_64K_SIZE;
+            } else if addr.is_multiple_of(BLOCK_32K_SIZE) && remaining >= BLOCK_32K_SIZE {
+                self.erase_block_32k(addr)?;
+                addr += BLOCK_32K_SIZE;
+            } else {
+                self.erase_sector(addr)?;
+                addr += SECTOR_SIZE;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lowest power mode, only `release_deep_power_down` is accepted afterwards
+    pub fn deep_power_down(&mut self) -> Result<(), Error<E>> {
+        self.command(Command::DeepPowerDown)?;
+        self.powered_down = true;
+        Ok(())
+    }
+
+    /// Wake up from deep power-down, fine to call when it's already awake
+    pub fn release_deep_power_down<D>(&mut self, delay: &mut D) -> Result<(), Error<E>>
+    where
+        D: DelayUs<u8>,
+    {
+        self.command(Command::ReleaseDeepPowerDown)?;
+        delay.delay_us(RELEASE_DPD_DELAY_US);
+        self.powered_down = false;
+        Ok(())
+    }
+
+    pub fn free(self) -> (SPI, CS) {
+        (self.spi, self.cs)
+    }
+
+    fn write_enable(&mut self) -> Result<(), Error<E>> {
+        self.command(Command::WriteEnable)
+    }
+
+    fn erase(&mut self, cmd: Command, size: u32, addr: u32) -> Result<(), Error<E>> {
+        self.check_awake()?;
+        if !addr.is_multiple_of(size) {
+            return Err(Error::NotAligned);
+        }
+        Self::check_bounds(addr, size as usize)?;
+        self.write_enable()?;
+        let cmd = Self::addr_command(cmd, addr);
+        self.select()?;
+        let res = self.spi.write(&cmd);
+        self.deselect();
+        res.map_err(Error::Spi)?;
+        self.wait_while_busy()
+    }
+
+    fn command(&mut self, cmd: Command) -> Result<(), Error<E>> {
+        self.select()?;
+        let res = self.spi.write(&[cmd.as_u8()]);
+        self.deselect();
+        res.map_err(Error::Spi)
+    }
+
+    fn transfer(&mut self, buf: &mut [u8]) -> Result<(), Error<E>> {
+        self.select()?;
+        let res = self.spi.transfer(buf).map(|_| ());
+        self.deselect();
+        res.map_err(Error::Spi)
+    }
+
+    fn select(&mut self) -> Result<(), Error<E>> {
+        self.cs.set_low().map_err(|_| Error::ChipSelect)
+    }
+
+    fn deselect(&mut self) {
+        self.cs.set_high().ok();
+    }
+
+    fn check_awake(&self) -> Result<(), Error<E>> {
+        if self.powered_down {
+            Err(Error::PoweredDown)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn addr_command(cmd: Command, addr: u32) -> [u8; 4] {
+        let a = addr.to_be_bytes();
+        [cmd.as_u8(), a[1], a[2], a[3]]
+    }
+
+    fn check_bounds(addr: u32, len: usize) -> Result<(), Error<E>> {
+        match addr.checked_add(len as u32) {
+            Some(end) if end <= CAPACITY => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+}
+
+impl<SPI, CS, E> ReadNorFlash for SpiFlash<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    type Error = Error<E>;
+
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.fast_read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        CAPACITY as usize
+    }
+}
+
+impl<SPI, CS, E> NorFlash for SpiFlash<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase_range(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.program(offset, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::convert::Infallible;
+    use std::{cell::RefCell, rc::Rc, vec, vec::Vec};
+
+    /// XT25F32B in RAM, driven through the chip select and SPI halves below
+    struct Chip {
+        memory: Vec<u8>,
+        selected: bool,
+        /// Bytes clocked in since the chip select was asserted
+        tx: Vec<u8>,
+        write_enabled: bool,
+        powered_down: bool,
+        /// Status reads left that report a program or erase in progress
+        busy_polls: u32,
+        stuck_busy: bool,
+        /// Command byte of every transaction the chip acted on
+        commands: Vec<u8>,
+    }
+
+    impl Chip {
+        fn new() -> Rc<RefCell<Self>> {
+            Rc::new(RefCell::new(Chip {
+                memory: vec![0xFF; CAPACITY as usize],
+                selected: false,
+                tx: Vec::new(),
+                write_enabled: false,
+                powered_down: false,
+                busy_polls: 0,
+                stuck_busy: false,
+                commands: Vec::new(),
+            }))
+        }
+
+        fn clock(&mut self, byte: u8) -> u8 {
+            assert!(self.selected, "clocked data without chip select");
+            let index = self.tx.len();
+            self.tx.push(byte);
+            if self.powered_down || index == 0 {
+                return 0;
+            }
+            match self.tx[0] {
+                0x9F if index <= 3 => [
+                    JEDEC_ID.manufacturer,
+                    JEDEC_ID.memory_type,
+                    JEDEC_ID.capacity,
+                ][index - 1],
+                0x05 => {
+                    let busy = self.stuck_busy || self.busy_polls > 0;
+                    self.busy_polls = self.busy_polls.saturating_sub(1);
+                    (busy as u8) | ((self.write_enabled as u8) << 1)
+                }
+                0x0B if index >= 5 => self.memory[self.addr() as usize + index - 5],
+                _ => 0,
+            }
+        }
+
+        fn addr(&self) -> u32 {
+            u32::from_be_bytes([0, self.tx[1], self.tx[2], self.tx[3]])
+        }
+
+        fn end_transaction(&mut self) {
+            let tx = core::mem::take(&mut self.tx);
+            let cmd = match tx.first() {
+                Some(&cmd) => cmd,
+                None => return,
+            };
+            if self.powered_down {
+                if cmd == 0xAB {
+                    self.powered_down = false;
+                    self.commands.push(cmd);
+                }
+                return;
+            }
+            self.commands.push(cmd);
+            self.tx = tx;
+            match self.tx[0] {
+                0x06 => self.write_enabled = true,
+                0x02 if self.write_enabled => {
+                    // Wraps around within the page like the real thing
+                    let addr = self.addr();
+                    for (i, b) in self.tx[4..].iter().enumerate() {
+                        let a = (addr & !(PAGE_SIZE - 1)) | ((addr + i as u32) % PAGE_SIZE);
+                        self.memory[a as usize] &= b;
+                    }
+                    self.write_enabled = false;
+                    self.busy_polls = 2;
+                }
+                0x20 | 0x52 | 0xD8 if self.write_enabled => {
+                    let size = match self.tx[0] {
+                        0x20 => SECTOR_SIZE,
+                        0x52 => BLOCK_32K_SIZE,
+                        _ => BLOCK_64K_SIZE,
+                    };
+                    let start = (self.addr() & !(size - 1)) as usize;
+                    self.memory[start..start + size as usize].fill(0xFF);
+                    self.write_enabled = false;
+                    self.busy_polls = 3;
+                }
+                0xB9 => self.powered_down = true,
+                _ => (),
+            }
+            self.tx.clear();
+        }
+    }
+
+    struct FakeSpi(Rc<RefCell<Chip>>);
+
+    impl Write<u8> for FakeSpi {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+            let mut chip = self.0.borrow_mut();
+            words.iter().for_each(|&w| {
+                chip.clock(w);
+            });
+            Ok(())
+        }
+    }
+
+    impl Transfer<u8> for FakeSpi {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+            let mut chip = self.0.borrow_mut();
+            words.iter_mut().for_each(|w| *w = chip.clock(*w));
+            Ok(words)
+        }
+    }
+
+    struct FakeCs(Rc<RefCell<Chip>>);
+
+    impl OutputPin for FakeCs {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().selected = true;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            let mut chip = self.0.borrow_mut();
+            if chip.selected {
+                chip.end_transaction();
+            }
+            chip.selected = false;
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayUs<u8> for NoDelay {
+        fn delay_us(&mut self, _us: u8) {}
+    }
+
+    type Flash = SpiFlash<FakeSpi, FakeCs>;
+
+    fn flash() -> (Flash, Rc<RefCell<Chip>>) {
+        let chip = Chip::new();
+        let flash = SpiFlash::new(FakeSpi(chip.clone()), FakeCs(chip.clone()));
+        (flash, chip)
+    }
+
+    #[test]
+    fn reads_the_jedec_id() {
+        let (mut flash, _chip) = flash();
+        assert_eq!(flash.jedec_id().unwrap(), JEDEC_ID);
+    }
+
+    #[test]
+    fn program_is_split_at_page_boundaries() {
+        let (mut flash, chip) = flash();
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        flash.program(250, &data).unwrap();
+
+        let mut buf = vec![0; 310];
+        flash.read(245, &mut buf).unwrap();
+        assert_eq!(buf[..5], [0xFF; 5]);
+        assert_eq!(buf[5..305], data[..]);
+        assert_eq!(buf[305..], [0xFF; 5]);
+
+        let programs = chip
+            .borrow()
+            .commands
+            .iter()
+            .filter(|&&c| c == 0x02)
+            .count();
+        assert_eq!(programs, 3);
+        assert!(!chip.borrow().write_enabled);
+    }
+
+    #[test]
+    fn erase_range_uses_the_largest_erases() {
+        let (mut flash, chip) = flash();
+        flash.program(0x1_8FFF, &[0]).unwrap();
+        flash.program(0x1_9000, &[0]).unwrap();
+        chip.borrow_mut().commands.clear();
+
+        flash.erase_range(0, 0x1_9000).unwrap();
+        let erases: Vec<u8> = chip
+            .borrow()
+            .commands
+            .iter()
+            .copied()
+            .filter(|&c| c != 0x06 && c != 0x05)
+            .collect();
+        assert_eq!(erases, [0xD8, 0x52, 0x20]);
+
+        let mut buf = [0; 2];
+        flash.read(0x1_8FFF, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0]);
+    }
+
+    #[test]
+    fn rejects_bad_addresses_without_touching_the_bus() {
+        let (mut flash, chip) = flash();
+        let mut buf = [0; 2];
+        assert!(matches!(
+            flash.fast_read(CAPACITY - 1, &mut buf),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            flash.page_program(PAGE_SIZE - 1, &[0, 0]),
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(flash.erase_sector(1), Err(Error::NotAligned)));
+        assert!(matches!(
+            flash.erase_range(0, CAPACITY + SECTOR_SIZE),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(chip.borrow().commands.is_empty());
+    }
+
+    #[test]
+    fn gives_up_when_it_stays_busy() {
+        let (mut flash, chip) = flash();
+        chip.borrow_mut().stuck_busy = true;
+        assert!(matches!(flash.erase_sector(0), Err(Error::Timeout)));
+
+        chip.borrow_mut().stuck_busy = false;
+        flash.erase_sector(0).unwrap();
+    }
+
+    #[test]
+    fn refuses_everything_but_waking_up_in_deep_power_down() {
+        let (mut flash, chip) = flash();
+        flash.deep_power_down().unwrap();
+        assert!(flash.is_powered_down());
+        chip.borrow_mut().commands.clear();
+
+        let mut buf = [0; 4];
+        assert!(matches!(flash.read(0, &mut buf), Err(Error::PoweredDown)));
+        assert!(matches!(flash.write(0, &[0]), Err(Error::PoweredDown)));
+        assert!(matches!(
+            flash.erase_range(0, SECTOR_SIZE),
+            Err(Error::PoweredDown)
+        ));
+        assert!(matches!(flash.jedec_id(), Err(Error::PoweredDown)));
+        assert!(chip.borrow().commands.is_empty());
+
+        flash.release_deep_power_down(&mut NoDelay).unwrap();
+        assert!(!flash.is_powered_down());
+        assert!(!chip.borrow().powered_down);
+        assert_eq!(flash.jedec_id().unwrap(), JEDEC_ID);
+    }
+}
@@ -40,6 +40,9 @@ pub const JETBRAINS_FONT_16_POINT_BOLD: MonoFont = MonoFont {
 pub struct FontStyles {
     pub watchface_time: Font,
     pub watchface_date: Font,
+    pub menu_title: Font,
+    pub menu_item: Font,
+    pub menu_item_selected: Font,
 }
 
 // dyn GlyphMapping + 'static)` cannot be shared between threads safely
@@ -62,6 +65,22 @@ impl FontStyles {
                     display::PixelFormat::MAX_B / 2,
                 ),
             },
+            menu_title: Font {
+                font: &JETBRAINS_FONT_16_POINT_BOLD,
+                text_color: display::PixelFormat::CYAN,
+            },
+            menu_item: Font {
+                font: &JETBRAINS_FONT_16_POINT_BOLD,
+                text_color: display::PixelFormat::new(
+                    display::PixelFormat::MAX_R / 2,
+                    display::PixelFormat::MAX_G / 2,
+                    display::PixelFormat::MAX_B / 2,
+                ),
+            },
+            menu_item_selected: Font {
+                font: &JETBRAINS_FONT_16_POINT_BOLD,
+                text_color: display::PixelFormat::WHITE,
+            },
         }
     }
 }
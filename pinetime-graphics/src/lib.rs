@@ -2,4 +2,5 @@
 
 pub mod font_styles;
 pub mod icons;
+pub mod line_graph;
 pub mod screens;
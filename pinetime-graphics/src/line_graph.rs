@@ -0,0 +1,69 @@
+//! Line graph widget
+//!
+//! Draws the whole graph at once, framed and cleared, from an iterator of values.
+
+use pinetime_common::display::{PixelFormat, BACKGROUND_COLOR};
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LineGraph {
+    pub area: Rectangle,
+    /// Values drawn at the bottom and top of the area, others are clamped
+    pub min: i32,
+    pub max: i32,
+    /// Number of values spanning the width, fewer end at the right edge
+    pub capacity: usize,
+    pub color: PixelFormat,
+    pub frame_color: PixelFormat,
+}
+
+impl LineGraph {
+    pub fn draw<D, I>(&self, values: I, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+        I: IntoIterator<Item = i32>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let frame_style = PrimitiveStyleBuilder::new()
+            .fill_color(BACKGROUND_COLOR)
+            .stroke_color(self.frame_color)
+            .stroke_width(1)
+            .build();
+        self.area.into_styled(frame_style).draw(display)?;
+
+        let values = values.into_iter();
+        // Only the newest ones if there are too many
+        let skip = values.len().saturating_sub(self.capacity);
+        let first_index = self.capacity.saturating_sub(values.len());
+        let line_style = PrimitiveStyle::with_stroke(self.color, 2);
+        let mut prev = None;
+        for (index, value) in values.skip(skip).enumerate() {
+            let index = first_index + index;
+            let point = self.point(index, value);
+            Line::new(prev.unwrap_or(point), point)
+                .into_styled(line_style)
+                .draw(display)?;
+            prev = Some(point);
+        }
+        Ok(())
+    }
+
+    fn point(&self, index: usize, value: i32) -> Point {
+        // Inside the frame
+        let inner = Rectangle::new(
+            self.area.top_left + Point::new(2, 2),
+            self.area.size.saturating_sub(Size::new(4, 4)),
+        );
+        let width = inner.size.width.saturating_sub(1) as i32;
+        let height = inner.size.height.saturating_sub(1) as i32;
+        let x = index as i32 * width / (self.capacity as i32 - 1).max(1);
+        let range = (self.max - self.min).max(1);
+        let y = (value.clamp(self.min, self.max) - self.min) * height / range;
+        inner.top_left + Point::new(x, height - y)
+    }
+}
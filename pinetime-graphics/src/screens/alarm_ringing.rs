@@ -0,0 +1,221 @@
+//! Shown while an alarm rings
+//!
+//! * Tap snooze : ring again in a few minutes
+//! * Tap dismiss : stop ringing
+//! * Button : snooze, a half asleep press shouldn't turn the alarm off for good
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    alarm::Response,
+    chrono::{NaiveTime, Timelike},
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, ButtonEvent, InputEvent, SystemTimeExt, TimeFormat,
+};
+
+const BUTTON_HEIGHT: u32 = 60;
+const BUTTON_MARGIN: u32 = 8;
+
+pub struct AlarmRingingScreen {
+    redraw: Redraw,
+    /// Minute resolution, seconds are always zero
+    time: NaiveTime,
+    time_format: TimeFormat,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const TIME = 1 << 1;
+        const BUTTONS = 1 << 2;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+/// Snooze on the left, dismiss on the right
+fn button_areas() -> [Rectangle; 2] {
+    let width = (display::WIDTH as u32 - 3 * BUTTON_MARGIN) / 2;
+    let top = (display::HEIGHT as u32 - BUTTON_MARGIN - BUTTON_HEIGHT) as i32;
+    let size = Size::new(width, BUTTON_HEIGHT);
+    [
+        Rectangle::new(Point::new(BUTTON_MARGIN as i32, top), size),
+        Rectangle::new(Point::new((2 * BUTTON_MARGIN + width) as i32, top), size),
+    ]
+}
+
+const RESPONSES: [(Response, &str); 2] =
+    [(Response::Snooze, "Snooze"), (Response::Dismiss, "Dismiss")];
+
+impl AlarmRingingScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        AlarmRingingScreen {
+            redraw: Redraw::ALL,
+            time: NaiveTime::from_hms(0, 0, 0),
+            time_format: TimeFormat::default(),
+            font_styles,
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Alarm",
+                Point::new((display::WIDTH / 2) as i32, 20),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_time<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::TIME) {
+            return Ok(());
+        }
+        let (hour, am_pm) = match self.time_format {
+            TimeFormat::H12 => {
+                let (is_pm, hour) = self.time.hour12();
+                (hour, if is_pm { "PM" } else { "AM" })
+            }
+            TimeFormat::H24 => (self.time.hour(), "  "),
+        };
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+
+        let mut text: String<8> = String::new();
+        // Always fits
+        write!(&mut text, "{:02}:{:02}", hour, self.time.minute()).ok();
+        let mut font_style = self.font_styles.watchface_time.style();
+        font_style.background_color = BACKGROUND_COLOR.into();
+        Text::with_text_style(
+            &text,
+            Point::new((display::WIDTH / 2) as i32, 90),
+            font_style,
+            text_style,
+        )
+        .draw(display)?;
+
+        let mut font_style = self.font_styles.watchface_date.style();
+        font_style.background_color = BACKGROUND_COLOR.into();
+        Text::with_text_style(
+            am_pm,
+            Point::new((display::WIDTH / 2) as i32, 145),
+            font_style,
+            text_style,
+        )
+        .draw(display)?;
+        Ok(())
+    }
+
+    fn draw_buttons<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::BUTTONS) {
+            return Ok(());
+        }
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        let font_style = self.font_styles.menu_item_selected.style();
+        for (area, (_, label)) in button_areas().iter().zip(RESPONSES.iter()) {
+            let color = self.font_styles.menu_item_selected.text_color;
+            area.into_styled(PrimitiveStyle::with_stroke(color, 2))
+                .draw(display)?;
+            Text::with_text_style(label, area.center(), font_style, text_style).draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for AlarmRingingScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        let time = res.sys_time.local().time();
+        let time = NaiveTime::from_hms(time.hour(), time.minute(), 0);
+        if time != self.time {
+            self.time = time;
+            self.redraw |= Redraw::TIME;
+        }
+        if res.settings.time_format != self.time_format {
+            self.time_format = res.settings.time_format;
+            self.redraw |= Redraw::TIME;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::AlarmResponse(Response::Snooze),
+            InputEvent::Tap(_) => match event.hit_test(button_areas().iter()) {
+                Some(index) => Action::AlarmResponse(RESPONSES[index].0),
+                None => Action::None,
+            },
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for AlarmRingingScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_time(target)?;
+        self.draw_buttons(target)?;
+        Ok(())
+    }
+}
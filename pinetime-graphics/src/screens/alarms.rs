@@ -0,0 +1,484 @@
+//! Alarm list and editor
+//!
+//! List:
+//! * Tap on/off : enable/disable the alarm
+//! * Tap elsewhere on an alarm : edit it
+//! * Slide right : stopwatch
+//! * Slide left : back
+//! * Button : back
+//!
+//! Editor:
+//! * Tap hour/minute : select it
+//! * Slide up/down : increase/decrease the selected hour (by 1) or minute (by 5)
+//! * Tap a day : toggle repeating on that day
+//! * Button : enable the alarm and go back to the list
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen, ScreenId},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    mono_font::{MonoTextStyle, MonoTextStyleBuilder},
+    primitives::Rectangle,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    alarm::{Alarm, MAX_ALARMS},
+    chrono::Weekday,
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, Settings, SystemTimeExt, TimeFormat,
+};
+
+const TITLE_HEIGHT: u32 = 40;
+const ITEM_HEIGHT: u32 = 50;
+const MARGIN: i32 = 8;
+
+/// Width of the on/off tap area at the right of each alarm
+const TOGGLE_WIDTH: u32 = 80;
+
+const TIME_CENTER_Y: i32 = 95;
+const AM_PM_Y: i32 = 145;
+const DAYS_Y: i32 = 180;
+const DAY_WIDTH: u32 = 30;
+const SUMMARY_Y: i32 = 218;
+
+const DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+const MINUTE_STEP: u8 = 5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum Field {
+    Hour,
+    Minute,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum Mode {
+    List,
+    Edit(usize, Field),
+}
+
+pub struct AlarmsScreen {
+    redraw: Redraw,
+    /// Take the system's settings on the next update
+    sync: bool,
+    settings: Settings,
+    mode: Mode,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const CLEAR = 1 << 0;
+        const TITLE = 1 << 1;
+        const LIST = 1 << 2;
+        const TIME = 1 << 3;
+        const DAYS = 1 << 4;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+fn item_area(index: usize) -> Rectangle {
+    Rectangle::new(
+        Point::new(0, (TITLE_HEIGHT + index as u32 * ITEM_HEIGHT) as i32),
+        Size::new(display::WIDTH as u32, ITEM_HEIGHT),
+    )
+}
+
+fn toggle_area(index: usize) -> Rectangle {
+    let item = item_area(index);
+    Rectangle::new(
+        Point::new(display::WIDTH as i32 - TOGGLE_WIDTH as i32, item.top_left.y),
+        Size::new(TOGGLE_WIDTH, ITEM_HEIGHT),
+    )
+}
+
+fn field_area(field: Field) -> Rectangle {
+    let half = display::WIDTH as u32 / 2;
+    let x = match field {
+        Field::Hour => 0,
+        Field::Minute => half as i32,
+    };
+    Rectangle::new(
+        Point::new(x, TITLE_HEIGHT as i32),
+        Size::new(half, (AM_PM_Y - TITLE_HEIGHT as i32) as u32),
+    )
+}
+
+fn day_area(index: usize) -> Rectangle {
+    let left = (display::WIDTH as u32 - DAYS.len() as u32 * DAY_WIDTH) / 2;
+    Rectangle::new(
+        Point::new((left + index as u32 * DAY_WIDTH) as i32, DAYS_Y - 15),
+        Size::new(DAY_WIDTH, 30),
+    )
+}
+
+/// `style` in another color, over the background
+fn with_color(
+    mut style: MonoTextStyle<'static, PixelFormat>,
+    color: PixelFormat,
+) -> MonoTextStyle<'static, PixelFormat> {
+    style.text_color = Some(color);
+    style.background_color = Some(BACKGROUND_COLOR);
+    style
+}
+
+/// Hour as shown and the AM/PM suffix
+fn display_hour(alarm: &Alarm, time_format: TimeFormat) -> (u8, &'static str) {
+    match time_format {
+        TimeFormat::H12 => {
+            let suffix = if alarm.hour >= 12 { "PM" } else { "AM" };
+            let hour = match alarm.hour % 12 {
+                0 => 12,
+                h => h,
+            };
+            (hour, suffix)
+        }
+        TimeFormat::H24 => (alarm.hour, ""),
+    }
+}
+
+impl AlarmsScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        AlarmsScreen {
+            redraw: Redraw::ALL,
+            sync: true,
+            settings: Settings::default(),
+            mode: Mode::List,
+            font_styles,
+        }
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.redraw.set_all();
+        }
+    }
+
+    fn handle_list_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideLeft, _) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideRight, _) => Action::Switch(ScreenId::Stopwatch),
+            InputEvent::Tap(_) => {
+                let mut toggles = [Rectangle::zero(); MAX_ALARMS];
+                let mut items = [Rectangle::zero(); MAX_ALARMS];
+                for (index, (toggle, item)) in toggles.iter_mut().zip(items.iter_mut()).enumerate()
+                {
+                    *toggle = toggle_area(index);
+                    *item = item_area(index);
+                }
+                if let Some(index) = event.hit_test(toggles.iter()) {
+                    let alarm = &mut self.settings.alarms[index];
+                    alarm.enabled = !alarm.enabled;
+                    self.redraw |= Redraw::LIST;
+                    Action::UpdateSettings(self.settings)
+                } else if let Some(index) = event.hit_test(items.iter()) {
+                    self.set_mode(Mode::Edit(index, Field::Hour));
+                    Action::None
+                } else {
+                    Action::None
+                }
+            }
+            _ => Action::None,
+        }
+    }
+
+    fn handle_edit_event(&mut self, index: usize, field: Field, event: InputEvent) -> Action {
+        let alarm = &mut self.settings.alarms[index];
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => {
+                alarm.enabled = true;
+                self.set_mode(Mode::List);
+                return Action::UpdateSettings(self.settings);
+            }
+            InputEvent::Gesture(Gesture::SlideUp, _) => match field {
+                Field::Hour => alarm.hour = (alarm.hour + 1) % 24,
+                Field::Minute => alarm.minute = (alarm.minute / MINUTE_STEP + 1) * MINUTE_STEP % 60,
+            },
+            InputEvent::Gesture(Gesture::SlideDown, _) => match field {
+                Field::Hour => alarm.hour = (alarm.hour + 23) % 24,
+                Field::Minute => {
+                    // Down to the previous step, from in between steps too
+                    let steps = 60 / MINUTE_STEP;
+                    let step = alarm.minute.div_ceil(MINUTE_STEP);
+                    alarm.minute = (step + steps - 1) % steps * MINUTE_STEP;
+                }
+            },
+            InputEvent::Tap(_) => {
+                let fields = [field_area(Field::Hour), field_area(Field::Minute)];
+                let mut days = [Rectangle::zero(); DAYS.len()];
+                days.iter_mut()
+                    .enumerate()
+                    .for_each(|(i, d)| *d = day_area(i));
+                if let Some(f) = event.hit_test(fields.iter()) {
+                    let field = if f == 0 { Field::Hour } else { Field::Minute };
+                    self.mode = Mode::Edit(index, field);
+                } else if let Some(d) = event.hit_test(days.iter()) {
+                    alarm.days = alarm.days.toggled(DAYS[d]);
+                    self.redraw |= Redraw::DAYS;
+                }
+            }
+            _ => (),
+        }
+        self.redraw |= Redraw::TIME;
+        Action::None
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let mut title: String<12> = String::new();
+            // Always fits
+            match self.mode {
+                Mode::List => write!(&mut title, "Alarms").ok(),
+                Mode::Edit(index, _) => write!(&mut title, "Alarm {}", index + 1).ok(),
+            };
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                &title,
+                Point::new((display::WIDTH / 2) as i32, (TITLE_HEIGHT / 2) as i32),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_list<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::LIST) {
+            return Ok(());
+        }
+        let left = TextStyleBuilder::new()
+            .baseline(Baseline::Top)
+            .alignment(Alignment::Left)
+            .build();
+        let right = TextStyleBuilder::new()
+            .baseline(Baseline::Top)
+            .alignment(Alignment::Right)
+            .build();
+        let time_format = self.settings.time_format;
+        for (index, alarm) in self.settings.alarms.iter().enumerate() {
+            let top = item_area(index).top_left.y;
+            let color = if alarm.enabled {
+                self.font_styles.menu_item_selected.text_color
+            } else {
+                self.font_styles.menu_item.text_color
+            };
+            let style = with_color(self.font_styles.menu_item.style(), color);
+
+            // Always fits
+            let mut text: String<16> = String::new();
+            let (hour, suffix) = display_hour(alarm, time_format);
+            write!(&mut text, "{:2}:{:02}{:<2}", hour, alarm.minute, suffix).ok();
+            Text::with_text_style(&text, Point::new(MARGIN, top), style, left).draw(display)?;
+
+            text.clear();
+            write!(&mut text, "{:<8}", alarm.days).ok();
+            let days_style = with_color(style, self.font_styles.menu_item.text_color);
+            Text::with_text_style(&text, Point::new(MARGIN, top + 25), days_style, left)
+                .draw(display)?;
+
+            let on_off = if alarm.enabled { " On" } else { "Off" };
+            Text::with_text_style(
+                on_off,
+                Point::new(display::WIDTH as i32 - MARGIN, top + 12),
+                style,
+                right,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_editor<D>(
+        &self,
+        display: &mut D,
+        index: usize,
+        field: Field,
+    ) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        let alarm = &self.settings.alarms[index];
+        let selected = self.font_styles.watchface_time.text_color;
+        let unselected = self.font_styles.menu_item.text_color;
+        let center = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+
+        if self.redraw.contains(Redraw::TIME) {
+            let (hour, suffix) = display_hour(alarm, self.settings.time_format);
+            let big = |selected_field| {
+                let color = if field == selected_field {
+                    selected
+                } else {
+                    unselected
+                };
+                MonoTextStyleBuilder::new()
+                    .font(self.font_styles.watchface_time.font)
+                    .text_color(color)
+                    .background_color(BACKGROUND_COLOR)
+                    .build()
+            };
+            let mut text: String<4> = String::new();
+            write!(&mut text, "{:02}", hour).ok();
+            Text::with_text_style(
+                &text,
+                Point::new(field_area(Field::Hour).center().x, TIME_CENTER_Y),
+                big(Field::Hour),
+                center,
+            )
+            .draw(display)?;
+            Text::with_text_style(
+                ":",
+                Point::new((display::WIDTH / 2) as i32, TIME_CENTER_Y),
+                big(Field::Hour),
+                center,
+            )
+            .draw(display)?;
+            text.clear();
+            write!(&mut text, "{:02}", alarm.minute).ok();
+            Text::with_text_style(
+                &text,
+                Point::new(field_area(Field::Minute).center().x, TIME_CENTER_Y),
+                big(Field::Minute),
+                center,
+            )
+            .draw(display)?;
+
+            // Blank in 24h mode, clears a previous AM/PM
+            let style = with_color(self.font_styles.menu_item.style(), unselected);
+            text.clear();
+            write!(&mut text, "{:<2}", suffix).ok();
+            Text::with_text_style(
+                &text,
+                Point::new(field_area(Field::Minute).center().x, AM_PM_Y),
+                style,
+                center,
+            )
+            .draw(display)?;
+        }
+
+        if self.redraw.contains(Redraw::DAYS) {
+            for (i, initial) in "MTWTFSS".chars().enumerate() {
+                let color = if alarm.days.contains(DAYS[i]) {
+                    selected
+                } else {
+                    unselected
+                };
+                let style = with_color(self.font_styles.menu_item.style(), color);
+                let mut text: String<4> = String::new();
+                text.push(initial).ok();
+                Text::with_text_style(&text, day_area(i).center(), style, center).draw(display)?;
+            }
+
+            let mut summary: String<12> = String::new();
+            // Padded so a shorter summary covers up a longer previous one
+            write!(&mut summary, "{:^8}", alarm.days).ok();
+            let style = with_color(self.font_styles.menu_item.style(), unselected);
+            Text::with_text_style(
+                &summary,
+                Point::new((display::WIDTH / 2) as i32, SUMMARY_Y),
+                style,
+                center,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for AlarmsScreen {
+    fn on_focus(&mut self) {
+        self.sync = true;
+        self.mode = Mode::List;
+        self.force_redraw();
+    }
+
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        // While focused the local copy is authoritative, see SettingsScreen
+        if self.sync {
+            self.sync = false;
+            if *res.settings != self.settings {
+                self.settings = *res.settings;
+                self.redraw.set_all();
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match self.mode {
+            Mode::List => self.handle_list_event(event),
+            Mode::Edit(index, field) => self.handle_edit_event(index, field, event),
+        }
+    }
+}
+
+impl Drawable for AlarmsScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::CLEAR) {
+            target.clear(BACKGROUND_COLOR)?;
+        }
+        self.draw_title(target)?;
+        match self.mode {
+            Mode::List => self.draw_list(target)?,
+            Mode::Edit(index, field) => self.draw_editor(target, index, field)?,
+        }
+        Ok(())
+    }
+}
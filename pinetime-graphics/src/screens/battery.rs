@@ -0,0 +1,278 @@
+//! Battery level, voltage and the history of the last day
+//!
+//! * Slide down : back
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    line_graph::LineGraph,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::{String, Vec};
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    prelude::*,
+    primitives::Rectangle,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    battery_history,
+    battery_model::ChargeState,
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, MilliVolts, SystemTimeExt,
+};
+
+const TITLE_Y: i32 = 15;
+const LEVEL_Y: i32 = 50;
+const STATUS_Y: i32 = 80;
+const GRAPH_TOP: i32 = 100;
+const GRAPH_HEIGHT: u32 = 110;
+const AXIS_Y: i32 = 225;
+const MARGIN: i32 = 8;
+
+pub struct BatteryScreen {
+    redraw: Redraw,
+    percent: u8,
+    voltage: MilliVolts,
+    state: ChargeState,
+    remaining_secs: Option<u32>,
+    history_revision: Option<u32>,
+    /// Percentages from the history, oldest first
+    history: Vec<u8, { battery_history::CAPACITY }>,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const LEVEL = 1 << 1;
+        const STATUS = 1 << 2;
+        const GRAPH = 1 << 3;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+impl BatteryScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        BatteryScreen {
+            redraw: Redraw::ALL,
+            percent: 0,
+            voltage: MilliVolts(0),
+            state: ChargeState::Discharging,
+            remaining_secs: None,
+            history_revision: None,
+            history: Vec::new(),
+            font_styles,
+        }
+    }
+
+    fn graph(&self) -> LineGraph {
+        LineGraph {
+            area: Rectangle::new(
+                Point::new(MARGIN, GRAPH_TOP),
+                Size::new((display::WIDTH as i32 - 2 * MARGIN) as u32, GRAPH_HEIGHT),
+            ),
+            min: 0,
+            max: 100,
+            capacity: battery_history::CAPACITY,
+            color: PixelFormat::GREEN,
+            frame_color: self.font_styles.menu_item.text_color,
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Battery",
+                Point::new((display::WIDTH / 2) as i32, TITLE_Y),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+
+            let font_style = self.font_styles.menu_item.style();
+            let left = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Left)
+                .build();
+            Text::with_text_style("-24h", Point::new(MARGIN, AXIS_Y), font_style, left)
+                .draw(display)?;
+            let right = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Right)
+                .build();
+            Text::with_text_style(
+                "now",
+                Point::new(display::WIDTH as i32 - MARGIN, AXIS_Y),
+                font_style,
+                right,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_level<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::LEVEL) {
+            let mut text: String<16> = String::new();
+            // Always fits, padded so a shorter level covers up a longer one
+            let mut level: String<16> = String::new();
+            write!(
+                &mut level,
+                "{}% {}.{:02}V",
+                self.percent,
+                self.voltage.0 / 1000,
+                self.voltage.0 % 1000 / 10
+            )
+            .ok();
+            write!(&mut text, "{:^12}", level.as_str()).ok();
+            self.draw_centered(&text, LEVEL_Y, true, display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_status<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::STATUS) {
+            let mut status: String<16> = String::new();
+            // Always fits, padded like the level
+            match (self.state, self.remaining_secs) {
+                (ChargeState::Charging, _) => write!(&mut status, "Charging"),
+                (ChargeState::Full, _) => write!(&mut status, "Full"),
+                (ChargeState::Discharging, Some(secs)) => {
+                    let minutes = secs / 60;
+                    write!(&mut status, "~{}h{:02}m left", minutes / 60, minutes % 60)
+                }
+                (ChargeState::Discharging, None) => Ok(()),
+            }
+            .ok();
+            let mut text: String<16> = String::new();
+            write!(&mut text, "{:^14}", status.as_str()).ok();
+            self.draw_centered(&text, STATUS_Y, false, display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_centered<D>(
+        &self,
+        text: &str,
+        y: i32,
+        highlight: bool,
+        display: &mut D,
+    ) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        let mut font_style = if highlight {
+            self.font_styles.menu_item_selected.style()
+        } else {
+            self.font_styles.menu_item.style()
+        };
+        font_style.background_color = BACKGROUND_COLOR.into();
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        Text::with_text_style(
+            text,
+            Point::new((display::WIDTH / 2) as i32, y),
+            font_style,
+            text_style,
+        )
+        .draw(display)?;
+        Ok(())
+    }
+}
+
+impl Screen for BatteryScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        let (percent, voltage) = (res.bat_ctl.percent_remaining(), res.bat_ctl.voltage());
+        if percent != self.percent || voltage != self.voltage {
+            self.percent = percent;
+            self.voltage = voltage;
+            self.redraw |= Redraw::LEVEL;
+        }
+        let state = res.bat_ctl.charge_state();
+        if state != self.state {
+            self.state = state;
+            self.redraw |= Redraw::STATUS;
+        }
+        let history = res.battery_history;
+        if Some(history.revision()) != self.history_revision {
+            self.history_revision = Some(history.revision());
+            self.history.clear();
+            for sample in history.iter() {
+                // Same capacity, always fits
+                self.history.push(sample.percent).ok();
+            }
+            self.remaining_secs = history.estimate_remaining_secs();
+            self.redraw |= Redraw::STATUS | Redraw::GRAPH;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideDown, _) => Action::Pop,
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for BatteryScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_level(target)?;
+        self.draw_status(target)?;
+        if self.redraw.contains(Redraw::GRAPH) {
+            let values = self.history.iter().map(|p| *p as i32);
+            self.graph().draw(values, target)?;
+        }
+        Ok(())
+    }
+}
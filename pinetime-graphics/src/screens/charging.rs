@@ -0,0 +1,254 @@
+//! Shown while on the charger, goes away when unplugged
+//!
+//! * Slide down : back
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    battery_model::ChargeState,
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, MilliVolts, SystemTimeExt,
+};
+
+/// Battery body, the terminal sticks out on the right
+const BODY: Rectangle = Rectangle::new(Point::new(50, 30), Size::new(124, 68));
+const TERMINAL: Rectangle = Rectangle::new(Point::new(174, 50), Size::new(12, 28));
+const BODY_STROKE: u32 = 4;
+/// Between the body's outline and the fill
+const FILL_MARGIN: u32 = 4;
+
+const PERCENT_Y: i32 = 150;
+const STATUS_Y: i32 = 215;
+
+/// The fill sweeps from the charge up to full in this many steps
+const ANIMATION_STEPS: u32 = 5;
+const ANIMATION_STEP_MS: u32 = 400;
+
+pub struct ChargingScreen {
+    redraw: Redraw,
+    percent: u8,
+    voltage: MilliVolts,
+    state: ChargeState,
+    /// 0 is just the charge, ANIMATION_STEPS is full
+    animation_step: u32,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const OUTLINE = 1 << 0;
+        const FILL = 1 << 1;
+        const PERCENT = 1 << 2;
+        const STATUS = 1 << 3;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+impl ChargingScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        ChargingScreen {
+            redraw: Redraw::ALL,
+            percent: 0,
+            voltage: MilliVolts(0),
+            state: ChargeState::Charging,
+            animation_step: 0,
+            font_styles,
+        }
+    }
+
+    /// Percentage the glyph shows, the animation adds to the charge
+    fn fill_percent(&self) -> u32 {
+        match self.state {
+            ChargeState::Full => 100,
+            ChargeState::Charging => {
+                let percent = self.percent as u32;
+                percent + (100 - percent.min(100)) * self.animation_step / ANIMATION_STEPS
+            }
+            ChargeState::Discharging => self.percent as u32,
+        }
+    }
+
+    fn draw_battery<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        let color = self.font_styles.menu_item_selected.text_color;
+        if self.redraw.contains(Redraw::OUTLINE) {
+            BODY.into_styled(PrimitiveStyle::with_stroke(color, BODY_STROKE))
+                .draw(display)?;
+            TERMINAL
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(display)?;
+        }
+        if self.redraw.contains(Redraw::FILL) {
+            let inset = BODY_STROKE + FILL_MARGIN;
+            let area = BODY.offset(-(inset as i32));
+            let width = area.size.width * self.fill_percent().min(100) / 100;
+            let filled = Rectangle::new(area.top_left, Size::new(width, area.size.height));
+            let empty = Rectangle::new(
+                area.top_left + Point::new(width as i32, 0),
+                Size::new(area.size.width - width, area.size.height),
+            );
+            filled
+                .into_styled(PrimitiveStyle::with_fill(PixelFormat::GREEN))
+                .draw(display)?;
+            empty
+                .into_styled(PrimitiveStyle::with_fill(BACKGROUND_COLOR))
+                .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_percent<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::PERCENT) {
+            let mut text: String<8> = String::new();
+            // Always fits, padded so 99% covers up 100%
+            write!(&mut text, "{:>3}%", self.percent).ok();
+            let mut font_style = self.font_styles.watchface_time.style();
+            font_style.background_color = BACKGROUND_COLOR.into();
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                &text,
+                Point::new((display::WIDTH / 2) as i32, PERCENT_Y),
+                font_style,
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_status<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::STATUS) {
+            let status = match self.state {
+                ChargeState::Charging => "Charging",
+                ChargeState::Full => "Full",
+                ChargeState::Discharging => "Unplugged",
+            };
+            let mut status_line: String<24> = String::new();
+            write!(
+                &mut status_line,
+                "{} {}.{:02}V",
+                status,
+                self.voltage.0 / 1000,
+                self.voltage.0 % 1000 / 10
+            )
+            .ok();
+            let mut text: String<24> = String::new();
+            // Always fits, padded so a shorter status covers up a longer one
+            write!(&mut text, "{:^16}", status_line.as_str()).ok();
+            let mut font_style = self.font_styles.menu_item.style();
+            font_style.background_color = BACKGROUND_COLOR.into();
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                &text,
+                Point::new((display::WIDTH / 2) as i32, STATUS_Y),
+                font_style,
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for ChargingScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        let percent = res.bat_ctl.percent_remaining();
+        if percent != self.percent {
+            self.percent = percent;
+            self.redraw |= Redraw::PERCENT | Redraw::FILL;
+        }
+        let voltage = res.bat_ctl.voltage();
+        if voltage != self.voltage {
+            self.voltage = voltage;
+            self.redraw |= Redraw::STATUS;
+        }
+        let state = res.bat_ctl.charge_state();
+        if state != self.state {
+            self.state = state;
+            self.redraw |= Redraw::STATUS | Redraw::FILL;
+        }
+        let animation_step = if state == ChargeState::Charging {
+            res.now_ms / ANIMATION_STEP_MS % (ANIMATION_STEPS + 1)
+        } else {
+            0
+        };
+        if animation_step != self.animation_step {
+            self.animation_step = animation_step;
+            self.redraw |= Redraw::FILL;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideDown, _) => Action::Pop,
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for ChargingScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_battery(target)?;
+        self.draw_percent(target)?;
+        self.draw_status(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+//! Shown on boot when the firmware crashed before the reset
+//!
+//! * Tap : back
+//! * Slide down : back
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::Point,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    crash,
+    display::{self, PixelFormat},
+    text, BatteryControllerExt, ButtonEvent, CrashRecord, Gesture, InputEvent, SystemTimeExt,
+};
+
+const TITLE_Y: i32 = 15;
+const TEXT_Y: i32 = 50;
+const LINE_HEIGHT: i32 = 24;
+const MAX_LINES: usize = 8;
+const MARGIN: i32 = 8;
+/// Characters per line
+const COLS: usize = 18;
+
+pub struct CrashScreen {
+    redraw: Redraw,
+    crash_record: Option<CrashRecord>,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const REPORT = 1 << 1;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+impl CrashScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        CrashScreen {
+            redraw: Redraw::ALL,
+            crash_record: None,
+            font_styles,
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Crashed",
+                Point::new((display::WIDTH / 2) as i32, TITLE_Y),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    /// The message, then where it happened
+    fn draw_report<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        let record = match &self.crash_record {
+            Some(record) if self.redraw.contains(Redraw::REPORT) => record,
+            _ => return Ok(()),
+        };
+        let mut report: String<{ crash::MAX_MESSAGE_LEN + crash::MAX_FILE_LEN + 24 }> =
+            String::new();
+        // Always fits
+        write!(&mut report, "{}", record.message()).ok();
+        if !record.file().is_empty() {
+            write!(&mut report, "\n{}:{}", record.file(), record.line()).ok();
+        }
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Left)
+            .build();
+        for (index, line) in text::wrap_lines(&report, COLS).take(MAX_LINES).enumerate() {
+            Text::with_text_style(
+                line,
+                Point::new(MARGIN, TEXT_Y + index as i32 * LINE_HEIGHT),
+                self.font_styles.menu_item.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for CrashScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        if res.crash_record != self.crash_record.as_ref() {
+            self.crash_record = res.crash_record.cloned();
+            self.redraw |= Redraw::REPORT;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Tap(_) => Action::Pop,
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideDown, _) => Action::Pop,
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for CrashScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_report(target)?;
+        Ok(())
+    }
+}
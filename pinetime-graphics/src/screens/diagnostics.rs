@@ -0,0 +1,283 @@
+//! Why the watch last reset, how often it reset for each reason and the self-test results
+//!
+//! * Slide down : back
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::Point,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, BootRecord, ButtonEvent, Gesture, InputEvent, ResetReason, SelfTest,
+    SystemTimeExt,
+};
+
+const TITLE_Y: i32 = 15;
+const LAST_RESET_Y: i32 = 45;
+const BOOTS_Y: i32 = 72;
+const SELF_TEST_Y: i32 = 99;
+const RESETS_Y: i32 = 135;
+const RESETS_ROW_HEIGHT: i32 = 25;
+const MARGIN: i32 = 8;
+
+/// Counts above this are shown as this, they'd run into the next column
+const MAX_SHOWN_COUNT: u16 = 999;
+
+pub struct DiagnosticsScreen {
+    redraw: Redraw,
+    boot_record: BootRecord,
+    self_test: SelfTest,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const BOOTS = 1 << 1;
+        const SELF_TEST = 1 << 2;
+        const RESETS = 1 << 3;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+/// Fits two columns of counts on the screen
+fn short_label(reason: ResetReason) -> &'static str {
+    match reason {
+        ResetReason::PowerOn => "Power",
+        ResetReason::Pin => "Pin",
+        ResetReason::Watchdog => "WDT",
+        ResetReason::Soft => "Soft",
+        ResetReason::Lockup => "Lock",
+        ResetReason::WakeUp => "Wake",
+        ResetReason::Other => "Other",
+    }
+}
+
+impl DiagnosticsScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        DiagnosticsScreen {
+            redraw: Redraw::ALL,
+            boot_record: BootRecord::new(),
+            self_test: SelfTest::default(),
+            font_styles,
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Diagnostics",
+                Point::new((display::WIDTH / 2) as i32, TITLE_Y),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_boots<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::BOOTS) {
+            let mut text: String<32> = String::new();
+            // Always fits, padded so a shorter line covers up a longer one
+            let mut line: String<32> = String::new();
+            write!(&mut line, "Last: {}", self.boot_record.last_reset()).ok();
+            write!(&mut text, "{:^18}", line.as_str()).ok();
+            self.draw_centered(&text, LAST_RESET_Y, true, display)?;
+
+            line.clear();
+            text.clear();
+            write!(
+                &mut line,
+                "Boots {} bad {}",
+                self.boot_record.boots(),
+                self.boot_record.bad_boots()
+            )
+            .ok();
+            write!(&mut text, "{:^18}", line.as_str()).ok();
+            self.draw_centered(&text, BOOTS_Y, false, display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_self_test<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::SELF_TEST) {
+            let mut line: String<32> = String::new();
+            // Always fits
+            if self.self_test.passed() {
+                write!(&mut line, "Self-test OK").ok();
+            } else {
+                let parts = [
+                    (self.self_test.display, "lcd"),
+                    (self.self_test.touch, "touch"),
+                    (self.self_test.battery, "adc"),
+                ];
+                write!(&mut line, "Fail").ok();
+                let mut separator = " ";
+                for (_, name) in parts.iter().filter(|(ok, _)| !ok) {
+                    write!(&mut line, "{}{}", separator, name).ok();
+                    separator = ",";
+                }
+            }
+            let mut text: String<32> = String::new();
+            write!(&mut text, "{:^18}", line.as_str()).ok();
+            self.draw_centered(&text, SELF_TEST_Y, !self.self_test.passed(), display)?;
+        }
+        Ok(())
+    }
+
+    /// Two columns, label on the left and count on the right of each
+    fn draw_resets<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::RESETS) {
+            let mut font_style = self.font_styles.menu_item.style();
+            font_style.background_color = BACKGROUND_COLOR.into();
+            let left = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Left)
+                .build();
+            let right = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Right)
+                .build();
+            let column_width = display::WIDTH as i32 / 2;
+            for (index, reason) in ResetReason::ALL.iter().enumerate() {
+                let x = (index % 2) as i32 * column_width;
+                let y = RESETS_Y + (index / 2) as i32 * RESETS_ROW_HEIGHT;
+                Text::with_text_style(
+                    short_label(*reason),
+                    Point::new(x + MARGIN, y),
+                    font_style,
+                    left,
+                )
+                .draw(display)?;
+                let mut count: String<8> = String::new();
+                let resets = self.boot_record.resets(*reason).min(MAX_SHOWN_COUNT);
+                write!(&mut count, "{:>3}", resets).ok();
+                Text::with_text_style(
+                    &count,
+                    Point::new(x + column_width - MARGIN, y),
+                    font_style,
+                    right,
+                )
+                .draw(display)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_centered<D>(
+        &self,
+        text: &str,
+        y: i32,
+        highlight: bool,
+        display: &mut D,
+    ) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        let mut font_style = if highlight {
+            self.font_styles.menu_item_selected.style()
+        } else {
+            self.font_styles.menu_item.style()
+        };
+        font_style.background_color = BACKGROUND_COLOR.into();
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        Text::with_text_style(
+            text,
+            Point::new((display::WIDTH / 2) as i32, y),
+            font_style,
+            text_style,
+        )
+        .draw(display)?;
+        Ok(())
+    }
+}
+
+impl Screen for DiagnosticsScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        if *res.boot_record != self.boot_record {
+            self.boot_record = *res.boot_record;
+            self.redraw |= Redraw::BOOTS | Redraw::RESETS;
+        }
+        if res.self_test != self.self_test {
+            self.self_test = res.self_test;
+            self.redraw |= Redraw::SELF_TEST;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideDown, _) => Action::Pop,
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for DiagnosticsScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_boots(target)?;
+        self.draw_self_test(target)?;
+        self.draw_resets(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! Live heart rate
+//!
+//! The sensor only runs while this screen is active.
+//!
+//! * Slide right : back
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::Point,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, SystemTimeExt,
+};
+
+pub struct HeartRateScreen {
+    redraw: Redraw,
+    bpm: Option<u8>,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const BPM = 1 << 1;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+impl HeartRateScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        HeartRateScreen {
+            redraw: Redraw::ALL,
+            bpm: None,
+            font_styles,
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Heart rate",
+                Point::new((display::WIDTH / 2) as i32, 20),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+            Text::with_text_style(
+                "BPM",
+                Point::new((display::WIDTH / 2) as i32, 170),
+                self.font_styles.watchface_date.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_bpm<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::BPM) {
+            let mut text: String<4> = String::new();
+            // Always fits, padded so fewer digits cover up more
+            match self.bpm {
+                Some(bpm) => write!(&mut text, "{:>3}", bpm).ok(),
+                None => write!(&mut text, "---").ok(),
+            };
+            let mut font_style = self.font_styles.watchface_time.style();
+            font_style.background_color = BACKGROUND_COLOR.into();
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                &text,
+                Point::new((display::WIDTH / 2) as i32, 110),
+                font_style,
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for HeartRateScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        if res.heart_rate_bpm != self.bpm {
+            self.bpm = res.heart_rate_bpm;
+            self.redraw |= Redraw::BPM;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideRight, _) => Action::Pop,
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for HeartRateScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_bpm(target)?;
+        Ok(())
+    }
+}
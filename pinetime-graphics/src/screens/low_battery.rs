@@ -0,0 +1,157 @@
+//! Pops up once when the battery gets low
+//!
+//! * Tap : back
+//! * Slide down : back
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::Point,
+    prelude::*,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, SystemTimeExt,
+};
+
+pub struct LowBatteryScreen {
+    redraw: Redraw,
+    percent: u8,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const PERCENT = 1 << 1;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+impl LowBatteryScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        LowBatteryScreen {
+            redraw: Redraw::ALL,
+            percent: 0,
+            font_styles,
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Battery low",
+                Point::new((display::WIDTH / 2) as i32, 30),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+            Text::with_text_style(
+                "Charge soon",
+                Point::new((display::WIDTH / 2) as i32, 200),
+                self.font_styles.menu_item.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_percent<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::PERCENT) {
+            let mut text: String<8> = String::new();
+            // Always fits, padded so 9% covers up 10%
+            write!(&mut text, "{:>3}%", self.percent).ok();
+            let mut font_style = self.font_styles.watchface_time.style();
+            font_style.text_color = PixelFormat::RED.into();
+            font_style.background_color = BACKGROUND_COLOR.into();
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                &text,
+                Point::new((display::WIDTH / 2) as i32, 115),
+                font_style,
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for LowBatteryScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        let percent = res.bat_ctl.percent_remaining();
+        if percent != self.percent {
+            self.percent = percent;
+            self.redraw |= Redraw::PERCENT;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideDown, _) => Action::Pop,
+            InputEvent::Tap(_) => Action::Pop,
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for LowBatteryScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_percent(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+//! Owns every screen and a stack of the ones currently navigated to
+//!
+//! The top of the stack is the active screen, it gets the input events,
+//! updates and draws. The bottom of the stack is always the watch face.
+//! A long press on the button opens the power menu over any screen.
+//!
+//! Screens only navigate, anything else they ask for comes back out of
+//! [`ScreenManager::handle_event`] as an [`Action`] for the owner to carry out.
+//!
+//! Which screen comes up for things happening outside of the UI, an alarm ringing or
+//! a notification arriving, is decided here by the `on_*` methods, so the firmware
+//! and the simulator navigate the same way. A ringing alarm is never hidden.
+
+use crate::{
+    font_styles::FontStyles,
+    icons::Icons,
+    screens::{
+        Action, AlarmRingingScreen, AlarmsScreen, BatteryScreen, ChargingScreen, CrashScreen,
+        DiagnosticsScreen, Error, HeartRateScreen, LowBatteryScreen, NotificationsScreen,
+        PowerMenuScreen, Resources, Screen, ScreenId, SettingsScreen, StopwatchScreen, TimerScreen,
+        WatchFace,
+    },
+};
+use heapless::Vec;
+use pinetime_common::{
+    display::{PixelFormat, BACKGROUND_COLOR},
+    embedded_graphics::{draw_target::DrawTarget, Drawable},
+    BatteryControllerExt, ButtonEvent, InputEvent, SystemTimeExt,
+};
+
+pub const MAX_STACK_DEPTH: usize = 8;
+
+/// Run `$body` with `$screen` bound to the active screen
+macro_rules! with_active_screen {
+    ($self:ident, $screen:ident => $body:expr) => {
+        match $self.active() {
+            ScreenId::WatchFace => {
+                let $screen = &mut $self.watch_face;
+                $body
+            }
+            ScreenId::Settings => {
+                let $screen = &mut $self.settings;
+                $body
+            }
+            ScreenId::HeartRate => {
+                let $screen = &mut $self.heart_rate;
+                $body
+            }
+            ScreenId::Notifications => {
+                let $screen = &mut $self.notifications;
+                $body
+            }
+            ScreenId::Alarms => {
+                let $screen = &mut $self.alarms;
+                $body
+            }
+            ScreenId::AlarmRinging => {
+                let $screen = &mut $self.alarm_ringing;
+                $body
+            }
+            ScreenId::Stopwatch => {
+                let $screen = &mut $self.stopwatch;
+                $body
+            }
+            ScreenId::Timer => {
+                let $screen = &mut $self.timer;
+                $body
+            }
+            ScreenId::Battery => {
+                let $screen = &mut $self.battery;
+                $body
+            }
+            ScreenId::LowBattery => {
+                let $screen = &mut $self.low_battery;
+                $body
+            }
+            ScreenId::Charging => {
+                let $screen = &mut $self.charging;
+                $body
+            }
+            ScreenId::Diagnostics => {
+                let $screen = &mut $self.diagnostics;
+                $body
+            }
+            ScreenId::Crash => {
+                let $screen = &mut $self.crash;
+                $body
+            }
+            ScreenId::PowerMenu => {
+                let $screen = &mut $self.power_menu;
+                $body
+            }
+        }
+    };
+}
+
+pub struct ScreenManager {
+    stack: Vec<ScreenId, MAX_STACK_DEPTH>,
+    clear_display: bool,
+    watch_face: WatchFace,
+    settings: SettingsScreen,
+    heart_rate: HeartRateScreen,
+    notifications: NotificationsScreen,
+    alarms: AlarmsScreen,
+    alarm_ringing: AlarmRingingScreen,
+    stopwatch: StopwatchScreen,
+    timer: TimerScreen,
+    battery: BatteryScreen,
+    low_battery: LowBatteryScreen,
+    charging: ChargingScreen,
+    diagnostics: DiagnosticsScreen,
+    crash: CrashScreen,
+    power_menu: PowerMenuScreen,
+}
+
+impl ScreenManager {
+    pub fn new(font_styles: &'static FontStyles, icons: &'static Icons) -> Self {
+        let mut stack = Vec::new();
+        stack.push(ScreenId::WatchFace).ok();
+        ScreenManager {
+            stack,
+            clear_display: true,
+            watch_face: WatchFace::new(font_styles, icons),
+            settings: SettingsScreen::new(font_styles),
+            heart_rate: HeartRateScreen::new(font_styles),
+            notifications: NotificationsScreen::new(font_styles),
+            alarms: AlarmsScreen::new(font_styles),
+            alarm_ringing: AlarmRingingScreen::new(font_styles),
+            stopwatch: StopwatchScreen::new(font_styles),
+            timer: TimerScreen::new(font_styles),
+            battery: BatteryScreen::new(font_styles),
+            low_battery: LowBatteryScreen::new(font_styles),
+            charging: ChargingScreen::new(font_styles),
+            diagnostics: DiagnosticsScreen::new(font_styles),
+            crash: CrashScreen::new(font_styles),
+            power_menu: PowerMenuScreen::new(font_styles),
+        }
+    }
+
+    pub fn active(&self) -> ScreenId {
+        self.stack.last().copied().unwrap_or(ScreenId::WatchFace)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Route an input event to the active screen and carry out the navigation it asks
+    /// for. The action is returned for the owner to carry out the rest, e.g. saving
+    /// settings or controlling the stopwatch, navigation actions need nothing more.
+    pub fn handle_event(&mut self, event: InputEvent) -> Action {
+        let action = if event == InputEvent::Button(ButtonEvent::LongPress) {
+            Action::Push(ScreenId::PowerMenu)
+        } else {
+            with_active_screen!(self, s => s.handle_event(event))
+        };
+        self.navigate(action);
+        action
+    }
+
+    /// Returns true if the active screen changed
+    fn navigate(&mut self, action: Action) -> bool {
+        match action {
+            Action::None
+            | Action::UpdateSettings(_)
+            | Action::Stopwatch(_)
+            | Action::Countdown(_) => false,
+            Action::Push(id) => self.push(id),
+            Action::Pop | Action::AlarmResponse(_) | Action::Power(_) => self.pop(),
+            Action::Switch(id) => self.switch(id),
+        }
+    }
+
+    /// The watch face only ever sits at the bottom, pushing it goes back down to it
+    pub fn push(&mut self, id: ScreenId) -> bool {
+        if id == ScreenId::WatchFace {
+            return self.reset();
+        }
+        if id == self.active() || self.stack.is_full() {
+            return false;
+        }
+        self.defocus();
+        self.stack.push(id).ok();
+        self.focus();
+        true
+    }
+
+    /// The watch face is never popped
+    pub fn pop(&mut self) -> bool {
+        if self.stack.len() <= 1 {
+            return false;
+        }
+        self.defocus();
+        self.stack.pop();
+        self.focus();
+        true
+    }
+
+    /// Replace the active screen, unless it's the watch face, then `id` is pushed on top
+    pub fn switch(&mut self, id: ScreenId) -> bool {
+        if id == ScreenId::WatchFace {
+            return self.reset();
+        }
+        if self.stack.len() <= 1 {
+            return self.push(id);
+        }
+        if id == self.active() {
+            return false;
+        }
+        self.defocus();
+        self.stack.pop();
+        self.stack.push(id).ok();
+        self.focus();
+        true
+    }
+
+    /// Drop everything above the watch face
+    pub fn reset(&mut self) -> bool {
+        if self.stack.len() <= 1 {
+            return false;
+        }
+        self.defocus();
+        self.stack.truncate(1);
+        self.focus();
+        true
+    }
+
+    /// Show the ringing alarm on top of the watch face
+    pub fn on_alarm_ringing(&mut self) -> bool {
+        if self.active() == ScreenId::AlarmRinging {
+            return false;
+        }
+        self.reset();
+        self.push(ScreenId::AlarmRinging)
+    }
+
+    /// Nobody responded to the alarm, back to where it was
+    pub fn on_alarm_silenced(&mut self) -> bool {
+        self.active() == ScreenId::AlarmRinging && self.pop()
+    }
+
+    /// Show the timer on top of the watch face
+    pub fn on_countdown_expired(&mut self) -> bool {
+        match self.active() {
+            ScreenId::Timer | ScreenId::AlarmRinging => false,
+            _ => {
+                self.reset();
+                self.push(ScreenId::Timer)
+            }
+        }
+    }
+
+    /// Show the notifications on top of the watch face, the new one is in the list
+    /// for later when an alarm is ringing
+    pub fn on_notification(&mut self) -> bool {
+        match self.active() {
+            ScreenId::Notifications | ScreenId::AlarmRinging => false,
+            _ => {
+                self.reset();
+                self.push(ScreenId::Notifications)
+            }
+        }
+    }
+
+    /// Show the charging screen while plugged in, back to where it was when unplugged
+    pub fn on_charging(&mut self, charging: bool) -> bool {
+        match (self.active(), charging) {
+            (ScreenId::Charging, false) => self.pop(),
+            (ScreenId::Charging, true) | (ScreenId::AlarmRinging, true) | (_, false) => false,
+            (_, true) => self.push(ScreenId::Charging),
+        }
+    }
+
+    /// Show the low battery warning over the active screen, the battery icon is red
+    /// anyway while an alarm is ringing
+    pub fn on_low_battery(&mut self) -> bool {
+        self.active() != ScreenId::AlarmRinging && self.push(ScreenId::LowBattery)
+    }
+
+    pub fn force_redraw(&mut self) {
+        self.clear_display = true;
+        with_active_screen!(self, s => s.force_redraw());
+    }
+
+    pub fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        with_active_screen!(self, s => s.update(res))
+    }
+
+    /// Draw whatever changed on the active screen
+    pub fn draw<D>(&mut self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.clear_display {
+            display.clear(BACKGROUND_COLOR)?;
+            self.clear_display = false;
+        }
+        with_active_screen!(self, s => {
+            s.draw(display)?;
+            s.clear_redraw();
+        });
+        Ok(())
+    }
+
+    fn defocus(&mut self) {
+        with_active_screen!(self, s => s.off_focus());
+    }
+
+    fn focus(&mut self) {
+        self.clear_display = true;
+        with_active_screen!(self, s => s.on_focus());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pinetime_common::Gesture;
+
+    static FONT_STYLES: FontStyles = FontStyles::new();
+    static ICONS: Icons = Icons::new();
+
+    fn manager() -> ScreenManager {
+        ScreenManager::new(&FONT_STYLES, &ICONS)
+    }
+
+    #[test]
+    fn watch_face_stays_at_the_bottom() {
+        let mut sm = manager();
+        assert!(!sm.pop());
+        assert!(!sm.reset());
+        assert_eq!(sm.active(), ScreenId::WatchFace);
+
+        // Switching away from the root keeps the watch face underneath
+        assert!(sm.switch(ScreenId::Stopwatch));
+        assert_eq!(sm.depth(), 2);
+        assert!(sm.pop());
+        assert_eq!(sm.active(), ScreenId::WatchFace);
+
+        // Navigating to the watch face goes back down to it
+        sm.push(ScreenId::Alarms);
+        sm.push(ScreenId::Battery);
+        assert!(sm.switch(ScreenId::WatchFace));
+        assert_eq!(sm.depth(), 1);
+        sm.push(ScreenId::Alarms);
+        assert!(sm.push(ScreenId::WatchFace));
+        assert_eq!(sm.depth(), 1);
+        assert!(!sm.push(ScreenId::WatchFace));
+    }
+
+    #[test]
+    fn actions_come_back_out_after_navigating() {
+        let mut sm = manager();
+        let point = Default::default();
+        assert_eq!(
+            sm.handle_event(InputEvent::Gesture(Gesture::SlideRight, point)),
+            Action::Push(ScreenId::Alarms)
+        );
+        assert_eq!(
+            sm.handle_event(InputEvent::Gesture(Gesture::SlideRight, point)),
+            Action::Switch(ScreenId::Stopwatch)
+        );
+        assert_eq!(sm.active(), ScreenId::Stopwatch);
+        assert_eq!(sm.depth(), 2);
+
+        assert_eq!(
+            sm.handle_event(InputEvent::Button(ButtonEvent::LongPress)),
+            Action::Push(ScreenId::PowerMenu)
+        );
+        assert_eq!(sm.active(), ScreenId::PowerMenu);
+        assert_eq!(
+            sm.handle_event(InputEvent::Button(ButtonEvent::ShortPress)),
+            Action::Pop
+        );
+        assert_eq!(sm.active(), ScreenId::Stopwatch);
+    }
+
+    #[test]
+    fn alarm_ringing_goes_over_everything() {
+        let mut sm = manager();
+        sm.push(ScreenId::Settings);
+        sm.push(ScreenId::Battery);
+        assert!(sm.on_alarm_ringing());
+        assert_eq!(sm.active(), ScreenId::AlarmRinging);
+        assert_eq!(sm.depth(), 2);
+        assert!(!sm.on_alarm_ringing());
+        assert_eq!(sm.depth(), 2);
+
+        // Nothing else hides it
+        assert!(!sm.on_notification());
+        assert!(!sm.on_countdown_expired());
+        assert!(!sm.on_charging(true));
+        assert!(!sm.on_low_battery());
+        assert_eq!(sm.active(), ScreenId::AlarmRinging);
+
+        assert!(sm.on_alarm_silenced());
+        assert_eq!(sm.active(), ScreenId::WatchFace);
+        assert!(!sm.on_alarm_silenced());
+    }
+
+    #[test]
+    fn notifications_and_timer_go_over_the_watch_face() {
+        let mut sm = manager();
+        sm.push(ScreenId::Alarms);
+        sm.push(ScreenId::Stopwatch);
+        assert!(sm.on_notification());
+        assert_eq!(sm.active(), ScreenId::Notifications);
+        assert_eq!(sm.depth(), 2);
+        assert!(!sm.on_notification());
+
+        assert!(sm.on_countdown_expired());
+        assert_eq!(sm.active(), ScreenId::Timer);
+        assert_eq!(sm.depth(), 2);
+        assert!(!sm.on_countdown_expired());
+        // Silenced only pops a ringing alarm
+        assert!(!sm.on_alarm_silenced());
+        assert_eq!(sm.active(), ScreenId::Timer);
+    }
+
+    #[test]
+    fn charging_and_low_battery_go_over_the_active_screen() {
+        let mut sm = manager();
+        sm.push(ScreenId::HeartRate);
+        assert!(sm.on_charging(true));
+        assert_eq!(sm.active(), ScreenId::Charging);
+        assert!(!sm.on_charging(true));
+        assert!(sm.on_charging(false));
+        assert_eq!(sm.active(), ScreenId::HeartRate);
+        // Unplugged with the charging screen already gone
+        assert!(!sm.on_charging(false));
+        assert_eq!(sm.active(), ScreenId::HeartRate);
+
+        assert!(sm.on_low_battery());
+        assert_eq!(sm.active(), ScreenId::LowBattery);
+        assert!(!sm.on_low_battery());
+        assert!(sm.pop());
+        assert_eq!(sm.active(), ScreenId::HeartRate);
+    }
+}
@@ -1,15 +1,123 @@
+use pinetime_common::{
+    alarm, display::PixelFormat, embedded_graphics::Drawable, err_derive, BatteryControllerExt,
+    BatteryHistory, BootRecord, Countdown, CrashRecord, InputEvent, NotificationStore, SelfTest,
+    Settings, Stopwatch, SystemTimeExt,
+};
+
+pub mod alarm_ringing;
+pub mod alarms;
+pub mod battery;
+pub mod charging;
+pub mod crash;
+pub mod diagnostics;
+pub mod heart_rate;
+pub mod low_battery;
+pub mod manager;
+pub mod notifications;
+pub mod power_menu;
+pub mod settings;
+pub mod stopwatch;
+pub mod timer;
 pub mod watch_face;
-pub use watch_face::{WatchFace, WatchFaceResources};
-
-// some trait, ScreenExt: Drawable
-// on_focus
-// off_focus
-//
-// update(Self::Resources)
-//
-// (use eg::ContainsPoint trait)
-// handle_event(Event) -> Forward/SomeAction (like switch to ScreenFoo)
-//   touch/gesture
-//   button press
-
-// enum Screen { WatchFace(..), ... }
+pub use alarm_ringing::AlarmRingingScreen;
+pub use alarms::AlarmsScreen;
+pub use battery::BatteryScreen;
+pub use charging::ChargingScreen;
+pub use crash::CrashScreen;
+pub use diagnostics::DiagnosticsScreen;
+pub use heart_rate::HeartRateScreen;
+pub use low_battery::LowBatteryScreen;
+pub use manager::ScreenManager;
+pub use notifications::NotificationsScreen;
+pub use power_menu::{PowerAction, PowerMenuScreen};
+pub use settings::SettingsScreen;
+pub use stopwatch::StopwatchScreen;
+pub use timer::TimerScreen;
+pub use watch_face::WatchFace;
+
+#[derive(Debug, err_derive::Error)]
+pub enum Error {
+    #[error(display = "Formatting error")]
+    Formatting(#[error(source)] core::fmt::Error),
+}
+
+/// Everything a screen can read from the rest of the system while updating
+pub struct Resources<'a, T: SystemTimeExt, B: BatteryControllerExt> {
+    pub sys_time: &'a T,
+    pub bat_ctl: &'a B,
+    pub settings: &'a Settings,
+    /// Latest estimate, None while the sensor isn't running or has no reading yet
+    pub heart_rate_bpm: Option<u8>,
+    pub notifications: &'a NotificationStore,
+    /// Free-running monotonic milliseconds, wraps
+    pub now_ms: u32,
+    pub stopwatch: &'a Stopwatch,
+    pub countdown: &'a Countdown,
+    pub battery_history: &'a BatteryHistory,
+    pub boot_record: &'a BootRecord,
+    pub self_test: SelfTest,
+    /// Crash from before the last reset
+    pub crash_record: Option<&'a CrashRecord>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ScreenId {
+    WatchFace,
+    Settings,
+    HeartRate,
+    Notifications,
+    Alarms,
+    AlarmRinging,
+    Stopwatch,
+    Timer,
+    Battery,
+    LowBattery,
+    Charging,
+    Diagnostics,
+    Crash,
+    PowerMenu,
+}
+
+/// What a screen wants the manager to do after handling an event
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Action {
+    /// Event was consumed (or ignored), stay on the current screen
+    None,
+    /// Show the screen on top of the current one
+    Push(ScreenId),
+    /// Go back to the previous screen
+    Pop,
+    /// Replace the current screen
+    Switch(ScreenId),
+    /// The user changed the settings, they should be applied and saved
+    UpdateSettings(Settings),
+    /// The user responded to the ringing alarm, the screen is done
+    AlarmResponse(alarm::Response),
+    /// Start/stop/lap/reset the stopwatch
+    Stopwatch(pinetime_common::stopwatch::Control),
+    /// Set/start/pause/reset the countdown timer
+    Countdown(pinetime_common::countdown::Control),
+    /// Reboot, power off or install an update, the screen is done
+    Power(PowerAction),
+}
+
+pub trait Screen: Drawable<Color = PixelFormat, Output = ()> {
+    /// Called when the screen becomes the active screen
+    fn on_focus(&mut self) {
+        self.force_redraw();
+    }
+
+    /// Called when the screen is no longer the active screen
+    fn off_focus(&mut self) {}
+
+    fn force_redraw(&mut self);
+
+    fn clear_redraw(&mut self);
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt;
+
+    fn handle_event(&mut self, event: InputEvent) -> Action;
+}
@@ -0,0 +1,299 @@
+//! Received notifications, newest first
+//!
+//! * Slide up/down : scroll down/up a page
+//! * Slide right : back
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::Point,
+    mono_font::MonoTextStyle,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    chrono::Timelike,
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    text, BatteryControllerExt, ButtonEvent, Gesture, InputEvent, Notification, NotificationStore,
+    SystemTimeExt, TimeFormat,
+};
+
+const TITLE_HEIGHT: u32 = 40;
+
+/// Glyph size of the menu fonts
+const CHAR_WIDTH: u32 = 13;
+const ROW_HEIGHT: u32 = 25;
+
+const COLS: usize = (display::WIDTH as u32 / CHAR_WIDTH) as usize;
+const ROWS: usize = ((display::HEIGHT as u32 - TITLE_HEIGHT) / ROW_HEIGHT) as usize;
+
+const MARGIN: i32 = (display::WIDTH as u32 - COLS as u32 * CHAR_WIDTH) as i32 / 2;
+
+/// One row of the scrollable list
+#[derive(Copy, Clone, Debug)]
+enum Line<'a> {
+    /// Time and category
+    Header(&'a Notification),
+    Title(&'a str),
+    Body(&'a str),
+    /// Between notifications
+    Separator,
+}
+
+pub struct NotificationsScreen {
+    redraw: Redraw,
+    /// Revision of the system's store the local copy was taken from
+    revision: Option<u32>,
+    notifications: NotificationStore,
+    time_format: TimeFormat,
+    /// First visible line
+    scroll: usize,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const LIST = 1 << 1;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+impl NotificationsScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        NotificationsScreen {
+            redraw: Redraw::ALL,
+            revision: None,
+            notifications: NotificationStore::new(),
+            time_format: TimeFormat::default(),
+            scroll: 0,
+            font_styles,
+        }
+    }
+
+    fn for_each_line<F>(&self, mut f: F)
+    where
+        F: FnMut(Line<'_>),
+    {
+        for (index, n) in self.notifications.iter().enumerate() {
+            if index != 0 {
+                f(Line::Separator);
+            }
+            f(Line::Header(n));
+            if !n.title.is_empty() {
+                text::wrap_lines(&n.title, COLS).for_each(|l| f(Line::Title(l)));
+            }
+            if !n.body.is_empty() {
+                text::wrap_lines(&n.body, COLS).for_each(|l| f(Line::Body(l)));
+            }
+        }
+    }
+
+    fn line_count(&self) -> usize {
+        let mut count = 0;
+        self.for_each_line(|_| count += 1);
+        count
+    }
+
+    fn scroll_to(&mut self, line: usize) {
+        let last_page = self.line_count().saturating_sub(ROWS);
+        let line = line.min(last_page);
+        if line != self.scroll {
+            self.scroll = line;
+            self.redraw |= Redraw::LIST;
+        }
+    }
+
+    fn write_header(&self, n: &Notification, text: &mut String<32>) -> core::fmt::Result {
+        let time = n.timestamp.time();
+        match self.time_format {
+            TimeFormat::H12 => {
+                let (is_pm, hour) = time.hour12();
+                let am_pm = if is_pm { "PM" } else { "AM" };
+                write!(text, "{:2}:{:02}{} ", hour, time.minute(), am_pm)?;
+            }
+            TimeFormat::H24 => write!(text, "{:02}:{:02} ", time.hour(), time.minute())?,
+        }
+        write!(text, "{}", n.category)
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            let mut font_style = self.font_styles.menu_title.style();
+            font_style.background_color = BACKGROUND_COLOR.into();
+            let mut title: String<{ COLS }> = String::new();
+            // Always fits
+            write!(&mut title, "Notifications {}", self.notifications.len()).ok();
+            Text::with_text_style(
+                &title,
+                Point::new((display::WIDTH / 2) as i32, (TITLE_HEIGHT / 2) as i32),
+                font_style,
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_row<D>(
+        &self,
+        display: &mut D,
+        row: usize,
+        line: &str,
+        mut font_style: MonoTextStyle<'static, PixelFormat>,
+    ) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        font_style.background_color = BACKGROUND_COLOR.into();
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Top)
+            .alignment(Alignment::Left)
+            .build();
+        // Padded so the whole row is overwritten, wrapped lines are at most COLS chars
+        let mut text: String<{ COLS * 4 }> = String::new();
+        write!(&mut text, "{:<width$}", line, width = COLS).ok();
+        let pos_y = (TITLE_HEIGHT + row as u32 * ROW_HEIGHT) as i32;
+        Text::with_text_style(&text, Point::new(MARGIN, pos_y), font_style, text_style)
+            .draw(display)?;
+        Ok(())
+    }
+
+    fn draw_list<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::LIST) {
+            return Ok(());
+        }
+
+        if self.notifications.is_empty() {
+            self.draw_row(
+                display,
+                0,
+                "Nothing new",
+                self.font_styles.menu_item.style(),
+            )?;
+            for row in 1..ROWS {
+                self.draw_row(display, row, "", self.font_styles.menu_item.style())?;
+            }
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        let mut index: usize = 0;
+        self.for_each_line(|line| {
+            let row = index.wrapping_sub(self.scroll);
+            index += 1;
+            if result.is_err() || row >= ROWS {
+                return;
+            }
+            let mut header: String<32> = String::new();
+            let (text, style) = match line {
+                Line::Header(n) => {
+                    // Always fits, cut down to the row (it's all ASCII)
+                    self.write_header(n, &mut header).ok();
+                    let end = header.len().min(COLS);
+                    (&header[..end], self.font_styles.menu_title.style())
+                }
+                Line::Title(t) => (t, self.font_styles.menu_item_selected.style()),
+                Line::Body(b) => (b, self.font_styles.menu_item.style()),
+                Line::Separator => ("", self.font_styles.menu_item.style()),
+            };
+            result = self.draw_row(display, row, text, style);
+        });
+        result?;
+
+        // Clear what's left of a previously longer list
+        let drawn = index.saturating_sub(self.scroll).min(ROWS);
+        for row in drawn..ROWS {
+            self.draw_row(display, row, "", self.font_styles.menu_item.style())?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for NotificationsScreen {
+    fn on_focus(&mut self) {
+        self.scroll = 0;
+        self.force_redraw();
+    }
+
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        if self.revision != Some(res.notifications.revision()) {
+            self.revision = Some(res.notifications.revision());
+            self.notifications = res.notifications.clone();
+            // Newest notification is at the top
+            self.scroll = 0;
+            self.redraw |= Redraw::TITLE | Redraw::LIST;
+        }
+        if self.time_format != res.settings.time_format {
+            self.time_format = res.settings.time_format;
+            self.redraw |= Redraw::LIST;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => return Action::Pop,
+            InputEvent::Gesture(Gesture::SlideRight, _) => return Action::Pop,
+            InputEvent::Gesture(Gesture::SlideUp, _) => self.scroll_to(self.scroll + ROWS - 1),
+            InputEvent::Gesture(Gesture::SlideDown, _) => {
+                self.scroll_to(self.scroll.saturating_sub(ROWS - 1))
+            }
+            _ => (),
+        }
+        Action::None
+    }
+}
+
+impl Drawable for NotificationsScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_list(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Opened by a long press on the button, from any screen
+//!
+//! * Tap reboot : reset
+//! * Tap power off : System OFF, the button starts the watch back up
+//! * Tap update : install the firmware update staged on the external flash
+//! * Tap elsewhere : back
+//! * Slide down : back
+//! * Button : back
+//!
+//! Keeping the button held down resets the watch regardless.
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen},
+};
+use bitflags::bitflags;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    display::{self, PixelFormat},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, SystemTimeExt,
+};
+
+const TITLE_Y: i32 = 20;
+const BUTTONS_TOP: u32 = 45;
+const BUTTON_HEIGHT: u32 = 55;
+const BUTTON_MARGIN: u32 = 8;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PowerAction {
+    Reboot,
+    PowerOff,
+    Update,
+}
+
+const ACTIONS: [(PowerAction, &str); 3] = [
+    (PowerAction::Reboot, "Reboot"),
+    (PowerAction::PowerOff, "Power off"),
+    (PowerAction::Update, "Update"),
+];
+
+pub struct PowerMenuScreen {
+    redraw: Redraw,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const BUTTONS = 1 << 1;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+/// One full width button per action, top to bottom
+fn button_areas() -> [Rectangle; 3] {
+    let size = Size::new(display::WIDTH as u32 - 2 * BUTTON_MARGIN, BUTTON_HEIGHT);
+    let top = |i: u32| (BUTTONS_TOP + i * (BUTTON_HEIGHT + BUTTON_MARGIN)) as i32;
+    [0, 1, 2].map(|i| Rectangle::new(Point::new(BUTTON_MARGIN as i32, top(i)), size))
+}
+
+impl PowerMenuScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        PowerMenuScreen {
+            redraw: Redraw::ALL,
+            font_styles,
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Power",
+                Point::new((display::WIDTH / 2) as i32, TITLE_Y),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_buttons<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::BUTTONS) {
+            return Ok(());
+        }
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        let font_style = self.font_styles.menu_item_selected.style();
+        for (area, (_, label)) in button_areas().iter().zip(ACTIONS.iter()) {
+            let color = self.font_styles.menu_item_selected.text_color;
+            area.into_styled(PrimitiveStyle::with_stroke(color, 2))
+                .draw(display)?;
+            Text::with_text_style(label, area.center(), font_style, text_style).draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for PowerMenuScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, _res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Tap(_) => match event.hit_test(button_areas().iter()) {
+                Some(index) => Action::Power(ACTIONS[index].0),
+                None => Action::Pop,
+            },
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideDown, _) => Action::Pop,
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for PowerMenuScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_buttons(target)?;
+        Ok(())
+    }
+}
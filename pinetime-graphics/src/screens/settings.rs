@@ -0,0 +1,378 @@
+//! Settings list
+//!
+//! * Slide up/down : select the next/previous item
+//! * Slide right/left : increase/decrease the selected item's value
+//! * Tap : select an item and cycle through its values
+//! * Long press : diagnostics
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen, ScreenId},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    primitives::Rectangle,
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    wrist_tilt::Sensitivity,
+    BatteryControllerExt, Brightness, ButtonEvent, Gesture, InputEvent, Settings, SystemTimeExt,
+    TimeZone,
+};
+
+/// Display timeout values to choose from, in seconds
+pub const DISPLAY_TIMEOUT_CHOICES: [u8; 5] = [5, 10, 15, 30, 60];
+
+const TITLE_HEIGHT: u32 = 40;
+const ITEM_HEIGHT: u32 = 28;
+const MARGIN: i32 = 8;
+
+/// UTC offset steps, 15 minutes covers all zones in use
+const UTC_OFFSET_STEP_MINUTES: i16 = 15;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum Item {
+    Brightness,
+    DisplayTimeout,
+    TimeFormat,
+    WristRaise,
+    WristRaiseSensitivity,
+    UtcOffset,
+    Dst,
+}
+
+impl Item {
+    const ALL: [Item; 7] = [
+        Item::Brightness,
+        Item::DisplayTimeout,
+        Item::TimeFormat,
+        Item::UtcOffset,
+        Item::Dst,
+        Item::WristRaise,
+        Item::WristRaiseSensitivity,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Item::Brightness => "Brightness",
+            Item::DisplayTimeout => "Timeout",
+            Item::TimeFormat => "Clock",
+            Item::WristRaise => "Wrist raise",
+            Item::WristRaiseSensitivity => "Sensitivity",
+            Item::UtcOffset => "UTC",
+            Item::Dst => "DST",
+        }
+    }
+
+    fn area(index: usize) -> Rectangle {
+        Rectangle::new(
+            Point::new(0, (TITLE_HEIGHT + index as u32 * ITEM_HEIGHT) as i32),
+            Size::new(display::WIDTH as u32, ITEM_HEIGHT),
+        )
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum Adjust {
+    Increase,
+    Decrease,
+    /// Increase, wrapping around to the lowest value
+    Cycle,
+}
+
+pub struct SettingsScreen {
+    redraw: Redraw,
+    /// Take the system's settings on the next update
+    sync: bool,
+    settings: Settings,
+    selected: usize,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const ITEMS = 1 << 1;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+impl SettingsScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        SettingsScreen {
+            redraw: Redraw::ALL,
+            sync: true,
+            settings: Settings::default(),
+            selected: 0,
+            font_styles,
+        }
+    }
+
+    fn select(&mut self, index: usize) {
+        let index = index.min(Item::ALL.len() - 1);
+        if index != self.selected {
+            self.selected = index;
+            self.redraw |= Redraw::ITEMS;
+        }
+    }
+
+    /// Returns true if the value changed
+    fn adjust(&mut self, item: Item, adjust: Adjust) -> bool {
+        let prev = self.settings;
+        let s = &mut self.settings;
+        match item {
+            Item::Brightness => {
+                s.brightness = match adjust {
+                    Adjust::Increase => s.brightness.brighter(),
+                    Adjust::Decrease => s.brightness.darker().max(Brightness::dimmest()),
+                    Adjust::Cycle if s.brightness == Brightness::brightest() => {
+                        Brightness::dimmest()
+                    }
+                    Adjust::Cycle => s.brightness.brighter(),
+                }
+            }
+            Item::DisplayTimeout => {
+                let last = DISPLAY_TIMEOUT_CHOICES.len() - 1;
+                // Closest choice at or above the current value
+                let index = DISPLAY_TIMEOUT_CHOICES
+                    .iter()
+                    .position(|t| *t >= s.display_timeout_secs)
+                    .unwrap_or(last);
+                let index = match adjust {
+                    Adjust::Increase => (index + 1).min(last),
+                    Adjust::Decrease => index.saturating_sub(1),
+                    Adjust::Cycle => (index + 1) % DISPLAY_TIMEOUT_CHOICES.len(),
+                };
+                s.display_timeout_secs = DISPLAY_TIMEOUT_CHOICES[index];
+            }
+            Item::TimeFormat => s.time_format = s.time_format.toggled(),
+            Item::WristRaise => s.wrist_raise = !s.wrist_raise,
+            Item::WristRaiseSensitivity => {
+                let sensitivity = s.wrist_raise_sensitivity;
+                s.wrist_raise_sensitivity = match adjust {
+                    Adjust::Increase => sensitivity.higher(),
+                    Adjust::Decrease => sensitivity.lower(),
+                    Adjust::Cycle if sensitivity == Sensitivity::High => Sensitivity::Low,
+                    Adjust::Cycle => sensitivity.higher(),
+                }
+            }
+            Item::UtcOffset => {
+                let offset = s.time_zone.utc_offset_minutes;
+                let offset = match adjust {
+                    Adjust::Increase => offset + UTC_OFFSET_STEP_MINUTES,
+                    Adjust::Decrease => offset - UTC_OFFSET_STEP_MINUTES,
+                    // Whole hours, tapping through quarters would take forever
+                    Adjust::Cycle if offset + 60 > TimeZone::MAX_OFFSET_MINUTES => {
+                        TimeZone::MIN_OFFSET_MINUTES
+                    }
+                    Adjust::Cycle => offset + 60,
+                };
+                s.time_zone = TimeZone::new(offset, s.time_zone.dst);
+            }
+            Item::Dst => s.time_zone.dst = s.time_zone.dst.cycled(),
+        }
+        let changed = prev != self.settings;
+        if changed {
+            self.redraw |= Redraw::ITEMS;
+        }
+        changed
+    }
+
+    fn write_value(&self, item: Item, text: &mut String<8>) -> Result<(), core::fmt::Error> {
+        let mut value: String<8> = String::new();
+        match item {
+            Item::Brightness => write!(&mut value, "{}", self.settings.brightness)?,
+            Item::DisplayTimeout => write!(&mut value, "{}s", self.settings.display_timeout_secs)?,
+            Item::TimeFormat => write!(&mut value, "{}", self.settings.time_format)?,
+            Item::WristRaise if self.settings.wrist_raise => write!(&mut value, "On")?,
+            Item::WristRaise => write!(&mut value, "Off")?,
+            Item::WristRaiseSensitivity => {
+                write!(&mut value, "{}", self.settings.wrist_raise_sensitivity)?
+            }
+            Item::UtcOffset => write!(&mut value, "{}", self.settings.time_zone)?,
+            Item::Dst => write!(&mut value, "{}", self.settings.time_zone.dst)?,
+        }
+        // Padded so a shorter value covers up a longer previous one
+        let width = match item {
+            Item::UtcOffset => 6,
+            _ => 4,
+        };
+        write!(text, "{:>width$}", value.as_str(), width = width)
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Settings",
+                Point::new((display::WIDTH / 2) as i32, (TITLE_HEIGHT / 2) as i32),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_items<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::ITEMS) {
+            for (index, item) in Item::ALL.iter().enumerate() {
+                let is_selected = index == self.selected;
+                let mut font_style = if is_selected {
+                    self.font_styles.menu_item_selected.style()
+                } else {
+                    self.font_styles.menu_item.style()
+                };
+                font_style.background_color = BACKGROUND_COLOR.into();
+
+                let pos_y = Item::area(index).center().y;
+                let label_style = TextStyleBuilder::new()
+                    .baseline(Baseline::Middle)
+                    .alignment(Alignment::Left)
+                    .build();
+                let marker = if is_selected { ">" } else { " " };
+                Text::with_text_style(marker, Point::new(MARGIN, pos_y), font_style, label_style)
+                    .draw(display)?;
+                Text::with_text_style(
+                    item.label(),
+                    Point::new(MARGIN + 20, pos_y),
+                    font_style,
+                    label_style,
+                )
+                .draw(display)?;
+
+                let mut value = String::new();
+                // Values always fit
+                self.write_value(*item, &mut value).ok();
+                let value_style = TextStyleBuilder::new()
+                    .baseline(Baseline::Middle)
+                    .alignment(Alignment::Right)
+                    .build();
+                Text::with_text_style(
+                    &value,
+                    Point::new(display::WIDTH as i32 - MARGIN, pos_y),
+                    font_style,
+                    value_style,
+                )
+                .draw(display)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Screen for SettingsScreen {
+    fn on_focus(&mut self) {
+        self.sync = true;
+        self.force_redraw();
+    }
+
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        // While focused the local copy is authoritative, the system may not
+        // have applied the latest change yet
+        if self.sync {
+            self.sync = false;
+            if *res.settings != self.settings {
+                self.settings = *res.settings;
+                self.redraw |= Redraw::ITEMS;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        let changed = match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => return Action::Pop,
+            InputEvent::Gesture(Gesture::LongPress, _) => {
+                return Action::Push(ScreenId::Diagnostics)
+            }
+            InputEvent::Gesture(Gesture::SlideUp, _) => {
+                self.select(self.selected + 1);
+                false
+            }
+            InputEvent::Gesture(Gesture::SlideDown, _) => {
+                self.select(self.selected.saturating_sub(1));
+                false
+            }
+            InputEvent::Gesture(Gesture::SlideRight, _) => {
+                self.adjust(Item::ALL[self.selected], Adjust::Increase)
+            }
+            InputEvent::Gesture(Gesture::SlideLeft, _) => {
+                self.adjust(Item::ALL[self.selected], Adjust::Decrease)
+            }
+            InputEvent::Tap(_) => {
+                let mut areas = [Rectangle::zero(); Item::ALL.len()];
+                areas
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(index, a)| *a = Item::area(index));
+                match event.hit_test(areas.iter()) {
+                    Some(index) => {
+                        self.select(index);
+                        self.adjust(Item::ALL[index], Adjust::Cycle)
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        };
+
+        if changed {
+            Action::UpdateSettings(self.settings)
+        } else {
+            Action::None
+        }
+    }
+}
+
+impl Drawable for SettingsScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_items(target)?;
+        Ok(())
+    }
+}
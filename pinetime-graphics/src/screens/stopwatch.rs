@@ -0,0 +1,368 @@
+//! Stopwatch with a lap list
+//!
+//! The stopwatch itself is owned by the system and keeps running while the screen
+//! isn't shown, this only displays it.
+//!
+//! * Tap start/stop : start/stop
+//! * Tap lap/reset : lap while running, reset while stopped
+//! * Slide up/down : scroll the laps
+//! * Slide right : countdown timer
+//! * Slide left : alarms
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen, ScreenId},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    stopwatch::{Control, Stopwatch},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, SystemTimeExt,
+};
+
+const TITLE_Y: i32 = 15;
+const TIME_Y: i32 = 75;
+/// Hours and hundredths, under the minutes and seconds
+const FRACTION_Y: i32 = 130;
+const LAPS_TOP: i32 = 145;
+const LAP_ROWS: usize = 2;
+const ROW_HEIGHT: i32 = 25;
+
+/// Left edges of "MM:SS" in the big font, 44 pixel glyphs with 2 pixels of spacing
+const MINUTES_X: i32 = 6;
+const COLON_X: i32 = MINUTES_X + 2 * 46;
+const SECONDS_X: i32 = COLON_X + 46;
+const DIGITS_WIDTH: i32 = 2 * 46 - 2;
+
+const BUTTON_HEIGHT: u32 = 36;
+const BUTTON_MARGIN: u32 = 4;
+
+/// Elapsed time split up the way it's shown
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+struct Elapsed {
+    hours: u32,
+    minutes: u32,
+    seconds: u32,
+    hundredths: u32,
+}
+
+impl Elapsed {
+    fn from_ms(ms: u32) -> Self {
+        Elapsed {
+            hours: ms / 3_600_000,
+            minutes: ms / 60_000 % 60,
+            seconds: ms / 1000 % 60,
+            hundredths: ms / 10 % 100,
+        }
+    }
+}
+
+pub struct StopwatchScreen {
+    redraw: Redraw,
+    stopwatch: Stopwatch,
+    elapsed: Elapsed,
+    /// Newest lap shown at the top
+    lap_scroll: usize,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const HOURS = 1 << 1;
+        const MINUTES = 1 << 2;
+        const SECONDS = 1 << 3;
+        const HUNDREDTHS = 1 << 4;
+        const LAPS = 1 << 5;
+        const BUTTONS = 1 << 6;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+/// Start/stop on the left, lap/reset on the right
+fn button_areas() -> [Rectangle; 2] {
+    let width = (display::WIDTH as u32 - 3 * BUTTON_MARGIN) / 2;
+    let top = (display::HEIGHT as u32 - BUTTON_MARGIN - BUTTON_HEIGHT) as i32;
+    let size = Size::new(width, BUTTON_HEIGHT);
+    [
+        Rectangle::new(Point::new(BUTTON_MARGIN as i32, top), size),
+        Rectangle::new(Point::new((2 * BUTTON_MARGIN + width) as i32, top), size),
+    ]
+}
+
+const CONTROLS: [Control; 2] = [Control::StartStop, Control::LapReset];
+
+impl StopwatchScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        StopwatchScreen {
+            redraw: Redraw::ALL,
+            stopwatch: Stopwatch::new(),
+            elapsed: Elapsed::default(),
+            lap_scroll: 0,
+            font_styles,
+        }
+    }
+
+    fn scroll_laps(&mut self, lap: usize) {
+        let last = self.stopwatch.laps().count().saturating_sub(LAP_ROWS);
+        let lap = lap.min(last);
+        if lap != self.lap_scroll {
+            self.lap_scroll = lap;
+            self.redraw |= Redraw::LAPS;
+        }
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Stopwatch",
+                Point::new((display::WIDTH / 2) as i32, TITLE_Y),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Left)
+                .build();
+            Text::with_text_style(
+                ":",
+                Point::new(COLON_X, TIME_Y),
+                self.font_styles.watchface_time.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_time<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        let big_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Left)
+            .build();
+        let small_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        let mut big_font = self.font_styles.watchface_time.style();
+        big_font.background_color = BACKGROUND_COLOR.into();
+        let mut small_font = self.font_styles.watchface_date.style();
+        small_font.background_color = BACKGROUND_COLOR.into();
+
+        let mut text: String<8> = String::new();
+        // All of these always fit
+        if self.redraw.contains(Redraw::MINUTES) {
+            write!(&mut text, "{:02}", self.elapsed.minutes).ok();
+            Text::with_text_style(&text, Point::new(MINUTES_X, TIME_Y), big_font, big_style)
+                .draw(display)?;
+        }
+        if self.redraw.contains(Redraw::SECONDS) {
+            text.clear();
+            write!(&mut text, "{:02}", self.elapsed.seconds).ok();
+            Text::with_text_style(&text, Point::new(SECONDS_X, TIME_Y), big_font, big_style)
+                .draw(display)?;
+        }
+        if self.redraw.contains(Redraw::HOURS) {
+            text.clear();
+            // Padded so it clears the previous value, blank under an hour
+            match self.elapsed.hours {
+                0 => write!(&mut text, "{:5}", "").ok(),
+                h => write!(&mut text, "{:>3}h ", h.min(999)).ok(),
+            };
+            let center = Point::new(MINUTES_X + DIGITS_WIDTH / 2, FRACTION_Y);
+            Text::with_text_style(&text, center, small_font, small_style).draw(display)?;
+        }
+        if self.redraw.contains(Redraw::HUNDREDTHS) {
+            text.clear();
+            write!(&mut text, ".{:02}", self.elapsed.hundredths).ok();
+            let center = Point::new(SECONDS_X + DIGITS_WIDTH / 2, FRACTION_Y);
+            Text::with_text_style(&text, center, small_font, small_style).draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_laps<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::LAPS) {
+            return Ok(());
+        }
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Top)
+            .alignment(Alignment::Center)
+            .build();
+        let mut font_style = self.font_styles.menu_item.style();
+        font_style.background_color = BACKGROUND_COLOR.into();
+        let mut laps = self.stopwatch.laps().skip(self.lap_scroll);
+        for row in 0..LAP_ROWS {
+            let mut text: String<20> = String::new();
+            // Always fits, blank rows clear previous laps
+            match laps.next() {
+                Some(lap) => {
+                    let t = Elapsed::from_ms(lap.duration_ms);
+                    let minutes = t.hours * 60 + t.minutes;
+                    write!(
+                        &mut text,
+                        "Lap{:>3} {:>3}:{:02}.{:02}",
+                        lap.number,
+                        minutes.min(999),
+                        t.seconds,
+                        t.hundredths
+                    )
+                    .ok()
+                }
+                None => write!(&mut text, "{:16}", "").ok(),
+            };
+            let pos = Point::new(
+                (display::WIDTH / 2) as i32,
+                LAPS_TOP + row as i32 * ROW_HEIGHT,
+            );
+            Text::with_text_style(&text, pos, font_style, text_style).draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_buttons<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::BUTTONS) {
+            return Ok(());
+        }
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        let mut font_style = self.font_styles.menu_item_selected.style();
+        font_style.background_color = BACKGROUND_COLOR.into();
+        let running = self.stopwatch.is_running();
+        // Same length so the previous label is covered up
+        let labels = [
+            if running { "Stop " } else { "Start" },
+            if running { " Lap " } else { "Reset" },
+        ];
+        let color = self.font_styles.menu_item_selected.text_color;
+        for (area, label) in button_areas().iter().zip(labels.iter()) {
+            area.into_styled(PrimitiveStyle::with_stroke(color, 2))
+                .draw(display)?;
+            Text::with_text_style(label, area.center(), font_style, text_style).draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for StopwatchScreen {
+    fn on_focus(&mut self) {
+        self.lap_scroll = 0;
+        self.force_redraw();
+    }
+
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        if *res.stopwatch != self.stopwatch {
+            if res.stopwatch.lap_count() != self.stopwatch.lap_count() {
+                // Show the new lap
+                self.lap_scroll = 0;
+            }
+            self.stopwatch = res.stopwatch.clone();
+            self.redraw |= Redraw::LAPS | Redraw::BUTTONS;
+        }
+
+        let elapsed = Elapsed::from_ms(self.stopwatch.elapsed_ms(res.now_ms));
+        if elapsed.hours != self.elapsed.hours {
+            self.redraw |= Redraw::HOURS;
+        }
+        if elapsed.minutes != self.elapsed.minutes {
+            self.redraw |= Redraw::MINUTES;
+        }
+        if elapsed.seconds != self.elapsed.seconds {
+            self.redraw |= Redraw::SECONDS;
+        }
+        if elapsed.hundredths != self.elapsed.hundredths {
+            self.redraw |= Redraw::HUNDREDTHS;
+        }
+        self.elapsed = elapsed;
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideLeft, _) => Action::Switch(ScreenId::Alarms),
+            InputEvent::Gesture(Gesture::SlideRight, _) => Action::Switch(ScreenId::Timer),
+            InputEvent::Gesture(Gesture::SlideUp, _) => {
+                self.scroll_laps(self.lap_scroll + 1);
+                Action::None
+            }
+            InputEvent::Gesture(Gesture::SlideDown, _) => {
+                self.scroll_laps(self.lap_scroll.saturating_sub(1));
+                Action::None
+            }
+            InputEvent::Tap(_) => match event.hit_test(button_areas().iter()) {
+                Some(index) => Action::Stopwatch(CONTROLS[index]),
+                None => Action::None,
+            },
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for StopwatchScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_time(target)?;
+        self.draw_laps(target)?;
+        self.draw_buttons(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+//! Countdown timer
+//!
+//! The countdown itself is owned by the system and keeps running while the screen
+//! isn't shown, the system also alerts when it expires.
+//!
+//! * Tap minutes/seconds : select them while stopped
+//! * Slide up/down : increase/decrease the selected minutes (by 1) or seconds (by 5)
+//! * Tap start/pause : start, pause or resume
+//! * Tap reset : back to the full duration
+//! * Slide left : stopwatch
+//! * Button : back
+
+use crate::{
+    font_styles::FontStyles,
+    screens::{Action, Error, Resources, Screen, ScreenId},
+};
+use bitflags::bitflags;
+use core::fmt::Write;
+use heapless::String;
+use pinetime_common::embedded_graphics::{
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    mono_font::MonoTextStyleBuilder,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+    Drawable,
+};
+use pinetime_common::{
+    countdown::{Control, Countdown, State},
+    display::{self, PixelFormat, BACKGROUND_COLOR},
+    BatteryControllerExt, ButtonEvent, Gesture, InputEvent, SystemTimeExt,
+};
+
+const TITLE_Y: i32 = 15;
+const TIME_Y: i32 = 75;
+const STATUS_Y: i32 = 150;
+
+/// Left edges of "MM:SS" in the big font, 44 pixel glyphs with 2 pixels of spacing
+const MINUTES_X: i32 = 6;
+const COLON_X: i32 = MINUTES_X + 2 * 46;
+const SECONDS_X: i32 = COLON_X + 46;
+
+const BUTTON_HEIGHT: u32 = 50;
+const BUTTON_MARGIN: u32 = 4;
+
+const SECONDS_STEP: u32 = 5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum Field {
+    Minutes,
+    Seconds,
+}
+
+pub struct TimerScreen {
+    redraw: Redraw,
+    countdown: Countdown,
+    /// Remaining whole seconds as shown, rounded up
+    remaining_secs: u32,
+    field: Field,
+    font_styles: &'static FontStyles,
+}
+
+bitflags! {
+    struct Redraw: u8 {
+        const ALL = 0xFF;
+        const TITLE = 1 << 0;
+        const MINUTES = 1 << 1;
+        const SECONDS = 1 << 2;
+        const STATUS = 1 << 3;
+        const BUTTONS = 1 << 4;
+    }
+}
+
+impl Redraw {
+    fn clear(&mut self) {
+        self.bits = 0;
+    }
+
+    fn set_all(&mut self) {
+        self.bits = Self::ALL.bits;
+    }
+}
+
+/// Start/pause on the left, reset on the right
+fn button_areas() -> [Rectangle; 2] {
+    let width = (display::WIDTH as u32 - 3 * BUTTON_MARGIN) / 2;
+    let top = (display::HEIGHT as u32 - BUTTON_MARGIN - BUTTON_HEIGHT) as i32;
+    let size = Size::new(width, BUTTON_HEIGHT);
+    [
+        Rectangle::new(Point::new(BUTTON_MARGIN as i32, top), size),
+        Rectangle::new(Point::new((2 * BUTTON_MARGIN + width) as i32, top), size),
+    ]
+}
+
+fn field_area(field: Field) -> Rectangle {
+    let half = display::WIDTH as u32 / 2;
+    let x = match field {
+        Field::Minutes => 0,
+        Field::Seconds => half as i32,
+    };
+    Rectangle::new(Point::new(x, TIME_Y - 45), Size::new(half, 90))
+}
+
+const CONTROLS: [Control; 2] = [Control::StartPause, Control::Reset];
+
+impl TimerScreen {
+    pub fn new(font_styles: &'static FontStyles) -> Self {
+        TimerScreen {
+            redraw: Redraw::ALL,
+            countdown: Countdown::new(),
+            remaining_secs: 0,
+            field: Field::Minutes,
+            font_styles,
+        }
+    }
+
+    fn is_editable(&self) -> bool {
+        self.countdown.state() == State::Idle
+    }
+
+    /// Change the selected field, applied locally right away so repeated slides add up
+    fn adjust(&mut self, up: bool) -> Action {
+        let secs = self.countdown.duration_ms() / 1000;
+        let (minutes, seconds) = (secs / 60, secs % 60);
+        let (minutes, seconds) = match (self.field, up) {
+            (Field::Minutes, true) => ((minutes + 1) % 100, seconds),
+            (Field::Minutes, false) => ((minutes + 99) % 100, seconds),
+            (Field::Seconds, true) => (minutes, (seconds / SECONDS_STEP + 1) * SECONDS_STEP % 60),
+            (Field::Seconds, false) => {
+                // Down to the previous step, from in between steps too
+                let steps = 60 / SECONDS_STEP;
+                let step = seconds.div_ceil(SECONDS_STEP);
+                (minutes, (step + steps - 1) % steps * SECONDS_STEP)
+            }
+        };
+        let control = Control::Set {
+            duration_ms: (minutes * 60 + seconds) * 1000,
+        };
+        self.countdown.control(control, 0);
+        Action::Countdown(control)
+    }
+
+    fn draw_title<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::TITLE) {
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Center)
+                .build();
+            Text::with_text_style(
+                "Timer",
+                Point::new((display::WIDTH / 2) as i32, TITLE_Y),
+                self.font_styles.menu_title.style(),
+                text_style,
+            )
+            .draw(display)?;
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Middle)
+                .alignment(Alignment::Left)
+                .build();
+            Text::with_text_style(
+                ":",
+                Point::new(COLON_X, TIME_Y),
+                self.font_styles.watchface_time.style(),
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_time<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Left)
+            .build();
+        let selected = self.font_styles.watchface_time.text_color;
+        let unselected = self.font_styles.menu_item.text_color;
+        // While stopped the selected field stands out
+        let font_style = |field| {
+            let color = if !self.is_editable() || field == self.field {
+                selected
+            } else {
+                unselected
+            };
+            MonoTextStyleBuilder::new()
+                .font(self.font_styles.watchface_time.font)
+                .text_color(color)
+                .background_color(BACKGROUND_COLOR)
+                .build()
+        };
+
+        let mut text: String<4> = String::new();
+        // Always fits, durations are capped at 99:59
+        if self.redraw.contains(Redraw::MINUTES) {
+            write!(&mut text, "{:02}", self.remaining_secs / 60).ok();
+            let pos = Point::new(MINUTES_X, TIME_Y);
+            Text::with_text_style(&text, pos, font_style(Field::Minutes), text_style)
+                .draw(display)?;
+        }
+        if self.redraw.contains(Redraw::SECONDS) {
+            text.clear();
+            write!(&mut text, "{:02}", self.remaining_secs % 60).ok();
+            let pos = Point::new(SECONDS_X, TIME_Y);
+            Text::with_text_style(&text, pos, font_style(Field::Seconds), text_style)
+                .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_status<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::STATUS) {
+            return Ok(());
+        }
+        let status = match self.countdown.state() {
+            State::Idle | State::Running { .. } => "",
+            State::Paused { .. } => "Paused",
+            State::Expired => "Time's up",
+        };
+        let mut text: String<12> = String::new();
+        // Padded so it covers up the longest status
+        write!(&mut text, "{:^9}", status).ok();
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        let mut font_style = self.font_styles.menu_title.style();
+        font_style.background_color = BACKGROUND_COLOR.into();
+        Text::with_text_style(
+            &text,
+            Point::new((display::WIDTH / 2) as i32, STATUS_Y),
+            font_style,
+            text_style,
+        )
+        .draw(display)?;
+        Ok(())
+    }
+
+    fn draw_buttons<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if !self.redraw.contains(Redraw::BUTTONS) {
+            return Ok(());
+        }
+        let text_style = TextStyleBuilder::new()
+            .baseline(Baseline::Middle)
+            .alignment(Alignment::Center)
+            .build();
+        let mut font_style = self.font_styles.menu_item_selected.style();
+        font_style.background_color = BACKGROUND_COLOR.into();
+        // Same length so the previous label is covered up
+        let start_pause = match self.countdown.state() {
+            State::Idle | State::Expired => "Start ",
+            State::Running { .. } => "Pause ",
+            State::Paused { .. } => "Resume",
+        };
+        let labels = [start_pause, "Reset "];
+        let color = self.font_styles.menu_item_selected.text_color;
+        for (area, label) in button_areas().iter().zip(labels.iter()) {
+            area.into_styled(PrimitiveStyle::with_stroke(color, 2))
+                .draw(display)?;
+            Text::with_text_style(label, area.center(), font_style, text_style).draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for TimerScreen {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        if *res.countdown != self.countdown {
+            if res.countdown.state() != self.countdown.state() {
+                // Field highlighting depends on the state too
+                self.redraw |= Redraw::MINUTES | Redraw::SECONDS;
+            }
+            self.countdown = *res.countdown;
+            self.redraw |= Redraw::STATUS | Redraw::BUTTONS;
+        }
+
+        let remaining_secs = self.countdown.remaining_ms(res.now_ms).div_ceil(1000);
+        if remaining_secs / 60 != self.remaining_secs / 60 {
+            self.redraw |= Redraw::MINUTES;
+        }
+        if remaining_secs % 60 != self.remaining_secs % 60 {
+            self.redraw |= Redraw::SECONDS;
+        }
+        self.remaining_secs = remaining_secs;
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        match event {
+            InputEvent::Button(ButtonEvent::ShortPress) => Action::Pop,
+            InputEvent::Gesture(Gesture::SlideLeft, _) => Action::Switch(ScreenId::Stopwatch),
+            InputEvent::Gesture(Gesture::SlideUp, _) if self.is_editable() => self.adjust(true),
+            InputEvent::Gesture(Gesture::SlideDown, _) if self.is_editable() => self.adjust(false),
+            InputEvent::Tap(_) => {
+                if let Some(index) = event.hit_test(button_areas().iter()) {
+                    return Action::Countdown(CONTROLS[index]);
+                }
+                let fields = [field_area(Field::Minutes), field_area(Field::Seconds)];
+                if let Some(index) = event.hit_test(fields.iter()) {
+                    if self.is_editable() {
+                        self.field = if index == 0 {
+                            Field::Minutes
+                        } else {
+                            Field::Seconds
+                        };
+                        self.redraw |= Redraw::MINUTES | Redraw::SECONDS;
+                    }
+                }
+                Action::None
+            }
+            _ => Action::None,
+        }
+    }
+}
+
+impl Drawable for TimerScreen {
+    type Color = PixelFormat;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        self.draw_title(target)?;
+        self.draw_time(target)?;
+        self.draw_status(target)?;
+        self.draw_buttons(target)?;
+        Ok(())
+    }
+}
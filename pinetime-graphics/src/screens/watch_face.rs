@@ -1,45 +1,43 @@
 use crate::{
     font_styles::FontStyles,
     icons::{Icon, Icons},
+    screens::{Action, Error, Resources, Screen, ScreenId},
 };
 use bitflags::bitflags;
 use core::fmt::Write;
 use heapless::String;
 use pinetime_common::embedded_graphics::{
     draw_target::DrawTarget,
-    geometry::Point,
+    geometry::{Point, Size},
     mono_font::MonoTextStyleBuilder,
     prelude::*,
+    primitives::Rectangle,
     text::{Alignment, Baseline, Text, TextStyleBuilder},
     Drawable,
 };
 use pinetime_common::{
     chrono::{Datelike, NaiveDateTime, Timelike},
     display::{self, PixelFormat, BACKGROUND_COLOR},
-    err_derive, BatteryControllerExt, SystemTimeExt,
+    BatteryControllerExt, Gesture, InputEvent, SystemTimeExt, TimeFormat,
 };
 
 const MONTHS: [&str; 12] = [
     "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
 ];
 
-#[derive(Debug, err_derive::Error)]
-pub enum Error {
-    #[error(display = "Formatting error")]
-    Formatting(#[error(source)] core::fmt::Error),
-}
-
-pub struct WatchFaceResources<'a, T: SystemTimeExt, B: BatteryControllerExt> {
-    pub sys_time: &'a T,
-    pub bat_ctl: &'a B,
+/// Around the battery icon and the charging plug, a tap shows the battery screen
+fn battery_area() -> Rectangle {
+    Rectangle::new(Point::new(display::WIDTH as i32 - 70, 0), Size::new(70, 50))
 }
 
 pub struct WatchFace {
     redraw: Redraw,
     dt: NaiveDateTime,
+    time_format: TimeFormat,
     is_charging: bool,
     battery_icon: Icon,
     time_text: String<6>,
+    am_pm_text: &'static str,
     date_text: String<18>,
     font_styles: &'static FontStyles,
     icons: &'static Icons,
@@ -52,6 +50,7 @@ bitflags! {
         const DATE = 1 << 1;
         const BATTERY = 1 << 2;
         const CHARGE_PLUG = 1 << 3;
+        const AM_PM = 1 << 4;
         const FORCE_UPDATE = 1 << 7;
     }
 }
@@ -71,37 +70,22 @@ impl WatchFace {
         WatchFace {
             redraw: Redraw::ALL,
             dt: NaiveDateTime::from_timestamp(0, 0),
+            time_format: TimeFormat::default(),
             is_charging: false,
             battery_icon: Icon::BatteryFull,
             time_text: String::new(),
+            am_pm_text: "",
             date_text: String::new(),
             font_styles,
             icons,
         }
     }
 
-    pub fn force_redraw(&mut self) {
-        self.redraw.set_all();
-    }
-
-    pub fn clear_redraw(&mut self) {
-        self.redraw.clear();
-    }
-
-    pub fn update<'a, T, B>(&mut self, res: &WatchFaceResources<'a, T, B>) -> Result<(), Error>
-    where
-        T: SystemTimeExt,
-        B: BatteryControllerExt,
-    {
-        let dt = res.sys_time.date_time();
-        let percent_remaining = res.bat_ctl.percent_remaining();
-        let is_charging = res.bat_ctl.is_charging();
-
-        self.update_date_time(dt)?;
-        self.update_battery_indicator(percent_remaining);
-        self.update_battery_charge_plug(is_charging);
-
-        Ok(())
+    fn update_time_format(&mut self, time_format: TimeFormat) {
+        if time_format != self.time_format {
+            self.time_format = time_format;
+            self.redraw |= Redraw::FORCE_UPDATE;
+        }
     }
 
     fn update_date_time(&mut self, dt: &NaiveDateTime) -> Result<(), Error> {
@@ -126,17 +110,24 @@ impl WatchFace {
         let prev_time = self.dt.time();
         let time = dt.time();
         if self.redraw.contains(Redraw::FORCE_UPDATE)
-            || prev_time.hour12() != time.hour12()
+            || prev_time.hour() != time.hour()
             || prev_time.minute() != time.minute()
         {
+            let (hour, am_pm_text) = match self.time_format {
+                TimeFormat::H12 => {
+                    let (is_pm, hour) = time.hour12();
+                    (hour, if is_pm { "PM" } else { "AM" })
+                }
+                // Blank text of the same length, clears any previous AM/PM
+                TimeFormat::H24 => (time.hour(), "  "),
+            };
             self.time_text.clear();
-            write!(
-                &mut self.time_text,
-                "{:02}:{:02}",
-                time.hour12().1,
-                time.minute()
-            )?;
+            write!(&mut self.time_text, "{:02}:{:02}", hour, time.minute())?;
             self.redraw |= Redraw::TIME;
+            if am_pm_text != self.am_pm_text {
+                self.am_pm_text = am_pm_text;
+                self.redraw |= Redraw::AM_PM;
+            }
             changed = true;
         }
 
@@ -187,6 +178,30 @@ impl WatchFace {
         Ok(())
     }
 
+    fn draw_am_pm<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = PixelFormat>,
+    {
+        if self.redraw.contains(Redraw::AM_PM) {
+            let mut font_style = self.font_styles.watchface_date.style();
+            font_style.background_color = BACKGROUND_COLOR.into();
+            let text_style = TextStyleBuilder::new()
+                .baseline(Baseline::Alphabetic)
+                .alignment(Alignment::Left)
+                .build();
+            let pos_x = 10;
+            let pos_y = 30;
+            Text::with_text_style(
+                self.am_pm_text,
+                Point::new(pos_x, pos_y),
+                font_style,
+                text_style,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+
     fn draw_date<D>(&self, display: &mut D) -> Result<(), <D as DrawTarget>::Error>
     where
         D: DrawTarget<Color = PixelFormat>,
@@ -269,6 +284,46 @@ impl WatchFace {
     }
 }
 
+impl Screen for WatchFace {
+    fn force_redraw(&mut self) {
+        self.redraw.set_all();
+    }
+
+    fn clear_redraw(&mut self) {
+        self.redraw.clear();
+    }
+
+    fn update<T, B>(&mut self, res: &Resources<'_, T, B>) -> Result<(), Error>
+    where
+        T: SystemTimeExt,
+        B: BatteryControllerExt,
+    {
+        let dt = res.sys_time.local();
+        let percent_remaining = res.bat_ctl.percent_remaining();
+        let is_charging = res.bat_ctl.is_charging();
+
+        self.update_time_format(res.settings.time_format);
+        self.update_date_time(&dt)?;
+        self.update_battery_indicator(percent_remaining);
+        self.update_battery_charge_plug(is_charging);
+
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: InputEvent) -> Action {
+        if event.is_tap_within(&battery_area()) {
+            return Action::Push(ScreenId::Battery);
+        }
+        match event.gesture() {
+            Some(Gesture::SlideUp) => Action::Push(ScreenId::Settings),
+            Some(Gesture::SlideLeft) => Action::Push(ScreenId::HeartRate),
+            Some(Gesture::SlideDown) => Action::Push(ScreenId::Notifications),
+            Some(Gesture::SlideRight) => Action::Push(ScreenId::Alarms),
+            _ => Action::None,
+        }
+    }
+}
+
 impl Drawable for WatchFace {
     type Color = PixelFormat;
     type Output = ();
@@ -278,6 +333,7 @@ impl Drawable for WatchFace {
         D: DrawTarget<Color = PixelFormat>,
     {
         self.draw_time(target)?;
+        self.draw_am_pm(target)?;
         self.draw_date(target)?;
         self.draw_battery_indicator(target)?;
         self.draw_battery_charge_plug(target)?;
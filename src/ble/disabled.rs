@@ -0,0 +1,52 @@
+//! Stand-in for the BLE peripheral when the `ble` feature is off
+//!
+//! The radio is powered down on init and nothing ever reaches the GATT server.
+
+use crate::hal::pac;
+use pinetime_ble::{services::device_information::DeviceInformation, GattServer};
+
+pub struct Radio;
+pub struct LinkLayer;
+pub struct Responder;
+
+pub struct Ble {
+    pub radio: Radio,
+    pub link_layer: LinkLayer,
+    pub responder: Responder,
+}
+
+/// Keep the radio powered off
+pub fn init(
+    radio: pac::RADIO,
+    _ficr: &pac::FICR,
+    _timer: pac::TIMER2,
+    _device_info: DeviceInformation,
+) -> Ble {
+    radio.tasks_txen.write(|w| unsafe { w.bits(0) });
+    radio.tasks_rxen.write(|w| unsafe { w.bits(0) });
+    radio.tasks_stop.write(|w| unsafe { w.bits(1) });
+    radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+    radio.tasks_bcstop.write(|w| unsafe { w.bits(1) });
+    radio.events_disabled.write(|w| unsafe { w.bits(1) });
+    radio.power.write(|w| unsafe { w.bits(0) });
+
+    Ble {
+        radio: Radio,
+        link_layer: LinkLayer,
+        responder: Responder,
+    }
+}
+
+pub fn radio_interrupt(_radio: &mut Radio, _link_layer: &mut LinkLayer) -> bool {
+    false
+}
+
+pub fn timer_interrupt(_radio: &mut Radio, _link_layer: &mut LinkLayer) -> bool {
+    false
+}
+
+pub fn process_work(_responder: &mut Responder) {}
+
+pub fn gatt_server(_responder: &mut Responder) -> Option<&mut GattServer> {
+    None
+}
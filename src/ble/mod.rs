@@ -0,0 +1,15 @@
+//! BLE peripheral
+//!
+//! With the `ble` feature this is rubble's link layer on the nRF52832 radio. Without it the
+//! same types and functions are placeholders that keep the radio powered off, so the RTIC
+//! app, its tasks and its resources are the same in both builds.
+
+#[cfg(not(feature = "ble"))]
+mod disabled;
+#[cfg(feature = "ble")]
+mod peripheral;
+
+#[cfg(not(feature = "ble"))]
+pub use disabled::*;
+#[cfg(feature = "ble")]
+pub use peripheral::*;
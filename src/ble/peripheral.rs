@@ -0,0 +1,136 @@
+//! BLE peripheral, rubble's link layer on the nRF52832 radio
+//!
+//! TIMER2 is used for the link layer timing. The GATT server lives inside
+//! the responder, use [`gatt_server`] to get at it.
+
+use crate::hal::pac;
+use cortex_m::singleton;
+use pinetime_ble::{
+    rubble::{
+        config::Config,
+        l2cap::{BleChannelMap, L2CAPState},
+        link::{
+            ad_structure::AdStructure,
+            queue::{PacketQueue, SimpleQueue},
+            MIN_PDU_BUF,
+        },
+        security::NoSecurity,
+        time::Duration,
+    },
+    services::device_information::DeviceInformation,
+    GattProvider, GattServer, DEVICE_NAME,
+};
+use rtt_target::rprintln;
+use rubble_nrf5x::{
+    radio::{BleRadio, PacketBuffer},
+    timer::BleTimer,
+    utils::get_device_address,
+};
+
+pub enum BleConfig {}
+
+impl Config for BleConfig {
+    type Timer = BleTimer<pac::TIMER2>;
+    type Transmitter = Radio;
+    type ChannelMapper = BleChannelMap<GattProvider, NoSecurity>;
+    type PacketQueue = &'static mut SimpleQueue;
+}
+
+pub type Radio = BleRadio;
+pub type LinkLayer = pinetime_ble::rubble::link::LinkLayer<BleConfig>;
+pub type Responder = pinetime_ble::rubble::link::Responder<BleConfig>;
+
+pub const ADVERTISING_INTERVAL_MS: u32 = 200;
+
+pub struct Ble {
+    pub radio: Radio,
+    pub link_layer: LinkLayer,
+    pub responder: Responder,
+}
+
+/// Set up the radio and start advertising, can only be called once
+pub fn init(
+    radio: pac::RADIO,
+    ficr: &pac::FICR,
+    timer: pac::TIMER2,
+    device_info: DeviceInformation,
+) -> Ble {
+    let tx_buf = singleton!(: PacketBuffer = [0; MIN_PDU_BUF]).unwrap();
+    let rx_buf = singleton!(: PacketBuffer = [0; MIN_PDU_BUF]).unwrap();
+    let tx_queue = singleton!(: SimpleQueue = SimpleQueue::new()).unwrap();
+    let rx_queue = singleton!(: SimpleQueue = SimpleQueue::new()).unwrap();
+
+    let mut radio = BleRadio::new(radio, ficr, tx_buf, rx_buf);
+    let (tx, tx_cons) = tx_queue.split();
+    let (rx_prod, rx) = rx_queue.split();
+
+    let mut link_layer = LinkLayer::new(get_device_address(), BleTimer::init(timer));
+
+    let gatt = GattProvider::new(GattServer::new(device_info));
+    let responder = Responder::new(
+        tx,
+        rx,
+        L2CAPState::new(BleChannelMap::with_attributes(gatt)),
+    );
+
+    let next_update = link_layer
+        .start_advertise(
+            Duration::from_millis(ADVERTISING_INTERVAL_MS),
+            &[AdStructure::CompleteLocalName(DEVICE_NAME)],
+            &mut radio,
+            tx_cons,
+            rx_prod,
+        )
+        .unwrap();
+    link_layer.timer().configure_interrupt(next_update);
+
+    Ble {
+        radio,
+        link_layer,
+        responder,
+    }
+}
+
+/// Handle a radio event, true if the responder has work queued up
+pub fn radio_interrupt(radio: &mut Radio, link_layer: &mut LinkLayer) -> bool {
+    match radio.recv_interrupt(link_layer.timer().now(), link_layer) {
+        Some(cmd) => {
+            radio.configure_receiver(cmd.radio);
+            link_layer.timer().configure_interrupt(cmd.next_update);
+            cmd.queued_work
+        }
+        None => false,
+    }
+}
+
+/// Handle a link layer timer event, true if the responder has work queued up
+pub fn timer_interrupt(radio: &mut Radio, link_layer: &mut LinkLayer) -> bool {
+    if !link_layer.timer().is_interrupt_pending() {
+        return false;
+    }
+    link_layer.timer().clear_interrupt();
+
+    let cmd = link_layer.update_timer(radio);
+    radio.configure_receiver(cmd.radio);
+    link_layer.timer().configure_interrupt(cmd.next_update);
+    cmd.queued_work
+}
+
+pub fn process_work(responder: &mut Responder) {
+    while responder.has_work() {
+        if let Err(e) = responder.process_one() {
+            rprintln!("BLE error {:?}", e);
+        }
+    }
+}
+
+/// Always there with the `ble` feature
+pub fn gatt_server(responder: &mut Responder) -> Option<&mut GattServer> {
+    Some(
+        responder
+            .l2cap()
+            .channel_mapper()
+            .attribute_provider()
+            .server_mut(),
+    )
+}
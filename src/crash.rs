@@ -0,0 +1,55 @@
+//! Panic and HardFault handlers, they leave a crash record for the next boot and reset
+//!
+//! The panic still goes out over RTT for an attached probe.
+
+use crate::retained;
+use core::panic::PanicInfo;
+use cortex_m::{interrupt, peripheral::SCB};
+use cortex_m_rt::{exception, ExceptionFrame};
+use pinetime_common::{crash::STACK_WORDS, CrashRecord};
+use rtt_target::rprintln;
+
+extern "C" {
+    /// Top of the stack, from cortex-m-rt's link.x
+    static _stack_start: u32;
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    interrupt::disable();
+    rprintln!("{}", info);
+
+    let mut record = CrashRecord::new();
+    record.set_message(format_args!("{}", info.message()));
+    if let Some(location) = info.location() {
+        record.set_location(location.file(), location.line(), location.column());
+    }
+    let stack_pointer = cortex_m::register::msp::read();
+    record_stack(&mut record, stack_pointer);
+    retained::store_crash_record(&record);
+    SCB::sys_reset()
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    interrupt::disable();
+    rprintln!("{:?}", frame);
+
+    let mut record = CrashRecord::new();
+    record.set_message(format_args!("HardFault at {:#010X}", frame.pc()));
+    // The stacked registers come first
+    record_stack(&mut record, frame as *const ExceptionFrame as u32);
+    retained::store_crash_record(&record);
+    SCB::sys_reset()
+}
+
+/// Copy the words from `stack_pointer` up to the top of the stack, as many as fit
+fn record_stack(record: &mut CrashRecord, stack_pointer: u32) {
+    let stack_top = unsafe { &_stack_start as *const u32 as u32 };
+    let words = (stack_top.saturating_sub(stack_pointer) / 4).min(STACK_WORDS as u32) as usize;
+    let mut stack = [0_u32; STACK_WORDS];
+    for (i, word) in stack[..words].iter_mut().enumerate() {
+        *word = unsafe { core::ptr::read_volatile((stack_pointer as *const u32).add(i)) };
+    }
+    record.set_stack(stack_pointer, &stack[..words]);
+}
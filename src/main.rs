@@ -1,9 +1,16 @@
 #![no_main]
 #![no_std]
+// A full task queue hands the message back in the `Err` of the spawn functions generated by
+// `rtic::app`, which doesn't take attributes on the app module. The BLE notification and DFU
+// page messages are big, but there's no heap to box them in and every caller drops the
+// returned message anyway.
+#![allow(clippy::result_large_err)]
 
 use nrf52832_hal as hal;
-use panic_rtt_target as _;
 
+mod ble;
+mod crash;
+mod retained;
 mod rtc_monotonic;
 mod system_time;
 
@@ -13,9 +20,12 @@ pub mod built_info {
 
 #[rtic::app(device = crate::hal::pac, peripherals = true, dispatchers = [SWI0_EGU0, SWI1_EGU1, SWI2_EGU2, SWI3_EGU3, SWI4_EGU4])]
 mod app {
-    use crate::{built_info, hal, rtc_monotonic, system_time};
+    use crate::{ble, built_info, hal, retained, rtc_monotonic, system_time};
+    use core::cell::RefCell;
+    use cortex_m::{interrupt::Mutex, singleton};
     use hal::{
         clocks::Clocks,
+        delay::Delay,
         gpio::{self, Level},
         gpiote::Gpiote,
         pac, ppi,
@@ -24,36 +34,107 @@ mod app {
         timer::Timer,
         twim::{self, Frequency, Twim},
     };
+    use pinetime_ble::{
+        services::{
+            alert_notification::NewAlert, current_time::LocalTimeInformation,
+            device_information::DeviceInformation,
+        },
+        GattEvent,
+    };
+    use pinetime_common::chrono::NaiveDateTime;
     use pinetime_common::{
-        display, embedded_graphics::prelude::*, AnimatedDisplay, AtomicDisplayAwakeState,
-        RefreshDirection,
+        alarm, battery_history,
+        clock_drift::TickCounter,
+        dfu::{
+            image,
+            receiver::{self, Failure, Page},
+            BootStateStore, SwapState,
+        },
+        display,
+        embedded_graphics::prelude::*,
+        flash_layout,
+        low_battery::{self, LowBatteryMonitor},
+        vibration::{self, Pattern},
+        wrist_tilt::{self, WristTiltDetector},
+        AnimatedDisplay, AtomicDisplayAwakeState, BatteryHistory, BootRecord, BpmEstimator,
+        Countdown, CrashRecord, InputEvent, Notification, NotificationStore, RefreshDirection,
+        ResetReason, SelfTest, Settings, SettingsStore, Stopwatch, SystemTimeExt, TaskHealth,
+        TimeSource, TimeZone,
     };
     use pinetime_drivers::{
         animated_st7789::AnimatedSt7789,
         backlight::{Backlight, Brightness},
         battery_controller::BatteryController,
+        bma421::{self, Bma421},
         button::Button,
         cst816s::{self, Cst816s, Gesture},
         display_interface_spi::SPIInterface,
+        hrs3300::Hrs3300,
+        i2c_bus::I2cProxy,
         lcd::{LcdCsPin, LcdDcPin, LcdResetPin},
         motor_controller::MotorController,
+        power::Power,
+        shared_bus,
+        spi_bus::{SpiBus, Spim0, Spim0ChipSelect, Spim0Proxy},
+        spi_flash::{self, FlashCsPin, SpiFlash},
         watchdog::Watchdog,
     };
     use pinetime_graphics::{
         font_styles::FontStyles,
         icons::Icons,
-        screens::{WatchFace, WatchFaceResources},
+        screens::{Action, PowerAction, Resources, ScreenId, ScreenManager},
     };
-    use rtc_monotonic::{Rtc1Monotonic, RtcMonotonic};
+    use rtc_monotonic::{Rtc1Monotonic, RtcMonotonic, MAX_TICKS};
     use rtic::time::duration::{Milliseconds, Seconds};
     use rtt_target::{rprintln, rtt_init_print};
     use system_time::SystemTime;
 
-    const SCREEN_REFRESH_INTERVAL: Milliseconds = Milliseconds(20_u32);
-    const DISPLAY_TIMEOUT: Seconds = Seconds(5_u32);
     const DISPLAY_TIMEOUT_POLL_INTERVAL: Seconds = Seconds(1_u32);
-    const DISPLAY_TIMEOUT_TIMER_TICKS: u32 =
-        Timer::<pac::TIMER0>::TICKS_PER_SECOND * DISPLAY_TIMEOUT.0;
+
+    /// Empty unless the blob is provided at build time, see build.rs
+    const BMA421_CONFIG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bma421_config.bin"));
+
+    /// Step counter interrupt every 20 steps
+    const STEP_COUNTER_WATERMARK: u16 = 1;
+
+    const WRIST_TILT_POLL_INTERVAL: Milliseconds = Milliseconds(100_u32);
+
+    /// Sample rate of the BPM estimator
+    const HEART_RATE_SAMPLE_INTERVAL: Milliseconds =
+        Milliseconds(1000 / BpmEstimator::SAMPLE_RATE_HZ);
+
+    /// How often to check whether the heart rate screen is shown while the sensor is off
+    const HEART_RATE_IDLE_POLL_INTERVAL: Milliseconds = Milliseconds(250_u32);
+
+    /// Up for this long after passing the self-test, a fresh update is kept from then on
+    const CONFIRM_BOOT_DELAY: Seconds = Seconds(10_u32);
+
+    /// The touch controller sometimes needs a few tries to come up
+    const TOUCH_INIT_ATTEMPTS: usize = 5;
+
+    /// Tasks that have to keep checking in for the watchdog to be pet
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum SupervisedTask {
+        DrawScreen,
+        UpdateSystemTime,
+    }
+
+    /// How long each supervised task can go without checking in, in milliseconds
+    const SUPERVISED_TASKS: [(SupervisedTask, u32); 2] = [
+        (SupervisedTask::DrawScreen, 2_000),
+        (SupervisedTask::UpdateSystemTime, 2_500),
+    ];
+
+    pub struct MonotonicTime;
+
+    impl TimeSource for MonotonicTime {
+        fn now_ms(&self) -> u32 {
+            now_ms()
+        }
+    }
+
+    /// Time for the peer to read the status before rebooting into a firmware update
+    const DFU_REBOOT_DELAY: Seconds = Seconds(1_u32);
 
     #[monotonic(binds = RTC1, default = true)]
     type RtcMono = Rtc1Monotonic;
@@ -62,6 +143,10 @@ mod app {
     struct Shared {
         display_state: AtomicDisplayAwakeState,
 
+        task_health: TaskHealth<SupervisedTask, MonotonicTime, { SUPERVISED_TASKS.len() }>,
+
+        settings: Settings,
+
         #[lock_free]
         display_sleep_timer: Timer<pac::TIMER0>,
 
@@ -79,21 +164,86 @@ mod app {
         // DisplayEvent::ChargeInd(bool) or whatev
         // ...
         #[lock_free]
-        display: AnimatedSt7789<SPIInterface<Spim<pac::SPIM0>, LcdDcPin, LcdCsPin>, LcdResetPin>,
+        display: AnimatedSt7789<
+            SPIInterface<Spim0Proxy, LcdDcPin, Spim0ChipSelect<LcdCsPin>>,
+            LcdResetPin,
+        >,
+
+        #[lock_free]
+        spi_flash: SpiFlash<Spim0Proxy, Spim0ChipSelect<FlashCsPin>>,
+
+        #[lock_free]
+        flash_delay: Delay,
+
+        #[lock_free]
+        boot_state_store: BootStateStore,
+
+        #[lock_free]
+        boot_record: BootRecord,
+
+        #[lock_free]
+        self_test: SelfTest,
+
+        #[lock_free]
+        crash_record: Option<CrashRecord>,
+
+        #[lock_free]
+        accelerometer: Bma421<I2cProxy>,
+
+        #[lock_free]
+        heart_rate_sensor: Hrs3300<I2cProxy>,
+
+        #[lock_free]
+        touch_controller: Cst816s<I2cProxy>,
+
+        #[lock_free]
+        heart_rate_bpm: Option<u8>,
 
         #[lock_free]
         battery_controller: BatteryController,
 
         #[lock_free]
         motor_controller: MotorController,
+
+        #[lock_free]
+        vibration_player: vibration::Player,
+
+        #[lock_free]
+        alarm_scheduler: alarm::Scheduler,
+
+        #[lock_free]
+        stopwatch: Stopwatch,
+
+        #[lock_free]
+        countdown: Countdown,
+
+        #[lock_free]
+        battery_history: BatteryHistory,
+
+        low_battery: LowBatteryMonitor,
+
+        #[lock_free]
+        screen_manager: ScreenManager,
+
+        #[lock_free]
+        notifications: NotificationStore,
+
+        #[lock_free]
+        ble_radio: ble::Radio,
+
+        #[lock_free]
+        ble_link_layer: ble::LinkLayer,
+
+        #[lock_free]
+        ble_responder: ble::Responder,
     }
 
     #[local]
     struct Local<'a> {
         gpiote: Gpiote,
-        touch_controller: Cst816s<pac::TWIM1>,
         watchdog: Watchdog,
-        watch_face: WatchFace,
+        settings_store: SettingsStore,
+        power: Power,
     }
 
     #[init(local = [font_styles: FontStyles = FontStyles::new(), icons: Icons = Icons::new()])]
@@ -113,8 +263,11 @@ mod app {
             GPIOTE,
             TWIM1,
             RADIO,
+            FICR,
+            TIMER2,
             SAADC,
             WDT,
+            POWER,
             ..
         } = ctx.device;
 
@@ -125,26 +278,56 @@ mod app {
         let gpiote = Gpiote::new(GPIOTE);
         let ppi_channels = ppi::Parts::new(PPI);
 
+        // Count this boot before anything can go wrong, it's marked good after the self-test
+        let mut power = Power::new(POWER);
+        let reset_reason = power.take_reset_reason();
+        let mut boot_record = match reset_reason {
+            ResetReason::PowerOn => BootRecord::new(),
+            _ => retained::load_boot_record(),
+        };
+        boot_record.start_boot(reset_reason);
+        retained::store_boot_record(&boot_record);
+        rprintln!(
+            "Reset reason {}, boot {}, {} bad boots in a row",
+            reset_reason,
+            boot_record.boots(),
+            boot_record.bad_boots()
+        );
+        let mut self_test = SelfTest::default();
+        let crash_record = retained::take_crash_record();
+        if let Some(crash) = &crash_record {
+            rprintln!("Crashed before the reset: {}", crash);
+            rprintln!(
+                "Stack at {:#010X}: {:08X?}",
+                crash.stack_pointer(),
+                crash.stack()
+            );
+        }
+
         let watchdog = Watchdog::new(WDT);
 
         let mono = RtcMonotonic::new(RTC1, TIMER1, ppi_channels.ppi3).unwrap();
-        let system_time = SystemTime::new();
+        let mut system_time = SystemTime::new();
 
-        // TODO - disable RADIO for now
-        // eventually make an enum for variants
+        // TODO - eventually make an enum for variants
         // UnInit(pac-devices)
         // Init(drivers)
         // ...
         // disabled on boot, enabled on-demand when the transport is needed
         // then reboot or button to turn it back off, only want the radio
         // on when needed
-        RADIO.tasks_txen.write(|w| unsafe { w.bits(0) });
-        RADIO.tasks_rxen.write(|w| unsafe { w.bits(0) });
-        RADIO.tasks_stop.write(|w| unsafe { w.bits(1) });
-        RADIO.tasks_disable.write(|w| unsafe { w.bits(1) });
-        RADIO.tasks_bcstop.write(|w| unsafe { w.bits(1) });
-        RADIO.events_disabled.write(|w| unsafe { w.bits(1) });
-        RADIO.power.write(|w| unsafe { w.bits(0) });
+        // The radio stays off without the ble feature
+        let mut ble = ble::init(
+            RADIO,
+            &FICR,
+            TIMER2,
+            DeviceInformation::new(built_info::PKG_VERSION),
+        );
+        if let (Some(crash), Some(gatt_server)) =
+            (&crash_record, ble::gatt_server(&mut ble.responder))
+        {
+            gatt_server.set_crash_report(crash);
+        }
 
         let mut delay = Timer::new(TIMER0);
 
@@ -167,24 +350,56 @@ mod app {
         let sda = gpio.p0_06.into_floating_input().degrade();
         let cst_rst = gpio.p0_10.into_push_pull_output(Level::High);
         let cst_int: cst816s::InterruptPin = gpio.p0_28.into_floating_input();
-        let mut cst_twim = Twim::new(TWIM1, twim::Pins { scl, sda }, Frequency::K400);
+        let mut twim1 = Twim::new(TWIM1, twim::Pins { scl, sda }, Frequency::K400);
 
         // The TWI device should work @ up to 400Khz but there is a HW bug which prevent it from
         // respecting correct timings. According to erratas heet, this magic value makes it run
         // at ~390Khz with correct timings.
-        cst_twim.disable();
+        twim1.disable();
         unsafe {
             let twim = pac::TWIM0::ptr();
             (*twim)
                 .frequency
                 .write(|w| w.frequency().bits(cst816s::MAX_FREQUENCY));
         }
-        cst_twim.enable();
+        twim1.enable();
+
+        // TWIM1 is shared by the touch controller, the accelerometer and the heart rate sensor
+        let i2c_bus = shared_bus::new_atomic_check!(Twim<pac::TWIM1> = twim1).unwrap();
 
         // CST816S generates events on channel 1
-        let mut touch_controller = Cst816s::new(cst_twim, cst_rst, cst_int, &gpiote.channel1());
-        while touch_controller.init(&mut delay).is_err() {
-            delay.delay_ms(5_u32);
+        let mut touch_controller =
+            Cst816s::new(i2c_bus.acquire_i2c(), cst_rst, cst_int, &gpiote.channel1());
+        self_test.touch = (0..TOUCH_INIT_ATTEMPTS).any(|_| {
+            let ok = touch_controller.init(&mut delay).is_ok();
+            if !ok {
+                delay.delay_ms(5_u32);
+            }
+            ok
+        });
+        if !self_test.touch {
+            rprintln!("Touch controller failed to initialize");
+        }
+
+        // BMA421 generates step counter events on channel 3
+        let bma_int: bma421::InterruptPin = gpio.p0_08.into_floating_input();
+        bma421::enable_interrupt(bma_int, &gpiote.channel3());
+        let mut accelerometer = Bma421::new(i2c_bus.acquire_i2c());
+        match accelerometer.init(&mut delay, BMA421_CONFIG) {
+            Ok(()) if accelerometer.is_config_loaded() => {
+                if let Err(e) = accelerometer.enable_step_counter(STEP_COUNTER_WATERMARK) {
+                    rprintln!("Failed to enable the step counter {:?}", e);
+                }
+            }
+            Ok(()) => rprintln!("No BMA421 config, step counter disabled"),
+            Err(e) => rprintln!("BMA421 error {:?}", e),
+        }
+        accelerometer.set_power_save(true).ok();
+
+        // Off until the heart rate screen is shown
+        let mut heart_rate_sensor = Hrs3300::new(i2c_bus.acquire_i2c());
+        if let Err(e) = heart_rate_sensor.init() {
+            rprintln!("HRS3300 error {:?}", e);
         }
 
         // PowerPresence pin generates events on GPIOTE channel 2
@@ -196,6 +411,13 @@ mod app {
             &gpiote.channel2(),
         );
         battery_controller.update();
+        self_test.battery = battery_controller.has_plausible_voltage();
+        if !self_test.battery {
+            rprintln!(
+                "Implausible battery voltage {}",
+                battery_controller.voltage()
+            );
+        }
 
         let spi_clk = gpio.p0_02.into_push_pull_output(Level::Low).degrade();
         let spi_mosi = gpio.p0_03.into_push_pull_output(Level::Low).degrade();
@@ -205,31 +427,90 @@ mod app {
             miso: Some(spi_miso),
             mosi: Some(spi_mosi),
         };
-        let display_spi = Spim::new(SPIM0, spi_pins, spim::Frequency::M8, spim::MODE_3, 0);
+        let spim0 = Spim::new(SPIM0, spi_pins, spim::Frequency::M8, spim::MODE_3, 0);
 
-        // Display control
+        // SPIM0 is shared by the display and the SPI NOR flash
+        let flash_cs: FlashCsPin = gpio.p0_05.into_push_pull_output(Level::High);
         let lcd_cs: LcdCsPin = gpio.p0_25.into_push_pull_output(Level::High);
+        let spi_bus = singleton!(: SpiBus<Spim0> = SpiBus::new(spim0))
+            .unwrap()
+            .split(lcd_cs, flash_cs);
+
+        let mut spi_flash = SpiFlash::new(spi_bus.flash_spi, spi_bus.flash_cs);
+        spi_flash.release_deep_power_down(&mut delay).unwrap();
+        match spi_flash.jedec_id() {
+            Ok(id) if id == spi_flash::JEDEC_ID => rprintln!("SPI flash {}", id),
+            Ok(id) => rprintln!("Unexpected SPI flash JEDEC ID {}", id),
+            Err(e) => rprintln!("SPI flash error {:?}", e),
+        }
+
+        let mut settings_store = SettingsStore::new(
+            flash_layout::SETTINGS_OFFSET,
+            flash_layout::SETTINGS_SECTORS,
+        );
+        let settings = match settings_store.load(&mut spi_flash) {
+            Ok(Some(s)) => s,
+            Ok(None) => {
+                rprintln!("No stored settings, using defaults");
+                Settings::default()
+            }
+            Err(e) => {
+                rprintln!("Failed to load settings {:?}", e);
+                Settings::default()
+            }
+        };
+        rprintln!("{:?}", settings);
+        system_time.set_time_zone(settings.time_zone);
+        system_time.set_drift_ppm(settings.rtc_drift_ppm as i32);
+        spi_flash.deep_power_down().unwrap();
+        let flash_delay = Delay::new(ctx.core.SYST);
+        let boot_state_store = BootStateStore::new(
+            flash_layout::BOOT_STATE_OFFSET,
+            flash_layout::BOOT_STATE_SECTORS,
+        );
+
+        // Display control
         let lcd_dc: LcdDcPin = gpio.p0_18.into_push_pull_output(Level::Low);
         let lcd_rst: LcdResetPin = gpio.p0_26.into_push_pull_output(Level::Low);
 
-        let di = SPIInterface::new(display_spi, lcd_dc, lcd_cs);
+        let di = SPIInterface::new(spi_bus.display_spi, lcd_dc, spi_bus.display_cs);
         let mut display = AnimatedSt7789::new(di, lcd_rst, display::WIDTH, display::HEIGHT);
-        display.init(&mut delay).unwrap();
-
-        display.clear(display::PixelFormat::BLACK).unwrap();
+        self_test.display = display
+            .init(&mut delay)
+            .and_then(|_| display.clear(display::PixelFormat::BLACK))
+            .map_err(|e| rprintln!("Display error {:?}", e))
+            .is_ok();
+
+        if self_test.passed() {
+            boot_record.mark_good();
+            retained::store_boot_record(&boot_record);
+        }
 
-        let watch_face = WatchFace::new(ctx.local.font_styles, ctx.local.icons);
+        let mut screen_manager = ScreenManager::new(ctx.local.font_styles, ctx.local.icons);
+        if crash_record.is_some() {
+            screen_manager.push(ScreenId::Crash);
+        }
 
         watchdog_petter::spawn().unwrap();
         update_system_time::spawn().unwrap();
         poll_battery_voltage::spawn().unwrap();
+        sample_battery::spawn().unwrap();
+        poll_wrist_tilt::spawn().unwrap();
+        poll_heart_rate::spawn().unwrap();
         draw_screen::spawn().unwrap();
         ramp_on_backlight::spawn().unwrap();
         wakeup_display::spawn().unwrap();
+        if self_test.passed() {
+            confirm_boot::spawn_after(CONFIRM_BOOT_DELAY, true).unwrap();
+        } else {
+            confirm_boot::spawn(false).unwrap();
+        }
 
         (
             Shared {
                 display_state: AtomicDisplayAwakeState::new(false),
+                task_health: TaskHealth::new(MonotonicTime, SUPERVISED_TASKS),
+                settings,
                 display_sleep_timer: delay,
                 button,
                 system_time,
@@ -237,12 +518,33 @@ mod app {
                 display,
                 battery_controller,
                 motor_controller,
+                vibration_player: vibration::Player::new(),
+                alarm_scheduler: alarm::Scheduler::new(),
+                stopwatch: Stopwatch::new(),
+                countdown: Countdown::new(),
+                battery_history: BatteryHistory::new(),
+                low_battery: LowBatteryMonitor::new(),
+                screen_manager,
+                notifications: NotificationStore::new(),
+                spi_flash,
+                flash_delay,
+                boot_state_store,
+                boot_record,
+                self_test,
+                crash_record,
+                accelerometer,
+                heart_rate_sensor,
+                heart_rate_bpm: None,
+                touch_controller,
+                ble_radio: ble.radio,
+                ble_link_layer: ble.link_layer,
+                ble_responder: ble.responder,
             },
             Local {
                 gpiote,
-                touch_controller,
                 watchdog,
-                watch_face,
+                settings_store,
+                power,
             },
             init::Monotonics(mono),
         )
@@ -262,29 +564,43 @@ mod app {
             ctx.local.gpiote.channel2().reset_events();
             poll_battery_io::spawn().ok();
         }
+        if ctx.local.gpiote.channel3().is_event_triggered() {
+            ctx.local.gpiote.channel3().reset_events();
+            step_event::spawn().ok();
+        }
         if ctx.local.gpiote.port().is_event_triggered() {
             rprintln!("Unexpected interrupt from port event");
         }
     }
 
-    #[task(local = [watchdog], shared = [button], priority = 4)]
+    #[task(local = [watchdog], shared = [&task_health, button], priority = 4)]
     fn watchdog_petter(ctx: watchdog_petter::Context) {
         //let t = monotonics::now();
         //let t = Milliseconds::<u32>::try_from(t.duration_since_epoch()).unwrap();
         //rprintln!("wdt {:?}", t);
 
-        // Holding the button down will eventually trip the watchdog and reset
-        if !ctx.shared.button.is_pressed() {
+        // A hung task or holding the button down for long enough trips the watchdog and resets
+        if let Some(task) = ctx.shared.task_health.overdue() {
+            rprintln!("{:?} is overdue, not petting the watchdog", task);
+        } else if !ctx.shared.button.is_very_long_press(now_ms()) {
             ctx.local.watchdog.pet();
         }
         watchdog_petter::spawn_after(Watchdog::PER_INTERVAL_MS).unwrap();
     }
 
-    #[task(shared = [system_time], priority = 5)]
+    #[task(shared = [&task_health, system_time], priority = 5)]
     fn update_system_time(ctx: update_system_time::Context) {
+        ctx.shared
+            .task_health
+            .check_in(SupervisedTask::UpdateSystemTime);
+
         let sys_time = ctx.shared.system_time;
         sys_time.update_time(monotonics::now());
 
+        ble_update_time::spawn(*sys_time.utc(), *sys_time.time_zone()).ok();
+
+        check_alarms::spawn(sys_time.local()).ok();
+
         /*
         let t = monotonics::now();
         let d = t.duration_since_epoch();
@@ -293,7 +609,7 @@ mod app {
         rprintln!("t = {}, ms = {}", ticks, ms);
 
         ctx.shared.system_time.update_time(t);
-        let dt = ctx.shared.system_time.date_time();
+        let dt = ctx.shared.system_time.local();
         let time = dt.time();
         rprintln!("ut {}", ctx.shared.system_time.uptime());
         rprintln!("{}:{}:{}", time.hour(), time.minute(), time.second());
@@ -302,25 +618,46 @@ mod app {
         update_system_time::spawn_after(Seconds(1_u32)).unwrap();
     }
 
-    #[task(shared = [button], priority = 4)]
-    fn poll_button(ctx: poll_button::Context) {
-        if ctx.shared.button.is_pressed() {
-            button_pressed::spawn().ok();
+    #[task(shared = [settings, system_time], priority = 5)]
+    fn set_system_time(mut ctx: set_system_time::Context, local: NaiveDateTime) {
+        rprintln!("Set time {}", local);
+        if let Some(ppm) = ctx.shared.system_time.set_local(local, monotonics::now()) {
+            rprintln!("RTC drift {} ppm", ppm);
+            let mut settings = ctx.shared.settings.lock(|s| *s);
+            settings.rtc_drift_ppm = ppm as i16;
+            update_settings::spawn(settings).ok();
         }
     }
 
-    #[task(shared = [&display_state], priority = 4)]
-    fn button_pressed(ctx: button_pressed::Context) {
-        // TODO - if already awake, then turn off disable
+    #[task(local = [ignore_press: bool = false], shared = [&display_state, button], priority = 4)]
+    fn poll_button(ctx: poll_button::Context) {
+        let button = ctx.shared.button;
         let display_state = ctx.shared.display_state;
-        if !display_state.is_awake() {
+        let ignore_press = ctx.local.ignore_press;
+
+        if button.is_pressed() && !button.is_busy() {
+            // The press that wakes up the display isn't passed on to the screens
+            *ignore_press = !display_state.is_awake();
             wakeup_display::spawn().ok();
         }
+
+        if let Some(event) = button.poll(now_ms()) {
+            rprintln!("{:?}", event);
+            if !*ignore_press {
+                handle_input::spawn(event.into()).ok();
+            }
+        }
+
+        if button.is_busy() {
+            poll_button::spawn_after(Button::POLL_INTERVAL_MS).ok();
+        } else {
+            *ignore_press = false;
+        }
     }
 
-    #[task(local = [touch_controller], shared = [&display_state, display], priority = 5)]
+    #[task(shared = [&display_state, display, touch_controller], priority = 5)]
     fn touch_event(ctx: touch_event::Context) {
-        let touch_controller = ctx.local.touch_controller;
+        let touch_controller = ctx.shared.touch_controller;
         let display_state = ctx.shared.display_state;
         let display = ctx.shared.display;
 
@@ -334,35 +671,235 @@ mod app {
                     }
                     _ => (),
                 }
+                if let Some(event) = touch_data.input_event() {
+                    handle_input::spawn(event).ok();
+                }
             }
             wakeup_display::spawn().ok();
         }
     }
 
-    #[task(shared = [backlight], priority = 6)]
-    fn ramp_on_backlight(ctx: ramp_on_backlight::Context) {
+    // Same priority as touch_event, they share the TWI bus
+    #[task(shared = [accelerometer], priority = 5)]
+    fn step_event(ctx: step_event::Context) {
+        let accelerometer = ctx.shared.accelerometer;
+        match accelerometer.step_count() {
+            Ok(steps) => rprintln!("Steps {}", steps),
+            Err(e) => rprintln!("BMA421 error {:?}", e),
+        }
+    }
+
+    // Same priority as touch_event, they share the TWI bus
+    #[task(
+        local = [
+            detector: WristTiltDetector = WristTiltDetector::new(wrist_tilt::Sensitivity::Medium)
+        ],
+        shared = [&display_state, settings, accelerometer],
+        priority = 5)
+    ]
+    fn poll_wrist_tilt(mut ctx: poll_wrist_tilt::Context) {
+        let detector = ctx.local.detector;
+        let (enabled, sensitivity) = ctx
+            .shared
+            .settings
+            .lock(|s| (s.wrist_raise, s.wrist_raise_sensitivity));
+
+        if !enabled {
+            detector.reset();
+        } else {
+            detector.set_sensitivity(sensitivity);
+            match ctx.shared.accelerometer.acceleration() {
+                Ok(acc) => {
+                    // BMA421 axes line up with the watch's frame
+                    let sample = wrist_tilt::Sample::new(
+                        acc.x_milli_g() as i16,
+                        acc.y_milli_g() as i16,
+                        acc.z_milli_g() as i16,
+                    );
+                    if detector.update(sample) && !ctx.shared.display_state.is_awake() {
+                        rprintln!("Wrist raise");
+                        wakeup_display::spawn().ok();
+                    }
+                }
+                Err(e) => rprintln!("BMA421 error {:?}", e),
+            }
+        }
+
+        poll_wrist_tilt::spawn_after(WRIST_TILT_POLL_INTERVAL).unwrap();
+    }
+
+    /// Runs the sensor while the heart rate screen is shown
+    ///
+    /// Same priority as touch_event, they share the TWI bus
+    #[task(
+        local = [estimator: BpmEstimator = BpmEstimator::new(), running: bool = false],
+        shared = [&display_state, heart_rate_sensor, heart_rate_bpm, screen_manager],
+        priority = 5)
+    ]
+    fn poll_heart_rate(ctx: poll_heart_rate::Context) {
+        let sensor = ctx.shared.heart_rate_sensor;
+        let estimator = ctx.local.estimator;
+        let running = ctx.local.running;
+        let shown = ctx.shared.display_state.is_awake()
+            && ctx.shared.screen_manager.active() == ScreenId::HeartRate;
+
+        if shown && !*running {
+            estimator.reset();
+            *running = sensor.enable().is_ok();
+        } else if !shown && *running {
+            sensor.disable().ok();
+            *running = false;
+            *ctx.shared.heart_rate_bpm = None;
+        } else if *running {
+            match sensor.read_hrs() {
+                Ok(raw) => {
+                    estimator.update(raw);
+                    *ctx.shared.heart_rate_bpm = estimator.bpm();
+                }
+                Err(e) => rprintln!("HRS3300 error {:?}", e),
+            }
+        }
+
+        let interval = if *running {
+            HEART_RATE_SAMPLE_INTERVAL
+        } else {
+            HEART_RATE_IDLE_POLL_INTERVAL
+        };
+        poll_heart_rate::spawn_after(interval).unwrap();
+    }
+
+    #[task(
+        shared = [system_time, alarm_scheduler, stopwatch, countdown, screen_manager],
+        capacity = 4,
+        priority = 5)
+    ]
+    fn handle_input(ctx: handle_input::Context, event: InputEvent) {
+        let screen_manager = ctx.shared.screen_manager;
+        match screen_manager.handle_event(event) {
+            Action::None => (),
+            Action::Push(_) | Action::Pop | Action::Switch(_) => {
+                rprintln!("Screen {:?}", screen_manager.active())
+            }
+            Action::UpdateSettings(settings) => {
+                update_settings::spawn(settings).ok();
+            }
+            Action::AlarmResponse(response) => {
+                rprintln!("Alarm {:?}", response);
+                let now = ctx.shared.system_time.local();
+                ctx.shared.alarm_scheduler.respond(response, &now);
+                stop_vibration::spawn().ok();
+            }
+            Action::Stopwatch(control) => ctx.shared.stopwatch.control(control, now_ms()),
+            Action::Countdown(control) => {
+                let countdown = ctx.shared.countdown;
+                countdown.control(control, now_ms());
+                if countdown.is_running() {
+                    poll_countdown::spawn_after(Milliseconds(countdown.remaining_ms(now_ms())))
+                        .ok();
+                }
+            }
+            Action::Power(action) => {
+                rprintln!("Power {:?}", action);
+                match action {
+                    PowerAction::Reboot => reboot::spawn().ok(),
+                    PowerAction::PowerOff => shut_down::spawn().ok(),
+                    PowerAction::Update => install_update::spawn().ok(),
+                };
+            }
+        }
+    }
+
+    /// Alert when the countdown runs out, scheduled for when it's expected to.
+    /// Scheduled checks left over from a countdown that was paused or reset do nothing.
+    #[task(shared = [countdown, screen_manager], capacity = 4, priority = 5)]
+    fn poll_countdown(ctx: poll_countdown::Context) {
+        let countdown = ctx.shared.countdown;
+        if countdown.poll(now_ms()) {
+            rprintln!("Countdown expired");
+            ctx.shared.screen_manager.on_countdown_expired();
+            wakeup_display::spawn().ok();
+            vibrate::spawn(Pattern::TIMER).ok();
+        } else if countdown.is_running() {
+            // Woke up a little early, ticks and milliseconds don't divide evenly
+            let remaining_ms = countdown.remaining_ms(now_ms()).max(1);
+            poll_countdown::spawn_after(Milliseconds(remaining_ms)).ok();
+        }
+    }
+
+    /// Ring the alarms that came due, or stop ringing if nobody responded
+    #[task(shared = [settings, alarm_scheduler, screen_manager], priority = 5)]
+    fn check_alarms(mut ctx: check_alarms::Context, now: NaiveDateTime) {
+        let mut settings = ctx.shared.settings.lock(|s| *s);
+        let screen_manager = ctx.shared.screen_manager;
+        match ctx.shared.alarm_scheduler.poll(&settings.alarms, &now) {
+            Some(alarm::Event::Ring { alarm }) => {
+                rprintln!("Alarm ringing {:?}", alarm);
+                // One-shot alarms are done once they ring, a snooze still brings them back
+                if let Some(index) = alarm.filter(|&i| settings.alarms[i].days.is_empty()) {
+                    settings.alarms[index].enabled = false;
+                    update_settings::spawn(settings).ok();
+                }
+                screen_manager.on_alarm_ringing();
+                wakeup_display::spawn().ok();
+                vibrate::spawn(Pattern::ALARM).ok();
+            }
+            Some(alarm::Event::Silenced) => {
+                rprintln!("Alarm snoozed, nobody responded");
+                screen_manager.on_alarm_silenced();
+                stop_vibration::spawn().ok();
+            }
+            None => (),
+        }
+    }
+
+    #[task(shared = [settings, low_battery, backlight], priority = 6)]
+    fn ramp_on_backlight(mut ctx: ramp_on_backlight::Context) {
+        let settings = ctx.shared.settings.lock(|s| *s);
+        let brightness = ctx
+            .shared
+            .low_battery
+            .lock(|l| l.max_brightness(settings.brightness));
         let backlight = ctx.shared.backlight;
-        if backlight.brightness() != Brightness::L7 {
+        if backlight.brightness() < brightness {
             backlight.brighter();
-            ramp_on_backlight::spawn_after(Backlight::RAMP_INC_MS).unwrap();
+            ramp_on_backlight::spawn_after(Milliseconds(settings.backlight_ramp_ms as u32))
+                .unwrap();
+        } else if backlight.brightness() > brightness {
+            backlight.darker();
+            ramp_on_backlight::spawn_after(Milliseconds(settings.backlight_ramp_ms as u32))
+                .unwrap();
         }
     }
 
-    #[task(shared = [backlight], priority = 6)]
-    fn ramp_off_backlight(ctx: ramp_off_backlight::Context) {
+    #[task(shared = [settings, backlight], priority = 6)]
+    fn ramp_off_backlight(mut ctx: ramp_off_backlight::Context) {
+        let ramp_ms = ctx.shared.settings.lock(|s| s.backlight_ramp_ms);
         let backlight = ctx.shared.backlight;
         if backlight.brightness() != Brightness::Off {
             backlight.darker();
-            ramp_off_backlight::spawn_after(Backlight::RAMP_INC_MS).unwrap();
+            ramp_off_backlight::spawn_after(Milliseconds(ramp_ms as u32)).unwrap();
         }
     }
 
-    #[task(shared = [&display_state, display_sleep_timer], priority = 6)]
-    fn wakeup_display(ctx: wakeup_display::Context) {
+    /// Free-running milliseconds since boot, wraps at `u32::MAX`
+    fn now_ms() -> u32 {
+        static TICKS: Mutex<RefCell<TickCounter>> =
+            Mutex::new(RefCell::new(TickCounter::new(MAX_TICKS)));
+        // Read the ticks in the critical section too, or a preempting task could count
+        // a later reading first and this one would look like a wrap
+        cortex_m::interrupt::free(|cs| {
+            let ticks = monotonics::now().duration_since_epoch().integer();
+            rtc_monotonic::ticks_to_ms(TICKS.borrow(cs).borrow_mut().update(ticks))
+        })
+    }
+
+    #[task(shared = [&display_state, settings, display_sleep_timer], priority = 6)]
+    fn wakeup_display(mut ctx: wakeup_display::Context) {
+        let timeout_secs = ctx.shared.settings.lock(|s| s.display_timeout_secs);
         let display_state = ctx.shared.display_state;
         let display_sleep_timer = ctx.shared.display_sleep_timer;
 
-        display_sleep_timer.start(DISPLAY_TIMEOUT_TIMER_TICKS);
+        display_sleep_timer.start(Timer::<pac::TIMER0>::TICKS_PER_SECOND * timeout_secs as u32);
         if !display_state.is_awake() {
             display_state.awaken();
             draw_screen::spawn().ok(); // backlight task is higher prio than display atm
@@ -389,7 +926,7 @@ mod app {
     }
 
     // TODO - consider starting/resetting a timer here instead, and checking after it expires
-    #[task(shared = [battery_controller], priority = 5)]
+    #[task(shared = [battery_controller, screen_manager], priority = 5)]
     fn poll_battery_io(ctx: poll_battery_io::Context) {
         if ctx.shared.battery_controller.update_charging_io() {
             rprintln!(
@@ -399,61 +936,503 @@ mod app {
                 ctx.shared.battery_controller.percent_remaining()
             );
 
+            ctx.shared
+                .screen_manager
+                .on_charging(ctx.shared.battery_controller.is_charging());
+
             wakeup_display::spawn().ok();
 
-            start_ring::spawn_after(
+            vibrate::spawn_after(
                 BatteryController::POWER_PRESENCE_DEBOUNCE_MS,
-                BatteryController::CHARGE_EVENT_RING_DURATION,
+                Pattern::CHARGING,
             )
             .ok();
         }
     }
 
-    #[task(shared = [battery_controller], priority = 5)]
-    fn poll_battery_voltage(ctx: poll_battery_voltage::Context) {
-        ctx.shared.battery_controller.update_voltage();
-        poll_battery_voltage::spawn_after(BatteryController::VOLTAGE_POLL_INTERVAL_MS).unwrap();
+    #[task(
+        shared = [
+            &display_state,
+            settings,
+            battery_controller,
+            low_battery,
+            screen_manager
+        ],
+        priority = 5)
+    ]
+    fn poll_battery_voltage(mut ctx: poll_battery_voltage::Context) {
+        let battery_controller = ctx.shared.battery_controller;
+        if battery_controller.update_charge_indication() {
+            rprintln!("Charge state {:?}", battery_controller.charge_state());
+        }
+        battery_controller.update_voltage();
+
+        ble_update_battery::spawn(battery_controller.percent_remaining()).ok();
+
+        // The shutdown voltage is for the filtered voltage, single readings sag under load
+        let (percent, voltage, state) = (
+            battery_controller.percent_remaining(),
+            battery_controller.filtered_voltage(),
+            battery_controller.charge_state(),
+        );
+        // A failed reading says nothing about the battery, a 0 mV one would shut down
+        let event = if battery_controller.has_plausible_voltage() {
+            ctx.shared
+                .low_battery
+                .lock(|l| l.update(percent, voltage, state))
+        } else {
+            rprintln!(
+                "Implausible battery reading {}",
+                battery_controller.voltage()
+            );
+            None
+        };
+        match event {
+            Some(low_battery::Event::Warning) => {
+                rprintln!("Battery low {}%", percent);
+                ctx.shared.screen_manager.on_low_battery();
+                wakeup_display::spawn().ok();
+                vibrate::spawn(Pattern::LOW_BATTERY).ok();
+            }
+            Some(low_battery::Event::PowerSave(power_save)) => {
+                rprintln!("Power save {}", power_save);
+                // Re-target the backlight to the new cap
+                if ctx.shared.display_state.is_awake() {
+                    ramp_on_backlight::spawn().ok();
+                }
+            }
+            Some(low_battery::Event::Shutdown) => {
+                rprintln!("Battery empty {}, shutting down", voltage);
+                shut_down::spawn().ok();
+                return;
+            }
+            None => (),
+        }
+
+        let poll_interval_ms = ctx.shared.settings.lock(|s| s.voltage_poll_interval_ms);
+        let poll_interval_ms = ctx
+            .shared
+            .low_battery
+            .lock(|l| l.voltage_poll_interval_ms(poll_interval_ms));
+        poll_battery_voltage::spawn_after(Milliseconds(poll_interval_ms as u32)).unwrap();
+    }
+
+    /// Darken the display for good, at the backlight's priority so nothing ramps it back on
+    #[task(shared = [&display_state, backlight], priority = 6)]
+    fn shut_down(ctx: shut_down::Context) {
+        ctx.shared.display_state.get_and_clear();
+        ctx.shared.backlight.off();
+        sleep_peripherals::spawn().ok();
+    }
+
+    /// Put everything that would keep drawing power through System OFF to sleep
+    ///
+    /// Runs at the display's priority, it shares the SPI bus with the flash and the TWI
+    /// bus with the sensors
+    #[task(
+        shared = [display, touch_controller, heart_rate_sensor, flash_delay],
+        priority = 5)
+    ]
+    fn sleep_peripherals(ctx: sleep_peripherals::Context) {
+        if let Err(e) = ctx.shared.display.sleep() {
+            rprintln!("Display sleep error {:?}", e);
+        }
+        let flash_delay = ctx.shared.flash_delay;
+        if let Err(e) = ctx.shared.touch_controller.sleep(flash_delay) {
+            rprintln!("Touch controller sleep error {:?}", e);
+        }
+        ctx.shared.heart_rate_sensor.disable().ok();
+        power_off::spawn().ok();
+    }
+
+    /// Enter System OFF, the button wakes the watch back up with a reset
+    ///
+    /// Runs at the motor's priority to make sure it isn't left running
+    #[task(local = [power], shared = [motor_controller, vibration_player], priority = 2)]
+    fn power_off(ctx: power_off::Context) {
+        ctx.shared.vibration_player.stop();
+        ctx.shared.motor_controller.off();
+        Button::enable_wakeup();
+        rprintln!("System off");
+        ctx.local.power.system_off();
     }
 
-    #[task(shared = [motor_controller], priority = 2)]
-    fn start_ring(ctx: start_ring::Context, duration: Milliseconds<u32>) {
-        if !ctx.shared.motor_controller.is_on() {
-            rprintln!("start ring {}", duration);
-            ctx.shared.motor_controller.on();
-            stop_ring::spawn_after(duration).ok();
+    /// Add to the battery history, for the battery screen
+    #[task(shared = [battery_controller, battery_history], priority = 5)]
+    fn sample_battery(ctx: sample_battery::Context) {
+        let battery_controller = ctx.shared.battery_controller;
+        ctx.shared.battery_history.push(battery_history::Sample {
+            voltage: battery_controller.voltage(),
+            percent: battery_controller.percent_remaining(),
+            state: battery_controller.charge_state(),
+        });
+
+        sample_battery::spawn_after(Seconds(battery_history::SAMPLE_INTERVAL_SECS)).unwrap();
+    }
+
+    /// Play a vibration pattern, unless a higher priority one is playing
+    #[task(shared = [motor_controller, vibration_player], capacity = 2, priority = 2)]
+    fn vibrate(ctx: vibrate::Context, pattern: Pattern) {
+        let player = ctx.shared.vibration_player;
+        if let Some(cmd) = player.play(pattern) {
+            run_vibration(ctx.shared.motor_controller, player, cmd);
         }
     }
 
-    #[task(shared = [motor_controller], priority = 2)]
-    fn stop_ring(ctx: stop_ring::Context) {
-        rprintln!("stop ring");
+    /// Previous step elapsed, steps of a replaced pattern are ignored by the player
+    /// Stop whatever pattern is playing
+    #[task(shared = [motor_controller, vibration_player], priority = 2)]
+    fn stop_vibration(ctx: stop_vibration::Context) {
+        ctx.shared.vibration_player.stop();
         ctx.shared.motor_controller.off();
     }
 
+    #[task(shared = [motor_controller, vibration_player], capacity = 4, priority = 2)]
+    fn vibration_step(ctx: vibration_step::Context, generation: u8) {
+        let player = ctx.shared.vibration_player;
+        if let Some(cmd) = player.advance(generation) {
+            run_vibration(ctx.shared.motor_controller, player, cmd);
+        }
+    }
+
+    fn run_vibration(
+        motor: &mut MotorController,
+        player: &mut vibration::Player,
+        cmd: vibration::Command,
+    ) {
+        match cmd {
+            vibration::Command::Step(step) => {
+                let duration = Milliseconds(step.duration_ms as u32);
+                if vibration_step::spawn_after(duration, step.generation).is_err() {
+                    // Don't leave the motor running without a step to turn it off
+                    player.stop();
+                    motor.off();
+                } else if step.motor_on {
+                    motor.on();
+                } else {
+                    motor.off();
+                }
+            }
+            vibration::Command::Off => motor.off(),
+        }
+    }
+
+    #[task(binds = RADIO, shared = [ble_radio, ble_link_layer], priority = 7)]
+    fn ble_radio(ctx: ble_radio::Context) {
+        if ble::radio_interrupt(ctx.shared.ble_radio, ctx.shared.ble_link_layer) {
+            ble_worker::spawn().ok();
+        }
+    }
+
+    #[task(binds = TIMER2, shared = [ble_radio, ble_link_layer], priority = 7)]
+    fn ble_timer(ctx: ble_timer::Context) {
+        if ble::timer_interrupt(ctx.shared.ble_radio, ctx.shared.ble_link_layer) {
+            ble_worker::spawn().ok();
+        }
+    }
+
+    #[task(shared = [ble_responder], priority = 3)]
+    fn ble_worker(ctx: ble_worker::Context) {
+        let responder = ctx.shared.ble_responder;
+        ble::process_work(responder);
+
+        let gatt_server = match ble::gatt_server(responder) {
+            Some(gatt_server) => gatt_server,
+            None => return,
+        };
+        while let Some(event) = gatt_server.pop_event() {
+            match event {
+                GattEvent::SetDateTime(dt) => {
+                    set_system_time::spawn(dt).ok();
+                }
+                GattEvent::SetLocalTimeInformation(info) => {
+                    set_time_zone::spawn(info).ok();
+                }
+                GattEvent::NewAlert(alert) => {
+                    new_notification::spawn(alert).ok();
+                }
+                GattEvent::DfuPage(page) => {
+                    if dfu_write::spawn(page).is_err() {
+                        gatt_server.set_dfu_result(Err(Failure::Busy));
+                    }
+                }
+                GattEvent::DfuFinish => {
+                    if dfu_finish::spawn().is_err() {
+                        gatt_server.set_dfu_result(Err(Failure::Busy));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Take the UTC offset from the phone, it's kept in the settings
+    #[task(shared = [settings, system_time], priority = 5)]
+    fn set_time_zone(mut ctx: set_time_zone::Context, info: LocalTimeInformation) {
+        let mut settings = ctx.shared.settings.lock(|s| *s);
+        settings.time_zone = info.apply(settings.time_zone);
+        // Right away, a Current Time write that follows is local time in the new zone
+        ctx.shared.system_time.set_time_zone(settings.time_zone);
+        rprintln!(
+            "Set time zone {} DST {}",
+            settings.time_zone,
+            settings.time_zone.dst
+        );
+        update_settings::spawn(settings).ok();
+    }
+
+    /// Store a notification from the phone, show it and buzz
+    #[task(shared = [system_time, notifications, screen_manager], capacity = 2, priority = 5)]
+    fn new_notification(ctx: new_notification::Context, alert: NewAlert) {
+        let notification = Notification::new(
+            alert.category,
+            ctx.shared.system_time.local(),
+            &alert.title,
+            &alert.body,
+        );
+        rprintln!(
+            "Notification {} '{}'",
+            notification.category,
+            notification.title
+        );
+        ctx.shared.notifications.push(notification);
+
+        ctx.shared.screen_manager.on_notification();
+
+        wakeup_display::spawn().ok();
+        vibrate::spawn(Pattern::NOTIFICATION).ok();
+    }
+
+    #[task(shared = [ble_responder], priority = 3)]
+    fn ble_update_battery(ctx: ble_update_battery::Context, percent_remaining: u8) {
+        if let Some(gatt_server) = ble::gatt_server(ctx.shared.ble_responder) {
+            gatt_server.set_battery_level(percent_remaining);
+        }
+    }
+
+    #[task(shared = [ble_responder], capacity = 2, priority = 3)]
+    fn ble_dfu_result(ctx: ble_dfu_result::Context, result: Result<(), Failure>) {
+        if let Some(gatt_server) = ble::gatt_server(ctx.shared.ble_responder) {
+            gatt_server.set_dfu_result(result);
+        }
+    }
+
+    /// Write a page of a firmware update into the staging slot
+    #[task(shared = [spi_flash, flash_delay], capacity = 4, priority = 5)]
+    fn dfu_write(ctx: dfu_write::Context, page: Page) {
+        let spi_flash = ctx.shared.spi_flash;
+        let flash_delay = ctx.shared.flash_delay;
+        let result = spi_flash
+            .release_deep_power_down(flash_delay)
+            .and_then(|_| receiver::write_page(spi_flash, flash_layout::DFU_STAGING_OFFSET, &page));
+        spi_flash.deep_power_down().ok();
+        if let Err(e) = result {
+            rprintln!("Failed to write firmware update {:?}", e);
+            ble_dfu_result::spawn(Err(Failure::Flash)).ok();
+        }
+    }
+
+    /// Check the received firmware update and reboot into the bootloader to install it
+    #[task(shared = [spi_flash, flash_delay, boot_state_store], priority = 5)]
+    fn dfu_finish(ctx: dfu_finish::Context) {
+        let result = request_install(
+            ctx.shared.spi_flash,
+            ctx.shared.flash_delay,
+            ctx.shared.boot_state_store,
+        );
+        if result.is_ok() {
+            reboot::spawn_after(DFU_REBOOT_DELAY).ok();
+        }
+        ble_dfu_result::spawn(result).ok();
+    }
+
+    /// Reboot into the bootloader to install the firmware update on the external flash,
+    /// picked from the power menu
+    #[task(shared = [spi_flash, flash_delay, boot_state_store], priority = 5)]
+    fn install_update(ctx: install_update::Context) {
+        let result = request_install(
+            ctx.shared.spi_flash,
+            ctx.shared.flash_delay,
+            ctx.shared.boot_state_store,
+        );
+        if result.is_ok() {
+            reboot::spawn().ok();
+        }
+    }
+
+    /// Have the bootloader install the staged firmware update on the next reset, if it
+    /// checks out
+    fn request_install(
+        spi_flash: &mut SpiFlash<Spim0Proxy, Spim0ChipSelect<FlashCsPin>>,
+        flash_delay: &mut Delay,
+        boot_state_store: &mut BootStateStore,
+    ) -> Result<(), Failure> {
+        let result = spi_flash
+            .release_deep_power_down(flash_delay)
+            .map_err(|e| {
+                rprintln!("SPI flash error {:?}", e);
+                Failure::Flash
+            })
+            .and_then(|_| {
+                image::verify(spi_flash, flash_layout::DFU_STAGING_OFFSET).map_err(|e| {
+                    rprintln!("Invalid firmware update {:?}", e);
+                    Failure::InvalidImage
+                })
+            })
+            .and_then(|image| {
+                rprintln!("Installing firmware {}", image.version);
+                boot_state_store
+                    .request_install(spi_flash, image)
+                    .map_err(|e| {
+                        rprintln!("Failed to request the install {:?}", e);
+                        Failure::Flash
+                    })
+            });
+        spi_flash.deep_power_down().ok();
+        result
+    }
+
+    #[task(shared = [ble_responder], priority = 3)]
+    fn ble_update_time(ctx: ble_update_time::Context, utc: NaiveDateTime, time_zone: TimeZone) {
+        if let Some(gatt_server) = ble::gatt_server(ctx.shared.ble_responder) {
+            gatt_server.set_current_time(&utc, &time_zone);
+        }
+    }
+
     #[task(
-        local = [watch_face],
-        shared = [&display_state, display, system_time, battery_controller],
+        shared = [
+            &display_state,
+            &task_health,
+            settings,
+            display,
+            system_time,
+            battery_controller,
+            heart_rate_bpm,
+            notifications,
+            stopwatch,
+            countdown,
+            battery_history,
+            boot_record,
+            self_test,
+            crash_record,
+            screen_manager
+        ],
         capacity = 2,
         priority = 5)
     ]
-    fn draw_screen(ctx: draw_screen::Context) {
+    fn draw_screen(mut ctx: draw_screen::Context) {
         let display = ctx.shared.display;
         let display_state = ctx.shared.display_state;
 
+        let settings = ctx.shared.settings.lock(|s| *s);
+
         if display_state.is_awake() {
-            let screen = ctx.local.watch_face;
+            let screen_manager = ctx.shared.screen_manager;
 
-            let res = WatchFaceResources {
+            let res = Resources {
                 sys_time: ctx.shared.system_time,
                 bat_ctl: ctx.shared.battery_controller,
+                settings: &settings,
+                heart_rate_bpm: *ctx.shared.heart_rate_bpm,
+                notifications: ctx.shared.notifications,
+                now_ms: now_ms(),
+                stopwatch: ctx.shared.stopwatch,
+                countdown: ctx.shared.countdown,
+                battery_history: ctx.shared.battery_history,
+                boot_record: ctx.shared.boot_record,
+                self_test: *ctx.shared.self_test,
+                crash_record: ctx.shared.crash_record.as_ref(),
             };
-            screen.update(&res).unwrap();
-            screen.draw(display).unwrap();
-            screen.clear_redraw();
+            screen_manager.update(&res).unwrap();
+            screen_manager.draw(display).unwrap();
         }
 
         display.update_animations().unwrap();
 
-        draw_screen::spawn_after(SCREEN_REFRESH_INTERVAL).unwrap();
+        ctx.shared.task_health.check_in(SupervisedTask::DrawScreen);
+        draw_screen::spawn_after(Milliseconds(settings.screen_refresh_interval_ms as u32)).unwrap();
+    }
+
+    /// Keep a freshly installed firmware update if it passed the self-test, the bootloader
+    /// rolls it back if this doesn't happen before the next reset. One that failed the
+    /// self-test is rolled back right away
+    #[task(shared = [spi_flash, flash_delay, boot_state_store], priority = 5)]
+    fn confirm_boot(ctx: confirm_boot::Context, self_test_passed: bool) {
+        let spi_flash = ctx.shared.spi_flash;
+        let flash_delay = ctx.shared.flash_delay;
+        let boot_state_store = ctx.shared.boot_state_store;
+        let result = spi_flash
+            .release_deep_power_down(flash_delay)
+            .map_err(|e| rprintln!("SPI flash error {:?}", e))
+            .and_then(|_| {
+                if self_test_passed {
+                    boot_state_store.confirm(spi_flash)
+                } else {
+                    boot_state_store.load(spi_flash)
+                }
+                .map_err(|e| rprintln!("Failed to read the boot state {:?}", e))
+            });
+        spi_flash.deep_power_down().ok();
+        match (result.map(|state| state.swap), self_test_passed) {
+            (Ok(SwapState::Testing), true) => rprintln!("Firmware update confirmed"),
+            (Ok(SwapState::Testing), false) => {
+                rprintln!("Firmware update failed the self-test, rolling back");
+                reboot::spawn().ok();
+            }
+            (Ok(SwapState::RolledBack), _) => rprintln!("Firmware update was rolled back"),
+            (_, false) => rprintln!("Self-test failed"),
+            _ => (),
+        }
+    }
+
+    /// Reset, through the bootloader
+    #[task(priority = 5)]
+    fn reboot(_: reboot::Context) {
+        rprintln!("Rebooting");
+        pac::SCB::sys_reset();
+    }
+
+    /// Apply and persist new settings
+    ///
+    /// Runs at the same priority as the display since they share the SPI bus
+    #[task(
+        local = [settings_store],
+        shared = [&display_state, settings, system_time, spi_flash, flash_delay],
+        capacity = 2,
+        priority = 5)
+    ]
+    fn update_settings(mut ctx: update_settings::Context, settings: Settings) {
+        let changed = ctx.shared.settings.lock(|s| {
+            let changed = *s != settings;
+            *s = settings;
+            changed
+        });
+        if !changed {
+            return;
+        }
+
+        let system_time = ctx.shared.system_time;
+        system_time.set_time_zone(settings.time_zone);
+        system_time.set_drift_ppm(settings.rtc_drift_ppm as i32);
+
+        // Re-target the backlight in case the brightness changed
+        if ctx.shared.display_state.is_awake() {
+            ramp_on_backlight::spawn().ok();
+        }
+
+        let spi_flash = ctx.shared.spi_flash;
+        let flash_delay = ctx.shared.flash_delay;
+        let settings_store = ctx.local.settings_store;
+        let result = spi_flash
+            .release_deep_power_down(flash_delay)
+            .map_err(|e| rprintln!("SPI flash error {:?}", e))
+            .and_then(|_| {
+                settings_store
+                    .save(spi_flash, &settings)
+                    .map_err(|e| rprintln!("Failed to save settings {:?}", e))
+            });
+        spi_flash.deep_power_down().ok();
+        if result.is_ok() {
+            rprintln!("Saved settings");
+        }
     }
 }
@@ -0,0 +1,57 @@
+//! State kept in RAM across resets, see the RETAINED region in memory.x.in
+//!
+//! Nothing here is initialized on startup, whatever is read back has to be checked.
+
+use core::{
+    mem::MaybeUninit,
+    ptr::{addr_of, addr_of_mut},
+};
+use pinetime_common::{BootRecord, CrashRecord};
+
+#[link_section = ".retained.boot_record"]
+static mut BOOT_RECORD: MaybeUninit<[u8; BootRecord::SIZE]> = MaybeUninit::uninit();
+
+#[link_section = ".retained.crash_record"]
+static mut CRASH_RECORD: MaybeUninit<[u8; CrashRecord::SIZE]> = MaybeUninit::uninit();
+
+/// Only call from init, nothing else touches it
+pub fn load_boot_record() -> BootRecord {
+    let bytes =
+        unsafe { core::ptr::read_volatile(addr_of!(BOOT_RECORD).cast::<[u8; BootRecord::SIZE]>()) };
+    BootRecord::from_retained(&bytes)
+}
+
+/// Only call from init, nothing else touches it
+pub fn store_boot_record(record: &BootRecord) {
+    unsafe {
+        core::ptr::write_volatile(
+            addr_of_mut!(BOOT_RECORD).cast::<[u8; BootRecord::SIZE]>(),
+            record.encode(),
+        )
+    };
+}
+
+/// The crash before the last reset, if there was one. Only call from init, it's cleared
+/// so the crash is only reported once
+pub fn take_crash_record() -> Option<CrashRecord> {
+    unsafe {
+        let record = CrashRecord::decode(&core::ptr::read_volatile(
+            addr_of!(CRASH_RECORD).cast::<[u8; CrashRecord::SIZE]>(),
+        ));
+        core::ptr::write_volatile(
+            addr_of_mut!(CRASH_RECORD).cast::<[u8; CrashRecord::SIZE]>(),
+            [0; CrashRecord::SIZE],
+        );
+        record
+    }
+}
+
+/// Only call with interrupts disabled, on the way to a reset
+pub fn store_crash_record(record: &CrashRecord) {
+    unsafe {
+        core::ptr::write_volatile(
+            addr_of_mut!(CRASH_RECORD).cast::<[u8; CrashRecord::SIZE]>(),
+            record.encode(),
+        )
+    };
+}
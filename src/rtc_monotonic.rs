@@ -164,3 +164,10 @@ where
         self.rtc.disable_event(RtcInterrupt::Compare0);
     }
 }
+
+/// Convert ticks, as extended to 64 bits by a `TickCounter`, to free-running
+/// milliseconds. Wraps at `u32::MAX` ms without skipping any, unlike converting the
+/// 32 bit tick count directly.
+pub fn ticks_to_ms(ticks: u64) -> u32 {
+    ((ticks * 1000) / TICK_RATE_HZ as u64) as u32
+}
@@ -1,17 +1,21 @@
-//! System time managing seconds since epoch
+//! System time managing milliseconds since epoch
 
 use crate::hal::{rtc, timer};
 use crate::rtc_monotonic::{RtcMonotonic, MAX_TICKS, TICK_RATE_HZ};
 use pinetime_common::{
     chrono::{Duration, NaiveDateTime},
-    SystemTimeExt,
+    clock_drift::{self, DriftCorrection, DriftEstimator},
+    SystemTimeExt, TimeZone,
 };
 use rtic::time::{duration::Seconds, Instant};
 
 pub struct SystemTime<RTC: rtc::Instance, TIM: timer::Instance> {
-    uptime: Seconds,
+    uptime_ms: u64,
     last_clock_instant: Instant<RtcMonotonic<RTC, TIM>>,
-    date_time: NaiveDateTime,
+    utc: NaiveDateTime,
+    time_zone: TimeZone,
+    drift: DriftCorrection,
+    drift_estimator: DriftEstimator,
 }
 
 impl<RTC, TIM> Default for SystemTime<RTC, TIM>
@@ -31,44 +35,57 @@ where
 {
     pub fn new() -> Self {
         SystemTime {
-            uptime: Seconds::new(0),
+            uptime_ms: 0,
             last_clock_instant: Instant::new(0),
-            date_time: NaiveDateTime::from_timestamp(0, 0),
+            utc: NaiveDateTime::from_timestamp(0, 0),
+            time_zone: TimeZone::UTC,
+            drift: DriftCorrection::new(TICK_RATE_HZ),
+            drift_estimator: DriftEstimator::new(TICK_RATE_HZ),
         }
     }
 
     pub fn update_time(&mut self, now: Instant<RtcMonotonic<RTC, TIM>>) {
         let ticks = now.duration_since_epoch().integer();
         let prev_ticks = self.last_clock_instant.duration_since_epoch().integer();
+        let tick_delta = clock_drift::tick_delta(prev_ticks, ticks, MAX_TICKS);
+        self.last_clock_instant = now;
 
-        let tick_delta = if ticks < prev_ticks {
-            (MAX_TICKS - prev_ticks) + (ticks + 1)
-        } else {
-            ticks - prev_ticks
-        };
-
-        let corrected_tick_delta = tick_delta / TICK_RATE_HZ;
-        let rest = tick_delta - (corrected_tick_delta * TICK_RATE_HZ);
-        let last_clock_ticks = if ticks >= rest {
-            ticks - rest
-        } else {
-            MAX_TICKS - (rest - ticks)
-        };
-
-        self.last_clock_instant = Instant::new(last_clock_ticks);
+        self.drift_estimator.add_ticks(tick_delta);
+        let ms = self.drift.ticks_to_ms(tick_delta);
+        self.uptime_ms += ms;
+        self.utc += Duration::milliseconds(ms as i64);
+    }
 
-        let sec = Seconds::new(corrected_tick_delta);
-        self.uptime = self.uptime + sec;
+    /// Set the clock from local time, e.g. from a BLE time sync. Uptime is unaffected.
+    ///
+    /// Syncs also refine the drift estimate, returns the new one when it changed.
+    pub fn set_local(
+        &mut self,
+        local: NaiveDateTime,
+        now: Instant<RtcMonotonic<RTC, TIM>>,
+    ) -> Option<i32> {
+        // Count the ticks up to the sync itself
+        self.update_time(now);
+        self.utc = self.time_zone.to_utc(&local);
+        let ppm = self
+            .drift_estimator
+            .sync(self.utc.timestamp_millis(), self.drift.ppm())?;
+        self.drift.set_ppm(ppm);
+        Some(self.drift.ppm())
+    }
 
-        self.date_time += Duration::from_std(core::time::Duration::from_secs(sec.0 as _)).unwrap();
+    /// Only changes how the clock is shown, the clock itself stays in UTC
+    pub fn set_time_zone(&mut self, time_zone: TimeZone) {
+        self.time_zone = time_zone;
     }
 
-    pub fn uptime(&self) -> Seconds {
-        self.uptime
+    /// Crystal error to correct for, e.g. as learned before a reboot
+    pub fn set_drift_ppm(&mut self, ppm: i32) {
+        self.drift.set_ppm(ppm);
     }
 
-    pub fn date_time(&self) -> &NaiveDateTime {
-        &self.date_time
+    pub fn uptime(&self) -> Seconds {
+        Seconds::new((self.uptime_ms / 1000) as u32)
     }
 }
 
@@ -77,7 +94,15 @@ where
     RTC: rtc::Instance,
     TIM: timer::Instance,
 {
-    fn date_time(&self) -> &NaiveDateTime {
-        SystemTime::date_time(self)
+    fn utc(&self) -> &NaiveDateTime {
+        &self.utc
+    }
+
+    fn uptime_ms(&self) -> u64 {
+        self.uptime_ms
+    }
+
+    fn time_zone(&self) -> &TimeZone {
+        &self.time_zone
     }
 }